@@ -0,0 +1,15 @@
+CREATE TABLE users
+(
+    id            SERIAL PRIMARY KEY,
+    username      TEXT NOT NULL UNIQUE,
+    password_hash TEXT NOT NULL
+);
+
+-- rows created before accounts existed have no owner and are not visible to anyone
+ALTER TABLE todos
+    ADD COLUMN user_id INTEGER REFERENCES users (id);
+ALTER TABLE labels
+    ADD COLUMN user_id INTEGER REFERENCES users (id);
+
+CREATE INDEX todos_user_id_idx ON todos (user_id);
+CREATE INDEX labels_user_id_idx ON labels (user_id);
@@ -0,0 +1,50 @@
+CREATE TYPE workspace_role AS ENUM ('owner', 'editor', 'viewer');
+
+CREATE TABLE workspaces
+(
+    id   SERIAL PRIMARY KEY,
+    name TEXT NOT NULL
+);
+
+CREATE TABLE workspace_members
+(
+    workspace_id INTEGER        NOT NULL REFERENCES workspaces (id),
+    user_id      INTEGER        NOT NULL REFERENCES users (id),
+    role         workspace_role NOT NULL,
+    PRIMARY KEY (workspace_id, user_id)
+);
+
+CREATE INDEX workspace_members_user_id_idx ON workspace_members (user_id);
+
+ALTER TABLE todos
+    ADD COLUMN workspace_id INTEGER REFERENCES workspaces (id);
+ALTER TABLE labels
+    ADD COLUMN workspace_id INTEGER REFERENCES workspaces (id);
+
+-- every existing user gets a personal workspace holding what they owned so far
+WITH created AS (
+    INSERT INTO workspaces (name)
+        SELECT username FROM users ORDER BY id
+        RETURNING id, name
+)
+INSERT INTO workspace_members (workspace_id, user_id, role)
+SELECT created.id, users.id, 'owner'
+FROM created
+         JOIN users ON users.username = created.name;
+
+UPDATE todos
+SET workspace_id = workspace_members.workspace_id
+FROM workspace_members
+WHERE workspace_members.user_id = todos.user_id;
+UPDATE labels
+SET workspace_id = workspace_members.workspace_id
+FROM workspace_members
+WHERE workspace_members.user_id = labels.user_id;
+
+ALTER TABLE todos
+    DROP COLUMN user_id;
+ALTER TABLE labels
+    DROP COLUMN user_id;
+
+CREATE INDEX todos_workspace_id_idx ON todos (workspace_id);
+CREATE INDEX labels_workspace_id_idx ON labels (workspace_id);
@@ -0,0 +1,5 @@
+ALTER TABLE todos
+    ADD COLUMN due_at    TIMESTAMPTZ,
+    ADD COLUMN starts_at TIMESTAMPTZ;
+
+CREATE INDEX todos_workspace_id_due_at_idx ON todos (workspace_id, due_at);
@@ -0,0 +1,6 @@
+CREATE TYPE todo_priority AS ENUM ('none', 'low', 'medium', 'high', 'urgent');
+
+ALTER TABLE todos
+    ADD COLUMN priority todo_priority NOT NULL DEFAULT 'none';
+
+CREATE INDEX todos_workspace_id_priority_idx ON todos (workspace_id, priority);
@@ -0,0 +1,10 @@
+ALTER TABLE todos
+    ADD COLUMN position BIGINT NOT NULL DEFAULT 0;
+
+-- keep the current newest-first order, spaced out so that todos can be moved in between
+UPDATE todos
+SET position = ranked.rank * 1024
+FROM (SELECT id, row_number() OVER (PARTITION BY workspace_id ORDER BY id DESC) AS rank FROM todos) ranked
+WHERE todos.id = ranked.id;
+
+CREATE INDEX todos_workspace_id_position_idx ON todos (workspace_id, position);
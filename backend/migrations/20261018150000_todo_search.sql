@@ -0,0 +1,5 @@
+-- 'simple' only lowercases and splits words, so results do not depend on the language of a todo
+ALTER TABLE todos
+    ADD COLUMN search TSVECTOR GENERATED ALWAYS AS (to_tsvector('simple', text)) STORED;
+
+CREATE INDEX todos_search_idx ON todos USING GIN (search);
@@ -0,0 +1,4 @@
+ALTER TABLE todos
+    ADD COLUMN deleted_at TIMESTAMPTZ;
+
+CREATE INDEX todos_workspace_id_deleted_at_idx ON todos (workspace_id, deleted_at) WHERE deleted_at IS NOT NULL;
@@ -0,0 +1,32 @@
+CREATE TYPE audit_entity AS ENUM ('todo', 'label');
+CREATE TYPE audit_action AS ENUM ('create', 'update', 'delete', 'restore', 'purge');
+
+-- entity_id has no foreign key: the history outlives the todo or label it describes
+CREATE TABLE audit_events
+(
+    id           BIGSERIAL PRIMARY KEY,
+    workspace_id INTEGER      NOT NULL REFERENCES workspaces (id),
+    actor_id     INTEGER REFERENCES users (id),
+    entity       audit_entity NOT NULL,
+    entity_id    INTEGER      NOT NULL,
+    action       audit_action NOT NULL,
+    before       JSONB,
+    after        JSONB,
+    created_at   TIMESTAMPTZ  NOT NULL DEFAULT now()
+);
+
+CREATE INDEX audit_events_workspace_id_idx ON audit_events (workspace_id, id);
+CREATE INDEX audit_events_entity_idx ON audit_events (workspace_id, entity, entity_id, id);
+
+CREATE FUNCTION audit_events_append_only() RETURNS trigger AS
+$$
+BEGIN
+    RAISE EXCEPTION 'audit_events is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_events_append_only
+    BEFORE UPDATE OR DELETE
+    ON audit_events
+    FOR EACH ROW
+EXECUTE FUNCTION audit_events_append_only();
@@ -0,0 +1,232 @@
+use crate::errors::ApiError;
+use anyhow::anyhow;
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use axum::{
+    async_trait,
+    extract::{Extension, FromRequest, Query, RequestParts},
+    http::header::AUTHORIZATION,
+};
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use rand_core::OsRng;
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{Arc, OnceLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+pub const TOKEN_TTL: Duration = Duration::from_secs(60 * 60 * 24);
+/// Stream tokens end up in URLs, so they are only good for opening a stream right away.
+pub const STREAM_TOKEN_TTL: Duration = Duration::from_secs(60);
+
+/// The scope of tokens that only open event streams and sockets.
+const STREAM_SCOPE: &str = "stream";
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Claims {
+    /// the user id, as a string like every JWT subject
+    pub sub: String,
+    pub exp: u64,
+    /// `None` for tokens good for the whole API
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+}
+
+/// Signs and verifies the HS256 tokens handed out by `/auth/login`.
+#[derive(Clone)]
+pub struct AuthKeys {
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+}
+
+impl AuthKeys {
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            encoding: EncodingKey::from_secret(secret),
+            decoding: DecodingKey::from_secret(secret),
+        }
+    }
+
+    pub fn issue(&self, user_id: i32) -> anyhow::Result<String> {
+        self.sign(user_id, TOKEN_TTL, None)
+    }
+
+    /// A token for the `access_token` query parameter of the event streams, which browsers
+    /// open without a way to set the `Authorization` header.
+    pub fn issue_stream(&self, user_id: i32) -> anyhow::Result<String> {
+        self.sign(user_id, STREAM_TOKEN_TTL, Some(STREAM_SCOPE))
+    }
+
+    pub fn verify(&self, token: &str) -> anyhow::Result<i32> {
+        self.check(token, None)
+    }
+
+    pub fn verify_stream(&self, token: &str) -> anyhow::Result<i32> {
+        self.check(token, Some(STREAM_SCOPE))
+    }
+
+    fn sign(&self, user_id: i32, ttl: Duration, scope: Option<&str>) -> anyhow::Result<String> {
+        let exp = (SystemTime::now() + ttl)
+            .duration_since(UNIX_EPOCH)?
+            .as_secs();
+        let claims = Claims {
+            sub: user_id.to_string(),
+            exp,
+            scope: scope.map(str::to_string),
+        };
+        Ok(encode(&Header::default(), &claims, &self.encoding)?)
+    }
+
+    fn check(&self, token: &str, scope: Option<&str>) -> anyhow::Result<i32> {
+        let data = decode::<Claims>(token, &self.decoding, &Validation::default())?;
+        if data.claims.scope.as_deref() != scope {
+            return Err(anyhow!("token of scope {:?}", data.claims.scope));
+        }
+        Ok(data.claims.sub.parse()?)
+    }
+}
+
+pub async fn hash_password(password: String) -> anyhow::Result<String> {
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|e| anyhow!(e.to_string()))
+    })
+    .await?
+}
+
+pub async fn verify_password(password: String, password_hash: String) -> anyhow::Result<bool> {
+    tokio::task::spawn_blocking(move || {
+        let hash = PasswordHash::new(&password_hash).map_err(|e| anyhow!(e.to_string()))?;
+        Ok(Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok())
+    })
+    .await?
+}
+
+/// A hash of no one's password, to verify against when there is no user, so that a failed
+/// login takes as long whether the username exists or not.
+pub async fn dummy_hash() -> anyhow::Result<String> {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    if let Some(hash) = DUMMY_HASH.get() {
+        return Ok(hash.clone());
+    }
+    let hash = hash_password("no one's password".to_string()).await?;
+    Ok(DUMMY_HASH.get_or_init(|| hash).clone())
+}
+
+/// The user identified by the `Authorization: Bearer <token>` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuthUser {
+    pub id: i32,
+}
+
+#[async_trait]
+impl<B> FromRequest<B> for AuthUser
+where
+    B: Send,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let keys = keys(req).await?;
+        let token = req
+            .headers()
+            .and_then(|headers| headers.get(AUTHORIZATION))
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| ApiError::Unauthorized("Missing bearer token".to_string()))?;
+        let id = keys
+            .verify(token)
+            .map_err(|_| ApiError::Unauthorized("Invalid token".to_string()))?;
+        Ok(AuthUser { id })
+    }
+}
+
+/// The user of an event stream or socket, identified like `AuthUser` or, for browsers
+/// that can not set headers there, by a stream token in the `access_token` query parameter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamUser(pub AuthUser);
+
+#[derive(Debug, Deserialize)]
+struct AccessToken {
+    access_token: Option<String>,
+}
+
+#[async_trait]
+impl<B> FromRequest<B> for StreamUser
+where
+    B: Send,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let Query(query) = Query::<AccessToken>::from_request(req)
+            .await
+            .map_err(|rejection| ApiError::InvalidQuery(rejection.to_string()))?;
+        let token = match query.access_token {
+            Some(token) => token,
+            None => return Ok(StreamUser(AuthUser::from_request(req).await?)),
+        };
+        let id = keys(req)
+            .await?
+            .verify_stream(&token)
+            .map_err(|_| ApiError::Unauthorized("Invalid token".to_string()))?;
+        Ok(StreamUser(AuthUser { id }))
+    }
+}
+
+async fn keys<B: Send>(req: &mut RequestParts<B>) -> Result<Arc<AuthKeys>, ApiError> {
+    let Extension(keys) = Extension::<Arc<AuthKeys>>::from_request(req)
+        .await
+        .map_err(|rejection| ApiError::Unexpected(rejection.to_string()))?;
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn token_round_trip() {
+        let keys = AuthKeys::new(b"secret");
+        let token = keys.issue(42).expect("failed issue token");
+        assert_eq!(42, keys.verify(&token).expect("failed verify token"));
+
+        let other_keys = AuthKeys::new(b"other secret");
+        assert!(other_keys.verify(&token).is_err());
+
+        // stream tokens only open streams, and nothing else does
+        let stream_token = keys.issue_stream(42).expect("failed issue token");
+        assert_eq!(
+            42,
+            keys.verify_stream(&stream_token)
+                .expect("failed verify token")
+        );
+        assert!(keys.verify(&stream_token).is_err());
+        assert!(keys.verify_stream(&token).is_err());
+    }
+
+    #[tokio::test]
+    async fn password_round_trip() {
+        let hash = hash_password("password".to_string())
+            .await
+            .expect("failed hash password");
+        assert!(verify_password("password".to_string(), hash.clone())
+            .await
+            .unwrap());
+        assert!(!verify_password("wrong password".to_string(), hash)
+            .await
+            .unwrap());
+        let dummy = dummy_hash().await.expect("failed hash password");
+        assert_eq!(dummy, dummy_hash().await.unwrap());
+        assert!(!verify_password("password".to_string(), dummy)
+            .await
+            .unwrap());
+    }
+}
@@ -0,0 +1,762 @@
+use crate::trace::X_REQUEST_ID;
+use axum::http::{
+    header::{HeaderName, ETAG},
+    HeaderValue, Method,
+};
+use clap::{Arg, Command};
+use serde::Deserialize;
+use std::{
+    ffi::OsString,
+    fmt, fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use thiserror::Error;
+use toml::{Table, Value};
+use tower_http::cors::{Any, CorsLayer, Origin};
+
+/// Read when neither `--config` nor `TODO_CONFIG` names a file, if it exists.
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+const CONFIG_ENV: &str = "TODO_CONFIG";
+/// A hundred years, far below where `chrono::Duration::days` overflows.
+const MAX_RETENTION_DAYS: i64 = 36_500;
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error(transparent)]
+    Cli(#[from] clap::Error),
+    #[error("cannot read config file [{0}]: {1}")]
+    Read(PathBuf, std::io::Error),
+    #[error("invalid config file [{0}]: {1}")]
+    Parse(PathBuf, toml::de::Error),
+    #[error("invalid [{0}]: {1}")]
+    Override(String, String),
+    #[error("invalid config: {0}")]
+    Invalid(String),
+}
+
+impl ConfigError {
+    /// Prints the error, or the help requested with `--help`, and exits.
+    pub fn exit(self) -> ! {
+        match self {
+            ConfigError::Cli(err) => err.exit(),
+            err => {
+                eprintln!("{}", err);
+                std::process::exit(2)
+            }
+        }
+    }
+}
+
+/// Settings of the server. Each one is read, in increasing precedence, from its default,
+/// the TOML file, its environment variable and its command line flag; see `SETTINGS`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub auth: AuthConfig,
+    pub cors: CorsConfig,
+    pub trash: TrashConfig,
+    pub features: FeaturesConfig,
+    pub log: LogConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub listen: SocketAddr,
+    /// until the response starts, so streams and sockets may stay open longer
+    pub request_timeout_secs: u64,
+    /// how long requests in flight may finish after SIGTERM or SIGINT
+    pub shutdown_timeout_secs: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            listen: SocketAddr::from(([0, 0, 0, 0], 3000)),
+            request_timeout_secs: 30,
+            shutdown_timeout_secs: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub url: String,
+    pub max_connections: u32,
+    pub min_connections: u32,
+    pub connect_timeout_secs: u64,
+    /// 0 keeps idle connections open
+    pub idle_timeout_secs: u64,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            max_connections: 10,
+            min_connections: 0,
+            connect_timeout_secs: 30,
+            idle_timeout_secs: 600,
+        }
+    }
+}
+
+#[derive(Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    pub jwt_secret: String,
+}
+
+impl fmt::Debug for AuthConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthConfig")
+            .field("jwt_secret", &"[redacted]")
+            .finish()
+    }
+}
+
+/// `["*"]` allows any origin, method or header.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        let strings = |values: &[&str]| values.iter().map(|value| value.to_string()).collect();
+        Self {
+            allowed_origins: strings(&["http://localhost:3001"]),
+            allowed_methods: strings(&["GET", "POST", "PUT", "PATCH", "DELETE"]),
+            allowed_headers: strings(&[
+                "content-type",
+                "authorization",
+                "if-match",
+                "if-none-match",
+                "last-event-id",
+                "x-request-id",
+            ]),
+        }
+    }
+}
+
+impl CorsConfig {
+    pub fn layer(&self) -> Result<CorsLayer, ConfigError> {
+        // clients send the `ETag` back in `If-Match` and `If-None-Match`
+        let layer = CorsLayer::new().expose_headers([X_REQUEST_ID.clone(), ETAG]);
+        let layer = if is_any(&self.allowed_origins) {
+            layer.allow_origin(Any)
+        } else {
+            layer.allow_origin(Origin::list(parse_all(
+                "cors.allowed_origins",
+                &self.allowed_origins,
+                |origin| HeaderValue::from_str(origin).ok(),
+            )?))
+        };
+        let layer = if is_any(&self.allowed_methods) {
+            layer.allow_methods(Any)
+        } else {
+            layer.allow_methods(parse_all(
+                "cors.allowed_methods",
+                &self.allowed_methods,
+                |method| method.parse::<Method>().ok(),
+            )?)
+        };
+        let layer = if is_any(&self.allowed_headers) {
+            layer.allow_headers(Any)
+        } else {
+            layer.allow_headers(parse_all(
+                "cors.allowed_headers",
+                &self.allowed_headers,
+                |header| header.parse::<HeaderName>().ok(),
+            )?)
+        };
+        Ok(layer)
+    }
+}
+
+fn is_any(values: &[String]) -> bool {
+    values.iter().any(|value| value == "*")
+}
+
+fn parse_all<T>(
+    key: &str,
+    values: &[String],
+    parse: impl Fn(&str) -> Option<T>,
+) -> Result<Vec<T>, ConfigError> {
+    values
+        .iter()
+        .map(|value| {
+            parse(value).ok_or_else(|| ConfigError::Invalid(format!("{}: `{}`", key, value)))
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TrashConfig {
+    pub retention_days: i64,
+}
+
+impl Default for TrashConfig {
+    fn default() -> Self {
+        Self { retention_days: 30 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeaturesConfig {
+    /// `/openapi.json` and `/docs`
+    pub docs: bool,
+    /// server-sent events and WebSockets, with the relay feeding them
+    pub realtime: bool,
+    /// hourly removal of todos past `trash.retention_days`
+    pub trash_purge: bool,
+    /// `/metrics` for Prometheus
+    pub metrics: bool,
+}
+
+impl Default for FeaturesConfig {
+    fn default() -> Self {
+        Self {
+            docs: true,
+            realtime: true,
+            trash_purge: true,
+            metrics: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// a `tracing_subscriber` filter, like `info,my_todo=debug`
+    pub level: String,
+    pub format: LogFormat,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            format: LogFormat::Text,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// human readable lines
+    Text,
+    /// one JSON object per line, for log pipelines
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Text,
+    Integer,
+    Boolean,
+    /// comma separated
+    List,
+}
+
+/// A setting that can be overridden by an environment variable and a command line flag.
+struct Setting {
+    key: &'static str,
+    env: &'static str,
+    flag: &'static str,
+    kind: Kind,
+    help: &'static str,
+}
+
+const fn setting(
+    key: &'static str,
+    env: &'static str,
+    flag: &'static str,
+    kind: Kind,
+    help: &'static str,
+) -> Setting {
+    Setting {
+        key,
+        env,
+        flag,
+        kind,
+        help,
+    }
+}
+
+/// The variables read before there was a config file keep their names.
+const SETTINGS: &[Setting] = &[
+    setting(
+        "server.listen",
+        "TODO_LISTEN",
+        "listen",
+        Kind::Text,
+        "Address to listen on",
+    ),
+    setting(
+        "server.request_timeout_secs",
+        "TODO_REQUEST_TIMEOUT_SECS",
+        "request-timeout-secs",
+        Kind::Integer,
+        "Seconds a request may take until its response starts",
+    ),
+    setting(
+        "server.shutdown_timeout_secs",
+        "TODO_SHUTDOWN_TIMEOUT_SECS",
+        "shutdown-timeout-secs",
+        Kind::Integer,
+        "Seconds requests in flight may take to finish at shutdown",
+    ),
+    setting(
+        "database.url",
+        "DATABASE_URL",
+        "database-url",
+        Kind::Text,
+        "Postgres URL",
+    ),
+    setting(
+        "database.max_connections",
+        "TODO_DATABASE_MAX_CONNECTIONS",
+        "database-max-connections",
+        Kind::Integer,
+        "Largest size of the connection pool",
+    ),
+    setting(
+        "database.min_connections",
+        "TODO_DATABASE_MIN_CONNECTIONS",
+        "database-min-connections",
+        Kind::Integer,
+        "Connections the pool keeps open",
+    ),
+    setting(
+        "database.connect_timeout_secs",
+        "TODO_DATABASE_CONNECT_TIMEOUT_SECS",
+        "database-connect-timeout-secs",
+        Kind::Integer,
+        "Seconds to wait for a connection",
+    ),
+    setting(
+        "database.idle_timeout_secs",
+        "TODO_DATABASE_IDLE_TIMEOUT_SECS",
+        "database-idle-timeout-secs",
+        Kind::Integer,
+        "Seconds before an idle connection is closed, 0 for never",
+    ),
+    setting(
+        "auth.jwt_secret",
+        "JWT_SECRET",
+        "jwt-secret",
+        Kind::Text,
+        "Secret signing tokens",
+    ),
+    setting(
+        "cors.allowed_origins",
+        "TODO_CORS_ALLOWED_ORIGINS",
+        "cors-allowed-origins",
+        Kind::List,
+        "Comma separated origins allowed by CORS, or *",
+    ),
+    setting(
+        "cors.allowed_methods",
+        "TODO_CORS_ALLOWED_METHODS",
+        "cors-allowed-methods",
+        Kind::List,
+        "Comma separated methods allowed by CORS, or *",
+    ),
+    setting(
+        "cors.allowed_headers",
+        "TODO_CORS_ALLOWED_HEADERS",
+        "cors-allowed-headers",
+        Kind::List,
+        "Comma separated headers allowed by CORS, or *",
+    ),
+    setting(
+        "trash.retention_days",
+        "TRASH_RETENTION_DAYS",
+        "trash-retention-days",
+        Kind::Integer,
+        "Days a todo stays in the trash",
+    ),
+    setting(
+        "features.docs",
+        "TODO_FEATURES_DOCS",
+        "features-docs",
+        Kind::Boolean,
+        "Serve /openapi.json and /docs",
+    ),
+    setting(
+        "features.realtime",
+        "TODO_FEATURES_REALTIME",
+        "features-realtime",
+        Kind::Boolean,
+        "Serve server-sent events and WebSockets",
+    ),
+    setting(
+        "features.trash_purge",
+        "TODO_FEATURES_TRASH_PURGE",
+        "features-trash-purge",
+        Kind::Boolean,
+        "Empty the trash of expired todos",
+    ),
+    setting(
+        "features.metrics",
+        "TODO_FEATURES_METRICS",
+        "features-metrics",
+        Kind::Boolean,
+        "Serve /metrics",
+    ),
+    setting(
+        "log.level",
+        "RUST_LOG",
+        "log-level",
+        Kind::Text,
+        "Log filter",
+    ),
+    setting(
+        "log.format",
+        "TODO_LOG_FORMAT",
+        "log-format",
+        Kind::Text,
+        "Log format, text or json",
+    ),
+];
+
+impl Config {
+    pub fn load() -> Result<Self, ConfigError> {
+        Self::load_from(std::env::args_os(), |name| std::env::var(name).ok())
+    }
+
+    fn load_from<I, T>(args: I, env: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let matches = command().try_get_matches_from(args)?;
+        let mut table = match matches
+            .get_one::<String>("config")
+            .cloned()
+            .or_else(|| env(CONFIG_ENV))
+        {
+            Some(path) => read_table(Path::new(&path))?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                read_table(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            None => Table::new(),
+        };
+        for setting in SETTINGS {
+            if let Some(value) = env(setting.env) {
+                setting.apply(&mut table, setting.env, &value)?;
+            }
+        }
+        for setting in SETTINGS {
+            if let Some(value) = matches.get_one::<String>(setting.flag) {
+                setting.apply(&mut table, &format!("--{}", setting.flag), value)?;
+            }
+        }
+        let config: Config = table
+            .try_into()
+            .map_err(|err: toml::de::Error| ConfigError::Invalid(err.message().to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |message: &str| Err(ConfigError::Invalid(message.to_string()));
+        if self.database.url.is_empty() {
+            return invalid("database.url is required");
+        }
+        if self.auth.jwt_secret.is_empty() {
+            return invalid("auth.jwt_secret is required");
+        }
+        if self.database.max_connections == 0 {
+            return invalid("database.max_connections must be at least 1");
+        }
+        if self.database.min_connections > self.database.max_connections {
+            return invalid("database.min_connections exceeds database.max_connections");
+        }
+        if self.server.request_timeout_secs == 0 || self.database.connect_timeout_secs == 0 {
+            return invalid("timeouts must be at least 1 second");
+        }
+        if self.trash.retention_days < 0 {
+            return invalid("trash.retention_days must not be negative");
+        }
+        if self.trash.retention_days > MAX_RETENTION_DAYS {
+            return invalid(&format!(
+                "trash.retention_days must be at most {}",
+                MAX_RETENTION_DAYS
+            ));
+        }
+        self.cors.layer()?;
+        Ok(())
+    }
+
+    pub fn request_timeout(&self) -> Duration {
+        Duration::from_secs(self.server.request_timeout_secs)
+    }
+
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.server.shutdown_timeout_secs)
+    }
+}
+
+fn command() -> Command {
+    let config = Arg::new("config")
+        .long("config")
+        .value_name("PATH")
+        .help(format!(
+            "TOML config file [env: {}] [default: {}]",
+            CONFIG_ENV, DEFAULT_CONFIG_FILE
+        ));
+    SETTINGS.iter().fold(
+        Command::new(env!("CARGO_PKG_NAME"))
+            .version(env!("CARGO_PKG_VERSION"))
+            .arg(config),
+        |command, setting| {
+            command.arg(
+                Arg::new(setting.flag)
+                    .long(setting.flag)
+                    .value_name(match setting.kind {
+                        Kind::Boolean => "BOOL",
+                        Kind::Integer => "NUMBER",
+                        Kind::Text | Kind::List => "VALUE",
+                    })
+                    .help(format!("{} [env: {}]", setting.help, setting.env)),
+            )
+        },
+    )
+}
+
+fn read_table(path: &Path) -> Result<Table, ConfigError> {
+    let text = fs::read_to_string(path).map_err(|err| ConfigError::Read(path.into(), err))?;
+    text.parse()
+        .map_err(|err| ConfigError::Parse(path.into(), err))
+}
+
+impl Setting {
+    /// Sets the key in the table, converting the value named by `source` to its kind.
+    fn apply(&self, table: &mut Table, source: &str, value: &str) -> Result<(), ConfigError> {
+        let invalid = |message: String| ConfigError::Override(source.to_string(), message);
+        let value = match self.kind {
+            Kind::Text => Value::String(value.to_string()),
+            Kind::Integer => Value::Integer(
+                value
+                    .trim()
+                    .parse()
+                    .map_err(|_| invalid(format!("expected a number, found `{}`", value)))?,
+            ),
+            Kind::Boolean => Value::Boolean(
+                value
+                    .trim()
+                    .parse()
+                    .map_err(|_| invalid(format!("expected true or false, found `{}`", value)))?,
+            ),
+            Kind::List => Value::Array(
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|item| !item.is_empty())
+                    .map(|item| Value::String(item.to_string()))
+                    .collect(),
+            ),
+        };
+        let (section, key) = self.key.split_once('.').expect("keys have a section");
+        match table
+            .entry(section)
+            .or_insert_with(|| Value::Table(Table::new()))
+        {
+            Value::Table(section) => {
+                section.insert(key.to_string(), value);
+                Ok(())
+            }
+            _ => Err(invalid(format!(
+                "[{}] in the config file is not a table",
+                section
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn load(
+        file: Option<&str>,
+        env: &[(&str, &str)],
+        args: &[&str],
+    ) -> Result<Config, ConfigError> {
+        let mut env: HashMap<String, String> = env
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        if let Some(file) = file {
+            let path = std::env::temp_dir().join(format!("my-todo-{}.toml", rand_suffix()));
+            fs::write(&path, file).expect("failed write config file");
+            env.insert(CONFIG_ENV.to_string(), path.display().to_string());
+        }
+        let args = std::iter::once("my-todo").chain(args.iter().copied());
+        let config = Config::load_from(args, |name| env.get(name).cloned());
+        if let Some(path) = env.get(CONFIG_ENV) {
+            fs::remove_file(path).ok();
+        }
+        config
+    }
+
+    fn rand_suffix() -> u64 {
+        use std::time::{SystemTime, UNIX_EPOCH};
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64
+    }
+
+    const REQUIRED: &[(&str, &str)] = &[
+        ("DATABASE_URL", "postgres://localhost/todos"),
+        ("JWT_SECRET", "secret"),
+    ];
+
+    #[test]
+    fn precedence_scenario() {
+        let file = r#"
+            [server]
+            listen = "127.0.0.1:4000"
+            request_timeout_secs = 5
+
+            [database]
+            max_connections = 20
+
+            [cors]
+            allowed_origins = ["https://todo.example"]
+
+            [features]
+            docs = false
+
+            [log]
+            format = "json"
+        "#;
+        let env = [
+            REQUIRED,
+            &[
+                ("TODO_LISTEN", "127.0.0.1:5000"),
+                ("TODO_DATABASE_MAX_CONNECTIONS", "30"),
+                (
+                    "TODO_CORS_ALLOWED_ORIGINS",
+                    "https://a.example, https://b.example",
+                ),
+            ],
+        ]
+        .concat();
+        let config =
+            load(Some(file), &env, &["--listen", "127.0.0.1:6000"]).expect("[load] returned Err");
+
+        // flag over environment over file over default
+        assert_eq!(
+            SocketAddr::from(([127, 0, 0, 1], 6000)),
+            config.server.listen
+        );
+        assert_eq!(30, config.database.max_connections);
+        assert_eq!(5, config.server.request_timeout_secs);
+        assert_eq!(0, config.database.min_connections);
+        assert_eq!(
+            vec!["https://a.example", "https://b.example"],
+            config.cors.allowed_origins
+        );
+        assert!(!config.features.docs);
+        assert!(config.features.realtime);
+        assert_eq!(LogFormat::Json, config.log.format);
+        assert_eq!("secret", config.auth.jwt_secret);
+
+        let config = load(None, REQUIRED, &[]).expect("[load] returned Err");
+        assert_eq!(
+            Config {
+                database: DatabaseConfig {
+                    url: "postgres://localhost/todos".to_string(),
+                    ..DatabaseConfig::default()
+                },
+                auth: AuthConfig {
+                    jwt_secret: "secret".to_string(),
+                },
+                ..Config::default()
+            },
+            config
+        );
+    }
+
+    #[test]
+    fn validation_scenario() {
+        let message = |result: Result<Config, ConfigError>| {
+            result.expect_err("[load] returned Ok").to_string()
+        };
+
+        assert_eq!(
+            "invalid config: database.url is required",
+            message(load(None, &[("JWT_SECRET", "secret")], &[]))
+        );
+        assert_eq!(
+            "invalid [TODO_DATABASE_MAX_CONNECTIONS]: expected a number, found `many`",
+            message(load(
+                None,
+                &[REQUIRED, &[("TODO_DATABASE_MAX_CONNECTIONS", "many")]].concat(),
+                &[]
+            ))
+        );
+        assert_eq!(
+            "invalid [--features-docs]: expected true or false, found `yes`",
+            message(load(None, REQUIRED, &["--features-docs", "yes"]))
+        );
+        assert_eq!(
+            "invalid config: database.min_connections exceeds database.max_connections",
+            message(load(
+                None,
+                REQUIRED,
+                &[
+                    "--database-min-connections",
+                    "5",
+                    "--database-max-connections",
+                    "2"
+                ]
+            ))
+        );
+        assert_eq!(
+            "invalid config: cors.allowed_methods: `GET POST`",
+            message(load(
+                None,
+                REQUIRED,
+                &["--cors-allowed-methods", "GET POST"]
+            ))
+        );
+        assert_eq!(
+            "invalid config: trash.retention_days must be at most 36500",
+            message(load(
+                None,
+                REQUIRED,
+                &["--trash-retention-days", "9223372036854775807"]
+            ))
+        );
+        assert!(message(load(None, REQUIRED, &["--log-format", "xml"]))
+            .starts_with("invalid config: unknown variant `xml`"));
+        assert!(message(load(None, REQUIRED, &["--listen", "localhost"]))
+            .starts_with("invalid config: invalid socket address"));
+        assert!(
+            message(load(Some("[server]\nport = 3000\n"), REQUIRED, &[]))
+                .starts_with("invalid config: unknown field `port`")
+        );
+        assert!(message(load(Some("[server"), REQUIRED, &[])).starts_with("invalid config file"));
+        assert!(matches!(
+            load(None, REQUIRED, &["--unknown"]),
+            Err(ConfigError::Cli(_))
+        ));
+    }
+}
@@ -0,0 +1,143 @@
+use crate::repositories::RepositoryError;
+use axum::{
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use utoipa::ToSchema;
+
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+#[derive(Debug, Error)]
+pub enum ApiError {
+    #[error("Unauthorized: [{0}]")]
+    Unauthorized(String),
+    #[error("Forbidden: [{0}]")]
+    Forbidden(String),
+    #[error("NotFound, id is {0}")]
+    NotFound(i32),
+    #[error("Duplicate data, id is {0}")]
+    Duplicate(i32),
+    #[error("In use by todos {1:?}, id is {0}")]
+    InUse(i32, Vec<i32>),
+    #[error("Json parse error: [{0}]")]
+    InvalidJson(String),
+    #[error("Query parse error: [{0}]")]
+    InvalidQuery(String),
+    #[error("Validation error: [{0}]")]
+    Validation(String),
+    #[error("Changed since the given version, id is {0}")]
+    PreconditionFailed(i32),
+    #[error("The request took too long")]
+    Timeout,
+    #[error("Unexpected Error: [{0}]")]
+    Unexpected(String),
+}
+
+impl ApiError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Duplicate(_) | ApiError::InUse(_, _) => StatusCode::CONFLICT,
+            ApiError::InvalidJson(_) | ApiError::InvalidQuery(_) => StatusCode::BAD_REQUEST,
+            ApiError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            ApiError::Timeout => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Unexpected(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Duplicate(_) => "duplicate",
+            ApiError::InUse(_, _) => "in_use",
+            ApiError::InvalidJson(_) => "invalid_json",
+            ApiError::InvalidQuery(_) => "invalid_query",
+            ApiError::Validation(_) => "validation_failed",
+            ApiError::PreconditionFailed(_) => "precondition_failed",
+            ApiError::Timeout => "timeout",
+            ApiError::Unexpected(_) => "unexpected",
+        }
+    }
+
+    pub fn problem(&self) -> Problem {
+        let status = self.status();
+        // internal details stay in the log, not in the response body
+        let detail = match self {
+            ApiError::Unexpected(_) => "An unexpected error occurred".to_string(),
+            _ => self.to_string(),
+        };
+        Problem {
+            r#type: "about:blank".to_string(),
+            title: status.canonical_reason().unwrap_or_default().to_string(),
+            status: status.as_u16(),
+            code: self.code().to_string(),
+            detail,
+            todo_ids: match self {
+                ApiError::InUse(_, todo_ids) => Some(todo_ids.clone()),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl From<RepositoryError> for ApiError {
+    fn from(e: RepositoryError) -> Self {
+        match e {
+            RepositoryError::NotFound(id) => ApiError::NotFound(id),
+            RepositoryError::Duplicate(id) => ApiError::Duplicate(id),
+            RepositoryError::InUse(id, todo_ids) => ApiError::InUse(id, todo_ids),
+            RepositoryError::Cycle(id) => ApiError::Validation(format!(
+                "parent_id: Todo {} is the todo itself or one of its subtasks",
+                id
+            )),
+            RepositoryError::VersionMismatch(id) => ApiError::PreconditionFailed(id),
+            RepositoryError::Unexpected(message) => ApiError::Unexpected(message),
+        }
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        match e.downcast::<RepositoryError>() {
+            Ok(e) => e.into(),
+            Err(e) => ApiError::Unexpected(e.to_string()),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        if let ApiError::Unexpected(message) = &self {
+            tracing::error!("{}", message);
+        }
+        let mut res = (self.status(), Json(self.problem())).into_response();
+        res.headers_mut()
+            .insert(header::CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON));
+        if let ApiError::Unauthorized(_) = self {
+            res.headers_mut()
+                .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        }
+        res
+    }
+}
+
+/// RFC 7807 problem details with a machine-readable `code`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, ToSchema)]
+pub struct Problem {
+    pub r#type: String,
+    pub title: String,
+    pub status: u16,
+    pub code: String,
+    pub detail: String,
+    /// todos still referring to the resource, for `in_use`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub todo_ids: Option<Vec<i32>>,
+}
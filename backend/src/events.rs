@@ -0,0 +1,570 @@
+use crate::repositories::{
+    audit::{AuditAction, AuditEntity, AuditEvent, AuditRepository, ChangeNotice, CHANGES_CHANNEL},
+    label::Label,
+    todo::TodoEntity,
+};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use sqlx::{postgres::PgListener, PgPool};
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+use tokio::sync::{broadcast, mpsc, watch};
+
+/// How many events are kept for clients resuming with `Last-Event-ID`.
+pub const REPLAY_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    TodoCreated(TodoEntity),
+    TodoUpdated {
+        todo: TodoEntity,
+        /// ids of the labels the todo carried before, to tell the subscribers of a label
+        /// that the todo left it
+        previous_labels: Vec<i32>,
+    },
+    TodoDeleted {
+        id: i32,
+        /// ids of the labels the todo carried
+        labels: Vec<i32>,
+    },
+    LabelCreated(Label),
+    LabelUpdated(Label),
+    LabelDeleted(i32),
+}
+
+impl Change {
+    /// The change an audit event stands for. Restored todos are announced as created,
+    /// since clients dropped them when they were deleted; purges are not announced at all.
+    pub fn from_audit(event: &AuditEvent) -> Option<Self> {
+        let after = || event.after.clone();
+        match (event.entity, event.action) {
+            (AuditEntity::Todo, AuditAction::Create | AuditAction::Restore) => {
+                snapshot(after()).map(Change::TodoCreated)
+            }
+            (AuditEntity::Todo, AuditAction::Update) => {
+                snapshot(after()).map(|todo| Change::TodoUpdated {
+                    todo,
+                    previous_labels: label_ids(event.before.clone()),
+                })
+            }
+            (AuditEntity::Todo, AuditAction::Delete) => Some(Change::TodoDeleted {
+                id: event.entity_id,
+                labels: label_ids(event.before.clone()),
+            }),
+            (AuditEntity::Label, AuditAction::Create) => {
+                snapshot(after()).map(Change::LabelCreated)
+            }
+            (AuditEntity::Label, AuditAction::Update) => {
+                snapshot(after()).map(Change::LabelUpdated)
+            }
+            (AuditEntity::Label, AuditAction::Delete) => {
+                Some(Change::LabelDeleted(event.entity_id))
+            }
+            (_, AuditAction::Purge) | (AuditEntity::Label, AuditAction::Restore) => None,
+        }
+    }
+
+    /// The SSE event name, like `todo.created`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Change::TodoCreated(_) => "todo.created",
+            Change::TodoUpdated { .. } => "todo.updated",
+            Change::TodoDeleted { .. } => "todo.deleted",
+            Change::LabelCreated(_) => "label.created",
+            Change::LabelUpdated(_) => "label.updated",
+            Change::LabelDeleted(_) => "label.deleted",
+        }
+    }
+
+    /// The entity as it is now, or only its id once it is deleted.
+    pub fn data(&self) -> Value {
+        match self {
+            Change::TodoCreated(todo) | Change::TodoUpdated { todo, .. } => json!(todo),
+            Change::LabelCreated(label) | Change::LabelUpdated(label) => json!(label),
+            Change::TodoDeleted { id, .. } | Change::LabelDeleted(id) => json!({ "id": id }),
+        }
+    }
+}
+
+fn snapshot<T: DeserializeOwned>(snapshot: Option<Value>) -> Option<T> {
+    serde_json::from_value(snapshot?).ok()
+}
+
+fn label_ids(todo: Option<Value>) -> Vec<i32> {
+    snapshot::<TodoEntity>(todo)
+        .map(|todo| todo.labels.iter().map(|label| label.id).collect())
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangeEvent {
+    /// the id of the audit event, the same on every replica, sent as the SSE `id`
+    pub id: i64,
+    pub workspace_id: i32,
+    pub change: Change,
+}
+
+pub struct Subscription {
+    /// the client's `Last-Event-ID` is not in the replay buffer, so it has to refetch
+    pub missed: bool,
+    /// buffered events after the client's `Last-Event-ID`
+    pub replay: Vec<Arc<ChangeEvent>>,
+    /// events published from now on
+    pub receiver: broadcast::Receiver<Arc<ChangeEvent>>,
+}
+
+/// Fans changes out to every subscriber and keeps the latest ones for replay, in the
+/// order they were published.
+#[derive(Debug)]
+pub struct EventBus {
+    sender: broadcast::Sender<Arc<ChangeEvent>>,
+    replay: Mutex<VecDeque<Arc<ChangeEvent>>>,
+    capacity: usize,
+    closed: watch::Sender<bool>,
+}
+
+impl EventBus {
+    /// `capacity` bounds both the replay buffer and how far a subscriber may fall behind.
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _receiver) = broadcast::channel(capacity);
+        Self {
+            sender,
+            replay: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+            closed: watch::channel(false).0,
+        }
+    }
+
+    /// Publishes the change recorded by the audit event `id`.
+    pub fn publish(&self, id: i64, workspace_id: i32, change: Change) {
+        // sent under the lock, so that subscribers receive events in the replay order
+        let mut replay = self.replay.lock().unwrap();
+        let event = Arc::new(ChangeEvent {
+            id,
+            workspace_id,
+            change,
+        });
+        if replay.len() == self.capacity {
+            replay.pop_front();
+        }
+        replay.push_back(event.clone());
+        // having no subscribers is fine
+        let _ = self.sender.send(event);
+    }
+
+    /// Publishes the change recorded by the event, if it is announced at all.
+    pub fn publish_audit(&self, workspace_id: i32, event: &AuditEvent) {
+        if let Some(change) = Change::from_audit(event) {
+            self.publish(event.id, workspace_id, change);
+        }
+    }
+
+    /// Tells the subscribers that the server is shutting down.
+    pub fn close(&self) {
+        self.closed.send_replace(true);
+    }
+
+    pub fn is_closed(&self) -> bool {
+        *self.closed.borrow()
+    }
+
+    /// Resolves once the bus is closed, right away if it already is.
+    pub async fn closed(&self) {
+        let mut closed = self.closed.subscribe();
+        while !*closed.borrow_and_update() {
+            // the sender lives as long as the bus
+            if closed.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Subscribes to every workspace. Subscribing under the publish lock means no event
+    /// is both replayed and received, nor lost in between.
+    ///
+    /// Audit events commit, and so arrive, out of id order now and then, and in another
+    /// order on each replica. So the replay holds the events buffered after
+    /// `last_event_id`, and those before it with a higher id, which the client may not
+    /// have received from the replica it was connected to; sending a snapshot twice is
+    /// harmless. Unless the event is buffered, after a restart say, the client missed
+    /// changes.
+    pub fn subscribe(&self, last_event_id: Option<i64>) -> Subscription {
+        let replay = self.replay.lock().unwrap();
+        let receiver = self.sender.subscribe();
+        let Some(last_event_id) = last_event_id else {
+            return Subscription {
+                missed: false,
+                replay: vec![],
+                receiver,
+            };
+        };
+        let position = replay.iter().position(|event| event.id == last_event_id);
+        Subscription {
+            missed: position.is_none(),
+            replay: replay
+                .iter()
+                .enumerate()
+                .filter(|(index, event)| {
+                    position.is_some_and(|position| *index > position) || event.id > last_event_id
+                })
+                .map(|(_, event)| event.clone())
+                .collect(),
+            receiver,
+        }
+    }
+}
+
+/// Listens on `CHANGES_CHANNEL`, so that no change committed from now on is missed.
+pub async fn listen_for_changes(pool: &PgPool) -> anyhow::Result<PgListener> {
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener.listen(CHANGES_CHANNEL).await?;
+    Ok(listener)
+}
+
+/// How far below the latest audit event relayed the catch-up reads again, for the events
+/// that committed after ones with a higher id.
+const CATCH_UP_WINDOW: i64 = REPLAY_CAPACITY as i64;
+
+/// The audit events relayed so far, kept from one listener to the next.
+#[derive(Debug, Default)]
+pub struct RelayCursor {
+    latest: Option<i64>,
+    /// ids relayed within `CATCH_UP_WINDOW` of the latest one
+    seen: BTreeSet<i64>,
+}
+
+impl RelayCursor {
+    /// Marks the event relayed, false if it already was.
+    fn relay(&mut self, id: i64) -> bool {
+        let latest = self.latest.map_or(id, |latest| latest.max(id));
+        self.latest = Some(latest);
+        let relayed = self.seen.insert(id);
+        while self
+            .seen
+            .first()
+            .is_some_and(|first| *first <= latest - CATCH_UP_WINDOW)
+        {
+            self.seen.pop_first();
+        }
+        relayed
+    }
+}
+
+/// Publishes the changes committed since the latest event relayed, and those within
+/// `CATCH_UP_WINDOW` below it that were not relayed yet, having committed late.
+async fn catch_up<A: AuditRepository>(
+    audit_repository: &A,
+    event_bus: &EventBus,
+    cursor: &mut RelayCursor,
+) -> anyhow::Result<()> {
+    let (mut after, until) = match cursor.latest {
+        Some(latest) => (latest - CATCH_UP_WINDOW, None),
+        // the changes committed from now on are announced to the listener, so those
+        // committed until now are only marked relayed
+        None => {
+            let latest = audit_repository.latest_id().await?;
+            cursor.latest = Some(latest);
+            (latest - CATCH_UP_WINDOW, Some(latest))
+        }
+    };
+    loop {
+        let events = audit_repository
+            .changes_after(after, REPLAY_CAPACITY as i64)
+            .await?;
+        for (workspace_id, event) in &events {
+            if until.is_some_and(|until| event.id > until) {
+                return Ok(());
+            }
+            if cursor.relay(event.id) && until.is_none() {
+                event_bus.publish_audit(*workspace_id, event);
+            }
+            after = event.id;
+        }
+        if events.len() < REPLAY_CAPACITY {
+            return Ok(());
+        }
+    }
+}
+
+/// Publishes the changes announced by any replica, this one included, until the listener
+/// fails or loses its connection, which drops the notifications sent meanwhile.
+///
+/// The changes missed since the events relayed with `cursor` are caught up on first.
+/// Notifications arriving while others are looked up are looked up together.
+pub async fn relay_changes<A: AuditRepository>(
+    mut listener: PgListener,
+    audit_repository: &A,
+    event_bus: &EventBus,
+    cursor: &mut RelayCursor,
+) -> anyhow::Result<()> {
+    // the caught up changes may be announced too, once they committed after the listener
+    // started
+    catch_up(audit_repository, event_bus, cursor).await?;
+
+    let (sender, mut notices) = mpsc::unbounded_channel();
+    let receive = async move {
+        // `None` once the connection is lost, so that the next listener catches up
+        while let Some(notification) = listener.try_recv().await? {
+            match serde_json::from_str::<ChangeNotice>(notification.payload()) {
+                Ok(notice) => {
+                    let _ = sender.send(notice.event_id);
+                }
+                Err(err) => {
+                    tracing::warn!("skip change notice [{}]: {}", notification.payload(), err)
+                }
+            }
+        }
+        anyhow::Ok(())
+    };
+    let relay = async {
+        while let Some(id) = notices.recv().await {
+            let mut ids = vec![id];
+            while let Ok(id) = notices.try_recv() {
+                ids.push(id);
+            }
+            ids.retain(|id| cursor.relay(*id));
+            if ids.is_empty() {
+                continue;
+            }
+            match audit_repository.changes(ids.clone()).await {
+                Ok(events) => {
+                    let mut events: HashMap<i64, (i32, AuditEvent)> = events
+                        .into_iter()
+                        .map(|(workspace_id, event)| (event.id, (workspace_id, event)))
+                        .collect();
+                    // in the order they committed
+                    for id in ids {
+                        if let Some((workspace_id, event)) = events.remove(&id) {
+                            event_bus.publish_audit(workspace_id, &event);
+                        }
+                    }
+                }
+                Err(err) => tracing::error!("fail read changes {:?}, skipped: {:?}", ids, err),
+            }
+        }
+    };
+    // the relay publishes the notifications received before the connection was lost
+    let (received, ()) = tokio::join!(receive, relay);
+    received
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ids(events: &[Arc<ChangeEvent>]) -> Vec<i64> {
+        events.iter().map(|event| event.id).collect()
+    }
+
+    #[tokio::test]
+    async fn replay_scenario() {
+        let bus = EventBus::new(4);
+        let mut live = bus.subscribe(None);
+        bus.publish(10, 1, Change::LabelDeleted(10));
+        let event = live.receiver.recv().await.expect("failed receive event");
+        assert_eq!(10, event.id);
+        assert_eq!(Change::LabelDeleted(10), event.change);
+        // 13 committed before 12
+        for id in [11, 13, 12, 14] {
+            bus.publish(id, 1, Change::LabelDeleted(id as i32));
+        }
+
+        // resuming within the buffer
+        let subscription = bus.subscribe(Some(13));
+        assert!(!subscription.missed);
+        assert_eq!(vec![12, 14], ids(&subscription.replay));
+        let subscription = bus.subscribe(Some(11));
+        assert!(!subscription.missed);
+        assert_eq!(vec![13, 12, 14], ids(&subscription.replay));
+        // from a replica that received 12 first
+        let subscription = bus.subscribe(Some(12));
+        assert!(!subscription.missed);
+        assert_eq!(vec![13, 14], ids(&subscription.replay));
+
+        // event 10 fell out of the buffer
+        let subscription = bus.subscribe(Some(10));
+        assert!(subscription.missed);
+        assert_eq!(vec![11, 13, 12, 14], ids(&subscription.replay));
+
+        // an event this process never published, before a restart say
+        let subscription = bus.subscribe(Some(100));
+        assert!(subscription.missed);
+        assert!(subscription.replay.is_empty());
+
+        let subscription = bus.subscribe(Some(14));
+        assert!(!subscription.missed);
+        assert!(subscription.replay.is_empty());
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "database-test")]
+    async fn relay_scenario() {
+        use crate::repositories::{
+            audit::AuditRepositoryForDb,
+            todo::{CreateTodo, TodoRepository, TodoRepositoryForDb},
+            user::UserRepositoryForDb,
+            workspace::{test_utils::prepare_workspace, WorkspaceRepositoryForDb},
+        };
+        use dotenv::dotenv;
+        use std::{env, time::Duration};
+
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database_url));
+        let workspace = prepare_workspace(
+            &UserRepositoryForDb::new(pool.clone()),
+            &WorkspaceRepositoryForDb::new(pool.clone()),
+            "[relay_scenario] user",
+            "[relay_scenario] workspace",
+        )
+        .await;
+        let event_bus = Arc::new(EventBus::new(REPLAY_CAPACITY));
+        let mut subscription = event_bus.subscribe(None);
+        let audit_repository = AuditRepositoryForDb::new(pool.clone());
+        let mut cursor = RelayCursor::default();
+        catch_up(&audit_repository, &event_bus, &mut cursor)
+            .await
+            .expect("[catch_up] returned Err");
+
+        // committed while no listener was connected
+        let repository = TodoRepositoryForDb::new(pool.clone());
+        let todo = repository
+            .create(
+                workspace.id,
+                CreateTodo::new("[relay_scenario] todo".to_string(), vec![]),
+            )
+            .await
+            .expect("[create] returned Err");
+        let listener = listen_for_changes(&pool)
+            .await
+            .expect("[listen] returned Err");
+        let relay = {
+            let event_bus = event_bus.clone();
+            tokio::spawn(async move {
+                relay_changes(listener, &audit_repository, &event_bus, &mut cursor).await
+            })
+        };
+        repository
+            .delete(workspace.id, todo.id, None)
+            .await
+            .expect("[delete] returned Err");
+
+        // other tests write to the database at the same time
+        let mut changes = vec![];
+        while changes.len() < 2 {
+            let event = tokio::time::timeout(Duration::from_secs(5), subscription.receiver.recv())
+                .await
+                .expect("no change relayed in time")
+                .expect("failed receive event");
+            if event.workspace_id == workspace.id {
+                changes.push(event.change.clone());
+            }
+        }
+        assert_eq!(
+            vec![
+                Change::TodoCreated(todo.clone()),
+                Change::TodoDeleted {
+                    id: todo.id,
+                    labels: vec![]
+                }
+            ],
+            changes
+        );
+        relay.abort();
+    }
+    #[tokio::test]
+    #[cfg(feature = "database-test")]
+    async fn late_commit_scenario() {
+        use crate::repositories::{
+            audit::AuditRepositoryForDb,
+            todo::{CreateTodo, TodoRepository, TodoRepositoryForDb},
+            unit_of_work::{UnitOfWork, UnitOfWorkForDb},
+            user::UserRepositoryForDb,
+            workspace::{test_utils::prepare_workspace, WorkspaceRepositoryForDb},
+        };
+        use dotenv::dotenv;
+        use std::env;
+
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database_url));
+        let workspace = prepare_workspace(
+            &UserRepositoryForDb::new(pool.clone()),
+            &WorkspaceRepositoryForDb::new(pool.clone()),
+            "[late_commit_scenario] user",
+            "[late_commit_scenario] workspace",
+        )
+        .await;
+        let event_bus = EventBus::new(REPLAY_CAPACITY);
+        let mut subscription = event_bus.subscribe(None);
+        let audit_repository = AuditRepositoryForDb::new(pool.clone());
+        let mut cursor = RelayCursor::default();
+        catch_up(&audit_repository, &event_bus, &mut cursor)
+            .await
+            .expect("[catch_up] returned Err");
+        let mut relayed = || {
+            let mut ids = vec![];
+            while let Ok(event) = subscription.receiver.try_recv() {
+                if event.workspace_id == workspace.id {
+                    ids.push(event.id);
+                }
+            }
+            ids
+        };
+
+        let create =
+            |text: &str| CreateTodo::new(format!("[late_commit_scenario] {}", text), vec![]);
+        let repository = TodoRepositoryForDb::new(pool.clone());
+        let first = repository
+            .create(workspace.id, create("first"))
+            .await
+            .expect("[create] returned Err");
+        catch_up(&audit_repository, &event_bus, &mut cursor)
+            .await
+            .expect("[catch_up] returned Err");
+        relayed();
+
+        // deleting the first todo takes the lower audit id, but commits after the second
+        let uow = UnitOfWorkForDb::begin(&pool)
+            .await
+            .expect("[begin] returned Err");
+        uow.todos()
+            .delete(workspace.id, first.id, None)
+            .await
+            .expect("[delete] returned Err");
+        let second = repository
+            .create(workspace.id, create("second"))
+            .await
+            .expect("[create] returned Err");
+        catch_up(&audit_repository, &event_bus, &mut cursor)
+            .await
+            .expect("[catch_up] returned Err");
+        let latest_event = |id| {
+            let audit_repository = &audit_repository;
+            async move {
+                let history = audit_repository
+                    .history(workspace.id, AuditEntity::Todo, id)
+                    .await
+                    .expect("[history] returned Err");
+                history.last().unwrap().id
+            }
+        };
+        let second_event = latest_event(second.id).await;
+        assert_eq!(vec![second_event], relayed());
+
+        uow.commit().await.expect("[commit] returned Err");
+        catch_up(&audit_repository, &event_bus, &mut cursor)
+            .await
+            .expect("[catch_up] returned Err");
+        let first_event = latest_event(first.id).await;
+        assert!(first_event < second_event);
+        // relayed once, without the second again
+        assert_eq!(vec![first_event], relayed());
+    }
+}
@@ -1,13 +1,28 @@
+use crate::errors::ApiError;
 use axum::{
     async_trait,
-    extract::{FromRequest, RequestParts},
+    extract::{FromRequest, Query, RequestParts},
+    http::header::{HeaderName, IF_MATCH, IF_NONE_MATCH},
     BoxError, Json,
 };
-use hyper::StatusCode;
 use serde::de::DeserializeOwned;
+use std::convert::Infallible;
 use validator::Validate;
+pub mod audit;
+pub mod auth;
+pub mod event;
+pub mod health;
 pub mod label;
 pub mod todo;
+pub mod workspace;
+pub mod ws;
+
+/// Runs the `validator` rules of a payload, in the shape of every other validation error.
+fn validate<T: Validate>(value: &T) -> Result<(), ApiError> {
+    value
+        .validate()
+        .map_err(|rejection| ApiError::Validation(rejection.to_string().replace('\n', ", ")))
+}
 
 #[derive(Debug)]
 pub struct ValidatedJson<T>(T);
@@ -20,17 +35,127 @@ where
     B::Data: Send,
     B::Error: Into<BoxError>,
 {
-    type Rejection = (StatusCode, String);
+    type Rejection = ApiError;
 
     async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
-        let Json(value) = Json::<T>::from_request(req).await.map_err(|rejection| {
-            let message = format!("Json parse error: [{}]", rejection);
-            (StatusCode::BAD_REQUEST, message)
-        })?;
-        value.validate().map_err(|rejection| {
-            let message = format!("Validation error: [{}]", rejection).replace('\n', ", ");
-            (StatusCode::BAD_REQUEST, message)
-        })?;
+        let Json(value) = Json::<T>::from_request(req)
+            .await
+            .map_err(|rejection| ApiError::InvalidJson(rejection.to_string()))?;
+        validate(&value)?;
         Ok(ValidatedJson(value))
     }
 }
+
+#[derive(Debug)]
+pub struct ValidatedQuery<T>(T);
+
+#[async_trait]
+impl<T, B> FromRequest<B> for ValidatedQuery<T>
+where
+    T: DeserializeOwned + Validate,
+    B: Send,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let Query(value) = Query::<T>::from_request(req)
+            .await
+            .map_err(|rejection| ApiError::InvalidQuery(rejection.to_string()))?;
+        validate(&value)?;
+        Ok(ValidatedQuery(value))
+    }
+}
+
+/// The `ETag` of an entity at the given version.
+pub fn etag(version: i32) -> String {
+    format!("\"{}\"", version)
+}
+
+/// Entity tags of an `If-Match` or `If-None-Match` header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntityTags {
+    /// `*`
+    Any,
+    /// versions of the listed tags, leaving out tags that we never handed out
+    Versions(Vec<i32>),
+}
+
+impl EntityTags {
+    fn parse(value: &str) -> Self {
+        if value.trim() == "*" {
+            return EntityTags::Any;
+        }
+        EntityTags::Versions(
+            value
+                .split(',')
+                .filter_map(|tag| {
+                    let tag = tag.trim();
+                    let tag = tag.strip_prefix("W/").unwrap_or(tag);
+                    tag.strip_prefix('"')?.strip_suffix('"')?.parse().ok()
+                })
+                .collect(),
+        )
+    }
+
+    pub fn matches(&self, version: i32) -> bool {
+        match self {
+            EntityTags::Any => true,
+            EntityTags::Versions(versions) => versions.contains(&version),
+        }
+    }
+
+    /// The versions a repository has to check, `None` when any version will do.
+    pub fn versions(self) -> Option<Vec<i32>> {
+        match self {
+            EntityTags::Any => None,
+            EntityTags::Versions(versions) => Some(versions),
+        }
+    }
+}
+
+fn entity_tags<B>(req: &RequestParts<B>, name: HeaderName) -> Option<EntityTags> {
+    let value = req.headers()?.get(name)?.to_str().ok()?;
+    Some(EntityTags::parse(value))
+}
+
+#[derive(Debug)]
+pub struct IfMatch(pub Option<EntityTags>);
+
+#[async_trait]
+impl<B: Send> FromRequest<B> for IfMatch {
+    type Rejection = Infallible;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        Ok(IfMatch(entity_tags(req, IF_MATCH)))
+    }
+}
+
+#[derive(Debug)]
+pub struct IfNoneMatch(pub Option<EntityTags>);
+
+#[async_trait]
+impl<B: Send> FromRequest<B> for IfNoneMatch {
+    type Rejection = Infallible;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        Ok(IfNoneMatch(entity_tags(req, IF_NONE_MATCH)))
+    }
+}
+
+/// The `Last-Event-ID` an `EventSource` sends when it reconnects.
+#[derive(Debug)]
+pub struct LastEventId(pub Option<i64>);
+
+#[async_trait]
+impl<B: Send> FromRequest<B> for LastEventId {
+    type Rejection = Infallible;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let id = req
+            .headers()
+            .and_then(|headers| headers.get("last-event-id"))
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse().ok());
+        Ok(LastEventId(id))
+    }
+}
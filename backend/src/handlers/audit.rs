@@ -0,0 +1,78 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Extension, Path},
+    response::IntoResponse,
+    Json,
+};
+use hyper::StatusCode;
+
+use crate::{
+    auth::AuthUser,
+    errors::ApiError,
+    openapi::{InvalidQuery, NotMember, OwnerRequired},
+    repositories::{
+        audit::{AuditEntity, AuditQuery, AuditRepository},
+        workspace::{Role, WorkspaceRepository},
+    },
+};
+
+use super::{workspace::authorize, ValidatedQuery};
+
+/// Every change made to the todo, oldest first, also after it was purged.
+#[utoipa::path(
+    get,
+    path = "/workspaces/{workspace_id}/todos/{id}/history",
+    tag = "audit",
+    params(
+        ("workspace_id" = i32, Path, description = "id of the workspace"),
+        ("id" = i32, Path, description = "id of the todo"),
+    ),
+    responses(
+        (status = 200, description = "Oldest first", body = [AuditEvent]),
+        (status = 404, description = "No history for the todo", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn todo_history<T: AuditRepository, W: WorkspaceRepository>(
+    user: AuthUser,
+    Path((workspace_id, id)): Path<(i32, i32)>,
+    Extension(repository): Extension<Arc<T>>,
+    Extension(workspace_repository): Extension<Arc<W>>,
+) -> Result<impl IntoResponse, ApiError> {
+    authorize(&*workspace_repository, workspace_id, user, Role::Viewer).await?;
+    let events = repository
+        .history(workspace_id, AuditEntity::Todo, id)
+        .await?;
+    if events.is_empty() {
+        return Err(ApiError::NotFound(id));
+    }
+    Ok((StatusCode::OK, Json(events)))
+}
+
+/// The audit log tells who did what to everything in the workspace, so only owners see it.
+#[utoipa::path(
+    get,
+    path = "/workspaces/{workspace_id}/audit",
+    tag = "audit",
+    params(
+        ("workspace_id" = i32, Path, description = "id of the workspace"),
+        AuditQuery,
+    ),
+    responses(
+        (status = 200, description = "Newest first", body = [AuditEvent]),
+        OwnerRequired,
+        NotMember,
+        InvalidQuery,
+    )
+)]
+pub async fn all_audit_event<T: AuditRepository, W: WorkspaceRepository>(
+    user: AuthUser,
+    Path(workspace_id): Path<i32>,
+    ValidatedQuery(query): ValidatedQuery<AuditQuery>,
+    Extension(repository): Extension<Arc<T>>,
+    Extension(workspace_repository): Extension<Arc<W>>,
+) -> Result<impl IntoResponse, ApiError> {
+    authorize(&*workspace_repository, workspace_id, user, Role::Owner).await?;
+    let events = repository.all(workspace_id, query).await?;
+    Ok((StatusCode::OK, Json(events)))
+}
@@ -0,0 +1,143 @@
+use std::sync::Arc;
+
+use axum::{extract::Extension, response::IntoResponse, Json};
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::Validate;
+
+use crate::{
+    auth::{dummy_hash, hash_password, verify_password, AuthKeys, AuthUser, STREAM_TOKEN_TTL},
+    errors::ApiError,
+    openapi::InvalidPayload,
+    repositories::{
+        unit_of_work::{AccountUnitOfWork, TransactionalUser},
+        user::{User, UserRepository},
+        workspace::WorkspaceRepository,
+    },
+};
+
+use super::ValidatedJson;
+
+/// Creates the user together with a personal workspace named after them.
+#[utoipa::path(
+    post,
+    path = "/auth/signup",
+    tag = "auth",
+    request_body = Credentials,
+    responses(
+        (status = 201, description = "Signed up, with a personal workspace", body = AuthResponse),
+        (status = 409, description = "Username taken", body = Problem, content_type = "application/problem+json"),
+        InvalidPayload,
+    ),
+    security(())
+)]
+pub async fn signup<T: TransactionalUser>(
+    ValidatedJson(payload): ValidatedJson<Credentials>,
+    Extension(repository): Extension<Arc<T>>,
+    Extension(keys): Extension<Arc<AuthKeys>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let password_hash = hash_password(payload.password).await?;
+    let uow = repository.begin().await?;
+    let user = uow.users().create(payload.username, password_hash).await?;
+    uow.workspaces()
+        .create(user.id, user.username.clone())
+        .await?;
+    uow.commit().await?;
+    let token = keys.issue(user.id)?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(AuthResponse {
+            token,
+            user: user.into(),
+        }),
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/login",
+    tag = "auth",
+    request_body = Credentials,
+    responses(
+        (status = 200, description = "Logged in", body = AuthResponse),
+        (status = 401, description = "Invalid username or password", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(())
+)]
+pub async fn login<T: UserRepository>(
+    ValidatedJson(payload): ValidatedJson<Credentials>,
+    Extension(repository): Extension<Arc<T>>,
+    Extension(keys): Extension<Arc<AuthKeys>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let invalid = || ApiError::Unauthorized("Invalid username or password".to_string());
+    let user = repository.find_by_username(&payload.username).await?;
+    // unknown usernames take as long to refuse as wrong passwords
+    let password_hash = match &user {
+        Some(user) => user.password_hash.clone(),
+        None => dummy_hash().await?,
+    };
+    let verified = verify_password(payload.password, password_hash).await?;
+    let user = user.filter(|_| verified).ok_or_else(invalid)?;
+    let token = keys.issue(user.id)?;
+
+    Ok((
+        StatusCode::OK,
+        Json(AuthResponse {
+            token,
+            user: user.into(),
+        }),
+    ))
+}
+
+/// Issues a token for the `access_token` query parameter of the event stream and the
+/// WebSocket, which browsers open without a way to set the `Authorization` header. It is
+/// only good for that, and for a minute.
+#[utoipa::path(
+    post,
+    path = "/auth/stream-token",
+    tag = "auth",
+    responses(
+        (status = 201, description = "Issued", body = StreamToken),
+    )
+)]
+pub async fn stream_token(
+    user: AuthUser,
+    Extension(keys): Extension<Arc<AuthKeys>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let token = keys.issue_stream(user.id)?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(StreamToken {
+            token,
+            expires_in: STREAM_TOKEN_TTL.as_secs(),
+        }),
+    ))
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Validate, ToSchema)]
+pub struct Credentials {
+    #[validate(length(min = 1, message = "Can not be empty"))]
+    #[validate(length(max = 100, message = "Over text length"))]
+    #[schema(min_length = 1, max_length = 100)]
+    username: String,
+    #[validate(length(min = 8, message = "Too short"))]
+    #[validate(length(max = 128, message = "Over text length"))]
+    #[schema(min_length = 8, max_length = 128)]
+    password: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, ToSchema)]
+pub struct AuthResponse {
+    pub token: String,
+    pub user: User,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, ToSchema)]
+pub struct StreamToken {
+    pub token: String,
+    /// seconds until the token expires
+    pub expires_in: u64,
+}
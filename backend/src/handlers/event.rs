@@ -0,0 +1,103 @@
+use super::{workspace::authorize, LastEventId};
+use crate::{
+    auth::StreamUser,
+    errors::ApiError,
+    events::{ChangeEvent, EventBus},
+    openapi::NotMember,
+    repositories::workspace::{Role, WorkspaceRepository},
+};
+use axum::{
+    extract::{Extension, Path},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse,
+    },
+};
+use futures::{future, stream, StreamExt};
+use std::sync::Arc;
+
+/// Streams the changes of the workspace, starting with those after `Last-Event-ID`. Event
+/// ids are those of the audit log, so a client may resume on any replica.
+/// A `reset` event tells the client that it missed changes and has to refetch, and a
+/// `shutdown` event ends the stream when the server goes down, so that it reconnects. The
+/// stream ends as well once the user is no member of the workspace anymore.
+/// Browsers, whose `EventSource` can not send the `Authorization` header, pass a token of
+/// `/auth/stream-token` as `access_token` instead.
+#[utoipa::path(
+    get,
+    path = "/workspaces/{workspace_id}/events",
+    tag = "events",
+    params(
+        ("workspace_id" = i32, Path, description = "id of the workspace"),
+        ("Last-Event-ID" = Option<i64>, Header, description = "id of the last event received"),
+        ("access_token" = Option<String>, Query, description = "token of `/auth/stream-token`, instead of the `Authorization` header"),
+    ),
+    responses(
+        (status = 200, description = "Server-sent events named like `todo.created`", body = String, content_type = "text/event-stream"),
+        NotMember,
+    )
+)]
+pub async fn workspace_events<W: WorkspaceRepository>(
+    StreamUser(user): StreamUser,
+    Path(workspace_id): Path<i32>,
+    LastEventId(last_event_id): LastEventId,
+    Extension(event_bus): Extension<Arc<EventBus>>,
+    Extension(workspace_repository): Extension<Arc<W>>,
+) -> Result<impl IntoResponse, ApiError> {
+    authorize(&*workspace_repository, workspace_id, user, Role::Viewer).await?;
+    let in_workspace = move |event: &Option<Arc<ChangeEvent>>| {
+        future::ready(
+            event
+                .as_ref()
+                .is_none_or(|event| event.workspace_id == workspace_id),
+        )
+    };
+    let subscription = event_bus.subscribe(last_event_id);
+    let reset = subscription
+        .missed
+        .then(|| Ok(Event::default().event("reset").data("{}")));
+    // a client that falls too far behind is cut off, and resumes from the replay buffer;
+    // `None` stands for the shutdown, after which the stream ends
+    let live = stream::unfold(Some(subscription.receiver), move |receiver| {
+        let event_bus = event_bus.clone();
+        async move {
+            let mut receiver = receiver?;
+            tokio::select! {
+                biased;
+                _ = event_bus.closed() => Some((None, None)),
+                event = receiver.recv() => Some((Some(event.ok()?), Some(receiver))),
+            }
+        }
+    })
+    .filter(in_workspace)
+    // removed members stop hearing about the workspace
+    .then(move |event| {
+        let workspace_repository = workspace_repository.clone();
+        async move {
+            match event {
+                Some(_)
+                    if authorize(&*workspace_repository, workspace_id, user, Role::Viewer)
+                        .await
+                        .is_err() =>
+                {
+                    None
+                }
+                event => Some(event),
+            }
+        }
+    })
+    .take_while(|event| future::ready(event.is_some()))
+    .filter_map(future::ready);
+    let events = stream::iter(subscription.replay)
+        .map(Some)
+        .filter(in_workspace)
+        .chain(live)
+        .map(|event| match event {
+            Some(event) => Event::default()
+                .id(event.id.to_string())
+                .event(event.change.name())
+                .json_data(event.change.data()),
+            None => Ok(Event::default().event("shutdown").data("{}")),
+        });
+    Ok(Sse::new(stream::iter(reset).chain(events)).keep_alive(KeepAlive::default()))
+}
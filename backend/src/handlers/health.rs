@@ -0,0 +1,109 @@
+use crate::{
+    events::EventBus,
+    repositories::health::{HealthRepository, SchemaStatus, MIGRATOR},
+};
+use axum::{extract::Extension, http::StatusCode, Json};
+use serde::Serialize;
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Health {
+    pub status: &'static str,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Readiness {
+    /// `ready`, or `not_ready` along with a 503
+    pub status: &'static str,
+    pub shutting_down: bool,
+    /// whether the database answered
+    pub database: bool,
+    pub schema: Option<SchemaStatus>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Version {
+    pub version: &'static str,
+    pub git_sha: &'static str,
+    /// RFC 3339
+    pub build_time: &'static str,
+    /// the latest migration built in
+    pub schema_version: Option<i64>,
+}
+
+/// Answers as long as the process serves requests.
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "health",
+    responses(
+        (status = 200, description = "Alive", body = Health),
+    ),
+    security(())
+)]
+pub async fn healthz() -> Json<Health> {
+    Json(Health { status: "ok" })
+}
+
+/// Ready once the database answers and has every migration of this build applied, until
+/// the server starts shutting down.
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "health",
+    responses(
+        (status = 200, description = "Ready", body = Readiness),
+        (status = 503, description = "Not ready", body = Readiness),
+    ),
+    security(())
+)]
+pub async fn readyz<H: HealthRepository>(
+    Extension(health_repository): Extension<Arc<H>>,
+    Extension(event_bus): Extension<Arc<EventBus>>,
+) -> (StatusCode, Json<Readiness>) {
+    // the bus is closed as soon as the shutdown begins
+    let shutting_down = event_bus.is_closed();
+    let schema = match health_repository.schema().await {
+        Ok(schema) => Some(schema),
+        Err(err) => {
+            tracing::warn!("not ready: {:?}", err);
+            None
+        }
+    };
+    let ready = !shutting_down
+        && schema
+            .as_ref()
+            .is_some_and(|schema| schema.pending.is_empty());
+    let readiness = Readiness {
+        status: if ready { "ready" } else { "not_ready" },
+        shutting_down,
+        database: schema.is_some(),
+        schema,
+    };
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(readiness))
+}
+
+/// Identifies the build.
+#[utoipa::path(
+    get,
+    path = "/version",
+    tag = "health",
+    responses(
+        (status = 200, description = "Build of the running server", body = Version),
+    ),
+    security(())
+)]
+pub async fn version() -> Json<Version> {
+    Json(Version {
+        version: env!("CARGO_PKG_VERSION"),
+        git_sha: env!("GIT_SHA"),
+        build_time: env!("BUILD_TIME"),
+        schema_version: MIGRATOR.iter().map(|migration| migration.version).max(),
+    })
+}
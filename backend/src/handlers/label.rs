@@ -7,45 +7,183 @@ use axum::{
 };
 use hyper::StatusCode;
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 use validator::Validate;
 
-use crate::repositories::label::LabelRepository;
+use crate::{
+    auth::AuthUser,
+    errors::ApiError,
+    openapi::{EditorRequired, InvalidPayload, NotMember},
+    repositories::{
+        label::{DeleteLabelStrategy, LabelRepository, UpdateLabel},
+        workspace::{Role, WorkspaceRepository},
+    },
+};
 
-use super::ValidatedJson;
+use super::{workspace::authorize, ValidatedJson, ValidatedQuery};
 
-pub async fn create_label<T: LabelRepository>(
+#[utoipa::path(
+    post,
+    path = "/workspaces/{workspace_id}/labels",
+    tag = "labels",
+    params(
+        ("workspace_id" = i32, Path, description = "id of the workspace"),
+    ),
+    request_body = CreateLabel,
+    responses(
+        (status = 201, description = "Created", body = Label),
+        EditorRequired,
+        NotMember,
+        (status = 409, description = "Name taken", body = Problem, content_type = "application/problem+json"),
+        InvalidPayload,
+    )
+)]
+pub async fn create_label<T: LabelRepository, W: WorkspaceRepository>(
+    user: AuthUser,
+    Path(workspace_id): Path<i32>,
     ValidatedJson(payload): ValidatedJson<CreateLabel>,
     Extension(repository): Extension<Arc<T>>,
-) -> Result<impl IntoResponse, StatusCode> {
+    Extension(workspace_repository): Extension<Arc<W>>,
+) -> Result<impl IntoResponse, ApiError> {
+    authorize(&*workspace_repository, workspace_id, user, Role::Editor).await?;
     let label = repository
-        .create(payload.name)
-        .await
-        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+        .acting_as(user.id)
+        .create(workspace_id, payload.name)
+        .await?;
 
     Ok((StatusCode::CREATED, Json(label)))
 }
 
-pub async fn all_label<T: LabelRepository>(
+#[utoipa::path(
+    get,
+    path = "/workspaces/{workspace_id}/labels/{id}",
+    tag = "labels",
+    params(
+        ("workspace_id" = i32, Path, description = "id of the workspace"),
+        ("id" = i32, Path, description = "id of the label"),
+    ),
+    responses(
+        (status = 200, description = "Found", body = Label),
+        (status = 404, description = "Label not found", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn find_label<T: LabelRepository, W: WorkspaceRepository>(
+    user: AuthUser,
+    Path((workspace_id, id)): Path<(i32, i32)>,
     Extension(repository): Extension<Arc<T>>,
-) -> Result<impl IntoResponse, StatusCode> {
-    let labels = repository.all().await.unwrap();
+    Extension(workspace_repository): Extension<Arc<W>>,
+) -> Result<impl IntoResponse, ApiError> {
+    authorize(&*workspace_repository, workspace_id, user, Role::Viewer).await?;
+    let label = repository.find(workspace_id, id).await?;
+    Ok((StatusCode::OK, Json(label)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/workspaces/{workspace_id}/labels",
+    tag = "labels",
+    params(
+        ("workspace_id" = i32, Path, description = "id of the workspace"),
+    ),
+    responses(
+        (status = 200, description = "Labels of the workspace", body = [Label]),
+        NotMember,
+    )
+)]
+pub async fn all_label<T: LabelRepository, W: WorkspaceRepository>(
+    user: AuthUser,
+    Path(workspace_id): Path<i32>,
+    Extension(repository): Extension<Arc<T>>,
+    Extension(workspace_repository): Extension<Arc<W>>,
+) -> Result<impl IntoResponse, ApiError> {
+    authorize(&*workspace_repository, workspace_id, user, Role::Viewer).await?;
+    let labels = repository.all(workspace_id).await?;
     Ok((StatusCode::OK, Json(labels)))
 }
 
-pub async fn delete_label<T: LabelRepository>(
-    Path(id): Path<i32>,
+#[utoipa::path(
+    patch,
+    path = "/workspaces/{workspace_id}/labels/{id}",
+    tag = "labels",
+    params(
+        ("workspace_id" = i32, Path, description = "id of the workspace"),
+        ("id" = i32, Path, description = "id of the label"),
+    ),
+    request_body = UpdateLabel,
+    responses(
+        (status = 200, description = "Updated", body = Label),
+        EditorRequired,
+        (status = 404, description = "Label not found", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "Name taken", body = Problem, content_type = "application/problem+json"),
+        InvalidPayload,
+    )
+)]
+pub async fn update_label<T: LabelRepository, W: WorkspaceRepository>(
+    user: AuthUser,
+    Path((workspace_id, id)): Path<(i32, i32)>,
+    ValidatedJson(payload): ValidatedJson<UpdateLabel>,
+    Extension(repository): Extension<Arc<T>>,
+    Extension(workspace_repository): Extension<Arc<W>>,
+) -> Result<impl IntoResponse, ApiError> {
+    authorize(&*workspace_repository, workspace_id, user, Role::Editor).await?;
+    let label = repository
+        .acting_as(user.id)
+        .update(workspace_id, id, payload)
+        .await?;
+    Ok((StatusCode::OK, Json(label)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/workspaces/{workspace_id}/labels/{id}",
+    tag = "labels",
+    params(
+        ("workspace_id" = i32, Path, description = "id of the workspace"),
+        ("id" = i32, Path, description = "id of the label"),
+        DeleteLabelQuery,
+    ),
+    responses(
+        (status = 204, description = "Deleted"),
+        EditorRequired,
+        (status = 404, description = "Label not found", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "Still in use, with the ids of the todos outside the trash", body = Problem, content_type = "application/problem+json"),
+        InvalidPayload,
+    )
+)]
+pub async fn delete_label<T: LabelRepository, W: WorkspaceRepository>(
+    user: AuthUser,
+    Path((workspace_id, id)): Path<(i32, i32)>,
+    ValidatedQuery(query): ValidatedQuery<DeleteLabelQuery>,
     Extension(repository): Extension<Arc<T>>,
-) -> StatusCode {
+    Extension(workspace_repository): Extension<Arc<W>>,
+) -> Result<StatusCode, ApiError> {
+    authorize(&*workspace_repository, workspace_id, user, Role::Editor).await?;
+    if query.strategy == DeleteLabelStrategy::Reassign(id) {
+        return Err(ApiError::Validation(
+            "strategy: Can not reassign to the deleted label".to_string(),
+        ));
+    }
     repository
-        .delete(id)
-        .await
-        .map(|_| StatusCode::NO_CONTENT)
-        .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+        .acting_as(user.id)
+        .delete(workspace_id, id, query.strategy)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Validate)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Validate, ToSchema)]
 pub struct CreateLabel {
     #[validate(length(min = 1, message = "Can not be empty"))]
     #[validate(length(max = 100, message = "Over text length"))]
+    #[schema(min_length = 1, max_length = 100)]
     name: String,
 }
+
+#[derive(Deserialize, Debug, PartialEq, Eq, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DeleteLabelQuery {
+    /// `refuse` (the default), `detach` or `reassign:<label id>`; trashed todos never hold
+    /// the label back and follow along
+    #[serde(default)]
+    #[param(value_type = Option<String>, example = "reassign:2")]
+    strategy: DeleteLabelStrategy,
+}
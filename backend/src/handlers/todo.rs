@@ -1,59 +1,374 @@
-use super::ValidatedJson;
-use crate::repositories::todo::{CreateTodo, TodoRepository, UpdateTodo};
+use super::workspace::authorize;
+use super::{etag, EntityTags, IfMatch, IfNoneMatch, ValidatedJson, ValidatedQuery};
+use crate::auth::AuthUser;
+use crate::errors::ApiError;
+use crate::openapi::{EditorRequired, InvalidPayload, InvalidQuery, NotMember, OwnerRequired};
+use crate::repositories::{
+    todo::{BulkTodo, CreateTodo, MoveTodo, SearchQuery, TodoQuery, TodoRepository, UpdateTodo},
+    unit_of_work::{Transactional, UnitOfWork},
+    workspace::{Role, WorkspaceRepository},
+};
 use axum::{
     extract::{Extension, Path},
-    http::StatusCode,
-    response::IntoResponse,
+    http::{header::ETAG, StatusCode},
+    response::{Headers, IntoResponse, Response},
     Json,
 };
 use std::sync::Arc;
 
-pub async fn create_todo<T: TodoRepository>(
+#[utoipa::path(
+    post,
+    path = "/workspaces/{workspace_id}/todos",
+    tag = "todos",
+    params(
+        ("workspace_id" = i32, Path, description = "id of the workspace"),
+    ),
+    request_body = CreateTodo,
+    responses(
+        (status = 201, description = "Created", body = TodoEntity),
+        EditorRequired,
+        (status = 404, description = "Workspace or label not found", body = Problem, content_type = "application/problem+json"),
+        InvalidPayload,
+    )
+)]
+pub async fn create_todo<T: Transactional, W: WorkspaceRepository>(
+    user: AuthUser,
+    Path(workspace_id): Path<i32>,
     ValidatedJson(payload): ValidatedJson<CreateTodo>,
     Extension(repository): Extension<Arc<T>>,
-) -> Result<impl IntoResponse, StatusCode> {
-    let todo = repository
-        .create(payload)
-        .await
-        .or(Err(StatusCode::NOT_FOUND))?;
+    Extension(workspace_repository): Extension<Arc<W>>,
+) -> Result<impl IntoResponse, ApiError> {
+    authorize(&*workspace_repository, workspace_id, user, Role::Editor).await?;
+    let uow = repository.acting_as(user.id).begin().await?;
+    let todo = uow.todos().create(workspace_id, payload).await?;
+    uow.commit().await?;
 
     Ok((StatusCode::CREATED, Json(todo)))
 }
 
-pub async fn find_todo<T: TodoRepository>(
-    Path(id): Path<i32>,
+/// Answers `304 Not Modified` when the client already holds the current version.
+#[utoipa::path(
+    get,
+    path = "/workspaces/{workspace_id}/todos/{id}",
+    tag = "todos",
+    params(
+        ("workspace_id" = i32, Path, description = "id of the workspace"),
+        ("id" = i32, Path, description = "id of the todo"),
+        ("If-None-Match" = Option<String>, Header, description = "ETags the client already holds"),
+    ),
+    responses(
+        (status = 200, description = "Found", body = TodoEntity, headers(("ETag" = String, description = "version of the todo"))),
+        (status = 304, description = "Not modified", headers(("ETag" = String, description = "version of the todo"))),
+        (status = 404, description = "Todo not found", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn find_todo<T: TodoRepository, W: WorkspaceRepository>(
+    user: AuthUser,
+    Path((workspace_id, id)): Path<(i32, i32)>,
+    IfNoneMatch(if_none_match): IfNoneMatch,
     Extension(repository): Extension<Arc<T>>,
-) -> Result<impl IntoResponse, StatusCode> {
-    let todo = repository.find(id).await.or(Err(StatusCode::NOT_FOUND))?;
-    Ok((StatusCode::OK, Json(todo)))
+    Extension(workspace_repository): Extension<Arc<W>>,
+) -> Result<Response, ApiError> {
+    authorize(&*workspace_repository, workspace_id, user, Role::Viewer).await?;
+    let todo = repository.find(workspace_id, id).await?;
+    let headers = Headers([(ETAG, etag(todo.version))]);
+    if if_none_match.is_some_and(|tags| tags.matches(todo.version)) {
+        return Ok((StatusCode::NOT_MODIFIED, headers).into_response());
+    }
+    Ok((StatusCode::OK, headers, Json(todo)).into_response())
 }
 
-pub async fn all_todo<T: TodoRepository>(
+#[utoipa::path(
+    get,
+    path = "/workspaces/{workspace_id}/todos",
+    tag = "todos",
+    params(
+        ("workspace_id" = i32, Path, description = "id of the workspace"),
+        TodoQuery,
+    ),
+    responses(
+        (status = 200, description = "One page of todos", body = TodoPage),
+        (status = 400, description = "Invalid query", body = Problem, content_type = "application/problem+json"),
+        NotMember,
+        InvalidQuery,
+    )
+)]
+pub async fn all_todo<T: TodoRepository, W: WorkspaceRepository>(
+    user: AuthUser,
+    Path(workspace_id): Path<i32>,
+    ValidatedQuery(query): ValidatedQuery<TodoQuery>,
     Extension(repository): Extension<Arc<T>>,
-) -> Result<impl IntoResponse, StatusCode> {
-    let todo = repository.all().await.unwrap();
+    Extension(workspace_repository): Extension<Arc<W>>,
+) -> Result<impl IntoResponse, ApiError> {
+    authorize(&*workspace_repository, workspace_id, user, Role::Viewer).await?;
+    let todo = repository.all(workspace_id, query).await?;
     Ok((StatusCode::OK, Json(todo)))
 }
 
-pub async fn update_todo<T: TodoRepository>(
-    Path(id): Path<i32>,
+#[utoipa::path(
+    get,
+    path = "/workspaces/{workspace_id}/todos/search",
+    tag = "todos",
+    params(
+        ("workspace_id" = i32, Path, description = "id of the workspace"),
+        SearchQuery,
+    ),
+    responses(
+        (status = 200, description = "Best matches first", body = [TodoSearchHit]),
+        (status = 400, description = "Invalid query", body = Problem, content_type = "application/problem+json"),
+        NotMember,
+        InvalidQuery,
+    )
+)]
+pub async fn search_todo<T: TodoRepository, W: WorkspaceRepository>(
+    user: AuthUser,
+    Path(workspace_id): Path<i32>,
+    ValidatedQuery(query): ValidatedQuery<SearchQuery>,
+    Extension(repository): Extension<Arc<T>>,
+    Extension(workspace_repository): Extension<Arc<W>>,
+) -> Result<impl IntoResponse, ApiError> {
+    authorize(&*workspace_repository, workspace_id, user, Role::Viewer).await?;
+    let hits = repository.search(workspace_id, query).await?;
+    Ok((StatusCode::OK, Json(hits)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/workspaces/{workspace_id}/todos/{id}/children",
+    tag = "todos",
+    params(
+        ("workspace_id" = i32, Path, description = "id of the workspace"),
+        ("id" = i32, Path, description = "id of the todo"),
+    ),
+    responses(
+        (status = 200, description = "Direct subtasks in list order", body = [TodoEntity]),
+        (status = 404, description = "Todo not found", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn all_child_todo<T: TodoRepository, W: WorkspaceRepository>(
+    user: AuthUser,
+    Path((workspace_id, id)): Path<(i32, i32)>,
+    Extension(repository): Extension<Arc<T>>,
+    Extension(workspace_repository): Extension<Arc<W>>,
+) -> Result<impl IntoResponse, ApiError> {
+    authorize(&*workspace_repository, workspace_id, user, Role::Viewer).await?;
+    let todos = repository.children(workspace_id, id).await?;
+    Ok((StatusCode::OK, Json(todos)))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/workspaces/{workspace_id}/todos/{id}",
+    tag = "todos",
+    params(
+        ("workspace_id" = i32, Path, description = "id of the workspace"),
+        ("id" = i32, Path, description = "id of the todo"),
+        ("If-Match" = Option<String>, Header, description = "ETags the todo must still match"),
+    ),
+    request_body = UpdateTodo,
+    responses(
+        (status = 201, description = "Updated", body = TodoEntity, headers(("ETag" = String, description = "version of the todo"))),
+        EditorRequired,
+        (status = 404, description = "Todo or label not found", body = Problem, content_type = "application/problem+json"),
+        (status = 412, description = "Todo changed since the given version", body = Problem, content_type = "application/problem+json"),
+        InvalidPayload,
+    )
+)]
+pub async fn update_todo<T: Transactional, W: WorkspaceRepository>(
+    user: AuthUser,
+    Path((workspace_id, id)): Path<(i32, i32)>,
+    IfMatch(if_match): IfMatch,
     ValidatedJson(payload): ValidatedJson<UpdateTodo>,
     Extension(repository): Extension<Arc<T>>,
-) -> Result<impl IntoResponse, StatusCode> {
+    Extension(workspace_repository): Extension<Arc<W>>,
+) -> Result<impl IntoResponse, ApiError> {
+    authorize(&*workspace_repository, workspace_id, user, Role::Editor).await?;
+    let uow = repository.acting_as(user.id).begin().await?;
+    let todo = uow
+        .todos()
+        .update(
+            workspace_id,
+            id,
+            payload,
+            if_match.and_then(EntityTags::versions),
+        )
+        .await?;
+    uow.commit().await?;
+    let headers = Headers([(ETAG, etag(todo.version))]);
+    Ok((StatusCode::CREATED, headers, Json(todo)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/workspaces/{workspace_id}/todos/{id}/move",
+    tag = "todos",
+    params(
+        ("workspace_id" = i32, Path, description = "id of the workspace"),
+        ("id" = i32, Path, description = "id of the todo"),
+    ),
+    request_body = MoveTodo,
+    responses(
+        (status = 200, description = "Moved", body = TodoEntity),
+        EditorRequired,
+        (status = 404, description = "Todo or neighbour not found", body = Problem, content_type = "application/problem+json"),
+        InvalidPayload,
+    )
+)]
+pub async fn move_todo<T: TodoRepository, W: WorkspaceRepository>(
+    user: AuthUser,
+    Path((workspace_id, id)): Path<(i32, i32)>,
+    ValidatedJson(payload): ValidatedJson<MoveTodo>,
+    Extension(repository): Extension<Arc<T>>,
+    Extension(workspace_repository): Extension<Arc<W>>,
+) -> Result<impl IntoResponse, ApiError> {
+    authorize(&*workspace_repository, workspace_id, user, Role::Editor).await?;
+    if payload
+        .neighbour_ids()
+        .any(|neighbour_id| neighbour_id == id)
+    {
+        return Err(ApiError::Validation(
+            "before, after: Can not move a todo next to itself".to_string(),
+        ));
+    }
     let todo = repository
-        .update(id, payload)
-        .await
-        .or(Err(StatusCode::NOT_FOUND))?;
-    Ok((StatusCode::CREATED, Json(todo)))
+        .acting_as(user.id)
+        .reposition(workspace_id, id, payload)
+        .await?;
+    Ok((StatusCode::OK, Json(todo)))
 }
 
-pub async fn delete_todo<T: TodoRepository>(
-    Path(id): Path<i32>,
+#[utoipa::path(
+    post,
+    path = "/workspaces/{workspace_id}/todos/bulk",
+    tag = "todos",
+    params(
+        ("workspace_id" = i32, Path, description = "id of the workspace"),
+    ),
+    request_body = BulkTodo,
+    responses(
+        (status = 200, description = "One result per distinct id", body = [BulkItemResult]),
+        EditorRequired,
+        (status = 404, description = "Workspace or label not found", body = Problem, content_type = "application/problem+json"),
+        InvalidPayload,
+    )
+)]
+pub async fn bulk_todo<T: Transactional, W: WorkspaceRepository>(
+    user: AuthUser,
+    Path(workspace_id): Path<i32>,
+    ValidatedJson(payload): ValidatedJson<BulkTodo>,
     Extension(repository): Extension<Arc<T>>,
-) -> StatusCode {
+    Extension(workspace_repository): Extension<Arc<W>>,
+) -> Result<impl IntoResponse, ApiError> {
+    authorize(&*workspace_repository, workspace_id, user, Role::Editor).await?;
+    let uow = repository.acting_as(user.id).begin().await?;
+    let results = uow.todos().bulk(workspace_id, payload).await?;
+    uow.commit().await?;
+    Ok((StatusCode::OK, Json(results)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/workspaces/{workspace_id}/todos/{id}",
+    tag = "todos",
+    params(
+        ("workspace_id" = i32, Path, description = "id of the workspace"),
+        ("id" = i32, Path, description = "id of the todo"),
+        ("If-Match" = Option<String>, Header, description = "ETags the todo must still match"),
+    ),
+    responses(
+        (status = 204, description = "Moved to the trash with its subtasks"),
+        EditorRequired,
+        (status = 404, description = "Todo not found", body = Problem, content_type = "application/problem+json"),
+        (status = 412, description = "Todo changed since the given version", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn delete_todo<T: TodoRepository, W: WorkspaceRepository>(
+    user: AuthUser,
+    Path((workspace_id, id)): Path<(i32, i32)>,
+    IfMatch(if_match): IfMatch,
+    Extension(repository): Extension<Arc<T>>,
+    Extension(workspace_repository): Extension<Arc<W>>,
+) -> Result<StatusCode, ApiError> {
+    authorize(&*workspace_repository, workspace_id, user, Role::Editor).await?;
     repository
-        .delete(id)
-        .await
-        .map(|_| StatusCode::NO_CONTENT)
-        .unwrap_or(StatusCode::NOT_FOUND)
+        .acting_as(user.id)
+        .delete(workspace_id, id, if_match.and_then(EntityTags::versions))
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    get,
+    path = "/workspaces/{workspace_id}/trash",
+    tag = "todos",
+    params(
+        ("workspace_id" = i32, Path, description = "id of the workspace"),
+    ),
+    responses(
+        (status = 200, description = "Trashed todos", body = [TodoEntity]),
+        NotMember,
+    )
+)]
+pub async fn all_trash<T: TodoRepository, W: WorkspaceRepository>(
+    user: AuthUser,
+    Path(workspace_id): Path<i32>,
+    Extension(repository): Extension<Arc<T>>,
+    Extension(workspace_repository): Extension<Arc<W>>,
+) -> Result<impl IntoResponse, ApiError> {
+    authorize(&*workspace_repository, workspace_id, user, Role::Viewer).await?;
+    let todos = repository.trash(workspace_id).await?;
+    Ok((StatusCode::OK, Json(todos)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/workspaces/{workspace_id}/todos/{id}/restore",
+    tag = "todos",
+    params(
+        ("workspace_id" = i32, Path, description = "id of the workspace"),
+        ("id" = i32, Path, description = "id of the todo"),
+    ),
+    responses(
+        (status = 200, description = "Restored", body = TodoEntity),
+        EditorRequired,
+        (status = 404, description = "Todo not in the trash", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn restore_todo<T: TodoRepository, W: WorkspaceRepository>(
+    user: AuthUser,
+    Path((workspace_id, id)): Path<(i32, i32)>,
+    Extension(repository): Extension<Arc<T>>,
+    Extension(workspace_repository): Extension<Arc<W>>,
+) -> Result<impl IntoResponse, ApiError> {
+    authorize(&*workspace_repository, workspace_id, user, Role::Editor).await?;
+    let todo = repository
+        .acting_as(user.id)
+        .restore(workspace_id, id)
+        .await?;
+    Ok((StatusCode::OK, Json(todo)))
+}
+
+/// Empties the trash for good, so only owners may do it.
+#[utoipa::path(
+    delete,
+    path = "/workspaces/{workspace_id}/trash",
+    tag = "todos",
+    params(
+        ("workspace_id" = i32, Path, description = "id of the workspace"),
+    ),
+    responses(
+        (status = 204, description = "Trash emptied"),
+        OwnerRequired,
+        NotMember,
+    )
+)]
+pub async fn purge_trash<T: TodoRepository, W: WorkspaceRepository>(
+    user: AuthUser,
+    Path(workspace_id): Path<i32>,
+    Extension(repository): Extension<Arc<T>>,
+    Extension(workspace_repository): Extension<Arc<W>>,
+) -> Result<StatusCode, ApiError> {
+    authorize(&*workspace_repository, workspace_id, user, Role::Owner).await?;
+    repository.acting_as(user.id).purge(workspace_id).await?;
+    Ok(StatusCode::NO_CONTENT)
 }
@@ -0,0 +1,184 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Extension, Path},
+    response::IntoResponse,
+    Json,
+};
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::Validate;
+
+use crate::{
+    auth::AuthUser,
+    errors::ApiError,
+    openapi::{InvalidPayload, NotMember, OwnerRequired},
+    repositories::{
+        user::UserRepository,
+        workspace::{Role, WorkspaceRepository},
+    },
+};
+
+use super::ValidatedJson;
+
+/// Checks that `user` may act as `required` in the workspace and returns their role.
+///
+/// Non-members get a 404 so that they cannot probe which workspaces exist.
+pub async fn authorize<W: WorkspaceRepository>(
+    repository: &W,
+    workspace_id: i32,
+    user: AuthUser,
+    required: Role,
+) -> Result<Role, ApiError> {
+    let role = repository
+        .role_of(workspace_id, user.id)
+        .await?
+        .ok_or(ApiError::NotFound(workspace_id))?;
+    if role < required {
+        return Err(ApiError::Forbidden(format!(
+            "{:?} role required in workspace {}",
+            required, workspace_id
+        )));
+    }
+    Ok(role)
+}
+
+#[utoipa::path(
+    post,
+    path = "/workspaces",
+    tag = "workspaces",
+    request_body = CreateWorkspace,
+    responses(
+        (status = 201, description = "Created, with the caller as owner", body = Workspace),
+        InvalidPayload,
+    )
+)]
+pub async fn create_workspace<T: WorkspaceRepository>(
+    user: AuthUser,
+    ValidatedJson(payload): ValidatedJson<CreateWorkspace>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let workspace = repository.create(user.id, payload.name).await?;
+
+    Ok((StatusCode::CREATED, Json(workspace)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/workspaces",
+    tag = "workspaces",
+    responses(
+        (status = 200, description = "Workspaces of the caller", body = [Workspace]),
+    )
+)]
+pub async fn all_workspace<T: WorkspaceRepository>(
+    user: AuthUser,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let workspaces = repository.all(user.id).await?;
+    Ok((StatusCode::OK, Json(workspaces)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/workspaces/{workspace_id}/members",
+    tag = "workspaces",
+    params(
+        ("workspace_id" = i32, Path, description = "id of the workspace"),
+    ),
+    responses(
+        (status = 200, description = "Members", body = [Member]),
+        NotMember,
+    )
+)]
+pub async fn all_member<T: WorkspaceRepository>(
+    user: AuthUser,
+    Path(workspace_id): Path<i32>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, ApiError> {
+    authorize(&*repository, workspace_id, user, Role::Viewer).await?;
+    let members = repository.members(workspace_id).await?;
+    Ok((StatusCode::OK, Json(members)))
+}
+
+#[utoipa::path(
+    put,
+    path = "/workspaces/{workspace_id}/members/{user_id}",
+    tag = "workspaces",
+    params(
+        ("workspace_id" = i32, Path, description = "id of the workspace"),
+        ("user_id" = i32, Path, description = "id of the user"),
+    ),
+    request_body = PutMember,
+    responses(
+        (status = 200, description = "Member added or role changed", body = Member),
+        OwnerRequired,
+        (status = 404, description = "Workspace or user not found", body = Problem, content_type = "application/problem+json"),
+        InvalidPayload,
+    )
+)]
+pub async fn put_member<T: WorkspaceRepository, U: UserRepository>(
+    user: AuthUser,
+    Path((workspace_id, user_id)): Path<(i32, i32)>,
+    ValidatedJson(payload): ValidatedJson<PutMember>,
+    Extension(repository): Extension<Arc<T>>,
+    Extension(user_repository): Extension<Arc<U>>,
+) -> Result<impl IntoResponse, ApiError> {
+    authorize(&*repository, workspace_id, user, Role::Owner).await?;
+    ensure_not_self(user, user_id)?;
+    user_repository.find(user_id).await?;
+    let member = repository
+        .set_member(workspace_id, user_id, payload.role)
+        .await?;
+    Ok((StatusCode::OK, Json(member)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/workspaces/{workspace_id}/members/{user_id}",
+    tag = "workspaces",
+    params(
+        ("workspace_id" = i32, Path, description = "id of the workspace"),
+        ("user_id" = i32, Path, description = "id of the user"),
+    ),
+    responses(
+        (status = 204, description = "Member removed"),
+        OwnerRequired,
+        NotMember,
+        InvalidPayload,
+    )
+)]
+pub async fn delete_member<T: WorkspaceRepository>(
+    user: AuthUser,
+    Path((workspace_id, user_id)): Path<(i32, i32)>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<StatusCode, ApiError> {
+    authorize(&*repository, workspace_id, user, Role::Owner).await?;
+    ensure_not_self(user, user_id)?;
+    repository.remove_member(workspace_id, user_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Owners can not demote or remove themselves, so a workspace always keeps one.
+fn ensure_not_self(user: AuthUser, user_id: i32) -> Result<(), ApiError> {
+    if user.id == user_id {
+        return Err(ApiError::Validation(
+            "user_id: Can not change your own membership".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Validate, ToSchema)]
+pub struct CreateWorkspace {
+    #[validate(length(min = 1, message = "Can not be empty"))]
+    #[validate(length(max = 100, message = "Over text length"))]
+    #[schema(min_length = 1, max_length = 100)]
+    name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Validate, ToSchema)]
+pub struct PutMember {
+    role: Role,
+}
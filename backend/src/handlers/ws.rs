@@ -0,0 +1,309 @@
+use super::{validate, workspace::authorize};
+use crate::{
+    auth::{AuthUser, StreamUser},
+    errors::{ApiError, Problem},
+    events::{Change, EventBus},
+    openapi::NotMember,
+    repositories::{
+        todo::{CreateTodo, TodoEntity, TodoRepository, UpdateTodo},
+        unit_of_work::{Transactional, UnitOfWork},
+        workspace::{Role, WorkspaceRepository},
+    },
+};
+use axum::{
+    extract::{
+        ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        Extension, Path,
+    },
+    response::IntoResponse,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{collections::HashSet, sync::Arc};
+use tokio::sync::broadcast::error::RecvError;
+
+/// The close code telling the client to reconnect, possibly to another replica.
+const GOING_AWAY: u16 = 1001;
+/// The close code telling the client that it is no member of the workspace anymore.
+const POLICY_VIOLATION: u16 = 1008;
+
+/// A frame sent by the client. Every frame may carry a `request_id`, which is echoed in
+/// the `ack` or `error` answering it.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Subscribe {
+        #[serde(default)]
+        todos: Vec<i32>,
+        #[serde(default)]
+        labels: Vec<i32>,
+    },
+    Unsubscribe {
+        #[serde(default)]
+        todos: Vec<i32>,
+        #[serde(default)]
+        labels: Vec<i32>,
+    },
+    CreateTodo {
+        todo: CreateTodo,
+    },
+    UpdateTodo {
+        todo_id: i32,
+        todo: UpdateTodo,
+        /// versions the client expects the todo to be at, like `If-Match`
+        if_match: Option<Vec<i32>>,
+    },
+    DeleteTodo {
+        todo_id: i32,
+        if_match: Option<Vec<i32>>,
+    },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    Ack {
+        request_id: Option<String>,
+        /// the todo after a create or update
+        #[serde(skip_serializing_if = "Option::is_none")]
+        todo: Option<TodoEntity>,
+    },
+    /// The error the REST API would have answered with.
+    Error {
+        request_id: Option<String>,
+        error: Problem,
+    },
+    /// A change to a subscribed todo or label, named like the server-sent events.
+    Change { event: &'static str, data: Value },
+    /// The socket fell behind and dropped changes, so the client has to refetch.
+    Reset,
+}
+
+/// Todos and labels a socket is subscribed to. Subscribing to a label covers every todo
+/// carrying it.
+#[derive(Debug, Default)]
+struct Subscriptions {
+    todos: HashSet<i32>,
+    labels: HashSet<i32>,
+}
+
+impl Subscriptions {
+    /// Label subscribers also hear about todos leaving the label.
+    fn matches(&self, change: &Change) -> bool {
+        let carries = |todo: &TodoEntity| {
+            todo.labels
+                .iter()
+                .any(|label| self.labels.contains(&label.id))
+        };
+        let carried = |labels: &[i32]| labels.iter().any(|id| self.labels.contains(id));
+        match change {
+            Change::TodoCreated(todo) => self.todos.contains(&todo.id) || carries(todo),
+            Change::TodoUpdated {
+                todo,
+                previous_labels,
+            } => self.todos.contains(&todo.id) || carries(todo) || carried(previous_labels),
+            Change::TodoDeleted { id, labels } => self.todos.contains(id) || carried(labels),
+            Change::LabelCreated(label) | Change::LabelUpdated(label) => {
+                self.labels.contains(&label.id)
+            }
+            Change::LabelDeleted(id) => self.labels.contains(id),
+        }
+    }
+}
+
+struct Session<T, W> {
+    user: AuthUser,
+    workspace_id: i32,
+    repository: Arc<T>,
+    workspace_repository: Arc<W>,
+    event_bus: Arc<EventBus>,
+    subscriptions: Subscriptions,
+}
+
+/// Upgrades to a socket on which the client subscribes to todos and labels of the
+/// workspace and sends todo mutations, each answered by an `ack` or an `error`. The server
+/// closes it with `1001 Going Away` when it shuts down, and with `1008 Policy Violation`
+/// once the user is no member of the workspace anymore.
+/// Browsers, whose `WebSocket` can not send the `Authorization` header, pass a token of
+/// `/auth/stream-token` as `access_token` instead.
+#[utoipa::path(
+    get,
+    path = "/workspaces/{workspace_id}/ws",
+    tag = "events",
+    params(
+        ("workspace_id" = i32, Path, description = "id of the workspace"),
+        ("access_token" = Option<String>, Query, description = "token of `/auth/stream-token`, instead of the `Authorization` header"),
+    ),
+    responses(
+        (status = 101, description = "WebSocket session"),
+        NotMember,
+    )
+)]
+pub async fn workspace_socket<T: Transactional, W: WorkspaceRepository>(
+    StreamUser(user): StreamUser,
+    Path(workspace_id): Path<i32>,
+    upgrade: WebSocketUpgrade,
+    Extension(repository): Extension<Arc<T>>,
+    Extension(workspace_repository): Extension<Arc<W>>,
+    Extension(event_bus): Extension<Arc<EventBus>>,
+) -> Result<impl IntoResponse, ApiError> {
+    authorize(&*workspace_repository, workspace_id, user, Role::Viewer).await?;
+    let session = Session {
+        user,
+        workspace_id,
+        repository,
+        workspace_repository,
+        event_bus,
+        subscriptions: Subscriptions::default(),
+    };
+    Ok(upgrade.on_upgrade(|socket| session.run(socket)))
+}
+
+impl<T: Transactional, W: WorkspaceRepository> Session<T, W> {
+    async fn run(mut self, mut socket: WebSocket) {
+        let mut receiver = self.event_bus.subscribe(None).receiver;
+        loop {
+            // changes already published go out before the next frame is handled, so a
+            // client sees the effects of its earlier mutations first
+            let reply = tokio::select! {
+                biased;
+                _ = self.event_bus.closed() => {
+                    let close = CloseFrame {
+                        code: GOING_AWAY,
+                        reason: "server shutting down".into(),
+                    };
+                    socket.send(Message::Close(Some(close))).await.ok();
+                    break;
+                }
+                event = receiver.recv() => match event {
+                    Ok(event) if event.workspace_id == self.workspace_id
+                        && self.subscriptions.matches(&event.change) =>
+                    {
+                        // removed members stop hearing about the workspace
+                        if self.authorize_as(Role::Viewer).await.is_err() {
+                            let close = CloseFrame {
+                                code: POLICY_VIOLATION,
+                                reason: "not a member of the workspace".into(),
+                            };
+                            socket.send(Message::Close(Some(close))).await.ok();
+                            break;
+                        }
+                        ServerMessage::Change {
+                            event: event.change.name(),
+                            data: event.change.data(),
+                        }
+                    }
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(_)) => ServerMessage::Reset,
+                    Err(RecvError::Closed) => break,
+                },
+                message = socket.recv() => match message {
+                    Some(Ok(Message::Text(text))) => self.handle(&text).await,
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    // pings are answered by the socket itself
+                    Some(Ok(_)) => continue,
+                },
+            };
+            let text = serde_json::to_string(&reply).expect("failed serialize message");
+            if socket.send(Message::Text(text)).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    async fn handle(&mut self, text: &str) -> ServerMessage {
+        let value: Value = match serde_json::from_str(text) {
+            Ok(value) => value,
+            Err(err) => {
+                return ServerMessage::Error {
+                    request_id: None,
+                    error: ApiError::InvalidJson(err.to_string()).problem(),
+                }
+            }
+        };
+        let request_id = value
+            .get("request_id")
+            .and_then(Value::as_str)
+            .map(str::to_string);
+        let result = match serde_json::from_value(value) {
+            Ok(message) => self.apply(message).await,
+            Err(err) => Err(ApiError::InvalidJson(err.to_string())),
+        };
+        match result {
+            Ok(todo) => ServerMessage::Ack { request_id, todo },
+            Err(err) => {
+                if let ApiError::Unexpected(message) = &err {
+                    tracing::error!("{}", message);
+                }
+                ServerMessage::Error {
+                    request_id,
+                    error: err.problem(),
+                }
+            }
+        }
+    }
+
+    async fn apply(&mut self, message: ClientMessage) -> Result<Option<TodoEntity>, ApiError> {
+        let workspace_id = self.workspace_id;
+        match message {
+            ClientMessage::Subscribe { todos, labels } => {
+                self.subscriptions.todos.extend(todos);
+                self.subscriptions.labels.extend(labels);
+                Ok(None)
+            }
+            ClientMessage::Unsubscribe { todos, labels } => {
+                self.subscriptions.todos.retain(|id| !todos.contains(id));
+                self.subscriptions.labels.retain(|id| !labels.contains(id));
+                Ok(None)
+            }
+            ClientMessage::CreateTodo { todo: payload } => {
+                validate(&payload)?;
+                self.authorize_as(Role::Editor).await?;
+                let uow = self.begin().await?;
+                let todo = uow.todos().create(workspace_id, payload).await?;
+                uow.commit().await?;
+                Ok(Some(todo))
+            }
+            ClientMessage::UpdateTodo {
+                todo_id,
+                todo: payload,
+                if_match,
+            } => {
+                validate(&payload)?;
+                self.authorize_as(Role::Editor).await?;
+                let uow = self.begin().await?;
+                let todo = uow
+                    .todos()
+                    .update(workspace_id, todo_id, payload, if_match)
+                    .await?;
+                uow.commit().await?;
+                Ok(Some(todo))
+            }
+            ClientMessage::DeleteTodo { todo_id, if_match } => {
+                self.authorize_as(Role::Editor).await?;
+                self.repository
+                    .acting_as(self.user.id)
+                    .delete(workspace_id, todo_id, if_match)
+                    .await?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Checked on every mutation and change sent, since the role may change while the
+    /// socket is open.
+    async fn authorize_as(&self, required: Role) -> Result<Role, ApiError> {
+        authorize(
+            &*self.workspace_repository,
+            self.workspace_id,
+            self.user,
+            required,
+        )
+        .await
+    }
+
+    async fn begin(&self) -> Result<T::UnitOfWork, ApiError> {
+        let uow = self.repository.acting_as(self.user.id).begin().await?;
+        Ok(uow)
+    }
+}
@@ -1,79 +1,406 @@
+mod auth;
+mod config;
+mod errors;
+mod events;
 mod handlers;
+mod metrics;
+mod openapi;
 mod repositories;
+mod trace;
+use auth::AuthKeys;
 use axum::{
+    error_handling::HandleErrorLayer,
     extract::Extension,
-    routing::{delete, get, post},
-    Router,
+    routing::{get, post, put},
+    BoxError, Router,
 };
+use chrono::Utc;
+use config::{Config, LogFormat};
 use dotenv::dotenv;
+use errors::ApiError;
+use events::{listen_for_changes, relay_changes, EventBus, RelayCursor, REPLAY_CAPACITY};
+use futures::Future;
 use handlers::{
-    label::{all_label, create_label, delete_label},
-    todo::{all_todo, create_todo, delete_todo, find_todo, update_todo},
+    audit::{all_audit_event, todo_history},
+    auth::{login, signup, stream_token},
+    event::workspace_events,
+    health::{healthz, readyz, version},
+    label::{all_label, create_label, delete_label, find_label, update_label},
+    todo::{
+        all_child_todo, all_todo, all_trash, bulk_todo, create_todo, delete_todo, find_todo,
+        move_todo, purge_trash, restore_todo, search_todo, update_todo,
+    },
+    workspace::{all_member, all_workspace, create_workspace, delete_member, put_member},
+    ws::workspace_socket,
 };
-use hyper::header::CONTENT_TYPE;
+use hyper::server::conn::AddrIncoming;
+use metrics::{Metrics, MetricsLayer};
 use repositories::todo::TodoRepositoryForDb;
-use repositories::{label::LabelRepository, todo::TodoRepository};
-use sqlx::PgPool;
-use std::{env, net::SocketAddr, sync::Arc};
-use tower_http::cors::Any;
-use tower_http::cors::CorsLayer;
-use tower_http::cors::Origin;
+use repositories::{
+    audit::{AuditRepository, AuditRepositoryForDb},
+    health::{HealthRepository, HealthRepositoryForDb},
+    label::LabelRepository,
+    measured::Measured,
+    todo::TodoRepository,
+    unit_of_work::{Transactional, TransactionalUser},
+    user::UserRepositoryForDb,
+    workspace::{WorkspaceRepository, WorkspaceRepositoryForDb},
+};
+use sqlx::{postgres::PgPoolOptions, PgPool};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::oneshot;
+use tower::ServiceBuilder;
+use trace::TraceLayer;
+use tracing_subscriber::EnvFilter;
 
 use crate::repositories::label::LabelRepositoryForDb;
 
 #[tokio::main]
 async fn main() {
-    // logging
-    let log_level = env::var("RUST_LOG").unwrap_or("info".to_string());
-    env::set_var("RUST_LOG", log_level);
-    tracing_subscriber::fmt::init();
     dotenv().ok();
+    let config = Config::load().unwrap_or_else(|err| err.exit());
+
+    // logging
+    let subscriber = tracing_subscriber::fmt().with_env_filter(EnvFilter::new(&config.log.level));
+    match config.log.format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber.json().flatten_event(true).init(),
+    }
 
     // api
-    let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
     tracing::debug!("start connect database...");
-    let pool = PgPool::connect(database_url)
+    let database = &config.database;
+    let pool = PgPoolOptions::new()
+        .max_connections(database.max_connections)
+        .min_connections(database.min_connections)
+        .connect_timeout(Duration::from_secs(database.connect_timeout_secs))
+        .idle_timeout(
+            (database.idle_timeout_secs > 0)
+                .then(|| Duration::from_secs(database.idle_timeout_secs)),
+        )
+        .connect(&database.url)
         .await
-        .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database_url));
+        .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database.url));
+    if config.features.trash_purge {
+        spawn_trash_purge(
+            TodoRepositoryForDb::new(pool.clone()),
+            chrono::Duration::days(config.trash.retention_days),
+        );
+    }
+    let event_bus = Arc::new(EventBus::new(REPLAY_CAPACITY));
+    if config.features.realtime {
+        spawn_change_relay(
+            pool.clone(),
+            AuditRepositoryForDb::new(pool.clone()),
+            event_bus.clone(),
+        );
+    }
     let app = create_app(
         TodoRepositoryForDb::new(pool.clone()),
         LabelRepositoryForDb::new(pool.clone()),
+        UserRepositoryForDb::new(pool.clone()),
+        WorkspaceRepositoryForDb::new(pool.clone()),
+        AuditRepositoryForDb::new(pool.clone()),
+        HealthRepositoryForDb::new(pool.clone()),
+        event_bus.clone(),
+        &config,
     );
-    let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
+    let addr = config.server.listen;
     tracing::debug!("listening on {}", addr);
 
-    axum::Server::bind(&addr)
+    let drained = serve_until(
+        axum::Server::bind(&addr),
+        app,
+        event_bus,
+        config.shutdown_timeout(),
+        shutdown_signal(),
+    )
+    .await
+    .unwrap();
+    close_pool(pool, drained, config.shutdown_timeout()).await;
+    tracing::info!("shut down");
+}
+
+/// Resolves on SIGINT or SIGTERM.
+async fn shutdown_signal() {
+    let interrupt = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("fail listen for SIGINT");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("fail listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = futures::future::pending::<()>();
+    tokio::select! {
+        _ = interrupt => {},
+        _ = terminate => {},
+    }
+}
+
+/// Serves until `signal` resolves, then stops accepting connections, closes the event bus
+/// so that event streams and sockets end, and waits up to `drain_timeout` for the
+/// requests in flight. Returns whether they all finished; the ones left behind keep running
+/// until the process exits.
+async fn serve_until(
+    server: hyper::server::Builder<AddrIncoming>,
+    app: Router,
+    event_bus: Arc<EventBus>,
+    drain_timeout: Duration,
+    signal: impl Future<Output = ()>,
+) -> hyper::Result<bool> {
+    let (draining, drain_started) = oneshot::channel();
+    let server = server
         .serve(app.into_make_service())
-        .await
-        .unwrap();
+        .with_graceful_shutdown(async move {
+            signal.await;
+            tracing::info!("shutting down, draining connections");
+            event_bus.close();
+            draining.send(()).ok();
+        });
+    let deadline = async {
+        match drain_started.await {
+            Ok(()) => tokio::time::sleep(drain_timeout).await,
+            // the server failed before any signal
+            Err(_) => futures::future::pending().await,
+        }
+    };
+    tokio::select! {
+        result = server => result.map(|()| true),
+        _ = deadline => {
+            tracing::warn!(
+                "requests still in flight after {:?}, exiting without them",
+                drain_timeout
+            );
+            Ok(false)
+        }
+    }
+}
+
+/// Closes the pool once the relay let go of its connection, see `spawn_change_relay`.
+/// Closing waits for every connection checked out, so it is skipped when requests were
+/// left behind, and bounded by `timeout` otherwise.
+async fn close_pool(pool: PgPool, drained: bool, timeout: Duration) {
+    if !drained {
+        tracing::warn!("exiting without closing the database pool");
+        return;
+    }
+    if tokio::time::timeout(timeout, pool.close()).await.is_err() {
+        tracing::warn!(
+            "database pool not closed after {:?}, exiting anyway",
+            timeout
+        );
+    }
+}
+
+/// Every hour, permanently deletes todos that have been in the trash longer than `retention`.
+fn spawn_trash_purge<T: TodoRepository>(repository: T, retention: chrono::Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+        loop {
+            interval.tick().await;
+            match repository.purge_expired(Utc::now() - retention).await {
+                Ok(purged) => tracing::debug!("purged {} todos from the trash", purged),
+                Err(err) => tracing::error!("fail purge trash: {:?}", err),
+            }
+        }
+    });
 }
 
-fn create_app<Todo: TodoRepository, Label: LabelRepository>(
+/// Feeds the changes written by every replica to the subscribers of this one, listening
+/// again, and catching up, after a failure, until the event bus is closed.
+fn spawn_change_relay<A: AuditRepository>(
+    pool: PgPool,
+    audit_repository: A,
+    event_bus: Arc<EventBus>,
+) {
+    tokio::spawn(async move {
+        let mut cursor = RelayCursor::default();
+        loop {
+            let relay = async {
+                let listener = listen_for_changes(&pool).await?;
+                relay_changes(listener, &audit_repository, &event_bus, &mut cursor).await
+            };
+            tokio::select! {
+                // drops the listener, releasing its connection to the pool
+                _ = event_bus.closed() => return,
+                result = relay => match result {
+                    Ok(()) => tracing::warn!("lost connection listening for changes"),
+                    Err(err) => tracing::error!("fail relay changes: {:?}", err),
+                },
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    });
+}
+
+#[allow(clippy::too_many_arguments)]
+fn create_app<
+    Todo: Transactional,
+    Label: LabelRepository,
+    User: TransactionalUser,
+    Workspace: WorkspaceRepository,
+    Audit: AuditRepository,
+    Health: HealthRepository,
+>(
     todo_repository: Todo,
     label_repository: Label,
+    user_repository: User,
+    workspace_repository: Workspace,
+    audit_repository: Audit,
+    health_repository: Health,
+    event_bus: Arc<EventBus>,
+    config: &Config,
+) -> Router {
+    let metrics = Arc::new(Metrics::new());
+    let mut router =
+        routes::<Measured<Todo>, Measured<Label>, User, Workspace, Audit, Health>(config);
+    if config.features.metrics {
+        router = router.route("/metrics", get(metrics::metrics::<Health>));
+    }
+    router
+        .route_layer(
+            ServiceBuilder::new()
+                .layer(MetricsLayer::new(metrics.clone()))
+                .layer(HandleErrorLayer::new(handle_timeout))
+                .timeout(config.request_timeout()),
+        )
+        .layer(Extension(Arc::new(Measured::new(
+            todo_repository,
+            metrics.clone(),
+        ))))
+        .layer(Extension(Arc::new(Measured::new(
+            label_repository,
+            metrics.clone(),
+        ))))
+        .layer(Extension(Arc::new(user_repository)))
+        .layer(Extension(Arc::new(workspace_repository)))
+        .layer(Extension(Arc::new(audit_repository)))
+        .layer(Extension(Arc::new(health_repository)))
+        .layer(Extension(Arc::new(AuthKeys::new(
+            config.auth.jwt_secret.as_bytes(),
+        ))))
+        .layer(Extension(event_bus))
+        .layer(Extension(metrics))
+        .layer(config.cors.layer().expect("invalid CORS config"))
+        .layer(TraceLayer::new())
+}
+
+/// The routes of the enabled features, reading their repositories from the extensions.
+fn routes<
+    Todo: Transactional,
+    Label: LabelRepository,
+    User: TransactionalUser,
+    Workspace: WorkspaceRepository,
+    Audit: AuditRepository,
+    Health: HealthRepository,
+>(
+    config: &Config,
 ) -> Router {
-    Router::new()
+    let mut router = Router::new()
         .route("/", get(root))
-        .route("/todos", post(create_todo::<Todo>).get(all_todo::<Todo>))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz::<Health>))
+        .route("/version", get(version))
+        .route("/auth/signup", post(signup::<User>))
+        .route("/auth/login", post(login::<User>))
+        .route("/auth/stream-token", post(stream_token))
+        .route(
+            "/workspaces",
+            post(create_workspace::<Workspace>).get(all_workspace::<Workspace>),
+        )
+        .route(
+            "/workspaces/:workspace_id/members",
+            get(all_member::<Workspace>),
+        )
+        .route(
+            "/workspaces/:workspace_id/members/:user_id",
+            put(put_member::<Workspace, User>).delete(delete_member::<Workspace>),
+        )
+        .route(
+            "/workspaces/:workspace_id/todos",
+            post(create_todo::<Todo, Workspace>).get(all_todo::<Todo, Workspace>),
+        )
+        .route(
+            "/workspaces/:workspace_id/todos/bulk",
+            post(bulk_todo::<Todo, Workspace>),
+        )
+        .route(
+            "/workspaces/:workspace_id/todos/search",
+            get(search_todo::<Todo, Workspace>),
+        )
+        .route(
+            "/workspaces/:workspace_id/todos/:id",
+            get(find_todo::<Todo, Workspace>)
+                .delete(delete_todo::<Todo, Workspace>)
+                .patch(update_todo::<Todo, Workspace>),
+        )
+        .route(
+            "/workspaces/:workspace_id/todos/:id/children",
+            get(all_child_todo::<Todo, Workspace>),
+        )
+        .route(
+            "/workspaces/:workspace_id/todos/:id/move",
+            post(move_todo::<Todo, Workspace>),
+        )
+        .route(
+            "/workspaces/:workspace_id/todos/:id/history",
+            get(todo_history::<Audit, Workspace>),
+        )
+        .route(
+            "/workspaces/:workspace_id/todos/:id/restore",
+            post(restore_todo::<Todo, Workspace>),
+        )
         .route(
-            "/todos/:id",
-            get(find_todo::<Todo>)
-                .delete(delete_todo::<Todo>)
-                .patch(update_todo::<Todo>),
+            "/workspaces/:workspace_id/trash",
+            get(all_trash::<Todo, Workspace>).delete(purge_trash::<Todo, Workspace>),
         )
         .route(
-            "/labels",
-            post(create_label::<Label>).get(all_label::<Label>),
+            "/workspaces/:workspace_id/audit",
+            get(all_audit_event::<Audit, Workspace>),
         )
-        .route("/labels/:id", delete(delete_label::<Label>))
-        .layer(Extension(Arc::new(todo_repository)))
-        .layer(Extension(Arc::new(label_repository)))
-        .layer(
-            CorsLayer::new()
-                .allow_origin(Origin::exact("http://localhost:3001".parse().unwrap()))
-                .allow_methods(Any)
-                .allow_headers(vec![CONTENT_TYPE]),
+        .route(
+            "/workspaces/:workspace_id/labels",
+            post(create_label::<Label, Workspace>).get(all_label::<Label, Workspace>),
         )
+        .route(
+            "/workspaces/:workspace_id/labels/:id",
+            get(find_label::<Label, Workspace>)
+                .delete(delete_label::<Label, Workspace>)
+                .patch(update_label::<Label, Workspace>),
+        );
+    if config.features.realtime {
+        router = router
+            .route(
+                "/workspaces/:workspace_id/events",
+                get(workspace_events::<Workspace>),
+            )
+            .route(
+                "/workspaces/:workspace_id/ws",
+                get(workspace_socket::<Todo, Workspace>),
+            );
+    }
+    if config.features.docs {
+        router = router
+            .route("/openapi.json", get(openapi::openapi_json))
+            .route("/docs", get(openapi::docs))
+            .route("/docs/swagger-ui.css", get(openapi::docs_css))
+            .route("/docs/swagger-ui-bundle.js", get(openapi::docs_js));
+    }
+    router
+}
+
+async fn handle_timeout(err: BoxError) -> ApiError {
+    if err.is::<tower::timeout::error::Elapsed>() {
+        ApiError::Timeout
+    } else {
+        ApiError::Unexpected(err.to_string())
+    }
 }
 
 async fn root() -> &'static str {
@@ -83,20 +410,88 @@ async fn root() -> &'static str {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::errors::{Problem, PROBLEM_JSON};
+    use crate::handlers::auth::{AuthResponse, StreamToken};
     use crate::repositories::{
+        audit::{test_utils::AuditRepositoryForMemory, AuditAction, AuditEvent},
+        health::{
+            test_utils::{latest_version, HealthRepositoryForMemory},
+            DatabaseStats, PoolStats, SchemaStatus,
+        },
         label::{test_utils::LabelRepositoryForMemory, Label},
-        todo::{test_utils::TodoRepositoryForMemory, CreateTodo, TodoEntity},
+        todo::{
+            test_utils::TodoRepositoryForMemory, BulkItemResult, BulkStatus, CreateTodo, Priority,
+            TodoEntity, TodoPage, TodoQuery, TodoRepository, TodoSearchHit, UpdateTodo,
+        },
+        user::{test_utils::UserRepositoryForMemory, UserRepository},
+        workspace::{test_utils::WorkspaceRepositoryForMemory, Member, Role, Workspace},
     };
     use axum::{body::Body, http::header, http::Method, http::Request, response::Response};
-    use hyper::StatusCode;
+    use hyper::{body::HttpBody, StatusCode};
     use serde::Deserialize;
     use tower::ServiceExt;
+    use utoipa::OpenApi;
+
+    const TEST_SECRET: &[u8] = b"test secret";
+    /// the user every request built by the helpers below is signed in as
+    const TEST_USER_ID: i32 = 1;
+    /// the workspace owned by the test user
+    const TEST_WORKSPACE_ID: i32 = 1;
+
+    fn create_test_app(
+        todo_repository: TodoRepositoryForMemory,
+        label_repository: LabelRepositoryForMemory,
+    ) -> Router {
+        create_test_app_with_event_bus(
+            todo_repository,
+            label_repository,
+            Arc::new(EventBus::new(REPLAY_CAPACITY)),
+        )
+    }
+
+    fn create_test_app_with_event_bus(
+        todo_repository: TodoRepositoryForMemory,
+        label_repository: LabelRepositoryForMemory,
+        event_bus: Arc<EventBus>,
+    ) -> Router {
+        // label changes reach the todos carrying them
+        let todo_repository = todo_repository.with_label_repository(label_repository);
+        let label_repository = todo_repository.label_repository();
+        let audit_repository = AuditRepositoryForMemory::new().with_event_bus(event_bus.clone());
+        // signing up creates workspaces the app serves
+        let user_repository = UserRepositoryForMemory::new()
+            .with_workspace_repository(WorkspaceRepositoryForMemory::with_owner(TEST_USER_ID));
+        create_app(
+            todo_repository.with_audit_repository(audit_repository.clone()),
+            label_repository.with_audit_repository(audit_repository.clone()),
+            user_repository.clone(),
+            user_repository.workspace_repository(),
+            audit_repository,
+            HealthRepositoryForMemory::new(),
+            event_bus,
+            &test_config(),
+        )
+    }
+
+    fn test_config() -> Config {
+        let mut config = Config::default();
+        config.auth.jwt_secret = String::from_utf8(TEST_SECRET.to_vec()).unwrap();
+        config
+    }
+
+    fn bearer(user_id: i32) -> String {
+        let token = AuthKeys::new(TEST_SECRET)
+            .issue(user_id)
+            .expect("failed issue token");
+        format!("Bearer {}", token)
+    }
 
     fn build_req_with_json(path: &str, method: Method, json_body: String) -> Request<Body> {
         Request::builder()
             .uri(path)
             .method(method)
             .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .header(header::AUTHORIZATION, bearer(TEST_USER_ID))
             .body(Body::from(json_body))
             .unwrap()
     }
@@ -105,10 +500,21 @@ mod tests {
         Request::builder()
             .uri(path)
             .method(method)
+            .header(header::AUTHORIZATION, bearer(TEST_USER_ID))
             .body(Body::empty())
             .unwrap()
     }
 
+    /// Reads the next chunk of a streamed body, such as one server-sent event.
+    async fn next_chunk(res: &mut Response) -> String {
+        let chunk = tokio::time::timeout(Duration::from_secs(5), res.body_mut().data())
+            .await
+            .expect("no chunk in time")
+            .expect("body ended")
+            .unwrap();
+        String::from_utf8(chunk.to_vec()).unwrap()
+    }
+
     async fn res_to_data<T: for<'a> Deserialize<'a>>(res: Response) -> T {
         let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
         let body = String::from_utf8(bytes.to_vec()).unwrap();
@@ -122,7 +528,7 @@ mod tests {
         let req = Request::builder().uri("/").body(Body::empty()).unwrap();
         let todo_repository = TodoRepositoryForMemory::new();
         let label_repository = LabelRepositoryForMemory::new();
-        let res = create_app(todo_repository, label_repository)
+        let res = create_test_app(todo_repository, label_repository)
             .oneshot(req)
             .await
             .unwrap();
@@ -132,17 +538,324 @@ mod tests {
         assert_eq!(body, "Hello, World!");
     }
 
+    #[tokio::test]
+    async fn should_report_health_readiness_and_version() {
+        let health_repository = HealthRepositoryForMemory::new();
+        let event_bus = Arc::new(EventBus::new(REPLAY_CAPACITY));
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            WorkspaceRepositoryForMemory::with_owner(TEST_USER_ID),
+            AuditRepositoryForMemory::new(),
+            health_repository.clone(),
+            event_bus.clone(),
+            &test_config(),
+        );
+        // probes need no token
+        let probe = |path: &str| {
+            let req = Request::builder().uri(path).body(Body::empty()).unwrap();
+            app.clone().oneshot(req)
+        };
+        let readiness = |res: Response| async move {
+            let status = res.status();
+            let body: serde_json::Value = res_to_data(res).await;
+            (status, body)
+        };
+
+        let res = probe("/healthz").await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+
+        let res = probe("/version").await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let body: serde_json::Value = res_to_data(res).await;
+        assert_eq!(env!("CARGO_PKG_VERSION"), body["version"]);
+        assert_eq!(serde_json::json!(latest_version()), body["schema_version"]);
+        assert!(body["git_sha"].is_string());
+        assert!(body["build_time"].is_string());
+
+        let (status, body) = readiness(probe("/readyz").await.unwrap()).await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!("ready", body["status"]);
+        assert_eq!(
+            serde_json::json!(latest_version()),
+            body["schema"]["version"]
+        );
+
+        health_repository.set(Some(SchemaStatus {
+            version: Some(1),
+            pending: vec![2],
+        }));
+        let (status, body) = readiness(probe("/readyz").await.unwrap()).await;
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, status);
+        assert_eq!(serde_json::json!([2]), body["schema"]["pending"]);
+
+        health_repository.set(None);
+        let (status, body) = readiness(probe("/readyz").await.unwrap()).await;
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, status);
+        assert_eq!(false, body["database"]);
+
+        health_repository.set(Some(SchemaStatus {
+            version: latest_version(),
+            pending: vec![],
+        }));
+        event_bus.close();
+        let (status, body) = readiness(probe("/readyz").await.unwrap()).await;
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, status);
+        assert_eq!(true, body["shutting_down"]);
+        // still alive while draining
+        let res = probe("/healthz").await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_assign_and_propagate_request_ids() {
+        let app = create_test_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        );
+        let request_id = |res: &Response| {
+            res.headers()[&trace::X_REQUEST_ID]
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+
+        // assigned, also to requests matching no route
+        let req = build_req_with_empty(Method::GET, "/workspaces/1/todos");
+        let first = request_id(&app.clone().oneshot(req).await.unwrap());
+        let req = build_req_with_empty(Method::GET, "/nowhere");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        let second = request_id(&res);
+        assert!(uuid::Uuid::parse_str(&first).is_ok(), "{}", first);
+        assert_ne!(first, second);
+
+        // propagated
+        let mut req = build_req_with_empty(Method::GET, "/workspaces/1/todos");
+        req.headers_mut()
+            .insert(&trace::X_REQUEST_ID, "upstream-42".parse().unwrap());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!("upstream-42", request_id(&res));
+
+        // replaced when not printable
+        let mut req = build_req_with_empty(Method::GET, "/workspaces/1/todos");
+        req.headers_mut()
+            .insert(&trace::X_REQUEST_ID, "two words".parse().unwrap());
+        let replaced = request_id(&app.oneshot(req).await.unwrap());
+        assert!(uuid::Uuid::parse_str(&replaced).is_ok(), "{}", replaced);
+    }
+
+    #[tokio::test]
+    async fn should_let_other_origins_read_the_etag() {
+        let todo_repository = TodoRepositoryForMemory::new();
+        todo_repository
+            .create(
+                TEST_WORKSPACE_ID,
+                CreateTodo::new("cross origin".to_string(), vec![]),
+            )
+            .await
+            .expect("failed create todo");
+        let app = create_test_app(todo_repository, LabelRepositoryForMemory::new());
+
+        let req = Request::builder()
+            .uri("/workspaces/1/todos/1")
+            .method(Method::OPTIONS)
+            .header(header::ORIGIN, "http://localhost:3001")
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "PATCH")
+            .header(
+                header::ACCESS_CONTROL_REQUEST_HEADERS,
+                "authorization,if-match",
+            )
+            .body(Body::empty())
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        let allowed = res.headers()[header::ACCESS_CONTROL_ALLOW_HEADERS]
+            .to_str()
+            .unwrap();
+        assert!(allowed.contains("if-match"), "{}", allowed);
+
+        let mut req = build_req_with_empty(Method::GET, "/workspaces/1/todos/1");
+        req.headers_mut()
+            .insert(header::ORIGIN, "http://localhost:3001".parse().unwrap());
+        let res = app.oneshot(req).await.unwrap();
+        let exposed = res.headers()[header::ACCESS_CONTROL_EXPOSE_HEADERS]
+            .to_str()
+            .unwrap();
+        assert!(exposed.contains("etag"), "{}", exposed);
+    }
+
+    #[tokio::test]
+    async fn should_expose_metrics() {
+        let health_repository = HealthRepositoryForMemory::new();
+        health_repository.set_pool(PoolStats { size: 5, idle: 3 });
+        health_repository.set_stats(DatabaseStats {
+            open_todos: 4,
+            completed_todos: 2,
+            labels: 7,
+        });
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            WorkspaceRepositoryForMemory::with_owner(TEST_USER_ID),
+            AuditRepositoryForMemory::new(),
+            health_repository.clone(),
+            Arc::new(EventBus::new(REPLAY_CAPACITY)),
+            &test_config(),
+        );
+        let req = build_req_with_json(
+            "/workspaces/1/todos",
+            Method::POST,
+            r#"{"text":"measured", "labels": []}"#.to_string(),
+        );
+        app.clone().oneshot(req).await.unwrap();
+        for _ in 0..2 {
+            let req = build_req_with_empty(Method::GET, "/workspaces/1/todos/1");
+            app.clone().oneshot(req).await.unwrap();
+        }
+        let req = build_req_with_empty(Method::GET, "/workspaces/1/todos/99");
+        app.clone().oneshot(req).await.unwrap();
+
+        let body = scrape(&app).await;
+        for line in [
+            r#"http_requests_total{method="POST",route="/workspaces/:workspace_id/todos",status="201"} 1"#,
+            r#"http_requests_total{method="GET",route="/workspaces/:workspace_id/todos/:id",status="200"} 2"#,
+            r#"http_requests_total{method="GET",route="/workspaces/:workspace_id/todos/:id",status="404"} 1"#,
+            r#"http_request_duration_seconds_count{method="GET",route="/workspaces/:workspace_id/todos/:id",status="200"} 2"#,
+            r#"repository_call_duration_seconds_count{method="create",repository="todo"} 1"#,
+            r#"repository_call_duration_seconds_count{method="find",repository="todo"} 3"#,
+            r#"db_pool_connections{state="active"} 2"#,
+            r#"db_pool_connections{state="idle"} 3"#,
+            r#"todos{state="open"} 4"#,
+            r#"todos{state="completed"} 2"#,
+            "labels 7",
+        ] {
+            assert!(
+                body.lines().any(|l| l == line),
+                "missing [{}] in\n{}",
+                line,
+                body
+            );
+        }
+
+        // the pool is read by every scrape, the table counts only now and then
+        health_repository.set_pool(PoolStats { size: 5, idle: 5 });
+        health_repository.set_stats(DatabaseStats {
+            labels: 8,
+            ..DatabaseStats::default()
+        });
+        let body = scrape(&app).await;
+        for line in [r#"db_pool_connections{state="idle"} 5"#, "labels 7"] {
+            assert!(
+                body.lines().any(|l| l == line),
+                "missing [{}] in\n{}",
+                line,
+                body
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn should_count_timed_out_requests() {
+        let health_repository = HealthRepositoryForMemory::new();
+        let mut config = test_config();
+        config.server.request_timeout_secs = 1;
+        let app = create_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            WorkspaceRepositoryForMemory::with_owner(TEST_USER_ID),
+            AuditRepositoryForMemory::new(),
+            health_repository.clone(),
+            Arc::new(EventBus::new(REPLAY_CAPACITY)),
+            &config,
+        );
+        health_repository.set_delay(Duration::from_secs(2));
+        let req = Request::builder()
+            .uri("/readyz")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, res.status());
+        let problem: Problem = res_to_data(res).await;
+        assert_eq!("timeout", problem.code);
+
+        let body = scrape(&app).await;
+        let line = r#"http_requests_total{method="GET",route="/readyz",status="503"} 1"#;
+        assert!(
+            body.lines().any(|l| l == line),
+            "missing [{}] in\n{}",
+            line,
+            body
+        );
+    }
+
+    async fn scrape(app: &Router) -> String {
+        let req = Request::builder()
+            .uri("/metrics")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert!(res.headers()[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("text/plain"));
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_serve_openapi_document_and_docs() {
+        let app = create_test_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        );
+        // no token needed
+        let req = Request::builder()
+            .uri("/openapi.json")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let spec: serde_json::Value = res_to_data(res).await;
+        assert_eq!(serde_json::json!(openapi::ApiDoc::openapi()), spec);
+
+        let req = Request::builder().uri("/docs").body(Body::empty()).unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = String::from_utf8(bytes.to_vec()).unwrap();
+        assert!(body.contains("/openapi.json"));
+        // the page loads nothing from other origins
+        assert!(!body.contains("https://"));
+
+        for (uri, content_type) in [
+            ("/docs/swagger-ui.css", "text/css; charset=utf-8"),
+            (
+                "/docs/swagger-ui-bundle.js",
+                "application/javascript; charset=utf-8",
+            ),
+        ] {
+            let req = Request::builder().uri(uri).body(Body::empty()).unwrap();
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+            assert_eq!(content_type, res.headers()[header::CONTENT_TYPE]);
+        }
+    }
+
     #[tokio::test]
     async fn should_created_todo() {
         let expected = TodoEntity::new(1, "should_return_created_todo".to_string());
         let todo_repository = TodoRepositoryForMemory::new();
         let label_repository = LabelRepositoryForMemory::new();
         let req = build_req_with_json(
-            "/todos",
+            "/workspaces/1/todos",
             Method::POST,
             r#"{"text":"should_return_created_todo", "labels": []}"#.to_string(),
         );
-        let res = create_app(todo_repository, label_repository)
+        let res = create_test_app(todo_repository, label_repository)
             .oneshot(req)
             .await
             .unwrap();
@@ -157,11 +870,14 @@ mod tests {
         let todo_repository = TodoRepositoryForMemory::new();
         let label_repository = LabelRepositoryForMemory::new();
         todo_repository
-            .create(CreateTodo::new("should_find_todo".to_string(), labels))
+            .create(
+                TEST_USER_ID,
+                CreateTodo::new("should_find_todo".to_string(), labels),
+            )
             .await
             .expect("failed create todo");
-        let req = build_req_with_empty(Method::GET, "/todos/1");
-        let res = create_app(todo_repository, label_repository)
+        let req = build_req_with_empty(Method::GET, "/workspaces/1/todos/1");
+        let res = create_test_app(todo_repository, label_repository)
             .oneshot(req)
             .await
             .unwrap();
@@ -176,30 +892,40 @@ mod tests {
         let todo_repository = TodoRepositoryForMemory::new();
         let label_repository = LabelRepositoryForMemory::new();
         todo_repository
-            .create(CreateTodo::new("should_get_all_todos".to_string(), labels))
+            .create(
+                TEST_USER_ID,
+                CreateTodo::new("should_get_all_todos".to_string(), labels),
+            )
             .await
             .expect("failed create todo");
-        let req = build_req_with_empty(Method::GET, "/todos");
-        let res = create_app(todo_repository, label_repository)
+        let req = build_req_with_empty(Method::GET, "/workspaces/1/todos");
+        let res = create_test_app(todo_repository, label_repository)
             .oneshot(req)
             .await
             .unwrap();
-        let todos: Vec<TodoEntity> = res_to_data(res).await;
-        assert_eq!(vec![expected], todos);
+        let todos: TodoPage = res_to_data(res).await;
+        assert_eq!(vec![expected], todos.items);
+        assert_eq!(None, todos.next_cursor);
     }
 
     #[tokio::test]
     async fn should_update_todo() {
-        let expected = TodoEntity::new(1, "should_update_todo".to_string());
+        let expected = TodoEntity {
+            version: 2,
+            ..TodoEntity::new(1, "should_update_todo".to_string())
+        };
         let labels = vec![];
         let todo_repository = TodoRepositoryForMemory::new();
         let label_repository = LabelRepositoryForMemory::new();
         todo_repository
-            .create(CreateTodo::new("before_update_todo".to_string(), labels))
+            .create(
+                TEST_USER_ID,
+                CreateTodo::new("before_update_todo".to_string(), labels),
+            )
             .await
             .expect("failed create todo");
         let req = build_req_with_json(
-            "/todos/1",
+            "/workspaces/1/todos/1",
             Method::PATCH,
             r#"{
                 "id": 1,
@@ -208,7 +934,7 @@ mod tests {
             }"#
             .to_string(),
         );
-        let res = create_app(todo_repository, label_repository)
+        let res = create_test_app(todo_repository, label_repository)
             .oneshot(req)
             .await
             .unwrap();
@@ -222,11 +948,14 @@ mod tests {
         let todo_repository = TodoRepositoryForMemory::new();
         let label_repository = LabelRepositoryForMemory::new();
         todo_repository
-            .create(CreateTodo::new("should_delete_todo".to_string(), labels))
+            .create(
+                TEST_USER_ID,
+                CreateTodo::new("should_delete_todo".to_string(), labels),
+            )
             .await
             .expect("failed create todo");
-        let req = build_req_with_empty(Method::DELETE, "/todos/1");
-        let res = create_app(todo_repository, label_repository)
+        let req = build_req_with_empty(Method::DELETE, "/workspaces/1/todos/1");
+        let res = create_test_app(todo_repository, label_repository)
             .oneshot(req)
             .await
             .unwrap();
@@ -239,11 +968,11 @@ mod tests {
         let todo_repository = TodoRepositoryForMemory::new();
         let label_repository = LabelRepositoryForMemory::new();
         let req = build_req_with_json(
-            "/labels",
+            "/workspaces/1/labels",
             Method::POST,
             r#"{"name":"should_return_created_label"}"#.to_string(),
         );
-        let res = create_app(todo_repository, label_repository)
+        let res = create_test_app(todo_repository, label_repository)
             .oneshot(req)
             .await
             .unwrap();
@@ -257,11 +986,11 @@ mod tests {
         let todo_repository = TodoRepositoryForMemory::new();
         let label_repository = LabelRepositoryForMemory::new();
         label_repository
-            .create("should_get_all_labels".to_string())
+            .create(TEST_WORKSPACE_ID, "should_get_all_labels".to_string())
             .await
             .expect("failed create label");
-        let req = build_req_with_empty(Method::GET, "/labels");
-        let res = create_app(todo_repository, label_repository)
+        let req = build_req_with_empty(Method::GET, "/workspaces/1/labels");
+        let res = create_test_app(todo_repository, label_repository)
             .oneshot(req)
             .await
             .unwrap();
@@ -274,14 +1003,1684 @@ mod tests {
         let todo_repository = TodoRepositoryForMemory::new();
         let label_repository = LabelRepositoryForMemory::new();
         label_repository
-            .create("should_delete_label".to_string())
+            .create(TEST_WORKSPACE_ID, "should_delete_label".to_string())
             .await
             .expect("failed create label");
-        let req = build_req_with_empty(Method::DELETE, "/labels/1");
-        let res = create_app(todo_repository, label_repository)
+        let req = build_req_with_empty(Method::DELETE, "/workspaces/1/labels/1");
+        let res = create_test_app(todo_repository, label_repository)
             .oneshot(req)
             .await
             .unwrap();
         assert_eq!(StatusCode::NO_CONTENT, res.status());
     }
+
+    #[tokio::test]
+    async fn should_return_not_found_problem() {
+        let todo_repository = TodoRepositoryForMemory::new();
+        let label_repository = LabelRepositoryForMemory::new();
+        let req = build_req_with_empty(Method::GET, "/workspaces/1/todos/1");
+        let res = create_test_app(todo_repository, label_repository)
+            .oneshot(req)
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        assert_eq!(PROBLEM_JSON, res.headers()[header::CONTENT_TYPE]);
+        let problem: Problem = res_to_data(res).await;
+        assert_eq!("not_found", problem.code);
+        assert_eq!(404, problem.status);
+    }
+
+    #[tokio::test]
+    async fn should_return_conflict_on_duplicate_label() {
+        let todo_repository = TodoRepositoryForMemory::new();
+        let label_repository = LabelRepositoryForMemory::new();
+        label_repository
+            .create(TEST_WORKSPACE_ID, "duplicate_label".to_string())
+            .await
+            .expect("failed create label");
+        let req = build_req_with_json(
+            "/workspaces/1/labels",
+            Method::POST,
+            r#"{"name":"duplicate_label"}"#.to_string(),
+        );
+        let res = create_test_app(todo_repository, label_repository)
+            .oneshot(req)
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CONFLICT, res.status());
+        let problem: Problem = res_to_data(res).await;
+        assert_eq!("duplicate", problem.code);
+    }
+
+    #[tokio::test]
+    async fn should_return_unprocessable_entity_on_validation_error() {
+        let todo_repository = TodoRepositoryForMemory::new();
+        let label_repository = LabelRepositoryForMemory::new();
+        let req = build_req_with_json(
+            "/workspaces/1/todos",
+            Method::POST,
+            r#"{"text":"", "labels": []}"#.to_string(),
+        );
+        let res = create_test_app(todo_repository, label_repository)
+            .oneshot(req)
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+        assert_eq!(PROBLEM_JSON, res.headers()[header::CONTENT_TYPE]);
+        let problem: Problem = res_to_data(res).await;
+        assert_eq!("validation_failed", problem.code);
+    }
+
+    #[tokio::test]
+    async fn should_return_bad_request_on_invalid_json() {
+        let todo_repository = TodoRepositoryForMemory::new();
+        let label_repository = LabelRepositoryForMemory::new();
+        let req = build_req_with_json(
+            "/workspaces/1/todos",
+            Method::POST,
+            r#"{"text":"#.to_string(),
+        );
+        let res = create_test_app(todo_repository, label_repository)
+            .oneshot(req)
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        let problem: Problem = res_to_data(res).await;
+        assert_eq!("invalid_json", problem.code);
+    }
+
+    #[tokio::test]
+    async fn should_return_not_found_on_unknown_label() {
+        let todo_repository = TodoRepositoryForMemory::new();
+        let label_repository = LabelRepositoryForMemory::new();
+        let req = build_req_with_json(
+            "/workspaces/1/todos",
+            Method::POST,
+            r#"{"text":"should_return_not_found_on_unknown_label", "labels": [1]}"#.to_string(),
+        );
+        let res = create_test_app(todo_repository.clone(), label_repository)
+            .oneshot(req)
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        let todos = todo_repository
+            .all(TEST_WORKSPACE_ID, TodoQuery::default())
+            .await
+            .expect("failed get all todos");
+        assert!(todos.items.is_empty());
+
+        todo_repository
+            .create(
+                TEST_WORKSPACE_ID,
+                CreateTodo::new(
+                    "should_return_not_found_on_unknown_label".to_string(),
+                    vec![],
+                ),
+            )
+            .await
+            .expect("failed create todo");
+        let req = build_req_with_json(
+            "/workspaces/1/todos/1",
+            Method::PATCH,
+            r#"{"text":"changed", "labels": [1]}"#.to_string(),
+        );
+        let res = create_test_app(todo_repository.clone(), LabelRepositoryForMemory::new())
+            .oneshot(req)
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        let todo = todo_repository
+            .find(TEST_WORKSPACE_ID, 1)
+            .await
+            .expect("failed find todo");
+        assert_eq!("should_return_not_found_on_unknown_label", todo.text);
+    }
+
+    #[tokio::test]
+    async fn should_page_todos() {
+        let todo_repository = TodoRepositoryForMemory::new();
+        let label_repository = LabelRepositoryForMemory::new();
+        for text in ["first", "second", "third"] {
+            todo_repository
+                .create(TEST_WORKSPACE_ID, CreateTodo::new(text.to_string(), vec![]))
+                .await
+                .expect("failed create todo");
+        }
+        let app = create_test_app(todo_repository, label_repository);
+
+        let req = build_req_with_empty(Method::GET, "/workspaces/1/todos?limit=2");
+        let res = app.clone().oneshot(req).await.unwrap();
+        let page: TodoPage = res_to_data(res).await;
+        let ids: Vec<i32> = page.items.iter().map(|todo| todo.id).collect();
+        assert_eq!(vec![3, 2], ids);
+        let cursor = page.next_cursor.expect("next cursor should exist");
+
+        let req = build_req_with_empty(
+            Method::GET,
+            &format!("/workspaces/1/todos?limit=2&after={}", cursor),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        let page: TodoPage = res_to_data(res).await;
+        let ids: Vec<i32> = page.items.iter().map(|todo| todo.id).collect();
+        assert_eq!(vec![1], ids);
+        assert_eq!(None, page.next_cursor);
+    }
+
+    #[tokio::test]
+    async fn should_filter_and_sort_todos() {
+        let todo_repository = TodoRepositoryForMemory::new();
+        let label_repository = LabelRepositoryForMemory::new();
+        let label = label_repository
+            .create(TEST_WORKSPACE_ID, "filter".to_string())
+            .await
+            .expect("failed create label");
+        let todo_repository = todo_repository.with_label_repository(label_repository.clone());
+        todo_repository
+            .create(
+                TEST_USER_ID,
+                CreateTodo::new("buy milk".to_string(), vec![label.id]),
+            )
+            .await
+            .expect("failed create todo");
+        todo_repository
+            .create(
+                TEST_USER_ID,
+                CreateTodo::new("Buy bread".to_string(), vec![]),
+            )
+            .await
+            .expect("failed create todo");
+        todo_repository
+            .create(
+                TEST_USER_ID,
+                CreateTodo::new("walk".to_string(), vec![label.id]),
+            )
+            .await
+            .expect("failed create todo");
+        todo_repository
+            .update(
+                TEST_WORKSPACE_ID,
+                3,
+                UpdateTodo::new(None, Some(true), None),
+                None,
+            )
+            .await
+            .expect("failed update todo");
+        let app = create_test_app(todo_repository, label_repository);
+
+        let req = build_req_with_empty(
+            Method::GET,
+            "/workspaces/1/todos?text=buy&sort=text&order=asc",
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        let page: TodoPage = res_to_data(res).await;
+        let ids: Vec<i32> = page.items.iter().map(|todo| todo.id).collect();
+        assert_eq!(vec![2, 1], ids);
+
+        let req = build_req_with_empty(Method::GET, "/workspaces/1/todos?completed=false&labels=1");
+        let res = app.oneshot(req).await.unwrap();
+        let page: TodoPage = res_to_data(res).await;
+        let ids: Vec<i32> = page.items.iter().map(|todo| todo.id).collect();
+        assert_eq!(vec![1], ids);
+    }
+
+    #[tokio::test]
+    async fn should_reject_invalid_todo_query() {
+        let todo_repository = TodoRepositoryForMemory::new();
+        let label_repository = LabelRepositoryForMemory::new();
+        let app = create_test_app(todo_repository, label_repository);
+
+        let req = build_req_with_empty(Method::GET, "/workspaces/1/todos?limit=0");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+
+        let req = build_req_with_empty(Method::GET, "/workspaces/1/todos?after=broken");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        let problem: Problem = res_to_data(res).await;
+        assert_eq!("invalid_query", problem.code);
+    }
+
+    #[tokio::test]
+    async fn should_find_label() {
+        let expected = Label::new(1, "should_find_label".to_string());
+        let todo_repository = TodoRepositoryForMemory::new();
+        let label_repository = LabelRepositoryForMemory::new();
+        label_repository
+            .create(TEST_WORKSPACE_ID, "should_find_label".to_string())
+            .await
+            .expect("failed create label");
+        let req = build_req_with_empty(Method::GET, "/workspaces/1/labels/1");
+        let res = create_test_app(todo_repository, label_repository)
+            .oneshot(req)
+            .await
+            .unwrap();
+        let label: Label = res_to_data(res).await;
+        assert_eq!(expected, label);
+    }
+
+    #[tokio::test]
+    async fn should_update_label() {
+        let expected = Label::new(1, "should_update_label".to_string());
+        let todo_repository = TodoRepositoryForMemory::new();
+        let label_repository = LabelRepositoryForMemory::new();
+        label_repository
+            .create(TEST_WORKSPACE_ID, "should_update_lable".to_string())
+            .await
+            .expect("failed create label");
+        let req = build_req_with_json(
+            "/workspaces/1/labels/1",
+            Method::PATCH,
+            r#"{"name":"should_update_label"}"#.to_string(),
+        );
+        let res = create_test_app(todo_repository, label_repository)
+            .oneshot(req)
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let label: Label = res_to_data(res).await;
+        assert_eq!(expected, label);
+    }
+
+    #[tokio::test]
+    async fn should_return_conflict_on_rename_to_existing_label() {
+        let todo_repository = TodoRepositoryForMemory::new();
+        let label_repository = LabelRepositoryForMemory::new();
+        for name in ["first", "second"] {
+            label_repository
+                .create(TEST_WORKSPACE_ID, name.to_string())
+                .await
+                .expect("failed create label");
+        }
+        let req = build_req_with_json(
+            "/workspaces/1/labels/2",
+            Method::PATCH,
+            r#"{"name":"first"}"#.to_string(),
+        );
+        let res = create_test_app(todo_repository, label_repository)
+            .oneshot(req)
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CONFLICT, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_refuse_deleting_label_in_use() {
+        let todo_repository = TodoRepositoryForMemory::new();
+        let label_repository = LabelRepositoryForMemory::new();
+        let label = label_repository
+            .create(TEST_WORKSPACE_ID, "in_use".to_string())
+            .await
+            .expect("failed create label");
+        let todo_repository = todo_repository.with_label_repository(label_repository.clone());
+        todo_repository
+            .create(
+                TEST_USER_ID,
+                CreateTodo::new("labeled".to_string(), vec![label.id]),
+            )
+            .await
+            .expect("failed create todo");
+        let app = create_test_app(todo_repository, label_repository);
+
+        let req = build_req_with_empty(Method::DELETE, "/workspaces/1/labels/1");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CONFLICT, res.status());
+        let problem: Problem = res_to_data(res).await;
+        assert_eq!("in_use", problem.code);
+        assert_eq!(Some(vec![1]), problem.todo_ids);
+
+        let req = build_req_with_empty(Method::DELETE, "/workspaces/1/labels/1?strategy=detach");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+
+        let req = build_req_with_empty(Method::GET, "/workspaces/1/todos/1");
+        let res = app.oneshot(req).await.unwrap();
+        let todo: TodoEntity = res_to_data(res).await;
+        assert!(todo.labels.is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_reject_unknown_delete_label_strategy() {
+        let todo_repository = TodoRepositoryForMemory::new();
+        let label_repository = LabelRepositoryForMemory::new();
+        label_repository
+            .create(TEST_WORKSPACE_ID, "label".to_string())
+            .await
+            .expect("failed create label");
+        let app = create_test_app(todo_repository, label_repository);
+
+        let req = build_req_with_empty(Method::DELETE, "/workspaces/1/labels/1?strategy=cascade");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+
+        let req =
+            build_req_with_empty(Method::DELETE, "/workspaces/1/labels/1?strategy=reassign:1");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_sign_up_and_log_in() {
+        let app = create_test_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        );
+        let credentials = r#"{"username":"alice", "password":"correct horse"}"#;
+
+        let req = build_req_with_json("/auth/signup", Method::POST, credentials.to_string());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let signed_up: AuthResponse = res_to_data(res).await;
+        assert_eq!("alice", signed_up.user.username);
+
+        let req = build_req_with_json("/auth/signup", Method::POST, credentials.to_string());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CONFLICT, res.status());
+
+        let req = build_req_with_json("/auth/login", Method::POST, credentials.to_string());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let logged_in: AuthResponse = res_to_data(res).await;
+        assert_eq!(signed_up.user, logged_in.user);
+
+        // the issued token is accepted, and a personal workspace is ready
+        let req = Request::builder()
+            .uri("/workspaces")
+            .header(header::AUTHORIZATION, format!("Bearer {}", logged_in.token))
+            .body(Body::empty())
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let workspaces: Vec<Workspace> = res_to_data(res).await;
+        assert!(workspaces
+            .iter()
+            .any(|workspace| workspace.name == "alice" && workspace.role == Role::Owner));
+
+        let req = build_req_with_json(
+            "/auth/login",
+            Method::POST,
+            r#"{"username":"alice", "password":"wrong password"}"#.to_string(),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+        let problem: Problem = res_to_data(res).await;
+        assert_eq!("unauthorized", problem.code);
+    }
+
+    #[tokio::test]
+    async fn should_return_unauthorized_without_valid_token() {
+        let app = create_test_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        );
+
+        let req = Request::builder()
+            .uri("/workspaces/1/todos")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+        assert_eq!("Bearer", res.headers()[header::WWW_AUTHENTICATE]);
+
+        let req = Request::builder()
+            .uri("/workspaces/1/labels")
+            .header(header::AUTHORIZATION, "Bearer broken")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+        let problem: Problem = res_to_data(res).await;
+        assert_eq!("unauthorized", problem.code);
+    }
+
+    #[tokio::test]
+    async fn should_hide_todos_of_other_workspaces() {
+        let other_user_id = TEST_USER_ID + 1;
+        let other_workspace_id = TEST_WORKSPACE_ID + 1;
+        let todo_repository = TodoRepositoryForMemory::new();
+        let label_repository = LabelRepositoryForMemory::new();
+        todo_repository
+            .create(
+                other_workspace_id,
+                CreateTodo::new("someone else's".to_string(), vec![]),
+            )
+            .await
+            .expect("failed create todo");
+        let app = create_test_app(todo_repository, label_repository);
+
+        let req = build_req_with_empty(Method::GET, "/workspaces/1/todos");
+        let res = app.clone().oneshot(req).await.unwrap();
+        let page: TodoPage = res_to_data(res).await;
+        assert!(page.items.is_empty());
+
+        let req = build_req_with_empty(Method::GET, "/workspaces/1/todos/1");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+        // the test user is no member of the other workspace
+        let req = build_req_with_empty(Method::GET, "/workspaces/2/todos/1");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+        // and a stranger is no member of the test workspace
+        let req = Request::builder()
+            .uri("/workspaces/1/todos")
+            .header(header::AUTHORIZATION, bearer(other_user_id))
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_forbid_writes_by_viewers() {
+        let viewer_id = TEST_USER_ID + 1;
+        let todo_repository = TodoRepositoryForMemory::new();
+        let label_repository = LabelRepositoryForMemory::new();
+        let user_repository = UserRepositoryForMemory::new();
+        for username in ["owner", "viewer"] {
+            user_repository
+                .create(username.to_string(), "hash".to_string())
+                .await
+                .expect("failed create user");
+        }
+        todo_repository
+            .create(
+                TEST_WORKSPACE_ID,
+                CreateTodo::new("shared".to_string(), vec![]),
+            )
+            .await
+            .expect("failed create todo");
+        let app = create_app(
+            todo_repository,
+            label_repository,
+            user_repository,
+            WorkspaceRepositoryForMemory::with_owner(TEST_USER_ID),
+            AuditRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            Arc::new(EventBus::new(REPLAY_CAPACITY)),
+            &test_config(),
+        );
+        let as_viewer = |method: Method, path: &str, body: Body| {
+            Request::builder()
+                .uri(path)
+                .method(method)
+                .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .header(header::AUTHORIZATION, bearer(viewer_id))
+                .body(body)
+                .unwrap()
+        };
+
+        // the owner invites a viewer
+        let req = build_req_with_json(
+            &format!("/workspaces/1/members/{}", viewer_id),
+            Method::PUT,
+            r#"{"role":"viewer"}"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let member: Member = res_to_data(res).await;
+        assert_eq!(Role::Viewer, member.role);
+
+        // viewers read
+        let req = as_viewer(Method::GET, "/workspaces/1/todos/1", Body::empty());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+
+        // but do not write
+        let req = as_viewer(
+            Method::PATCH,
+            "/workspaces/1/todos/1",
+            Body::from(r#"{"completed":true}"#),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, res.status());
+        let problem: Problem = res_to_data(res).await;
+        assert_eq!("forbidden", problem.code);
+        let req = as_viewer(
+            Method::POST,
+            "/workspaces/1/labels",
+            Body::from(r#"{"name":"label"}"#),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, res.status());
+
+        // nor manage members
+        let req = as_viewer(
+            Method::DELETE,
+            &format!("/workspaces/1/members/{}", TEST_USER_ID),
+            Body::empty(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, res.status());
+
+        // promoted to editor, they write
+        let req = build_req_with_json(
+            &format!("/workspaces/1/members/{}", viewer_id),
+            Method::PUT,
+            r#"{"role":"editor"}"#.to_string(),
+        );
+        app.clone().oneshot(req).await.unwrap();
+        let req = as_viewer(
+            Method::PATCH,
+            "/workspaces/1/todos/1",
+            Body::from(r#"{"completed":true}"#),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_manage_workspace_members() {
+        let app = create_test_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        );
+
+        let req = build_req_with_json(
+            "/workspaces",
+            Method::POST,
+            r#"{"name":"team"}"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let workspace: Workspace = res_to_data(res).await;
+        assert_eq!(Role::Owner, workspace.role);
+
+        let req = build_req_with_empty(Method::GET, "/workspaces");
+        let res = app.clone().oneshot(req).await.unwrap();
+        let workspaces: Vec<Workspace> = res_to_data(res).await;
+        assert_eq!(2, workspaces.len());
+
+        // unknown users can not be added
+        let req = build_req_with_json(
+            &format!("/workspaces/{}/members/42", workspace.id),
+            Method::PUT,
+            r#"{"role":"editor"}"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+        // owners keep their own membership
+        let req = build_req_with_empty(
+            Method::DELETE,
+            &format!("/workspaces/{}/members/{}", workspace.id, TEST_USER_ID),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+
+        let req = build_req_with_empty(
+            Method::GET,
+            &format!("/workspaces/{}/members", workspace.id),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        let members: Vec<Member> = res_to_data(res).await;
+        assert_eq!(
+            vec![Member {
+                user_id: TEST_USER_ID,
+                role: Role::Owner
+            }],
+            members
+        );
+    }
+
+    #[tokio::test]
+    async fn should_set_and_clear_due_dates() {
+        let app = create_test_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        );
+
+        let req = build_req_with_json(
+            "/workspaces/1/todos",
+            Method::POST,
+            r#"{"text":"dated", "labels": [], "starts_at": "2022-11-01T09:00:00Z", "due_at": "2022-11-02T09:00:00Z"}"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        let todo: TodoEntity = res_to_data(res).await;
+        assert_eq!(Some("2022-11-02T09:00:00Z".parse().unwrap()), todo.due_at);
+
+        // leaving a date out keeps it
+        let req = build_req_with_json(
+            "/workspaces/1/todos/1",
+            Method::PATCH,
+            r#"{"text":"still dated"}"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        let todo: TodoEntity = res_to_data(res).await;
+        assert!(todo.due_at.is_some());
+
+        // an explicit null clears it
+        let req = build_req_with_json(
+            "/workspaces/1/todos/1",
+            Method::PATCH,
+            r#"{"due_at":null}"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        let todo: TodoEntity = res_to_data(res).await;
+        assert_eq!(None, todo.due_at);
+        assert!(todo.starts_at.is_some());
+
+        let req = build_req_with_empty(Method::GET, "/workspaces/1/todos?overdue=true");
+        let res = app.clone().oneshot(req).await.unwrap();
+        let page: TodoPage = res_to_data(res).await;
+        assert!(page.items.is_empty());
+
+        // a todo can not start after it is due
+        let req = build_req_with_json(
+            "/workspaces/1/todos",
+            Method::POST,
+            r#"{"text":"backwards", "labels": [], "starts_at": "2022-11-02T09:00:00Z", "due_at": "2022-11-01T09:00:00Z"}"#.to_string(),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_sort_todos_by_priority() {
+        let app = create_test_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        );
+
+        for body in [
+            r#"{"text":"whenever", "labels": []}"#,
+            r#"{"text":"now", "labels": [], "priority": "urgent"}"#,
+            r#"{"text":"soon", "labels": [], "priority": "medium"}"#,
+        ] {
+            let req = build_req_with_json("/workspaces/1/todos", Method::POST, body.to_string());
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+
+        let req = build_req_with_json(
+            "/workspaces/1/todos/1",
+            Method::PATCH,
+            r#"{"priority":"low"}"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        let todo: TodoEntity = res_to_data(res).await;
+        assert_eq!(Priority::Low, todo.priority);
+
+        let req = build_req_with_empty(Method::GET, "/workspaces/1/todos?sort=priority");
+        let res = app.clone().oneshot(req).await.unwrap();
+        let page: TodoPage = res_to_data(res).await;
+        let texts: Vec<&str> = page.items.iter().map(|todo| todo.text.as_str()).collect();
+        assert_eq!(vec!["now", "soon", "whenever"], texts);
+
+        // unknown priorities are rejected
+        let req = build_req_with_json(
+            "/workspaces/1/todos",
+            Method::POST,
+            r#"{"text":"later", "labels": [], "priority": "critical"}"#.to_string(),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_nest_subtasks() {
+        let app = create_test_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        );
+
+        for body in [
+            r#"{"text":"big task", "labels": []}"#,
+            r#"{"text":"first step", "labels": [], "parent_id": 1}"#,
+            r#"{"text":"second step", "labels": [], "parent_id": 1}"#,
+        ] {
+            let req = build_req_with_json("/workspaces/1/todos", Method::POST, body.to_string());
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+
+        let req = build_req_with_empty(Method::GET, "/workspaces/1/todos/1");
+        let res = app.clone().oneshot(req).await.unwrap();
+        let todo: TodoEntity = res_to_data(res).await;
+        assert_eq!(vec![3, 2], todo.child_ids);
+
+        let req = build_req_with_empty(Method::GET, "/workspaces/1/todos/1/children");
+        let res = app.clone().oneshot(req).await.unwrap();
+        let children: Vec<TodoEntity> = res_to_data(res).await;
+        let texts: Vec<&str> = children.iter().map(|todo| todo.text.as_str()).collect();
+        assert_eq!(vec!["second step", "first step"], texts);
+
+        // cycles are rejected
+        let req = build_req_with_json(
+            "/workspaces/1/todos/1",
+            Method::PATCH,
+            r#"{"parent_id":2}"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+
+        // unknown parents are not found
+        let req = build_req_with_json(
+            "/workspaces/1/todos",
+            Method::POST,
+            r#"{"text":"orphan", "labels": [], "parent_id": 99}"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+        // subtasks go with their parent
+        let req = build_req_with_empty(Method::DELETE, "/workspaces/1/todos/1");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+        let req = build_req_with_empty(Method::GET, "/workspaces/1/todos/2");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_move_todos() {
+        let app = create_test_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        );
+
+        for text in ["first", "second", "third"] {
+            let req = build_req_with_json(
+                "/workspaces/1/todos",
+                Method::POST,
+                format!(r#"{{"text":"{}", "labels": []}}"#, text),
+            );
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+
+        let req = build_req_with_json(
+            "/workspaces/1/todos/1/move",
+            Method::POST,
+            r#"{"after":3}"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+
+        let req = build_req_with_empty(Method::GET, "/workspaces/1/todos");
+        let res = app.clone().oneshot(req).await.unwrap();
+        let page: TodoPage = res_to_data(res).await;
+        let ids: Vec<i32> = page.items.iter().map(|todo| todo.id).collect();
+        assert_eq!(vec![3, 1, 2], ids);
+
+        // a neighbour is required and can not be the todo itself
+        for body in [r#"{}"#, r#"{"before":1}"#, r#"{"before":2,"after":2}"#] {
+            let req =
+                build_req_with_json("/workspaces/1/todos/1/move", Method::POST, body.to_string());
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+        }
+
+        let req = build_req_with_json(
+            "/workspaces/1/todos/1/move",
+            Method::POST,
+            r#"{"before":99}"#.to_string(),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_search_todos() {
+        let app = create_test_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        );
+
+        for text in ["Buy milk", "Walk the dog", "Buy dog food"] {
+            let req = build_req_with_json(
+                "/workspaces/1/todos",
+                Method::POST,
+                format!(r#"{{"text":"{}", "labels": []}}"#, text),
+            );
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+
+        let req = build_req_with_empty(Method::GET, "/workspaces/1/todos/search?q=buy%20dog");
+        let res = app.clone().oneshot(req).await.unwrap();
+        let hits: Vec<TodoSearchHit> = res_to_data(res).await;
+        assert_eq!(1, hits.len());
+        assert_eq!(3, hits[0].todo.id);
+        assert_eq!("<mark>Buy</mark> <mark>dog</mark> food", hits[0].snippet);
+
+        let req = build_req_with_empty(Method::GET, "/workspaces/1/todos/search?q=");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_apply_bulk_operations() {
+        let labeled = Label {
+            id: 1,
+            name: "should_apply_bulk_operations".to_string(),
+        };
+        let label_repository = LabelRepositoryForMemory::new();
+        label_repository
+            .create(TEST_WORKSPACE_ID, labeled.name.clone())
+            .await
+            .expect("failed create label");
+        let app = create_test_app(TodoRepositoryForMemory::new(), label_repository);
+
+        for text in ["first", "second"] {
+            let req = build_req_with_json(
+                "/workspaces/1/todos",
+                Method::POST,
+                format!(r#"{{"text":"{}", "labels": []}}"#, text),
+            );
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+
+        let req = build_req_with_json(
+            "/workspaces/1/todos/bulk",
+            Method::POST,
+            r#"{"ids":[1,2,3], "operation": {"type":"add_labels", "labels":[1]}}"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let results: Vec<BulkItemResult> = res_to_data(res).await;
+        let statuses: Vec<BulkStatus> = results.iter().map(|result| result.status).collect();
+        assert_eq!(
+            vec![BulkStatus::Ok, BulkStatus::Ok, BulkStatus::NotFound],
+            statuses
+        );
+        assert_eq!(vec![labeled], results[1].todo.as_ref().unwrap().labels);
+
+        let req = build_req_with_json(
+            "/workspaces/1/todos/bulk",
+            Method::POST,
+            r#"{"ids":[1,2], "operation": {"type":"complete"}}"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        let results: Vec<BulkItemResult> = res_to_data(res).await;
+        assert!(results
+            .iter()
+            .all(|result| result.todo.as_ref().unwrap().completed));
+
+        // unknown labels fail the whole request
+        let req = build_req_with_json(
+            "/workspaces/1/todos/bulk",
+            Method::POST,
+            r#"{"ids":[1], "operation": {"type":"set_labels", "labels":[99]}}"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+        let req = build_req_with_json(
+            "/workspaces/1/todos/bulk",
+            Method::POST,
+            r#"{"ids":[], "operation": {"type":"delete"}}"#.to_string(),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_trash_and_restore_todos() {
+        let app = create_test_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        );
+        for text in ["parent", "subtask", "other"] {
+            let parent = if text == "subtask" { "1" } else { "null" };
+            let req = build_req_with_json(
+                "/workspaces/1/todos",
+                Method::POST,
+                format!(
+                    r#"{{"text":"{}", "labels": [], "parent_id": {}}}"#,
+                    text, parent
+                ),
+            );
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+
+        let req = build_req_with_empty(Method::DELETE, "/workspaces/1/todos/1");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+        let req = build_req_with_empty(Method::GET, "/workspaces/1/todos/2");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        let req = build_req_with_empty(Method::GET, "/workspaces/1/todos");
+        let res = app.clone().oneshot(req).await.unwrap();
+        let page: TodoPage = res_to_data(res).await;
+        assert_eq!(
+            vec![3],
+            page.items.iter().map(|todo| todo.id).collect::<Vec<_>>()
+        );
+
+        let req = build_req_with_empty(Method::GET, "/workspaces/1/trash");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let trash: Vec<TodoEntity> = res_to_data(res).await;
+        let mut trashed: Vec<i32> = trash.iter().map(|todo| todo.id).collect();
+        trashed.sort();
+        assert_eq!(vec![1, 2], trashed);
+        assert!(trash.iter().all(|todo| todo.deleted_at.is_some()));
+
+        let req = build_req_with_empty(Method::POST, "/workspaces/1/todos/1/restore");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let todo: TodoEntity = res_to_data(res).await;
+        assert_eq!(None, todo.deleted_at);
+        assert_eq!(vec![2], todo.child_ids);
+
+        // only trashed todos can be restored
+        let req = build_req_with_empty(Method::POST, "/workspaces/1/todos/1/restore");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+        let req = build_req_with_empty(Method::DELETE, "/workspaces/1/todos/3");
+        app.clone().oneshot(req).await.unwrap();
+        let req = build_req_with_empty(Method::DELETE, "/workspaces/1/trash");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+        let req = build_req_with_empty(Method::GET, "/workspaces/1/trash");
+        let res = app.clone().oneshot(req).await.unwrap();
+        let trash: Vec<TodoEntity> = res_to_data(res).await;
+        assert!(trash.is_empty());
+        let req = build_req_with_empty(Method::POST, "/workspaces/1/todos/3/restore");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_honor_etag_preconditions() {
+        let app = create_test_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        );
+        let req = build_req_with_json(
+            "/workspaces/1/todos",
+            Method::POST,
+            r#"{"text":"etag", "labels": []}"#.to_string(),
+        );
+        app.clone().oneshot(req).await.unwrap();
+
+        let req = build_req_with_empty(Method::GET, "/workspaces/1/todos/1");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(r#""1""#, res.headers()[header::ETAG]);
+
+        // the client's copy is still current
+        let mut req = build_req_with_empty(Method::GET, "/workspaces/1/todos/1");
+        req.headers_mut()
+            .insert(header::IF_NONE_MATCH, r#"W/"1""#.parse().unwrap());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_MODIFIED, res.status());
+
+        let mut req = build_req_with_json(
+            "/workspaces/1/todos/1",
+            Method::PATCH,
+            r#"{"completed": true}"#.to_string(),
+        );
+        req.headers_mut()
+            .insert(header::IF_MATCH, r#""1""#.parse().unwrap());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        assert_eq!(r#""2""#, res.headers()[header::ETAG]);
+
+        // a lost update is refused
+        let mut req = build_req_with_json(
+            "/workspaces/1/todos/1",
+            Method::PATCH,
+            r#"{"completed": false}"#.to_string(),
+        );
+        req.headers_mut()
+            .insert(header::IF_MATCH, r#""1""#.parse().unwrap());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::PRECONDITION_FAILED, res.status());
+        let problem: Problem = res_to_data(res).await;
+        assert_eq!("precondition_failed", problem.code);
+
+        let mut req = build_req_with_empty(Method::GET, "/workspaces/1/todos/1");
+        req.headers_mut()
+            .insert(header::IF_NONE_MATCH, r#""1""#.parse().unwrap());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let todo: TodoEntity = res_to_data(res).await;
+        assert!(todo.completed);
+
+        let mut req = build_req_with_empty(Method::DELETE, "/workspaces/1/todos/1");
+        req.headers_mut()
+            .insert(header::IF_MATCH, r#""1""#.parse().unwrap());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::PRECONDITION_FAILED, res.status());
+        let mut req = build_req_with_empty(Method::DELETE, "/workspaces/1/todos/1");
+        req.headers_mut()
+            .insert(header::IF_MATCH, "*".parse().unwrap());
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_change_etag_with_labels_and_subtasks() {
+        let app = create_test_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        );
+        let req = build_req_with_json(
+            "/workspaces/1/labels",
+            Method::POST,
+            r#"{"name":"before"}"#.to_string(),
+        );
+        app.clone().oneshot(req).await.unwrap();
+        let req = build_req_with_json(
+            "/workspaces/1/todos",
+            Method::POST,
+            r#"{"text":"parent", "labels": [1]}"#.to_string(),
+        );
+        app.clone().oneshot(req).await.unwrap();
+        let get_with_etag = |etag: &str| {
+            let mut req = build_req_with_empty(Method::GET, "/workspaces/1/todos/1");
+            req.headers_mut()
+                .insert(header::IF_NONE_MATCH, etag.parse().unwrap());
+            app.clone().oneshot(req)
+        };
+
+        let req = build_req_with_json(
+            "/workspaces/1/labels/1",
+            Method::PATCH,
+            r#"{"name":"after"}"#.to_string(),
+        );
+        app.clone().oneshot(req).await.unwrap();
+        let res = get_with_etag(r#""1""#).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(r#""2""#, res.headers()[header::ETAG]);
+        let todo: TodoEntity = res_to_data(res).await;
+        assert_eq!("after", todo.labels[0].name);
+
+        let req = build_req_with_json(
+            "/workspaces/1/todos",
+            Method::POST,
+            r#"{"text":"subtask", "labels": [], "parent_id": 1}"#.to_string(),
+        );
+        app.clone().oneshot(req).await.unwrap();
+        let res = get_with_etag(r#""2""#).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let todo: TodoEntity = res_to_data(res).await;
+        assert_eq!(vec![2], todo.child_ids);
+
+        let req = build_req_with_empty(Method::DELETE, "/workspaces/1/todos/2");
+        app.clone().oneshot(req).await.unwrap();
+        let res = get_with_etag(r#""3""#).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let todo: TodoEntity = res_to_data(res).await;
+        assert!(todo.child_ids.is_empty());
+
+        let req = build_req_with_empty(Method::DELETE, "/workspaces/1/labels/1?strategy=detach");
+        app.clone().oneshot(req).await.unwrap();
+        let res = get_with_etag(r#""4""#).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let todo: TodoEntity = res_to_data(res).await;
+        assert!(todo.labels.is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_stream_changes() {
+        let app = create_test_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        );
+        let req = build_req_with_json(
+            "/workspaces/1/todos",
+            Method::POST,
+            r#"{"text":"streamed", "labels": []}"#.to_string(),
+        );
+        app.clone().oneshot(req).await.unwrap();
+
+        let req = build_req_with_empty(Method::GET, "/workspaces/1/events");
+        let mut live = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, live.status());
+        assert_eq!("text/event-stream", live.headers()[header::CONTENT_TYPE]);
+        let req = build_req_with_json(
+            "/workspaces/1/todos/1",
+            Method::PATCH,
+            r#"{"completed": true}"#.to_string(),
+        );
+        app.clone().oneshot(req).await.unwrap();
+        let chunk = next_chunk(&mut live).await;
+        assert!(chunk.contains("event: todo.updated\n"), "{}", chunk);
+        assert!(chunk.contains("id: 2\n"), "{}", chunk);
+        assert!(chunk.contains(r#""completed":true"#), "{}", chunk);
+
+        // resuming replays what was missed
+        let mut req = build_req_with_empty(Method::GET, "/workspaces/1/events");
+        req.headers_mut()
+            .insert("last-event-id", "1".parse().unwrap());
+        let mut res = app.clone().oneshot(req).await.unwrap();
+        let chunk = next_chunk(&mut res).await;
+        assert!(chunk.contains("event: todo.updated\n"), "{}", chunk);
+        assert!(chunk.contains("id: 2\n"), "{}", chunk);
+
+        // ids from before a restart
+        let mut req = build_req_with_empty(Method::GET, "/workspaces/1/events");
+        req.headers_mut()
+            .insert("last-event-id", "100".parse().unwrap());
+        let mut res = app.oneshot(req).await.unwrap();
+        let chunk = next_chunk(&mut res).await;
+        assert!(chunk.contains("event: reset\n"), "{}", chunk);
+    }
+
+    #[tokio::test]
+    async fn should_stream_changes_to_browsers_with_a_stream_token() {
+        let app = create_test_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        );
+        let req = build_req_with_empty(Method::POST, "/auth/stream-token");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let StreamToken { token, expires_in } = res_to_data(res).await;
+        assert_eq!(60, expires_in);
+
+        // an `EventSource` sends no `Authorization` header
+        let events = |token: &str| {
+            Request::builder()
+                .uri(format!("/workspaces/1/events?access_token={}", token))
+                .body(Body::empty())
+                .unwrap()
+        };
+        let mut live = app.clone().oneshot(events(&token)).await.unwrap();
+        assert_eq!(StatusCode::OK, live.status());
+        let req = build_req_with_json(
+            "/workspaces/1/todos",
+            Method::POST,
+            r#"{"text":"streamed", "labels": []}"#.to_string(),
+        );
+        app.clone().oneshot(req).await.unwrap();
+        let chunk = next_chunk(&mut live).await;
+        assert!(chunk.contains("event: todo.created\n"), "{}", chunk);
+
+        // tokens for the whole API stay out of URLs, and stream tokens out of the API
+        let api_token = AuthKeys::new(TEST_SECRET).issue(TEST_USER_ID).unwrap();
+        let res = app.clone().oneshot(events(&api_token)).await.unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+        let req = Request::builder()
+            .uri("/workspaces/1/todos")
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_open_websocket_from_browsers_with_a_stream_token() {
+        use futures::{SinkExt, StreamExt};
+        use serde_json::{json, Value};
+        use tokio_tungstenite::tungstenite::{self, Message};
+
+        let app = create_test_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        );
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.into_make_service());
+        tokio::spawn(server);
+
+        // a browser `WebSocket` sends no `Authorization` header
+        let url = format!("ws://{}/workspaces/1/ws", addr);
+        match tokio_tungstenite::connect_async(url.as_str()).await {
+            Err(tungstenite::Error::Http(res)) => {
+                assert_eq!(StatusCode::UNAUTHORIZED, res.status())
+            }
+            other => panic!("connected without a token: {:?}", other.map(|_| ())),
+        }
+        let token = AuthKeys::new(TEST_SECRET)
+            .issue_stream(TEST_USER_ID)
+            .unwrap();
+        let (mut socket, _res) =
+            tokio_tungstenite::connect_async(format!("{}?access_token={}", url, token))
+                .await
+                .expect("failed connect websocket");
+        let frame = json!({"type": "subscribe", "request_id": "s", "todos": [1]});
+        socket.send(Message::Text(frame.to_string())).await.unwrap();
+        let message = tokio::time::timeout(Duration::from_secs(5), socket.next())
+            .await
+            .expect("no message in time")
+            .expect("socket closed")
+            .unwrap();
+        let message: Value = serde_json::from_str(message.to_text().unwrap()).unwrap();
+        assert_eq!(json!({"type": "ack", "request_id": "s"}), message);
+    }
+
+    #[tokio::test]
+    async fn should_collaborate_over_websocket() {
+        use futures::{SinkExt, StreamExt};
+        use serde_json::{json, Value};
+        use tokio_tungstenite::tungstenite::Message;
+
+        let app = create_test_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        );
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.clone().into_make_service());
+        tokio::spawn(server);
+        let req = Request::builder()
+            .uri(format!("ws://{}/workspaces/1/ws", addr))
+            .header(header::AUTHORIZATION, bearer(TEST_USER_ID))
+            .body(())
+            .unwrap();
+        let (mut socket, _res) = tokio_tungstenite::connect_async(req)
+            .await
+            .expect("failed connect websocket");
+        let frames = [
+            json!({"type": "subscribe", "request_id": "s", "todos": [1]}).to_string(),
+            json!({
+                "type": "create_todo",
+                "request_id": "c",
+                "todo": {"text": "over the socket", "labels": []}
+            })
+            .to_string(),
+            json!({
+                "type": "update_todo",
+                "request_id": "u",
+                "todo_id": 1,
+                "todo": {"completed": true},
+                "if_match": [5]
+            })
+            .to_string(),
+            json!({"type": "create_todo", "request_id": "v", "todo": {"text": "", "labels": []}})
+                .to_string(),
+            "broken".to_string(),
+        ];
+        for frame in frames {
+            socket.send(Message::Text(frame)).await.unwrap();
+        }
+
+        let mut messages = vec![];
+        while messages.len() < 6 {
+            let message = tokio::time::timeout(Duration::from_secs(5), socket.next())
+                .await
+                .expect("no message in time")
+                .expect("socket closed")
+                .unwrap();
+            if let Message::Text(text) = message {
+                messages.push(serde_json::from_str::<Value>(&text).unwrap());
+            }
+        }
+        assert_eq!(json!({"type": "ack", "request_id": "s"}), messages[0]);
+        assert_eq!("ack", messages[1]["type"]);
+        assert_eq!("c", messages[1]["request_id"]);
+        assert_eq!(1, messages[1]["todo"]["id"]);
+        // the subscribed todo was created
+        assert_eq!("change", messages[2]["type"]);
+        assert_eq!("todo.created", messages[2]["event"]);
+        assert_eq!("over the socket", messages[2]["data"]["text"]);
+        assert_eq!("error", messages[3]["type"]);
+        assert_eq!("u", messages[3]["request_id"]);
+        assert_eq!("precondition_failed", messages[3]["error"]["code"]);
+        assert_eq!("v", messages[4]["request_id"]);
+        assert_eq!("validation_failed", messages[4]["error"]["code"]);
+        assert_eq!(Value::Null, messages[5]["request_id"]);
+        assert_eq!("invalid_json", messages[5]["error"]["code"]);
+
+        // changes made over REST reach the socket too
+        let req = build_req_with_json(
+            "/workspaces/1/todos/1",
+            Method::PATCH,
+            r#"{"completed": true}"#.to_string(),
+        );
+        app.oneshot(req).await.unwrap();
+        let message = tokio::time::timeout(Duration::from_secs(5), socket.next())
+            .await
+            .expect("no message in time")
+            .expect("socket closed")
+            .unwrap();
+        let message: Value = serde_json::from_str(message.to_text().unwrap()).unwrap();
+        assert_eq!("todo.updated", message["event"]);
+        assert_eq!(true, message["data"]["completed"]);
+    }
+
+    #[tokio::test]
+    async fn should_tell_label_subscribers_about_todos_leaving_the_label() {
+        use futures::{SinkExt, StreamExt};
+        use serde_json::{json, Value};
+        use tokio_tungstenite::tungstenite::Message;
+
+        let app = create_test_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        );
+        let req = build_req_with_json(
+            "/workspaces/1/labels",
+            Method::POST,
+            r#"{"name":"watched"}"#.to_string(),
+        );
+        app.clone().oneshot(req).await.unwrap();
+        for text in ["unlabelled", "deleted"] {
+            let req = build_req_with_json(
+                "/workspaces/1/todos",
+                Method::POST,
+                json!({"text": text, "labels": [1]}).to_string(),
+            );
+            app.clone().oneshot(req).await.unwrap();
+        }
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.clone().into_make_service());
+        tokio::spawn(server);
+        let req = Request::builder()
+            .uri(format!("ws://{}/workspaces/1/ws", addr))
+            .header(header::AUTHORIZATION, bearer(TEST_USER_ID))
+            .body(())
+            .unwrap();
+        let (mut socket, _res) = tokio_tungstenite::connect_async(req)
+            .await
+            .expect("failed connect websocket");
+        let subscribe = json!({"type": "subscribe", "labels": [1]}).to_string();
+        socket.send(Message::Text(subscribe)).await.unwrap();
+        async fn next_message(
+            socket: &mut (impl futures::Stream<Item = tokio_tungstenite::tungstenite::Result<Message>>
+                      + Unpin),
+        ) -> Value {
+            let message = tokio::time::timeout(Duration::from_secs(5), socket.next())
+                .await
+                .expect("no message in time")
+                .expect("socket closed")
+                .unwrap();
+            serde_json::from_str(message.to_text().unwrap()).unwrap()
+        }
+        assert_eq!("ack", next_message(&mut socket).await["type"]);
+
+        let req = build_req_with_json(
+            "/workspaces/1/todos/1",
+            Method::PATCH,
+            r#"{"labels": []}"#.to_string(),
+        );
+        app.clone().oneshot(req).await.unwrap();
+        let message = next_message(&mut socket).await;
+        assert_eq!("todo.updated", message["event"]);
+        assert_eq!(1, message["data"]["id"]);
+        assert_eq!(json!([]), message["data"]["labels"]);
+
+        let req = build_req_with_empty(Method::DELETE, "/workspaces/1/todos/2");
+        app.oneshot(req).await.unwrap();
+        let message = next_message(&mut socket).await;
+        assert_eq!("todo.deleted", message["event"]);
+        assert_eq!(json!({"id": 2}), message["data"]);
+    }
+
+    #[tokio::test]
+    async fn should_stop_streaming_to_removed_members() {
+        use futures::{SinkExt, StreamExt};
+        use serde_json::json;
+        use tokio_tungstenite::tungstenite::{protocol::frame::coding::CloseCode, Message};
+
+        let member_id = TEST_USER_ID + 1;
+        let workspace_repository = WorkspaceRepositoryForMemory::with_owner(TEST_USER_ID);
+        workspace_repository
+            .set_member(TEST_WORKSPACE_ID, member_id, Role::Viewer)
+            .await
+            .expect("failed add member");
+        let event_bus = Arc::new(EventBus::new(REPLAY_CAPACITY));
+        let audit_repository = AuditRepositoryForMemory::new().with_event_bus(event_bus.clone());
+        let app = create_app(
+            TodoRepositoryForMemory::new().with_audit_repository(audit_repository.clone()),
+            LabelRepositoryForMemory::new().with_audit_repository(audit_repository.clone()),
+            UserRepositoryForMemory::new(),
+            workspace_repository.clone(),
+            audit_repository,
+            HealthRepositoryForMemory::new(),
+            event_bus,
+            &test_config(),
+        );
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.clone().into_make_service());
+        tokio::spawn(server);
+        let req = Request::builder()
+            .uri(format!("ws://{}/workspaces/1/ws", addr))
+            .header(header::AUTHORIZATION, bearer(member_id))
+            .body(())
+            .unwrap();
+        let (mut socket, _res) = tokio_tungstenite::connect_async(req)
+            .await
+            .expect("failed connect websocket");
+        let subscribe = json!({"type": "subscribe", "todos": [1, 2]}).to_string();
+        socket.send(Message::Text(subscribe)).await.unwrap();
+        let ack = tokio::time::timeout(Duration::from_secs(5), socket.next())
+            .await
+            .expect("no message in time")
+            .expect("socket closed")
+            .unwrap();
+        assert!(ack.to_text().unwrap().contains("ack"), "{}", ack);
+        let req = Request::builder()
+            .uri("/workspaces/1/events")
+            .header(header::AUTHORIZATION, bearer(member_id))
+            .body(Body::empty())
+            .unwrap();
+        let mut events = app.clone().oneshot(req).await.unwrap();
+        let create = |text: &str| {
+            build_req_with_json(
+                "/workspaces/1/todos",
+                Method::POST,
+                json!({"text": text, "labels": []}).to_string(),
+            )
+        };
+
+        // members follow the workspace
+        app.clone().oneshot(create("seen")).await.unwrap();
+        let chunk = next_chunk(&mut events).await;
+        assert!(chunk.contains("seen"), "{}", chunk);
+        let message = tokio::time::timeout(Duration::from_secs(5), socket.next())
+            .await
+            .expect("no message in time")
+            .expect("socket closed")
+            .unwrap();
+        assert!(message.to_text().unwrap().contains("seen"), "{}", message);
+
+        // until they are removed
+        workspace_repository
+            .remove_member(TEST_WORKSPACE_ID, member_id)
+            .await
+            .expect("failed remove member");
+        app.oneshot(create("unseen")).await.unwrap();
+        let end = tokio::time::timeout(Duration::from_secs(5), events.body_mut().data())
+            .await
+            .expect("stream not ended in time");
+        assert!(end.is_none(), "{:?}", end);
+        let message = tokio::time::timeout(Duration::from_secs(5), socket.next())
+            .await
+            .expect("no message in time")
+            .expect("socket closed")
+            .unwrap();
+        match message {
+            Message::Close(Some(frame)) => assert_eq!(CloseCode::Policy, frame.code),
+            message => panic!("expected close frame, got {:?}", message),
+        }
+    }
+
+    #[tokio::test]
+    async fn should_record_todo_history() {
+        let app = create_test_app(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+        );
+        let req = build_req_with_json(
+            "/workspaces/1/todos",
+            Method::POST,
+            r#"{"text":"should_record_todo_history", "labels": []}"#.to_string(),
+        );
+        app.clone().oneshot(req).await.unwrap();
+        let req = build_req_with_json(
+            "/workspaces/1/todos/1",
+            Method::PATCH,
+            r#"{"completed": true}"#.to_string(),
+        );
+        app.clone().oneshot(req).await.unwrap();
+        let req = build_req_with_json(
+            "/workspaces/1/labels",
+            Method::POST,
+            r#"{"name":"should_record_todo_history"}"#.to_string(),
+        );
+        app.clone().oneshot(req).await.unwrap();
+        let req = build_req_with_empty(Method::DELETE, "/workspaces/1/todos/1");
+        app.clone().oneshot(req).await.unwrap();
+
+        let req = build_req_with_empty(Method::GET, "/workspaces/1/todos/1/history");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let history: Vec<AuditEvent> = res_to_data(res).await;
+        let actions: Vec<AuditAction> = history.iter().map(|event| event.action).collect();
+        assert_eq!(
+            vec![
+                AuditAction::Create,
+                AuditAction::Update,
+                AuditAction::Delete
+            ],
+            actions
+        );
+        assert!(history
+            .iter()
+            .all(|event| event.actor_id == Some(TEST_USER_ID)));
+        assert_eq!(
+            Some(false),
+            history[1].before.as_ref().unwrap()["completed"].as_bool()
+        );
+        assert_eq!(
+            Some(true),
+            history[1].after.as_ref().unwrap()["completed"].as_bool()
+        );
+
+        let req = build_req_with_empty(Method::GET, "/workspaces/1/todos/2/history");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+        let req = build_req_with_empty(Method::GET, "/workspaces/1/audit?entity=label");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let events: Vec<AuditEvent> = res_to_data(res).await;
+        assert_eq!(1, events.len());
+        assert_eq!(AuditAction::Create, events[0].action);
+
+        let req = build_req_with_empty(Method::GET, "/workspaces/1/audit?action=erase");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_close_connections_on_shutdown() {
+        use futures::StreamExt;
+        use tokio_tungstenite::tungstenite::{protocol::frame::coding::CloseCode, Message};
+
+        let event_bus = Arc::new(EventBus::new(REPLAY_CAPACITY));
+        let app = create_test_app_with_event_bus(
+            TodoRepositoryForMemory::new(),
+            LabelRepositoryForMemory::new(),
+            event_bus.clone(),
+        );
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (stop, stopped) = oneshot::channel::<()>();
+        let server = tokio::spawn(serve_until(
+            axum::Server::from_tcp(listener).unwrap(),
+            app.clone(),
+            event_bus,
+            Duration::from_secs(5),
+            async {
+                stopped.await.ok();
+            },
+        ));
+        let req = build_req_with_empty(Method::GET, "/workspaces/1/events");
+        let mut events = app.oneshot(req).await.unwrap();
+        let req = Request::builder()
+            .uri(format!("ws://{}/workspaces/1/ws", addr))
+            .header(header::AUTHORIZATION, bearer(TEST_USER_ID))
+            .body(())
+            .unwrap();
+        let (mut socket, _res) = tokio_tungstenite::connect_async(req)
+            .await
+            .expect("failed connect websocket");
+
+        stop.send(()).unwrap();
+        let chunk = next_chunk(&mut events).await;
+        assert!(chunk.contains("event: shutdown\n"), "{}", chunk);
+        let end = tokio::time::timeout(Duration::from_secs(5), events.body_mut().data())
+            .await
+            .expect("stream not ended in time");
+        assert!(end.is_none());
+        let message = tokio::time::timeout(Duration::from_secs(5), socket.next())
+            .await
+            .expect("no message in time")
+            .expect("socket closed")
+            .unwrap();
+        match message {
+            Message::Close(Some(frame)) => assert_eq!(CloseCode::Away, frame.code),
+            message => panic!("expected close frame, got {:?}", message),
+        }
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .expect("server not drained in time")
+            .unwrap()
+            .expect("server failed");
+    }
+
+    #[tokio::test]
+    async fn should_stop_waiting_for_requests_after_drain_timeout() {
+        let started = Arc::new(tokio::sync::Notify::new());
+        let app = Router::new().route(
+            "/slow",
+            get({
+                let started = started.clone();
+                move || async move {
+                    started.notify_one();
+                    tokio::time::sleep(Duration::from_secs(60)).await;
+                }
+            }),
+        );
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (stop, stopped) = oneshot::channel::<()>();
+        let server = tokio::spawn(serve_until(
+            axum::Server::from_tcp(listener).unwrap(),
+            app,
+            Arc::new(EventBus::new(REPLAY_CAPACITY)),
+            Duration::from_millis(100),
+            async {
+                stopped.await.ok();
+            },
+        ));
+        tokio::spawn(hyper::Client::new().get(format!("http://{}/slow", addr).parse().unwrap()));
+        started.notified().await;
+
+        stop.send(()).unwrap();
+        let drained = tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .expect("server kept waiting for the request")
+            .unwrap()
+            .expect("server failed");
+        assert!(!drained);
+    }
+
+    #[cfg(feature = "database-test")]
+    #[tokio::test]
+    async fn should_bound_closing_the_pool() {
+        dotenv().ok();
+        let database_url = &std::env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database_url));
+        // a query that never returns its connection
+        let _connection = pool.acquire().await.unwrap();
+
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            close_pool(pool, true, Duration::from_millis(100)),
+        )
+        .await
+        .expect("pool close not bounded");
+    }
 }
@@ -0,0 +1,251 @@
+use crate::repositories::health::{DatabaseStats, HealthRepository, PoolStats};
+use axum::{
+    body::Body,
+    extract::{Extension, MatchedPath},
+    http::{header, Method, Request, StatusCode},
+    response::{Headers, IntoResponse, Response},
+};
+use futures::future::BoxFuture;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tower::{Layer, Service};
+
+/// How long the table counts are kept before a scrape reads them again.
+const STATS_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The Prometheus metrics of one app, rendered by `/metrics`.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    request_duration: HistogramVec,
+    repository_duration: HistogramVec,
+    pool_connections: IntGaugeVec,
+    todos: IntGaugeVec,
+    labels: IntGauge,
+    /// when a scrape last read the table counts
+    stats_read_at: Arc<Mutex<Option<Instant>>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+        let requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "Requests answered"),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time until the response started",
+            ),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let repository_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "repository_call_duration_seconds",
+                "Time spent in repository calls",
+            ),
+            &["repository", "method"],
+        )
+        .unwrap();
+        let pool_connections = IntGaugeVec::new(
+            Opts::new("db_pool_connections", "Connections of the database pool"),
+            &["state"],
+        )
+        .unwrap();
+        let todos =
+            IntGaugeVec::new(Opts::new("todos", "Todos outside the trash"), &["state"]).unwrap();
+        let labels = IntGauge::new("labels", "Labels").unwrap();
+        registry.register(Box::new(requests.clone())).unwrap();
+        registry
+            .register(Box::new(request_duration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(repository_duration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(pool_connections.clone()))
+            .unwrap();
+        registry.register(Box::new(todos.clone())).unwrap();
+        registry.register(Box::new(labels.clone())).unwrap();
+        Self {
+            registry,
+            requests,
+            request_duration,
+            repository_duration,
+            pool_connections,
+            todos,
+            labels,
+            stats_read_at: Arc::default(),
+        }
+    }
+
+    fn observe_request(&self, method: &Method, route: &str, status: StatusCode, elapsed: Duration) {
+        let labels = [method.as_str(), route, status.as_str()];
+        self.requests.with_label_values(&labels).inc();
+        self.request_duration
+            .with_label_values(&labels)
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// Runs a repository call, recording how long it took.
+    pub async fn time<T>(
+        &self,
+        repository: &str,
+        method: &str,
+        call: impl Future<Output = T>,
+    ) -> T {
+        let start = Instant::now();
+        let result = call.await;
+        self.repository_duration
+            .with_label_values(&[repository, method])
+            .observe(start.elapsed().as_secs_f64());
+        result
+    }
+
+    fn set_pool(&self, pool: &PoolStats) {
+        let active = pool.size.saturating_sub(pool.idle);
+        self.pool_connections
+            .with_label_values(&["active"])
+            .set(active.into());
+        self.pool_connections
+            .with_label_values(&["idle"])
+            .set(pool.idle.into());
+    }
+
+    /// Whether the table counts are due to be read again, claiming the read when they are,
+    /// so that concurrent scrapes do not read them twice.
+    fn claim_stats_read(&self) -> bool {
+        let mut read_at = self.stats_read_at.lock().unwrap();
+        match *read_at {
+            Some(at) if at.elapsed() < STATS_INTERVAL => false,
+            _ => {
+                *read_at = Some(Instant::now());
+                true
+            }
+        }
+    }
+
+    fn set_stats(&self, stats: &DatabaseStats) {
+        self.todos
+            .with_label_values(&["open"])
+            .set(stats.open_todos);
+        self.todos
+            .with_label_values(&["completed"])
+            .set(stats.completed_todos);
+        self.labels.set(stats.labels);
+    }
+
+    fn render(&self) -> String {
+        let mut buffer = vec![];
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("failed encode metrics");
+        String::from_utf8(buffer).expect("metrics are not UTF-8")
+    }
+}
+
+/// Serves the metrics in the Prometheus text format, reading the database gauges first.
+/// The table counts are read at most every `STATS_INTERVAL`, however often this is scraped.
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "health",
+    responses(
+        (status = 200, description = "Prometheus text format", body = String, content_type = "text/plain"),
+    ),
+    security(())
+)]
+pub async fn metrics<H: HealthRepository>(
+    Extension(metrics): Extension<Arc<Metrics>>,
+    Extension(health_repository): Extension<Arc<H>>,
+) -> impl IntoResponse {
+    metrics.set_pool(&health_repository.pool());
+    if metrics.claim_stats_read() {
+        match health_repository.stats().await {
+            Ok(stats) => metrics.set_stats(&stats),
+            // the gauges keep their last values
+            Err(err) => tracing::warn!("fail read database stats: {:?}", err),
+        }
+    }
+    (
+        Headers([(
+            header::CONTENT_TYPE,
+            TextEncoder::new().format_type().to_string(),
+        )]),
+        metrics.render(),
+    )
+}
+
+/// Counts and times requests by method, route and status. Added with `route_layer`, so
+/// that the route is known and requests matching none stay out of the metrics, and outside
+/// of the request timeout, so that timed out requests count with their `503`.
+#[derive(Debug, Clone)]
+pub struct MetricsLayer {
+    metrics: Arc<Metrics>,
+}
+
+impl MetricsLayer {
+    pub fn new(metrics: Arc<Metrics>) -> Self {
+        Self { metrics }
+    }
+}
+
+impl<S> Layer<S> for MetricsLayer {
+    type Service = MetricsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        MetricsService {
+            inner,
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MetricsService<S> {
+    inner: S,
+    metrics: Arc<Metrics>,
+}
+
+impl<S> Service<Request<Body>> for MetricsService<S>
+where
+    S: Service<Request<Body>, Response = Response>,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let method = req.method().clone();
+        let route = req
+            .extensions()
+            .get::<MatchedPath>()
+            .map(|path| path.as_str().to_string())
+            .unwrap_or_default();
+        let metrics = self.metrics.clone();
+        let start = Instant::now();
+        let response = self.inner.call(req);
+        Box::pin(async move {
+            let response = response.await?;
+            metrics.observe_request(&method, &route, response.status(), start.elapsed());
+            Ok(response)
+        })
+    }
+}
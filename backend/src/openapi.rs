@@ -0,0 +1,301 @@
+use crate::metrics;
+use crate::{
+    errors::Problem,
+    handlers::{
+        audit, auth,
+        auth::{AuthResponse, Credentials, StreamToken},
+        event,
+        health::{self, Health, Readiness, Version},
+        label::{self, CreateLabel},
+        todo,
+        workspace::{self, CreateWorkspace, PutMember},
+        ws,
+    },
+    repositories::{
+        audit::{AuditAction, AuditEntity, AuditEvent},
+        health::SchemaStatus,
+        label::{Label, UpdateLabel},
+        todo::{
+            BulkItemResult, BulkOperation, BulkStatus, BulkTodo, CreateTodo, MoveTodo, Priority,
+            SortOrder, TodoEntity, TodoPage, TodoSearchHit, TodoSort, UpdateTodo,
+        },
+        user::User,
+        workspace::{Member, Role, Workspace},
+    },
+};
+use axum::{
+    http::header,
+    response::{Headers, Html, IntoResponse},
+    Json,
+};
+use std::collections::BTreeMap;
+use utoipa::{
+    openapi::{
+        security::{Http, HttpAuthScheme, SecurityScheme},
+        ContentBuilder, Ref, RefOr, Response, ResponseBuilder,
+    },
+    IntoResponses, Modify, OpenApi,
+};
+
+/// The REST API, generated from the handler signatures and the payload types.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Todo API"),
+    paths(
+        auth::signup,
+        auth::login,
+        auth::stream_token,
+        workspace::create_workspace,
+        workspace::all_workspace,
+        workspace::all_member,
+        workspace::put_member,
+        workspace::delete_member,
+        todo::create_todo,
+        todo::all_todo,
+        todo::search_todo,
+        todo::find_todo,
+        todo::all_child_todo,
+        todo::update_todo,
+        todo::move_todo,
+        todo::bulk_todo,
+        todo::delete_todo,
+        todo::all_trash,
+        todo::restore_todo,
+        todo::purge_trash,
+        label::create_label,
+        label::all_label,
+        label::find_label,
+        label::update_label,
+        label::delete_label,
+        audit::todo_history,
+        audit::all_audit_event,
+        event::workspace_events,
+        ws::workspace_socket,
+        health::healthz,
+        health::readyz,
+        health::version,
+        metrics::metrics,
+    ),
+    components(schemas(
+        Problem,
+        Credentials,
+        AuthResponse,
+        StreamToken,
+        User,
+        CreateWorkspace,
+        PutMember,
+        Workspace,
+        Member,
+        Role,
+        CreateTodo,
+        UpdateTodo,
+        MoveTodo,
+        BulkTodo,
+        BulkOperation,
+        BulkStatus,
+        BulkItemResult,
+        TodoEntity,
+        TodoPage,
+        TodoSearchHit,
+        TodoSort,
+        SortOrder,
+        Priority,
+        CreateLabel,
+        UpdateLabel,
+        Label,
+        AuditEvent,
+        AuditEntity,
+        AuditAction,
+        Health,
+        Readiness,
+        Version,
+        SchemaStatus,
+    )),
+    modifiers(&BearerAuth, &UnauthorizedResponse),
+    security(("bearer" = [])),
+    tags(
+        (name = "auth", description = "Sign up and log in"),
+        (name = "workspaces", description = "Workspaces and their members"),
+        (name = "todos", description = "Todos, subtasks and the trash"),
+        (name = "labels", description = "Labels of a workspace"),
+        (name = "audit", description = "History of changes"),
+        (name = "events", description = "Live changes"),
+        (name = "health", description = "Probes, metrics and build information"),
+    )
+)]
+pub struct ApiDoc;
+
+/// Declares the JWT handed out by signup and login.
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        let mut scheme = Http::new(HttpAuthScheme::Bearer);
+        scheme.bearer_format = Some("JWT".to_string());
+        components.add_security_scheme("bearer", SecurityScheme::Http(scheme));
+    }
+}
+
+/// Documents the `401` of every operation requiring the token, rather than each of them.
+struct UnauthorizedResponse;
+
+impl Modify for UnauthorizedResponse {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let operations = openapi
+            .paths
+            .paths
+            .values_mut()
+            .flat_map(|path| path.operations.values_mut());
+        // public operations clear the security requirement with `security(())`
+        for operation in operations.filter(|operation| operation.security.is_none()) {
+            operation
+                .responses
+                .responses
+                .extend(Unauthorized::responses());
+        }
+    }
+}
+
+/// A response with a `Problem` body, like those of `ApiError`.
+fn problem(status: u16, description: &str) -> BTreeMap<String, RefOr<Response>> {
+    let content = ContentBuilder::new()
+        .schema(Ref::from_schema_name("Problem"))
+        .build();
+    let response = ResponseBuilder::new()
+        .description(description)
+        .content("application/problem+json", content)
+        .build();
+    BTreeMap::from([(status.to_string(), response.into())])
+}
+
+// The problems most operations share, listed in `responses(...)` of `utoipa::path` next to
+// their own.
+
+pub struct Unauthorized;
+
+impl IntoResponses for Unauthorized {
+    fn responses() -> BTreeMap<String, RefOr<Response>> {
+        problem(401, "Missing or invalid token")
+    }
+}
+
+pub struct EditorRequired;
+
+impl IntoResponses for EditorRequired {
+    fn responses() -> BTreeMap<String, RefOr<Response>> {
+        problem(403, "Editor role required")
+    }
+}
+
+pub struct OwnerRequired;
+
+impl IntoResponses for OwnerRequired {
+    fn responses() -> BTreeMap<String, RefOr<Response>> {
+        problem(403, "Owner role required")
+    }
+}
+
+pub struct NotMember;
+
+impl IntoResponses for NotMember {
+    fn responses() -> BTreeMap<String, RefOr<Response>> {
+        problem(404, "Workspace not found or not a member")
+    }
+}
+
+pub struct InvalidPayload;
+
+impl IntoResponses for InvalidPayload {
+    fn responses() -> BTreeMap<String, RefOr<Response>> {
+        problem(422, "Invalid payload")
+    }
+}
+
+pub struct InvalidQuery;
+
+impl IntoResponses for InvalidQuery {
+    fn responses() -> BTreeMap<String, RefOr<Response>> {
+        problem(422, "Invalid query")
+    }
+}
+
+pub async fn openapi_json() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+/// Swagger UI reading `/openapi.json`.
+pub async fn docs() -> Html<&'static str> {
+    Html(DOCS_PAGE)
+}
+
+/// The stylesheet of the Swagger UI, vendored in `assets/swagger-ui` rather than loaded
+/// from a CDN.
+pub async fn docs_css() -> impl IntoResponse {
+    asset(
+        mime::TEXT_CSS_UTF_8,
+        include_str!("../assets/swagger-ui/swagger-ui.css"),
+    )
+}
+
+/// The script of the Swagger UI, vendored like its stylesheet.
+pub async fn docs_js() -> impl IntoResponse {
+    asset(
+        mime::APPLICATION_JAVASCRIPT_UTF_8,
+        include_str!("../assets/swagger-ui/swagger-ui-bundle.js"),
+    )
+}
+
+fn asset(content_type: mime::Mime, body: &'static str) -> impl IntoResponse {
+    (
+        Headers([
+            (header::CONTENT_TYPE, content_type.to_string()),
+            // the assets only change with a new build
+            (header::CACHE_CONTROL, "public, max-age=86400".to_string()),
+        ]),
+        body,
+    )
+}
+
+const DOCS_PAGE: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <title>Todo API</title>
+  <link rel="stylesheet" href="/docs/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="/docs/swagger-ui-bundle.js"></script>
+  <script>
+    window.ui = SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });
+  </script>
+</body>
+</html>
+"##;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{env, fs, path::Path};
+
+    /// Fails when the document changes without the committed `openapi.json` being
+    /// updated; rerun with `UPDATE_OPENAPI=1` to accept the change.
+    #[test]
+    fn spec_snapshot() {
+        let spec = ApiDoc::openapi()
+            .to_pretty_json()
+            .expect("failed serialize spec")
+            + "\n";
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("openapi.json");
+        if env::var_os("UPDATE_OPENAPI").is_some() {
+            fs::write(&path, &spec).expect("failed write openapi.json");
+            return;
+        }
+        let committed = fs::read_to_string(&path).expect("failed read openapi.json");
+        assert!(
+            committed == spec,
+            "the OpenAPI document changed, rerun with UPDATE_OPENAPI=1 and review openapi.json"
+        );
+    }
+}
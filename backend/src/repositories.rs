@@ -1,13 +1,25 @@
+pub mod audit;
+pub mod health;
 pub mod label;
+pub mod measured;
 pub mod todo;
+pub mod unit_of_work;
+pub mod user;
+pub mod workspace;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
-enum RepositoryError {
+pub enum RepositoryError {
     #[error("Unexpected Error: [{0}]")]
     Unexpected(String),
     #[error("NotFound, id is {0}")]
     NotFound(i32),
     #[error("Duplicate data, id is {0}")]
     Duplicate(i32),
+    #[error("In use, id is {0}")]
+    InUse(i32, Vec<i32>),
+    #[error("Cycle, id is {0}")]
+    Cycle(i32),
+    #[error("Version mismatch, id is {0}")]
+    VersionMismatch(i32),
 }
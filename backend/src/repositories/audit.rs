@@ -0,0 +1,687 @@
+use super::unit_of_work::DbContext;
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{postgres::PgRow, FromRow, PgConnection, PgPool, Row};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+pub const DEFAULT_AUDIT_LIMIT: i64 = 50;
+/// Postgres channel on which every recorded event is announced to all replicas.
+pub const CHANGES_CHANNEL: &str = "changes";
+
+#[async_trait]
+pub trait AuditRepository: Clone + std::marker::Send + std::marker::Sync + 'static {
+    /// Every event about one todo or label, oldest first.
+    async fn history(
+        &self,
+        workspace_id: i32,
+        entity: AuditEntity,
+        entity_id: i32,
+    ) -> anyhow::Result<Vec<AuditEvent>>;
+    /// Events of the workspace matching the query, newest first.
+    async fn all(&self, workspace_id: i32, query: AuditQuery) -> anyhow::Result<Vec<AuditEvent>>;
+    /// The events with these ids, in any workspace, each with the id of its workspace.
+    async fn changes(&self, ids: Vec<i64>) -> anyhow::Result<Vec<(i32, AuditEvent)>>;
+    /// Up to `limit` events after `id`, in any workspace and oldest first, each with the id
+    /// of its workspace.
+    async fn changes_after(&self, id: i64, limit: i64) -> anyhow::Result<Vec<(i32, AuditEvent)>>;
+    /// The id of the latest event, 0 before the first one.
+    async fn latest_id(&self) -> anyhow::Result<i64>;
+}
+
+/// What an audit event is about.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, sqlx::Type, ToSchema)]
+#[sqlx(type_name = "audit_entity", rename_all = "lowercase")]
+#[serde(rename_all = "snake_case")]
+pub enum AuditEntity {
+    Todo,
+    Label,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, sqlx::Type, ToSchema)]
+#[sqlx(type_name = "audit_action", rename_all = "lowercase")]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Create,
+    Update,
+    /// moved to the trash for todos, gone for labels
+    Delete,
+    /// taken out of the trash
+    Restore,
+    /// removed from the trash for good, without snapshots
+    Purge,
+}
+
+/// One recorded change, with the todo or label as it was before and after it.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, FromRow, ToSchema)]
+pub struct AuditEvent {
+    pub id: i64,
+    /// the user who made the change, none for automatic ones like expiring the trash
+    pub actor_id: Option<i32>,
+    pub entity: AuditEntity,
+    pub entity_id: i32,
+    pub action: AuditAction,
+    #[schema(value_type = Option<Object>)]
+    pub before: Option<Value>,
+    #[schema(value_type = Option<Object>)]
+    pub after: Option<Value>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Payload of a notification on `CHANGES_CHANNEL`. Snapshots can outgrow the payload
+/// limit of `pg_notify`, so listeners read the event itself.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct ChangeNotice {
+    pub workspace_id: i32,
+    pub event_id: i64,
+}
+
+/// A change to record, built up by the repository making it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditRecord {
+    pub entity: AuditEntity,
+    pub entity_id: i32,
+    pub action: AuditAction,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+}
+
+impl AuditRecord {
+    pub fn new(entity: AuditEntity, entity_id: i32, action: AuditAction) -> Self {
+        Self {
+            entity,
+            entity_id,
+            action,
+            before: None,
+            after: None,
+        }
+    }
+
+    pub fn before<T: Serialize>(self, snapshot: &T) -> Self {
+        Self {
+            before: Some(to_snapshot(snapshot)),
+            ..self
+        }
+    }
+
+    pub fn after<T: Serialize>(self, snapshot: &T) -> Self {
+        Self {
+            after: Some(to_snapshot(snapshot)),
+            ..self
+        }
+    }
+}
+
+fn to_snapshot<T: Serialize>(snapshot: &T) -> Value {
+    // entities are plain structs with string keys, which always serialize
+    serde_json::to_value(snapshot).expect("entity is not serializable to json")
+}
+
+/// Query string of `GET /audit`, e.g. `?entity=todo&action=delete&actor_id=2`
+/// or `?since=2022-11-01T00:00:00Z`. Newest events come first; pass the id of the
+/// last one as `before_id` for the next page.
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq, Default, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AuditQuery {
+    #[validate(range(min = 1, max = 100, message = "Out of range"))]
+    #[param(minimum = 1, maximum = 100)]
+    pub limit: Option<i64>,
+    pub before_id: Option<i64>,
+    #[param(inline)]
+    pub entity: Option<AuditEntity>,
+    pub entity_id: Option<i32>,
+    pub actor_id: Option<i32>,
+    #[param(inline)]
+    pub action: Option<AuditAction>,
+    /// events at or after this time
+    pub since: Option<DateTime<Utc>>,
+    /// events strictly before this time
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl AuditQuery {
+    pub fn limit(&self) -> i64 {
+        self.limit.unwrap_or(DEFAULT_AUDIT_LIMIT)
+    }
+}
+
+/// Appends the changes to the audit log of the workspace, in the caller's transaction,
+/// and announces them on `CHANGES_CHANNEL` once it commits.
+pub async fn record_events(
+    conn: &mut PgConnection,
+    workspace_id: i32,
+    actor_id: Option<i32>,
+    records: Vec<AuditRecord>,
+) -> anyhow::Result<()> {
+    for record in records {
+        let event_id: i64 = sqlx::query_scalar(
+            r#"insert into audit_events (workspace_id, actor_id, entity, entity_id, action, before, after) values ($1, $2, $3, $4, $5, $6, $7) returning id;"#,
+        )
+        .bind(workspace_id)
+        .bind(actor_id)
+        .bind(record.entity)
+        .bind(record.entity_id)
+        .bind(record.action)
+        .bind(record.before)
+        .bind(record.after)
+        .fetch_one(&mut *conn)
+        .await?;
+        let notice = serde_json::to_string(&ChangeNotice {
+            workspace_id,
+            event_id,
+        })?;
+        sqlx::query(r#"select pg_notify($1, $2);"#)
+            .bind(CHANGES_CHANNEL)
+            .bind(notice)
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub struct AuditRepositoryForDb {
+    ctx: DbContext,
+}
+
+impl AuditRepositoryForDb {
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            ctx: DbContext::Pool(pool),
+        }
+    }
+
+    /// Lets database tests read the log inside a unit of work.
+    #[cfg(test)]
+    pub fn with_context(ctx: DbContext) -> Self {
+        Self { ctx }
+    }
+}
+
+async fn entity_history(
+    conn: &mut PgConnection,
+    workspace_id: i32,
+    entity: AuditEntity,
+    entity_id: i32,
+) -> anyhow::Result<Vec<AuditEvent>> {
+    let events = sqlx::query_as::<_, AuditEvent>(
+        r#"select * from audit_events where workspace_id = $1 and entity = $2 and entity_id = $3 order by id;"#,
+    )
+    .bind(workspace_id)
+    .bind(entity)
+    .bind(entity_id)
+    .fetch_all(conn)
+    .await?;
+
+    Ok(events)
+}
+
+async fn all_events(
+    conn: &mut PgConnection,
+    workspace_id: i32,
+    query: AuditQuery,
+) -> anyhow::Result<Vec<AuditEvent>> {
+    let events = sqlx::query_as::<_, AuditEvent>(
+        r#"select * from audit_events
+        where workspace_id = $1
+        and ($2::bigint is null or id < $2)
+        and ($3::audit_entity is null or entity = $3)
+        and ($4::integer is null or entity_id = $4)
+        and ($5::integer is null or actor_id = $5)
+        and ($6::audit_action is null or action = $6)
+        and ($7::timestamptz is null or created_at >= $7)
+        and ($8::timestamptz is null or created_at < $8)
+        order by id desc limit $9;"#,
+    )
+    .bind(workspace_id)
+    .bind(query.before_id)
+    .bind(query.entity)
+    .bind(query.entity_id)
+    .bind(query.actor_id)
+    .bind(query.action)
+    .bind(query.since)
+    .bind(query.until)
+    .bind(query.limit())
+    .fetch_all(conn)
+    .await?;
+
+    Ok(events)
+}
+
+fn workspace_event(row: &PgRow) -> sqlx::Result<(i32, AuditEvent)> {
+    Ok((row.try_get("workspace_id")?, AuditEvent::from_row(row)?))
+}
+
+async fn find_changes(
+    conn: &mut PgConnection,
+    ids: Vec<i64>,
+) -> anyhow::Result<Vec<(i32, AuditEvent)>> {
+    let rows = sqlx::query(r#"select * from audit_events where id = any($1) order by id;"#)
+        .bind(ids)
+        .fetch_all(conn)
+        .await?;
+
+    Ok(rows
+        .iter()
+        .map(workspace_event)
+        .collect::<sqlx::Result<_>>()?)
+}
+
+async fn changes_after(
+    conn: &mut PgConnection,
+    id: i64,
+    limit: i64,
+) -> anyhow::Result<Vec<(i32, AuditEvent)>> {
+    let rows = sqlx::query(r#"select * from audit_events where id > $1 order by id limit $2;"#)
+        .bind(id)
+        .bind(limit)
+        .fetch_all(conn)
+        .await?;
+
+    Ok(rows
+        .iter()
+        .map(workspace_event)
+        .collect::<sqlx::Result<_>>()?)
+}
+
+async fn latest_event_id(conn: &mut PgConnection) -> anyhow::Result<i64> {
+    let id = sqlx::query_scalar(r#"select coalesce(max(id), 0) from audit_events;"#)
+        .fetch_one(conn)
+        .await?;
+
+    Ok(id)
+}
+
+#[async_trait]
+impl AuditRepository for AuditRepositoryForDb {
+    #[tracing::instrument(name = "audit.history", skip(self))]
+    async fn history(
+        &self,
+        workspace_id: i32,
+        entity: AuditEntity,
+        entity_id: i32,
+    ) -> anyhow::Result<Vec<AuditEvent>> {
+        self.ctx
+            .transaction(|conn| Box::pin(entity_history(conn, workspace_id, entity, entity_id)))
+            .await
+    }
+
+    #[tracing::instrument(name = "audit.all", skip(self, query))]
+    async fn all(&self, workspace_id: i32, query: AuditQuery) -> anyhow::Result<Vec<AuditEvent>> {
+        self.ctx
+            .transaction(|conn| Box::pin(all_events(conn, workspace_id, query)))
+            .await
+    }
+
+    #[tracing::instrument(name = "audit.changes", skip(self))]
+    async fn changes(&self, ids: Vec<i64>) -> anyhow::Result<Vec<(i32, AuditEvent)>> {
+        self.ctx
+            .transaction(|conn| Box::pin(find_changes(conn, ids)))
+            .await
+    }
+
+    #[tracing::instrument(name = "audit.changes_after", skip(self))]
+    async fn changes_after(&self, id: i64, limit: i64) -> anyhow::Result<Vec<(i32, AuditEvent)>> {
+        self.ctx
+            .transaction(|conn| Box::pin(changes_after(conn, id, limit)))
+            .await
+    }
+
+    #[tracing::instrument(name = "audit.latest_id", skip(self))]
+    async fn latest_id(&self) -> anyhow::Result<i64> {
+        self.ctx
+            .transaction(|conn| Box::pin(latest_event_id(conn)))
+            .await
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod test {
+    use super::*;
+    use crate::repositories::{
+        label::{DeleteLabelStrategy, LabelRepository, UpdateLabel},
+        todo::{CreateTodo, TodoEntity, TodoRepository, UpdateTodo},
+        unit_of_work::{UnitOfWork, UnitOfWorkForDb},
+        user::{test_utils::prepare_user, UserRepositoryForDb},
+        workspace::{test_utils::prepare_workspace, WorkspaceRepositoryForDb},
+    };
+    use dotenv::dotenv;
+    use std::env;
+
+    #[tokio::test]
+    async fn audit_scenario() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database_url));
+        // nothing is committed, so other tests never see these rows
+        let uow = UnitOfWorkForDb::begin(&pool)
+            .await
+            .expect("[begin] returned Err");
+        let ctx = DbContext::UnitOfWork(uow.clone());
+        let users = UserRepositoryForDb::with_context(ctx.clone());
+        let workspace = prepare_workspace(
+            &users,
+            &WorkspaceRepositoryForDb::with_context(ctx.clone()),
+            "[audit_scenario] user",
+            "[audit_scenario] workspace",
+        )
+        .await;
+        let user = prepare_user(&users, "[audit_scenario] user").await;
+        let todos = uow.todos().acting_as(user.id);
+        let labels = uow.labels().acting_as(user.id);
+        let repository = AuditRepositoryForDb::with_context(ctx);
+
+        let label = labels
+            .create(workspace.id, "[audit_scenario] label".to_string())
+            .await
+            .expect("[create label] returned Err");
+        let todo = todos
+            .create(
+                workspace.id,
+                CreateTodo::new("[audit_scenario] todo".to_string(), vec![label.id]),
+            )
+            .await
+            .expect("[create] returned Err");
+        let updated = todos
+            .update(
+                workspace.id,
+                todo.id,
+                UpdateTodo::new(None, Some(true), None),
+                None,
+            )
+            .await
+            .expect("[update] returned Err");
+        todos
+            .delete(workspace.id, todo.id, None)
+            .await
+            .expect("[delete] returned Err");
+        todos
+            .restore(workspace.id, todo.id)
+            .await
+            .expect("[restore] returned Err");
+        labels
+            .update(
+                workspace.id,
+                label.id,
+                UpdateLabel {
+                    name: "[audit_scenario] renamed".to_string(),
+                },
+            )
+            .await
+            .expect("[update label] returned Err");
+        labels
+            .delete(workspace.id, label.id, DeleteLabelStrategy::Detach)
+            .await
+            .expect("[delete label] returned Err");
+
+        // the history of a todo holds snapshots around each change
+        let history = repository
+            .history(workspace.id, AuditEntity::Todo, todo.id)
+            .await
+            .expect("[history] returned Err");
+        let actions: Vec<AuditAction> = history.iter().map(|event| event.action).collect();
+        assert_eq!(
+            vec![
+                AuditAction::Create,
+                AuditAction::Update,
+                AuditAction::Delete,
+                AuditAction::Restore,
+                AuditAction::Update
+            ],
+            actions
+        );
+        assert!(history.iter().all(|event| event.actor_id == Some(user.id)));
+        assert_eq!(None, history[0].before);
+        assert_eq!(Some(to_snapshot(&todo)), history[0].after);
+        assert_eq!(Some(to_snapshot(&todo)), history[1].before);
+        assert_eq!(Some(to_snapshot(&updated)), history[1].after);
+        assert_eq!(None, history[2].after);
+        // detaching a deleted label changes the todo as well
+        let detached: TodoEntity =
+            serde_json::from_value(history[4].after.clone().unwrap()).unwrap();
+        assert!(detached.labels.is_empty());
+
+        // filters combine, newest first
+        let events = repository
+            .all(
+                workspace.id,
+                AuditQuery {
+                    entity: Some(AuditEntity::Label),
+                    ..AuditQuery::default()
+                },
+            )
+            .await
+            .expect("[all] returned Err");
+        let actions: Vec<AuditAction> = events.iter().map(|event| event.action).collect();
+        assert_eq!(
+            vec![
+                AuditAction::Delete,
+                AuditAction::Update,
+                AuditAction::Create
+            ],
+            actions
+        );
+        let events = repository
+            .all(
+                workspace.id,
+                AuditQuery {
+                    action: Some(AuditAction::Update),
+                    before_id: Some(events[0].id),
+                    limit: Some(1),
+                    ..AuditQuery::default()
+                },
+            )
+            .await
+            .expect("[all] returned Err");
+        assert_eq!(1, events.len());
+        assert_eq!(AuditEntity::Label, events[0].entity);
+
+        // the log is append-only
+        let res = DbContext::UnitOfWork(uow.clone())
+            .transaction(|conn| {
+                Box::pin(async move {
+                    sqlx::query(r#"delete from audit_events where workspace_id = $1;"#)
+                        .bind(workspace.id)
+                        .execute(conn)
+                        .await?;
+                    Ok(())
+                })
+            })
+            .await;
+        assert!(res.is_err());
+    }
+}
+
+#[cfg(test)]
+pub mod test_utils {
+    use super::*;
+    use crate::events::EventBus;
+    use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+    /// events in the order they happened, together with the id of their workspace
+    type AuditData = Vec<(i32, AuditEvent)>;
+
+    #[derive(Debug, Clone)]
+    pub struct AuditRepositoryForMemory {
+        store: Arc<RwLock<AuditData>>,
+        event_bus: Option<Arc<EventBus>>,
+    }
+
+    impl AuditRepositoryForMemory {
+        pub fn new() -> Self {
+            AuditRepositoryForMemory {
+                store: Arc::default(),
+                event_bus: None,
+            }
+        }
+
+        /// Publishes recorded events straight away, in place of the database listener.
+        pub fn with_event_bus(self, event_bus: Arc<EventBus>) -> Self {
+            Self {
+                event_bus: Some(event_bus),
+                ..self
+            }
+        }
+
+        fn write_store_ref(&self) -> RwLockWriteGuard<'_, AuditData> {
+            self.store.write().unwrap()
+        }
+
+        fn read_store_ref(&self) -> RwLockReadGuard<'_, AuditData> {
+            self.store.read().unwrap()
+        }
+
+        /// Same as `record_events` of the database repositories.
+        pub fn record(&self, workspace_id: i32, actor_id: Option<i32>, records: Vec<AuditRecord>) {
+            let mut store = self.write_store_ref();
+            for record in records {
+                let event = AuditEvent {
+                    id: store.len() as i64 + 1,
+                    actor_id,
+                    entity: record.entity,
+                    entity_id: record.entity_id,
+                    action: record.action,
+                    before: record.before,
+                    after: record.after,
+                    created_at: Utc::now(),
+                };
+                if let Some(event_bus) = &self.event_bus {
+                    event_bus.publish_audit(workspace_id, &event);
+                }
+                store.push((workspace_id, event));
+            }
+        }
+    }
+
+    #[async_trait]
+    impl AuditRepository for AuditRepositoryForMemory {
+        async fn history(
+            &self,
+            workspace_id: i32,
+            entity: AuditEntity,
+            entity_id: i32,
+        ) -> anyhow::Result<Vec<AuditEvent>> {
+            let store = self.read_store_ref();
+            Ok(store
+                .iter()
+                .filter(|(scope, event)| {
+                    *scope == workspace_id && event.entity == entity && event.entity_id == entity_id
+                })
+                .map(|(_scope, event)| event.clone())
+                .collect())
+        }
+
+        async fn all(
+            &self,
+            workspace_id: i32,
+            query: AuditQuery,
+        ) -> anyhow::Result<Vec<AuditEvent>> {
+            let store = self.read_store_ref();
+            Ok(store
+                .iter()
+                .rev()
+                .filter(|(scope, _event)| *scope == workspace_id)
+                .map(|(_scope, event)| event)
+                .filter(|event| query.before_id.is_none_or(|id| event.id < id))
+                .filter(|event| query.entity.is_none_or(|entity| event.entity == entity))
+                .filter(|event| query.entity_id.is_none_or(|id| event.entity_id == id))
+                .filter(|event| query.actor_id.is_none_or(|id| event.actor_id == Some(id)))
+                .filter(|event| query.action.is_none_or(|action| event.action == action))
+                .filter(|event| query.since.is_none_or(|since| event.created_at >= since))
+                .filter(|event| query.until.is_none_or(|until| event.created_at < until))
+                .take(query.limit() as usize)
+                .cloned()
+                .collect())
+        }
+
+        async fn changes(&self, ids: Vec<i64>) -> anyhow::Result<Vec<(i32, AuditEvent)>> {
+            let store = self.read_store_ref();
+            Ok(store
+                .iter()
+                .filter(|(_scope, event)| ids.contains(&event.id))
+                .cloned()
+                .collect())
+        }
+
+        async fn changes_after(
+            &self,
+            id: i64,
+            limit: i64,
+        ) -> anyhow::Result<Vec<(i32, AuditEvent)>> {
+            let store = self.read_store_ref();
+            Ok(store
+                .iter()
+                .filter(|(_scope, event)| event.id > id)
+                .take(limit as usize)
+                .cloned()
+                .collect())
+        }
+
+        async fn latest_id(&self) -> anyhow::Result<i64> {
+            Ok(self.read_store_ref().len() as i64)
+        }
+    }
+
+    mod test {
+        use super::*;
+
+        const WORKSPACE_ID: i32 = 1;
+        const OTHER_WORKSPACE_ID: i32 = 2;
+
+        #[tokio::test]
+        async fn audit_query_scenario() {
+            let repository = AuditRepositoryForMemory::new();
+            repository.record(
+                WORKSPACE_ID,
+                Some(1),
+                vec![
+                    AuditRecord::new(AuditEntity::Todo, 1, AuditAction::Create).after(&"todo"),
+                    AuditRecord::new(AuditEntity::Label, 1, AuditAction::Create).after(&"label"),
+                ],
+            );
+            repository.record(
+                WORKSPACE_ID,
+                None,
+                vec![AuditRecord::new(AuditEntity::Todo, 1, AuditAction::Purge)],
+            );
+            repository.record(
+                OTHER_WORKSPACE_ID,
+                Some(1),
+                vec![AuditRecord::new(AuditEntity::Todo, 1, AuditAction::Create)],
+            );
+
+            let history = repository
+                .history(WORKSPACE_ID, AuditEntity::Todo, 1)
+                .await
+                .unwrap();
+            let ids: Vec<i64> = history.iter().map(|event| event.id).collect();
+            assert_eq!(vec![1, 3], ids);
+            assert_eq!(Some(Value::from("todo")), history[0].after);
+
+            let events = repository
+                .all(WORKSPACE_ID, AuditQuery::default())
+                .await
+                .unwrap();
+            let ids: Vec<i64> = events.iter().map(|event| event.id).collect();
+            assert_eq!(vec![3, 2, 1], ids);
+            let events = repository
+                .all(
+                    WORKSPACE_ID,
+                    AuditQuery {
+                        actor_id: Some(1),
+                        before_id: Some(2),
+                        ..AuditQuery::default()
+                    },
+                )
+                .await
+                .unwrap();
+            let ids: Vec<i64> = events.iter().map(|event| event.id).collect();
+            assert_eq!(vec![1], ids);
+        }
+    }
+}
@@ -0,0 +1,270 @@
+use axum::async_trait;
+use serde::Serialize;
+use sqlx::{migrate::Migrator, PgPool};
+use utoipa::ToSchema;
+
+/// The migrations built into the binary, checked against the database for readiness.
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// The database schema compared to `MIGRATOR`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct SchemaStatus {
+    /// the latest migration applied
+    pub version: Option<i64>,
+    /// migrations of this build not applied yet
+    pub pending: Vec<i64>,
+}
+
+impl SchemaStatus {
+    fn new(applied: &[i64]) -> Self {
+        Self {
+            version: applied.iter().max().copied(),
+            pending: MIGRATOR
+                .iter()
+                .filter(|migration| !migration.migration_type.is_down_migration())
+                .map(|migration| migration.version)
+                .filter(|version| !applied.contains(version))
+                .collect(),
+        }
+    }
+}
+
+/// Connections of the pool, read by every metrics scrape.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    pub size: u32,
+    pub idle: u32,
+}
+
+/// Counts of the domain tables. They take full table scans, so the metrics read them
+/// less often than they are scraped.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DatabaseStats {
+    /// todos outside the trash
+    pub open_todos: i64,
+    pub completed_todos: i64,
+    pub labels: i64,
+}
+
+/// The state of the database, for the probes and the metrics.
+#[async_trait]
+pub trait HealthRepository: Clone + std::marker::Send + std::marker::Sync + 'static {
+    /// Fails when the database cannot be reached.
+    async fn schema(&self) -> anyhow::Result<SchemaStatus>;
+    fn pool(&self) -> PoolStats;
+    async fn stats(&self) -> anyhow::Result<DatabaseStats>;
+}
+
+#[derive(Debug, Clone)]
+pub struct HealthRepositoryForDb {
+    pool: PgPool,
+}
+
+impl HealthRepositoryForDb {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+/// Postgres' `undefined_table`, raised before the first migration.
+const UNDEFINED_TABLE: &str = "42P01";
+
+#[async_trait]
+impl HealthRepository for HealthRepositoryForDb {
+    #[tracing::instrument(name = "health.schema", skip(self))]
+    async fn schema(&self) -> anyhow::Result<SchemaStatus> {
+        let applied =
+            sqlx::query_scalar::<_, i64>(r#"select version from _sqlx_migrations where success;"#)
+                .fetch_all(&self.pool)
+                .await;
+        match applied {
+            Ok(applied) => Ok(SchemaStatus::new(&applied)),
+            Err(sqlx::Error::Database(err)) if err.code().as_deref() == Some(UNDEFINED_TABLE) => {
+                Ok(SchemaStatus::new(&[]))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn pool(&self) -> PoolStats {
+        PoolStats {
+            size: self.pool.size(),
+            idle: self.pool.num_idle() as u32,
+        }
+    }
+
+    #[tracing::instrument(name = "health.stats", skip(self))]
+    async fn stats(&self) -> anyhow::Result<DatabaseStats> {
+        let (open_todos, completed_todos, labels) = sqlx::query_as::<_, (i64, i64, i64)>(
+            r#"select (select count(*) from todos where deleted_at is null and not completed), (select count(*) from todos where deleted_at is null and completed), (select count(*) from labels);"#,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(DatabaseStats {
+            open_todos,
+            completed_todos,
+            labels,
+        })
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod test {
+    use super::*;
+    use dotenv::dotenv;
+    use std::env;
+
+    #[tokio::test]
+    async fn schema_scenario() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database_url));
+        let status = HealthRepositoryForDb::new(pool)
+            .schema()
+            .await
+            .expect("[schema] returned Err");
+        assert_eq!(test_utils::latest_version(), status.version);
+        assert!(status.pending.is_empty(), "{:?}", status.pending);
+    }
+
+    #[tokio::test]
+    async fn stats_scenario() {
+        use crate::repositories::{
+            label::{LabelRepository, LabelRepositoryForDb},
+            todo::{CreateTodo, TodoRepository, TodoRepositoryForDb, UpdateTodo},
+            user::UserRepositoryForDb,
+            workspace::{test_utils::prepare_workspace, WorkspaceRepositoryForDb},
+        };
+
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database_url));
+        let workspace = prepare_workspace(
+            &UserRepositoryForDb::new(pool.clone()),
+            &WorkspaceRepositoryForDb::new(pool.clone()),
+            "[stats_scenario] user",
+            "[stats_scenario] workspace",
+        )
+        .await;
+        let repository = HealthRepositoryForDb::new(pool.clone());
+        let todos = TodoRepositoryForDb::new(pool.clone());
+        repository.stats().await.expect("[stats] returned Err");
+        assert!(repository.pool().size >= 1);
+
+        todos
+            .create(
+                workspace.id,
+                CreateTodo::new("[stats_scenario] open".to_string(), vec![]),
+            )
+            .await
+            .expect("[create] returned Err");
+        let completed = todos
+            .create(
+                workspace.id,
+                CreateTodo::new("[stats_scenario] completed".to_string(), vec![]),
+            )
+            .await
+            .expect("[create] returned Err");
+        todos
+            .update(
+                workspace.id,
+                completed.id,
+                UpdateTodo::new(None, Some(true), None),
+                None,
+            )
+            .await
+            .expect("[update] returned Err");
+        LabelRepositoryForDb::new(pool.clone())
+            // the scenario commits, so a rerun needs a name that is not taken yet
+            .create(
+                workspace.id,
+                format!("[stats_scenario] label {}", completed.id),
+            )
+            .await
+            .expect("[create] returned Err");
+        let after = repository.stats().await.expect("[stats] returned Err");
+        // other tests write to the database at the same time
+        assert!(after.open_todos >= 1 && after.completed_todos >= 1 && after.labels >= 1);
+    }
+}
+
+#[cfg(test)]
+pub mod test_utils {
+    use super::*;
+    use anyhow::anyhow;
+    use std::{
+        sync::{Arc, RwLock},
+        time::Duration,
+    };
+
+    pub fn latest_version() -> Option<i64> {
+        MIGRATOR.iter().map(|migration| migration.version).max()
+    }
+
+    /// A database with every migration applied, until told otherwise.
+    #[derive(Debug, Clone)]
+    pub struct HealthRepositoryForMemory {
+        status: Arc<RwLock<Option<SchemaStatus>>>,
+        pool: Arc<RwLock<PoolStats>>,
+        stats: Arc<RwLock<DatabaseStats>>,
+        delay: Arc<RwLock<Duration>>,
+    }
+
+    impl HealthRepositoryForMemory {
+        pub fn new() -> Self {
+            Self {
+                status: Arc::new(RwLock::new(Some(SchemaStatus {
+                    version: latest_version(),
+                    pending: vec![],
+                }))),
+                pool: Arc::default(),
+                stats: Arc::default(),
+                delay: Arc::default(),
+            }
+        }
+
+        pub fn set_pool(&self, pool: PoolStats) {
+            *self.pool.write().unwrap() = pool;
+        }
+
+        pub fn set_stats(&self, stats: DatabaseStats) {
+            *self.stats.write().unwrap() = stats;
+        }
+
+        /// Makes reading the schema take `delay`, like a database under load.
+        pub fn set_delay(&self, delay: Duration) {
+            *self.delay.write().unwrap() = delay;
+        }
+
+        /// `None` makes the database unreachable.
+        pub fn set(&self, status: Option<SchemaStatus>) {
+            *self.status.write().unwrap() = status;
+        }
+    }
+
+    #[async_trait]
+    impl HealthRepository for HealthRepositoryForMemory {
+        async fn schema(&self) -> anyhow::Result<SchemaStatus> {
+            let delay = *self.delay.read().unwrap();
+            tokio::time::sleep(delay).await;
+            self.status
+                .read()
+                .unwrap()
+                .clone()
+                .ok_or_else(|| anyhow!("database unreachable"))
+        }
+
+        fn pool(&self) -> PoolStats {
+            self.pool.read().unwrap().clone()
+        }
+
+        async fn stats(&self) -> anyhow::Result<DatabaseStats> {
+            Ok(self.stats.read().unwrap().clone())
+        }
+    }
+}
@@ -1,78 +1,333 @@
+use super::audit::{record_events, AuditAction, AuditEntity, AuditRecord};
+use super::todo::{find_todos, todo_record};
+use super::unit_of_work::DbContext;
 use super::RepositoryError;
 use axum::async_trait;
 use serde::{Deserialize, Serialize};
-use sqlx::PgPool;
+use sqlx::{PgConnection, PgPool};
+use utoipa::ToSchema;
+use validator::Validate;
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, sqlx::FromRow)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, sqlx::FromRow, ToSchema)]
 pub struct Label {
     pub id: i32,
     pub name: String,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate, ToSchema)]
 pub struct UpdateLabel {
-    pub id: i32,
+    #[validate(length(min = 1, message = "Can not be empty"))]
+    #[validate(length(max = 100, message = "Over text length"))]
+    #[schema(min_length = 1, max_length = 100)]
     pub name: String,
 }
 
+/// What happens to todos carrying a label when the label is deleted. Trashed todos go along
+/// without holding the label back, so a restored todo comes back with the label it was
+/// reassigned to, or without the deleted one.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(try_from = "String")]
+pub enum DeleteLabelStrategy {
+    /// fail with the ids of the live todos using the label
+    #[default]
+    Refuse,
+    /// remove the label from its todos
+    Detach,
+    /// move its todos over to another label
+    Reassign(i32),
+}
+
+impl TryFrom<String> for DeleteLabelStrategy {
+    type Error = String;
+
+    fn try_from(strategy: String) -> Result<Self, Self::Error> {
+        match strategy.split_once(':') {
+            None if strategy == "refuse" => Ok(DeleteLabelStrategy::Refuse),
+            None if strategy == "detach" => Ok(DeleteLabelStrategy::Detach),
+            Some(("reassign", id)) => id
+                .parse()
+                .map(DeleteLabelStrategy::Reassign)
+                .map_err(|_| format!("invalid label id `{}`", id)),
+            _ => Err(format!(
+                "unknown strategy `{}`, expected refuse, detach or reassign:<id>",
+                strategy
+            )),
+        }
+    }
+}
+
 #[async_trait]
 pub trait LabelRepository: Clone + std::marker::Send + std::marker::Sync + 'static {
-    async fn create(&self, name: String) -> anyhow::Result<Label>;
-    async fn all(&self) -> anyhow::Result<Vec<Label>>;
-    async fn delete(&self, id: i32) -> anyhow::Result<()>;
+    /// The same repository, recording `user_id` as the author of its changes.
+    fn acting_as(&self, user_id: i32) -> Self;
+    async fn create(&self, workspace_id: i32, name: String) -> anyhow::Result<Label>;
+    async fn find(&self, workspace_id: i32, id: i32) -> anyhow::Result<Label>;
+    async fn all(&self, workspace_id: i32) -> anyhow::Result<Vec<Label>>;
+    async fn update(
+        &self,
+        workspace_id: i32,
+        id: i32,
+        payload: UpdateLabel,
+    ) -> anyhow::Result<Label>;
+    async fn delete(
+        &self,
+        workspace_id: i32,
+        id: i32,
+        strategy: DeleteLabelStrategy,
+    ) -> anyhow::Result<()>;
 }
 
 #[derive(Debug, Clone)]
 pub struct LabelRepositoryForDb {
-    pool: PgPool,
+    ctx: DbContext,
+    /// the user changes are recorded for in the audit log
+    actor: Option<i32>,
 }
 
 impl LabelRepositoryForDb {
     pub fn new(pool: PgPool) -> Self {
-        Self { pool }
+        Self {
+            ctx: DbContext::Pool(pool),
+            actor: None,
+        }
+    }
+
+    /// Lets database tests change labels inside a unit of work.
+    #[cfg(test)]
+    pub fn with_context(ctx: DbContext) -> Self {
+        Self { ctx, actor: None }
     }
 }
 
-#[async_trait]
-impl LabelRepository for LabelRepositoryForDb {
-    async fn create(&self, name: String) -> anyhow::Result<Label> {
-        let optional_label = sqlx::query_as::<_, Label>(r#"select * from labels where name = $1;"#)
-            .bind(name.clone())
-            .fetch_optional(&self.pool)
-            .await?;
+fn label_record(action: AuditAction, id: i32) -> AuditRecord {
+    AuditRecord::new(AuditEntity::Label, id, action)
+}
+
+/// Bumps the version of the todos carrying the label, which show its name, so that their
+/// `ETag` changes with it.
+async fn touch_todos_of(conn: &mut PgConnection, id: i32) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"update todos set version = version + 1 where id in (select todo_id from todo_labels where label_id = $1);"#,
+    )
+    .bind(id)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+async fn create_label(
+    conn: &mut PgConnection,
+    workspace_id: i32,
+    actor: Option<i32>,
+    name: String,
+) -> anyhow::Result<Label> {
+    let optional_label = sqlx::query_as::<_, Label>(
+        r#"select * from labels where name = $1 and workspace_id = $2;"#,
+    )
+    .bind(name.clone())
+    .bind(workspace_id)
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    if let Some(label) = optional_label {
+        return Err(RepositoryError::Duplicate(label.id).into());
+    }
+
+    let label = sqlx::query_as::<_, Label>(
+        r#"insert into labels ( name, workspace_id ) values ( $1, $2 ) returning *;"#,
+    )
+    .bind(name.clone())
+    .bind(workspace_id)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let record = label_record(AuditAction::Create, label.id).after(&label);
+    record_events(conn, workspace_id, actor, vec![record]).await?;
+    Ok(label)
+}
 
-        if let Some(label) = optional_label {
-            return Err(RepositoryError::Duplicate(label.id).into());
+async fn find_label(conn: &mut PgConnection, workspace_id: i32, id: i32) -> anyhow::Result<Label> {
+    let label =
+        sqlx::query_as::<_, Label>(r#"select * from labels where id = $1 and workspace_id = $2;"#)
+            .bind(id)
+            .bind(workspace_id)
+            .fetch_optional(&mut *conn)
+            .await?
+            .ok_or(RepositoryError::NotFound(id))?;
+
+    Ok(label)
+}
+
+async fn update_label(
+    conn: &mut PgConnection,
+    workspace_id: i32,
+    actor: Option<i32>,
+    id: i32,
+    payload: UpdateLabel,
+) -> anyhow::Result<Label> {
+    let optional_label = sqlx::query_as::<_, Label>(
+        r#"select * from labels where name = $1 and id <> $2 and workspace_id = $3;"#,
+    )
+    .bind(payload.name.clone())
+    .bind(id)
+    .bind(workspace_id)
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    if let Some(label) = optional_label {
+        return Err(RepositoryError::Duplicate(label.id).into());
+    }
+
+    let old_label = find_label(&mut *conn, workspace_id, id).await?;
+    let label = sqlx::query_as::<_, Label>(
+        r#"update labels set name = $1 where id = $2 and workspace_id = $3 returning *;"#,
+    )
+    .bind(payload.name)
+    .bind(id)
+    .bind(workspace_id)
+    .fetch_one(&mut *conn)
+    .await?;
+    touch_todos_of(&mut *conn, id).await?;
+
+    let record = label_record(AuditAction::Update, id)
+        .before(&old_label)
+        .after(&label);
+    record_events(conn, workspace_id, actor, vec![record]).await?;
+    Ok(label)
+}
+
+async fn delete_label(
+    conn: &mut PgConnection,
+    workspace_id: i32,
+    actor: Option<i32>,
+    id: i32,
+    strategy: DeleteLabelStrategy,
+) -> anyhow::Result<()> {
+    let label = find_label(&mut *conn, workspace_id, id).await?;
+    let todo_ids: Vec<i32> = sqlx::query_scalar(
+        r#"select todo_id from todo_labels join todos on todos.id = todo_labels.todo_id
+        where label_id = $1 and todos.deleted_at is null order by todo_id;"#,
+    )
+    .bind(id)
+    .fetch_all(&mut *conn)
+    .await?;
+    match strategy {
+        DeleteLabelStrategy::Refuse if !todo_ids.is_empty() => {
+            return Err(RepositoryError::InUse(id, todo_ids).into());
+        }
+        DeleteLabelStrategy::Reassign(target) => {
+            find_label(&mut *conn, workspace_id, target).await?;
         }
+        _ => {}
+    }
+    let before = find_todos(&mut *conn, &todo_ids).await?;
+    touch_todos_of(&mut *conn, id).await?;
+    if let DeleteLabelStrategy::Reassign(target) = strategy {
+        sqlx::query(
+            r#"update todo_labels set label_id = $2 where label_id = $1 and todo_id not in (select todo_id from todo_labels where label_id = $2);"#,
+        )
+        .bind(id)
+        .bind(target)
+        .execute(&mut *conn)
+        .await?;
+    }
+    sqlx::query(r#"delete from todo_labels where label_id = $1;"#)
+        .bind(id)
+        .execute(&mut *conn)
+        .await?;
+
+    let result = sqlx::query(r#"delete from labels where id=$1"#)
+        .bind(id)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| RepositoryError::Unexpected(e.to_string()))?;
+    if result.rows_affected() == 0 {
+        return Err(RepositoryError::NotFound(id).into());
+    }
+
+    // trashed todos are not announced, clients dropped them
+    let todos = find_todos(&mut *conn, &todo_ids).await?;
+    let records = todo_ids
+        .iter()
+        .filter_map(|id| Some((before.get(id)?, todos.get(id)?)))
+        .map(|(old_todo, todo)| {
+            todo_record(AuditAction::Update, todo.id)
+                .before(old_todo)
+                .after(todo)
+        });
+    let records = [label_record(AuditAction::Delete, id).before(&label)]
+        .into_iter()
+        .chain(records)
+        .collect();
+    record_events(conn, workspace_id, actor, records).await?;
+    Ok(())
+}
 
-        let label =
-            sqlx::query_as::<_, Label>(r#"insert into labels ( name ) values ( $1 ) returning *;"#)
-                .bind(name.clone())
-                .fetch_one(&self.pool)
-                .await?;
+#[async_trait]
+impl LabelRepository for LabelRepositoryForDb {
+    fn acting_as(&self, user_id: i32) -> Self {
+        Self {
+            ctx: self.ctx.clone(),
+            actor: Some(user_id),
+        }
+    }
 
-        Ok(label)
+    #[tracing::instrument(name = "label.create", skip(self, name))]
+    async fn create(&self, workspace_id: i32, name: String) -> anyhow::Result<Label> {
+        let actor = self.actor;
+        self.ctx
+            .transaction(|conn| Box::pin(create_label(conn, workspace_id, actor, name)))
+            .await
     }
 
-    async fn all(&self) -> anyhow::Result<Vec<Label>> {
-        let labels = sqlx::query_as::<_, Label>(r#"select * from labels order by labels.id asc;"#)
-            .fetch_all(&self.pool)
-            .await?;
+    #[tracing::instrument(name = "label.find", skip(self))]
+    async fn find(&self, workspace_id: i32, id: i32) -> anyhow::Result<Label> {
+        self.ctx
+            .transaction(|conn| Box::pin(find_label(conn, workspace_id, id)))
+            .await
+    }
 
-        Ok(labels)
+    #[tracing::instrument(name = "label.all", skip(self))]
+    async fn all(&self, workspace_id: i32) -> anyhow::Result<Vec<Label>> {
+        self.ctx
+            .transaction(|conn| {
+                Box::pin(async move {
+                    let labels = sqlx::query_as::<_, Label>(
+                        r#"select * from labels where workspace_id = $1 order by labels.id asc;"#,
+                    )
+                    .bind(workspace_id)
+                    .fetch_all(conn)
+                    .await?;
+                    Ok(labels)
+                })
+            })
+            .await
     }
 
-    async fn delete(&self, id: i32) -> anyhow::Result<()> {
-        sqlx::query(r#"delete from labels where id=$1"#)
-            .bind(id)
-            .execute(&self.pool)
+    #[tracing::instrument(name = "label.update", skip(self, payload))]
+    async fn update(
+        &self,
+        workspace_id: i32,
+        id: i32,
+        payload: UpdateLabel,
+    ) -> anyhow::Result<Label> {
+        let actor = self.actor;
+        self.ctx
+            .transaction(|conn| Box::pin(update_label(conn, workspace_id, actor, id, payload)))
             .await
-            .map_err(|e| match e {
-                sqlx::Error::RowNotFound => RepositoryError::NotFound(id),
-                _ => RepositoryError::Unexpected(e.to_string()),
-            })?;
+    }
 
-        Ok(())
+    #[tracing::instrument(name = "label.delete", skip(self))]
+    async fn delete(
+        &self,
+        workspace_id: i32,
+        id: i32,
+        strategy: DeleteLabelStrategy,
+    ) -> anyhow::Result<()> {
+        let actor = self.actor;
+        self.ctx
+            .transaction(|conn| Box::pin(delete_label(conn, workspace_id, actor, id, strategy)))
+            .await
     }
 }
 
@@ -80,6 +335,10 @@ impl LabelRepository for LabelRepositoryForDb {
 #[cfg(feature = "database-test")]
 mod test {
     use super::*;
+    use crate::repositories::{
+        user::UserRepositoryForDb,
+        workspace::{test_utils::prepare_workspace, WorkspaceRepositoryForDb},
+    };
     use dotenv::dotenv;
     use sqlx::PgPool;
     use std::env;
@@ -91,33 +350,210 @@ mod test {
         let pool = PgPool::connect(database_url)
             .await
             .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database_url));
+        let workspace = prepare_workspace(
+            &UserRepositoryForDb::new(pool.clone()),
+            &WorkspaceRepositoryForDb::new(pool.clone()),
+            "[crud_scenario] label user",
+            "[crud_scenario] label workspace",
+        )
+        .await;
         let repository = LabelRepositoryForDb::new(pool);
         let label_text = "[crud_scenario] test_label";
 
         // create
         let label = repository
-            .create(label_text.to_string())
+            .create(workspace.id, label_text.to_string())
             .await
             .expect("[create] returned Err");
         assert_eq!(label.name, label_text);
 
         // all
-        let labels = repository.all().await.expect("[all] returned Err");
+        let labels = repository
+            .all(workspace.id)
+            .await
+            .expect("[all] returned Err");
         let label = labels.last().unwrap();
         assert_eq!(label.name, label_text);
 
+        // find
+        let found = repository
+            .find(workspace.id, label.id)
+            .await
+            .expect("[find] returned Err");
+        assert_eq!(*label, found);
+
+        // update
+        let updated_text = "[crud_scenario] updated test_label";
+        let label = repository
+            .update(
+                workspace.id,
+                label.id,
+                UpdateLabel {
+                    name: updated_text.to_string(),
+                },
+            )
+            .await
+            .expect("[update] returned Err");
+        assert_eq!(label.name, updated_text);
+
+        // update to a name already in use
+        let other = repository
+            .create(workspace.id, "[crud_scenario] other test_label".to_string())
+            .await
+            .expect("[create] returned Err");
+        let res = repository
+            .update(
+                workspace.id,
+                label.id,
+                UpdateLabel {
+                    name: other.name.clone(),
+                },
+            )
+            .await;
+        assert!(matches!(
+            res.unwrap_err().downcast::<RepositoryError>(),
+            Ok(RepositoryError::Duplicate(id)) if id == other.id
+        ));
+
         // delete
         repository
-            .delete(label.id)
+            .delete(workspace.id, other.id, DeleteLabelStrategy::Refuse)
+            .await
+            .expect("[delete] returned Err");
+        repository
+            .delete(workspace.id, label.id, DeleteLabelStrategy::Refuse)
+            .await
+            .expect("[delete] returned Err");
+        let res = repository.find(workspace.id, label.id).await;
+        assert!(res.is_err());
+    }
+
+    #[tokio::test]
+    async fn delete_strategy_scenario() {
+        use crate::repositories::{
+            todo::{CreateTodo, TodoRepository},
+            unit_of_work::{UnitOfWork, UnitOfWorkForDb},
+        };
+
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database_url));
+        // nothing is committed, so other tests never see these rows
+        let uow = UnitOfWorkForDb::begin(&pool)
+            .await
+            .expect("[begin] returned Err");
+        let ctx = DbContext::UnitOfWork(uow.clone());
+        let workspace = prepare_workspace(
+            &UserRepositoryForDb::with_context(ctx.clone()),
+            &WorkspaceRepositoryForDb::with_context(ctx),
+            "[delete_strategy_scenario] user",
+            "[delete_strategy_scenario] workspace",
+        )
+        .await;
+        let repository = uow.labels();
+        let mut labels = vec![];
+        for name in ["first", "second"] {
+            let label = repository
+                .create(workspace.id, format!("[delete_strategy_scenario] {}", name))
+                .await
+                .expect("[create] returned Err");
+            labels.push(label);
+        }
+        let (first, second) = (labels[0].clone(), labels[1].clone());
+        let todo_1 = uow
+            .todos()
+            .create(
+                workspace.id,
+                CreateTodo::new(
+                    "[delete_strategy_scenario] todo 1".to_string(),
+                    vec![first.id],
+                ),
+            )
+            .await
+            .expect("[create todo] returned Err");
+        let todo_2 = uow
+            .todos()
+            .create(
+                workspace.id,
+                CreateTodo::new(
+                    "[delete_strategy_scenario] todo 2".to_string(),
+                    vec![first.id, second.id],
+                ),
+            )
+            .await
+            .expect("[create todo] returned Err");
+        // trashed todos neither hold a label back nor show up in the refusal
+        let trashed = uow
+            .todos()
+            .create(
+                workspace.id,
+                CreateTodo::new(
+                    "[delete_strategy_scenario] trashed".to_string(),
+                    vec![first.id],
+                ),
+            )
+            .await
+            .expect("[create todo] returned Err");
+        uow.todos()
+            .delete(workspace.id, trashed.id, None)
+            .await
+            .expect("[delete todo] returned Err");
+
+        // refuse
+        let res = repository
+            .delete(workspace.id, first.id, DeleteLabelStrategy::Refuse)
+            .await;
+        assert!(matches!(
+            res.unwrap_err().downcast::<RepositoryError>(),
+            Ok(RepositoryError::InUse(id, todo_ids)) if id == first.id && todo_ids == vec![todo_1.id, todo_2.id]
+        ));
+
+        // reassign
+        repository
+            .delete(
+                workspace.id,
+                first.id,
+                DeleteLabelStrategy::Reassign(second.id),
+            )
+            .await
+            .expect("[delete] returned Err");
+        let todo = uow.todos().find(workspace.id, todo_1.id).await.unwrap();
+        assert_eq!(vec![second.clone()], todo.labels);
+        assert_eq!(todo_1.version + 1, todo.version);
+        let todo = uow.todos().find(workspace.id, todo_2.id).await.unwrap();
+        assert_eq!(vec![second.clone()], todo.labels);
+        let todo = uow
+            .todos()
+            .restore(workspace.id, trashed.id)
+            .await
+            .expect("[restore todo] returned Err");
+        assert_eq!(vec![second.clone()], todo.labels);
+
+        // detach
+        repository
+            .delete(workspace.id, second.id, DeleteLabelStrategy::Detach)
             .await
             .expect("[delete] returned Err");
+        let todo = uow.todos().find(workspace.id, todo_2.id).await.unwrap();
+        assert!(todo.labels.is_empty());
+        let res = repository.find(workspace.id, second.id).await;
+        assert!(res.is_err());
     }
 }
 
 #[cfg(test)]
 pub mod test_utils {
     use super::*;
-    use crate::repositories::RepositoryError;
+    use crate::repositories::{
+        audit::test_utils::AuditRepositoryForMemory,
+        todo::{
+            test_utils::{touch_todos, with_relations, TodoData},
+            TodoEntity,
+        },
+        RepositoryError,
+    };
     use axum::async_trait;
     use std::{
         collections::HashMap,
@@ -130,51 +566,257 @@ pub mod test_utils {
         }
     }
 
-    type LabelData = HashMap<i32, Label>;
+    /// labels per id, together with the id of the workspace holding them
+    type LabelData = HashMap<i32, (i32, Label)>;
+    /// label ids per todo id, the in-memory `todo_labels` table
+    type TodoLabelData = HashMap<i32, Vec<i32>>;
 
     #[derive(Debug, Clone)]
     pub struct LabelRepositoryForMemory {
         store: Arc<RwLock<LabelData>>,
+        todo_labels: Arc<RwLock<TodoLabelData>>,
+        /// the todos carrying the labels, whose versions change with them
+        todos: Arc<RwLock<TodoData>>,
+        audit: AuditRepositoryForMemory,
+        actor: Option<i32>,
     }
 
     impl LabelRepositoryForMemory {
         pub fn new() -> Self {
             LabelRepositoryForMemory {
                 store: Arc::default(),
+                todo_labels: Arc::default(),
+                todos: Arc::default(),
+                audit: AuditRepositoryForMemory::new(),
+                actor: None,
             }
         }
 
-        fn write_store_ref(&self) -> RwLockWriteGuard<LabelData> {
+        /// Shares the label stores but records changes in `audit`.
+        pub fn with_audit_repository(&self, audit: AuditRepositoryForMemory) -> Self {
+            LabelRepositoryForMemory {
+                audit,
+                ..self.clone()
+            }
+        }
+
+        /// Shares the label stores but bumps the versions of the todos in `todos`.
+        pub fn with_todo_store(&self, todos: Arc<RwLock<TodoData>>) -> Self {
+            LabelRepositoryForMemory {
+                todos,
+                ..self.clone()
+            }
+        }
+
+        /// The todos with the given ids that are not in the trash, by id.
+        fn find_todos(&self, ids: &[i32]) -> HashMap<i32, TodoEntity> {
+            let todos = self.todos.read().unwrap();
+            ids.iter()
+                .filter_map(|id| todos.get(id))
+                .filter(|(_scope, todo)| todo.deleted_at.is_none())
+                .map(|(_scope, todo)| (todo.id, with_relations(&todos, self, todo)))
+                .collect()
+        }
+
+        /// Ids of the todos carrying the label.
+        fn todos_of(&self, id: i32) -> Vec<i32> {
+            let mut todo_ids: Vec<i32> = self
+                .read_todo_labels_ref()
+                .iter()
+                .filter(|(_todo_id, label_ids)| label_ids.contains(&id))
+                .map(|(todo_id, _label_ids)| *todo_id)
+                .collect();
+            todo_ids.sort_unstable();
+            todo_ids
+        }
+
+        fn record(&self, workspace_id: i32, record: AuditRecord) {
+            self.audit.record(workspace_id, self.actor, vec![record]);
+        }
+
+        fn write_store_ref(&self) -> RwLockWriteGuard<'_, LabelData> {
             self.store.write().unwrap()
         }
 
-        fn read_store_ref(&self) -> RwLockReadGuard<LabelData> {
+        fn read_store_ref(&self) -> RwLockReadGuard<'_, LabelData> {
             self.store.read().unwrap()
         }
+
+        fn write_todo_labels_ref(&self) -> RwLockWriteGuard<'_, TodoLabelData> {
+            self.todo_labels.write().unwrap()
+        }
+
+        fn read_todo_labels_ref(&self) -> RwLockReadGuard<'_, TodoLabelData> {
+            self.todo_labels.read().unwrap()
+        }
+
+        pub fn labels_of(&self, todo_id: i32) -> Vec<Label> {
+            let store = self.read_store_ref();
+            let todo_labels = self.read_todo_labels_ref();
+            todo_labels
+                .get(&todo_id)
+                .map(|ids| {
+                    ids.iter()
+                        .filter_map(|id| store.get(id).map(|(_workspace_id, label)| label.clone()))
+                        .collect()
+                })
+                .unwrap_or_default()
+        }
+
+        pub fn set_labels_of(
+            &self,
+            workspace_id: i32,
+            todo_id: i32,
+            label_ids: &[i32],
+        ) -> anyhow::Result<()> {
+            let store = self.read_store_ref();
+            if let Some(id) = label_ids
+                .iter()
+                .find(|id| !owned_by(&store, workspace_id, **id))
+            {
+                return Err(RepositoryError::NotFound(*id).into());
+            }
+            self.write_todo_labels_ref()
+                .insert(todo_id, label_ids.to_vec());
+            Ok(())
+        }
+
+        pub fn remove_labels_of(&self, todo_id: i32) {
+            self.write_todo_labels_ref().remove(&todo_id);
+        }
+    }
+
+    fn owned_by(store: &LabelData, workspace_id: i32, id: i32) -> bool {
+        matches!(store.get(&id), Some((scope, _label)) if *scope == workspace_id)
     }
 
     #[async_trait]
     impl LabelRepository for LabelRepositoryForMemory {
-        async fn create(&self, name: String) -> anyhow::Result<Label> {
+        fn acting_as(&self, user_id: i32) -> Self {
+            LabelRepositoryForMemory {
+                actor: Some(user_id),
+                ..self.clone()
+            }
+        }
+
+        async fn create(&self, workspace_id: i32, name: String) -> anyhow::Result<Label> {
             let mut store = self.write_store_ref();
-            if let Some((_key, label)) = store.iter().find(|(_key, label)| label.name == name) {
-                return Ok(label.clone());
+            if let Some((_scope, label)) = store
+                .values()
+                .find(|(scope, label)| *scope == workspace_id && label.name == name)
+            {
+                return Err(RepositoryError::Duplicate(label.id).into());
             };
-            let id = (store.len() + 1) as i32;
+            let id = store.keys().max().unwrap_or(&0) + 1;
             let label = Label::new(id, name);
-            store.insert(id, label.clone());
+            store.insert(id, (workspace_id, label.clone()));
+            self.record(
+                workspace_id,
+                label_record(AuditAction::Create, id).after(&label),
+            );
+            Ok(label)
+        }
+
+        async fn find(&self, workspace_id: i32, id: i32) -> anyhow::Result<Label> {
+            let store = self.read_store_ref();
+            let label = store
+                .get(&id)
+                .filter(|(scope, _label)| *scope == workspace_id)
+                .map(|(_scope, label)| label.clone())
+                .ok_or(RepositoryError::NotFound(id))?;
             Ok(label)
         }
 
-        async fn all(&self) -> anyhow::Result<Vec<Label>> {
+        async fn all(&self, workspace_id: i32) -> anyhow::Result<Vec<Label>> {
             let store = self.read_store_ref();
-            let labels = Vec::from_iter(store.values().cloned());
+            let mut labels: Vec<Label> = store
+                .values()
+                .filter(|(scope, _label)| *scope == workspace_id)
+                .map(|(_scope, label)| label.clone())
+                .collect();
+            labels.sort_by_key(|label| label.id);
             Ok(labels)
         }
 
-        async fn delete(&self, id: i32) -> anyhow::Result<()> {
+        async fn update(
+            &self,
+            workspace_id: i32,
+            id: i32,
+            payload: UpdateLabel,
+        ) -> anyhow::Result<Label> {
             let mut store = self.write_store_ref();
-            store.remove(&id).ok_or(RepositoryError::NotFound(id))?;
+            if let Some((_scope, label)) = store.values().find(|(scope, label)| {
+                *scope == workspace_id && label.name == payload.name && label.id != id
+            }) {
+                return Err(RepositoryError::Duplicate(label.id).into());
+            };
+            let (_scope, label) = store
+                .get_mut(&id)
+                .filter(|(scope, _label)| *scope == workspace_id)
+                .ok_or(RepositoryError::NotFound(id))?;
+            let record = label_record(AuditAction::Update, id).before(label);
+            label.name = payload.name;
+            self.record(workspace_id, record.after(label));
+            let label = label.clone();
+            drop(store);
+            touch_todos(&mut self.todos.write().unwrap(), self.todos_of(id));
+            Ok(label)
+        }
+
+        async fn delete(
+            &self,
+            workspace_id: i32,
+            id: i32,
+            strategy: DeleteLabelStrategy,
+        ) -> anyhow::Result<()> {
+            let all_todo_ids = self.todos_of(id);
+            let before = self.find_todos(&all_todo_ids);
+            let mut todo_ids: Vec<i32> = before.keys().copied().collect();
+            todo_ids.sort_unstable();
+            let label = {
+                let mut store = self.write_store_ref();
+                let mut todo_labels = self.write_todo_labels_ref();
+                if !owned_by(&store, workspace_id, id) {
+                    return Err(RepositoryError::NotFound(id).into());
+                }
+                match strategy {
+                    DeleteLabelStrategy::Refuse if !todo_ids.is_empty() => {
+                        return Err(RepositoryError::InUse(id, todo_ids).into());
+                    }
+                    DeleteLabelStrategy::Reassign(target)
+                        if !owned_by(&store, workspace_id, target) =>
+                    {
+                        return Err(RepositoryError::NotFound(target).into());
+                    }
+                    _ => {}
+                }
+                for label_ids in todo_labels.values_mut() {
+                    if let DeleteLabelStrategy::Reassign(target) = strategy {
+                        if label_ids.contains(&id) && !label_ids.contains(&target) {
+                            label_ids.push(target);
+                        }
+                    }
+                    label_ids.retain(|label_id| *label_id != id);
+                }
+                store.remove(&id).map(|(_scope, label)| label)
+            };
+            touch_todos(&mut self.todos.write().unwrap(), all_todo_ids);
+
+            let todos = self.find_todos(&todo_ids);
+            let records = todo_ids
+                .iter()
+                .filter_map(|id| Some((before.get(id)?, todos.get(id)?)))
+                .map(|(old_todo, todo)| {
+                    todo_record(AuditAction::Update, todo.id)
+                        .before(old_todo)
+                        .after(todo)
+                });
+            let records = label
+                .map(|label| label_record(AuditAction::Delete, id).before(&label))
+                .into_iter()
+                .chain(records)
+                .collect();
+            self.audit.record(workspace_id, self.actor, records);
             Ok(())
         }
     }
@@ -182,6 +824,9 @@ pub mod test_utils {
     mod test {
         use super::*;
 
+        const WORKSPACE_ID: i32 = 1;
+        const OTHER_WORKSPACE_ID: i32 = 2;
+
         #[tokio::test]
         async fn label_crud_scenario() {
             let name = "label name".to_string();
@@ -191,18 +836,134 @@ pub mod test_utils {
             // create
             let repository = LabelRepositoryForMemory::new();
             let label = repository
-                .create(name.clone())
+                .create(WORKSPACE_ID, name.clone())
                 .await
                 .expect("failed create label");
             assert_eq!(expected, label);
 
             // all
-            let labels = repository.all().await.expect("failed get all labels");
-            assert_eq!(vec![expected], labels);
+            let labels = repository
+                .all(WORKSPACE_ID)
+                .await
+                .expect("failed get all labels");
+            assert_eq!(vec![expected.clone()], labels);
+
+            // find
+            let label = repository
+                .find(WORKSPACE_ID, id)
+                .await
+                .expect("failed find label");
+            assert_eq!(expected, label);
+
+            // other workspaces neither see nor touch it
+            let labels = repository
+                .all(OTHER_WORKSPACE_ID)
+                .await
+                .expect("failed get all labels");
+            assert!(labels.is_empty());
+            let res = repository.find(OTHER_WORKSPACE_ID, id).await;
+            assert!(res.is_err());
+            let res = repository
+                .delete(OTHER_WORKSPACE_ID, id, DeleteLabelStrategy::Refuse)
+                .await;
+            assert!(res.is_err());
+
+            // update
+            let name = "updated label name".to_string();
+            let label = repository
+                .update(WORKSPACE_ID, id, UpdateLabel { name: name.clone() })
+                .await
+                .expect("failed update label");
+            assert_eq!(Label::new(id, name), label);
 
             // delete
-            let res = repository.delete(id).await;
+            let res = repository
+                .delete(WORKSPACE_ID, id, DeleteLabelStrategy::Refuse)
+                .await;
             assert!(res.is_ok());
         }
+
+        #[tokio::test]
+        async fn label_delete_strategies() {
+            use crate::repositories::{
+                audit::{AuditQuery, AuditRepository},
+                todo::{test_utils::TodoRepositoryForMemory, CreateTodo, TodoRepository},
+            };
+
+            let todos = TodoRepositoryForMemory::new();
+            let repository = todos.label_repository();
+            let first = repository
+                .create(WORKSPACE_ID, "first".to_string())
+                .await
+                .unwrap();
+            let second = repository
+                .create(WORKSPACE_ID, "second".to_string())
+                .await
+                .unwrap();
+            let third = repository
+                .create(WORKSPACE_ID, "third".to_string())
+                .await
+                .unwrap();
+            for labels in [vec![first.id], vec![first.id, second.id], vec![first.id]] {
+                todos
+                    .create(WORKSPACE_ID, CreateTodo::new("todo".to_string(), labels))
+                    .await
+                    .unwrap();
+            }
+            // trashed todos neither hold a label back nor hear about it
+            todos.delete(WORKSPACE_ID, 3, None).await.unwrap();
+
+            // refuse
+            let res = repository
+                .delete(WORKSPACE_ID, first.id, DeleteLabelStrategy::Refuse)
+                .await;
+            assert!(matches!(
+                res.unwrap_err().downcast::<RepositoryError>(),
+                Ok(RepositoryError::InUse(id, todo_ids)) if id == first.id && todo_ids == vec![1, 2]
+            ));
+
+            // reassign
+            repository
+                .delete(
+                    WORKSPACE_ID,
+                    first.id,
+                    DeleteLabelStrategy::Reassign(second.id),
+                )
+                .await
+                .expect("failed delete label");
+            assert_eq!(vec![second.clone()], repository.labels_of(1));
+            assert_eq!(vec![second.clone()], repository.labels_of(2));
+            // the todos are changed like any other update
+            let todo = todos.find(WORKSPACE_ID, 1).await.unwrap();
+            assert_eq!(2, todo.version);
+            let query = AuditQuery {
+                limit: Some(3),
+                ..AuditQuery::default()
+            };
+            let events = repository.audit.all(WORKSPACE_ID, query).await.unwrap();
+            let changed: Vec<(AuditEntity, AuditAction, i32)> = events
+                .iter()
+                .map(|event| (event.entity, event.action, event.entity_id))
+                .collect();
+            assert_eq!(
+                vec![
+                    (AuditEntity::Todo, AuditAction::Update, 2),
+                    (AuditEntity::Todo, AuditAction::Update, 1),
+                    (AuditEntity::Label, AuditAction::Delete, first.id),
+                ],
+                changed
+            );
+            // and a restored todo comes back with the label it was reassigned to
+            let todo = todos.restore(WORKSPACE_ID, 3).await.unwrap();
+            assert_eq!(vec![second.clone()], todo.labels);
+
+            // detach
+            repository
+                .delete(WORKSPACE_ID, second.id, DeleteLabelStrategy::Detach)
+                .await
+                .expect("failed delete label");
+            assert!(repository.labels_of(1).is_empty());
+            assert_eq!(vec![third], repository.all(WORKSPACE_ID).await.unwrap());
+        }
     }
 }
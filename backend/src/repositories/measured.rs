@@ -0,0 +1,190 @@
+use super::{
+    label::{DeleteLabelStrategy, Label, LabelRepository, UpdateLabel},
+    todo::{
+        BulkItemResult, BulkTodo, CreateTodo, MoveTodo, SearchQuery, TodoEntity, TodoPage,
+        TodoQuery, TodoRepository, TodoSearchHit, UpdateTodo,
+    },
+    unit_of_work::{Transactional, UnitOfWork},
+};
+use crate::metrics::Metrics;
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+
+/// A repository recording the duration of each of its calls in the metrics.
+#[derive(Debug, Clone)]
+pub struct Measured<R> {
+    inner: R,
+    metrics: Arc<Metrics>,
+}
+
+impl<R> Measured<R> {
+    pub fn new(inner: R, metrics: Arc<Metrics>) -> Self {
+        Self { inner, metrics }
+    }
+}
+
+const TODO: &str = "todo";
+const LABEL: &str = "label";
+
+#[async_trait]
+impl<T: TodoRepository> TodoRepository for Measured<T> {
+    fn acting_as(&self, user_id: i32) -> Self {
+        Self::new(self.inner.acting_as(user_id), self.metrics.clone())
+    }
+
+    async fn create(&self, workspace_id: i32, payload: CreateTodo) -> anyhow::Result<TodoEntity> {
+        let call = self.inner.create(workspace_id, payload);
+        self.metrics.time(TODO, "create", call).await
+    }
+
+    async fn find(&self, workspace_id: i32, id: i32) -> anyhow::Result<TodoEntity> {
+        let call = self.inner.find(workspace_id, id);
+        self.metrics.time(TODO, "find", call).await
+    }
+
+    async fn all(&self, workspace_id: i32, query: TodoQuery) -> anyhow::Result<TodoPage> {
+        let call = self.inner.all(workspace_id, query);
+        self.metrics.time(TODO, "all", call).await
+    }
+
+    async fn update(
+        &self,
+        workspace_id: i32,
+        id: i32,
+        payload: UpdateTodo,
+        if_match: Option<Vec<i32>>,
+    ) -> anyhow::Result<TodoEntity> {
+        let call = self.inner.update(workspace_id, id, payload, if_match);
+        self.metrics.time(TODO, "update", call).await
+    }
+
+    async fn delete(
+        &self,
+        workspace_id: i32,
+        id: i32,
+        if_match: Option<Vec<i32>>,
+    ) -> anyhow::Result<()> {
+        let call = self.inner.delete(workspace_id, id, if_match);
+        self.metrics.time(TODO, "delete", call).await
+    }
+
+    async fn children(&self, workspace_id: i32, id: i32) -> anyhow::Result<Vec<TodoEntity>> {
+        let call = self.inner.children(workspace_id, id);
+        self.metrics.time(TODO, "children", call).await
+    }
+
+    async fn reposition(
+        &self,
+        workspace_id: i32,
+        id: i32,
+        payload: MoveTodo,
+    ) -> anyhow::Result<TodoEntity> {
+        let call = self.inner.reposition(workspace_id, id, payload);
+        self.metrics.time(TODO, "reposition", call).await
+    }
+
+    async fn search(
+        &self,
+        workspace_id: i32,
+        query: SearchQuery,
+    ) -> anyhow::Result<Vec<TodoSearchHit>> {
+        let call = self.inner.search(workspace_id, query);
+        self.metrics.time(TODO, "search", call).await
+    }
+
+    async fn bulk(
+        &self,
+        workspace_id: i32,
+        payload: BulkTodo,
+    ) -> anyhow::Result<Vec<BulkItemResult>> {
+        let call = self.inner.bulk(workspace_id, payload);
+        self.metrics.time(TODO, "bulk", call).await
+    }
+
+    async fn trash(&self, workspace_id: i32) -> anyhow::Result<Vec<TodoEntity>> {
+        let call = self.inner.trash(workspace_id);
+        self.metrics.time(TODO, "trash", call).await
+    }
+
+    async fn restore(&self, workspace_id: i32, id: i32) -> anyhow::Result<TodoEntity> {
+        let call = self.inner.restore(workspace_id, id);
+        self.metrics.time(TODO, "restore", call).await
+    }
+
+    async fn purge(&self, workspace_id: i32) -> anyhow::Result<u64> {
+        let call = self.inner.purge(workspace_id);
+        self.metrics.time(TODO, "purge", call).await
+    }
+
+    async fn purge_expired(&self, deleted_before: DateTime<Utc>) -> anyhow::Result<u64> {
+        let call = self.inner.purge_expired(deleted_before);
+        self.metrics.time(TODO, "purge_expired", call).await
+    }
+}
+
+#[async_trait]
+impl<L: LabelRepository> LabelRepository for Measured<L> {
+    fn acting_as(&self, user_id: i32) -> Self {
+        Self::new(self.inner.acting_as(user_id), self.metrics.clone())
+    }
+
+    async fn create(&self, workspace_id: i32, name: String) -> anyhow::Result<Label> {
+        let call = self.inner.create(workspace_id, name);
+        self.metrics.time(LABEL, "create", call).await
+    }
+
+    async fn find(&self, workspace_id: i32, id: i32) -> anyhow::Result<Label> {
+        let call = self.inner.find(workspace_id, id);
+        self.metrics.time(LABEL, "find", call).await
+    }
+
+    async fn all(&self, workspace_id: i32) -> anyhow::Result<Vec<Label>> {
+        let call = self.inner.all(workspace_id);
+        self.metrics.time(LABEL, "all", call).await
+    }
+
+    async fn update(
+        &self,
+        workspace_id: i32,
+        id: i32,
+        payload: UpdateLabel,
+    ) -> anyhow::Result<Label> {
+        let call = self.inner.update(workspace_id, id, payload);
+        self.metrics.time(LABEL, "update", call).await
+    }
+
+    async fn delete(
+        &self,
+        workspace_id: i32,
+        id: i32,
+        strategy: DeleteLabelStrategy,
+    ) -> anyhow::Result<()> {
+        let call = self.inner.delete(workspace_id, id, strategy);
+        self.metrics.time(LABEL, "delete", call).await
+    }
+}
+
+/// Measures the repositories handed out by the unit of work as well.
+#[async_trait]
+impl<U: UnitOfWork> UnitOfWork for Measured<U> {
+    type Todo = Measured<U::Todo>;
+
+    fn todos(&self) -> Self::Todo {
+        Measured::new(self.inner.todos(), self.metrics.clone())
+    }
+
+    async fn commit(self) -> anyhow::Result<()> {
+        self.inner.commit().await
+    }
+}
+
+#[async_trait]
+impl<T: Transactional> Transactional for Measured<T> {
+    type UnitOfWork = Measured<T::UnitOfWork>;
+
+    async fn begin(&self) -> anyhow::Result<Self::UnitOfWork> {
+        let uow = self.inner.begin().await?;
+        Ok(Measured::new(uow, self.metrics.clone()))
+    }
+}
@@ -1,19 +1,70 @@
+use super::audit::{record_events, AuditAction, AuditEntity, AuditRecord};
 use super::label::Label;
+use super::unit_of_work::DbContext;
 use super::RepositoryError;
 use anyhow::Ok;
 use axum::async_trait;
-use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use serde::{de, Deserialize, Deserializer, Serialize};
 use sqlx::FromRow;
-use sqlx::PgPool;
-use validator::Validate;
+use sqlx::{PgConnection, PgPool};
+use std::collections::HashMap;
+use utoipa::{IntoParams, ToSchema};
+use validator::{Validate, ValidationError};
 
 #[async_trait]
 pub trait TodoRepository: Clone + std::marker::Send + std::marker::Sync + 'static {
-    async fn create(&self, payload: CreateTodo) -> anyhow::Result<TodoEntity>;
-    async fn find(&self, id: i32) -> anyhow::Result<TodoEntity>;
-    async fn all(&self) -> anyhow::Result<Vec<TodoEntity>>;
-    async fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<TodoEntity>;
-    async fn delete(&self, id: i32) -> anyhow::Result<()>;
+    /// The same repository, recording `user_id` as the author of its changes.
+    fn acting_as(&self, user_id: i32) -> Self;
+    async fn create(&self, workspace_id: i32, payload: CreateTodo) -> anyhow::Result<TodoEntity>;
+    async fn find(&self, workspace_id: i32, id: i32) -> anyhow::Result<TodoEntity>;
+    async fn all(&self, workspace_id: i32, query: TodoQuery) -> anyhow::Result<TodoPage>;
+    /// Applies the update, unless `if_match` is given and the todo is at none of its versions.
+    async fn update(
+        &self,
+        workspace_id: i32,
+        id: i32,
+        payload: UpdateTodo,
+        if_match: Option<Vec<i32>>,
+    ) -> anyhow::Result<TodoEntity>;
+    /// Moves the todo together with all of its subtasks to the trash, with the same
+    /// `if_match` check as `update`.
+    async fn delete(
+        &self,
+        workspace_id: i32,
+        id: i32,
+        if_match: Option<Vec<i32>>,
+    ) -> anyhow::Result<()>;
+    /// Direct subtasks of the todo, in list order.
+    async fn children(&self, workspace_id: i32, id: i32) -> anyhow::Result<Vec<TodoEntity>>;
+    /// Moves the todo to a new place in the list order.
+    async fn reposition(
+        &self,
+        workspace_id: i32,
+        id: i32,
+        payload: MoveTodo,
+    ) -> anyhow::Result<TodoEntity>;
+    /// Todos containing every word of the query, best match first.
+    async fn search(
+        &self,
+        workspace_id: i32,
+        query: SearchQuery,
+    ) -> anyhow::Result<Vec<TodoSearchHit>>;
+    /// Applies one operation to all todos of the workspace among `payload.ids` at once,
+    /// reporting the others as not found.
+    async fn bulk(
+        &self,
+        workspace_id: i32,
+        payload: BulkTodo,
+    ) -> anyhow::Result<Vec<BulkItemResult>>;
+    /// Trashed todos of the workspace, most recently deleted first.
+    async fn trash(&self, workspace_id: i32) -> anyhow::Result<Vec<TodoEntity>>;
+    /// Takes the todo out of the trash, with the subtasks that were deleted along with it.
+    async fn restore(&self, workspace_id: i32, id: i32) -> anyhow::Result<TodoEntity>;
+    /// Permanently deletes the trashed todos of the workspace, returning how many went.
+    async fn purge(&self, workspace_id: i32) -> anyhow::Result<u64>;
+    /// Permanently deletes the todos of every workspace trashed before `deleted_before`.
+    async fn purge_expired(&self, deleted_before: DateTime<Utc>) -> anyhow::Result<u64>;
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, FromRow)]
@@ -23,271 +74,2635 @@ struct TodoFromRow {
     completed: bool,
 }
 
+#[derive(Debug, Clone, PartialEq, FromRow)]
+struct TodoSearchFromRow {
+    id: i32,
+    rank: f32,
+    snippet: String,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, FromRow)]
 struct TodoWithLabelFromRow {
     id: i32,
     text: String,
     completed: bool,
+    priority: Priority,
+    due_at: Option<DateTime<Utc>>,
+    starts_at: Option<DateTime<Utc>>,
+    parent_id: Option<i32>,
+    child_ids: Vec<i32>,
+    position: i64,
+    deleted_at: Option<DateTime<Utc>>,
+    version: i32,
     label_id: Option<i32>,
     label_name: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, ToSchema)]
 pub struct TodoEntity {
     pub id: i32,
     pub text: String,
     pub completed: bool,
+    pub priority: Priority,
+    pub due_at: Option<DateTime<Utc>>,
+    pub starts_at: Option<DateTime<Utc>>,
+    pub parent_id: Option<i32>,
+    /// ids of the direct subtasks, in list order
+    pub child_ids: Vec<i32>,
+    /// place in the list, ascending from the top
+    pub position: i64,
+    /// when the todo was moved to the trash
+    pub deleted_at: Option<DateTime<Utc>>,
+    /// bumped by every change to the todo, its labels or its list of subtasks, handed to
+    /// clients as the `ETag`
+    pub version: i32,
     pub labels: Vec<Label>,
 }
 
+/// Folds the rows of a todo joined with its labels into one entity per todo, in row order.
 fn fold_entities(rows: Vec<TodoWithLabelFromRow>) -> Vec<TodoEntity> {
-    let mut rows = rows.iter();
     let mut accum: Vec<TodoEntity> = vec![];
-    'outer: while let Some(row) = rows.next() {
-        let mut todos = accum.iter_mut();
-        while let Some(todo) = todos.next() {
-            if todo.id == row.id {
-                todo.labels.push(Label {
-                    id: row.label_id.unwrap(),
-                    name: row.label_name.clone().unwrap(),
-                });
-                continue 'outer;
-            }
-        }
-
-        let labels = if row.label_id.is_some() {
-            vec![Label {
-                id: row.label_id.unwrap(),
-                name: row.label_name.clone().unwrap(),
-            }]
-        } else {
-            vec![]
-        };
-
-        accum.push(TodoEntity {
-            id: row.id,
-            text: row.text.clone(),
-            completed: row.completed,
-            labels,
+    let mut positions: HashMap<i32, usize> = HashMap::new();
+    for row in rows {
+        let position = *positions.entry(row.id).or_insert_with(|| {
+            accum.push(TodoEntity {
+                id: row.id,
+                text: row.text,
+                completed: row.completed,
+                priority: row.priority,
+                due_at: row.due_at,
+                starts_at: row.starts_at,
+                parent_id: row.parent_id,
+                child_ids: row.child_ids,
+                position: row.position,
+                deleted_at: row.deleted_at,
+                version: row.version,
+                labels: vec![],
+            });
+            accum.len() - 1
         });
+        if let (Some(id), Some(name)) = (row.label_id, row.label_name) {
+            accum[position].labels.push(Label { id, name });
+        }
     }
     accum
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+/// Sorts from `none` to `urgent`, matching the order of the `todo_priority` enum.
+#[derive(
+    Debug,
+    Serialize,
+    Deserialize,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Default,
+    sqlx::Type,
+    ToSchema,
+)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "todo_priority", rename_all = "lowercase")]
+pub enum Priority {
+    #[default]
+    None,
+    Low,
+    Medium,
+    High,
+    Urgent,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate, ToSchema)]
+#[validate(schema(function = "validate_create_dates"))]
 pub struct CreateTodo {
     #[validate(length(min = 1, message = "Can not be empty"))]
     #[validate(length(max = 100, message = "Over text length"))]
+    #[schema(min_length = 1, max_length = 100)]
     text: String,
     labels: Vec<i32>,
+    #[serde(default)]
+    priority: Priority,
+    #[serde(default)]
+    due_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    starts_at: Option<DateTime<Utc>>,
+    /// makes the todo a subtask of this one
+    #[serde(default)]
+    parent_id: Option<i32>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+/// Fields left out stay as they are; `due_at`, `starts_at` and `parent_id` are cleared by an
+/// explicit `null`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default, Validate, ToSchema)]
+#[validate(schema(function = "validate_update_dates"))]
 pub struct UpdateTodo {
     #[validate(length(min = 1, message = "Can not be empty"))]
     #[validate(length(max = 100, message = "Over text length"))]
+    #[schema(min_length = 1, max_length = 100)]
     text: Option<String>,
     completed: Option<bool>,
     labels: Option<Vec<i32>>,
+    priority: Option<Priority>,
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    #[schema(value_type = Option<DateTime<Utc>>)]
+    due_at: Option<Option<DateTime<Utc>>>,
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    #[schema(value_type = Option<DateTime<Utc>>)]
+    starts_at: Option<Option<DateTime<Utc>>>,
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    #[schema(value_type = Option<i32>)]
+    parent_id: Option<Option<i32>>,
+}
+
+/// Tells an explicit `null` (`Some(None)`) apart from a missing field (`None`).
+fn deserialize_nullable<'de, D: Deserializer<'de>, T: Deserialize<'de>>(
+    deserializer: D,
+) -> Result<Option<Option<T>>, D::Error> {
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+fn validate_dates(
+    starts_at: Option<&DateTime<Utc>>,
+    due_at: Option<&DateTime<Utc>>,
+) -> Result<(), ValidationError> {
+    match (starts_at, due_at) {
+        (Some(starts_at), Some(due_at)) if starts_at > due_at => {
+            let mut error = ValidationError::new("starts_after_due");
+            error.message = Some("Can not start after it is due".into());
+            Err(error)
+        }
+        _ => Result::Ok(()),
+    }
+}
+
+fn validate_create_dates(payload: &CreateTodo) -> Result<(), ValidationError> {
+    validate_dates(payload.starts_at.as_ref(), payload.due_at.as_ref())
+}
+
+fn validate_update_dates(payload: &UpdateTodo) -> Result<(), ValidationError> {
+    validate_dates(
+        payload.starts_at.flatten().as_ref(),
+        payload.due_at.flatten().as_ref(),
+    )
+}
+
+/// New place of a todo: right before `before`, right after `after`, or between the two.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default, Validate, ToSchema)]
+#[validate(schema(function = "validate_neighbours"))]
+pub struct MoveTodo {
+    pub before: Option<i32>,
+    pub after: Option<i32>,
+}
+
+impl MoveTodo {
+    pub fn neighbour_ids(&self) -> impl Iterator<Item = i32> {
+        self.before.into_iter().chain(self.after)
+    }
+}
+
+fn validate_neighbours(payload: &MoveTodo) -> Result<(), ValidationError> {
+    let (code, message) = match (payload.before, payload.after) {
+        (None, None) => ("missing_neighbour", "Either before or after is required"),
+        (Some(before), Some(after)) if before == after => (
+            "same_neighbour",
+            "Can not be before and after the same todo",
+        ),
+        _ => return Result::Ok(()),
+    };
+    let mut error = ValidationError::new(code);
+    error.message = Some(message.into());
+    Err(error)
+}
+
+/// Body of `POST /todos/bulk`, e.g. `{"ids": [1, 2], "operation": {"type": "add_labels", "labels": [3]}}`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate, ToSchema)]
+pub struct BulkTodo {
+    #[validate(length(min = 1, message = "Can not be empty"))]
+    #[validate(length(max = 100, message = "Too many todos"))]
+    #[schema(min_items = 1, max_items = 100)]
+    pub ids: Vec<i32>,
+    pub operation: BulkOperation,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BulkOperation {
+    Complete,
+    Uncomplete,
+    /// also trashes the subtasks, like `delete`
+    Delete,
+    AddLabels {
+        labels: Vec<i32>,
+    },
+    RemoveLabels {
+        labels: Vec<i32>,
+    },
+    SetLabels {
+        labels: Vec<i32>,
+    },
+}
+
+impl BulkOperation {
+    pub fn labels(&self) -> &[i32] {
+        match self {
+            BulkOperation::AddLabels { labels }
+            | BulkOperation::RemoveLabels { labels }
+            | BulkOperation::SetLabels { labels } => labels,
+            _ => &[],
+        }
+    }
+
+    /// The `completed` the operation sets, if it sets it.
+    pub fn completed(&self) -> Option<bool> {
+        match self {
+            BulkOperation::Complete => Some(true),
+            BulkOperation::Uncomplete => Some(false),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BulkStatus {
+    Ok,
+    /// the todo already was as the operation would leave it, like completing a completed todo
+    Unchanged,
+    NotFound,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, ToSchema)]
+pub struct BulkItemResult {
+    pub id: i32,
+    pub status: BulkStatus,
+    /// the todo after the operation, unless it was deleted or not found
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub todo: Option<TodoEntity>,
+}
+
+impl BulkItemResult {
+    /// One result per distinct id of `ids`, in request order.
+    fn collect(
+        ids: &[i32],
+        mut todos: HashMap<i32, TodoEntity>,
+        found: &[i32],
+        changed: &[i32],
+    ) -> Vec<Self> {
+        let mut results: Vec<Self> = vec![];
+        for id in ids {
+            if results.iter().any(|result| result.id == *id) {
+                continue;
+            }
+            results.push(BulkItemResult {
+                id: *id,
+                status: if changed.contains(id) {
+                    BulkStatus::Ok
+                } else if found.contains(id) {
+                    BulkStatus::Unchanged
+                } else {
+                    BulkStatus::NotFound
+                },
+                todo: todos.remove(id),
+            });
+        }
+        results
+    }
+}
+
+/// Space left between the positions of new or rebalanced todos.
+pub const POSITION_GAP: i64 = 1024;
+
+/// A position strictly between two neighbours, or `None` when there is no room left.
+fn position_between(above: Option<i64>, below: Option<i64>) -> Option<i64> {
+    match (above, below) {
+        (Some(above), Some(below)) if below - above >= 2 => Some(above + (below - above) / 2),
+        (Some(_), Some(_)) => None,
+        (Some(above), None) => Some(above + POSITION_GAP),
+        (None, Some(below)) => Some(below - POSITION_GAP),
+        (None, None) => Some(0),
+    }
+}
+
+pub const DEFAULT_PAGE_LIMIT: i64 = 50;
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TodoSort {
+    Position,
+    Id,
+    Text,
+    Completed,
+    Priority,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+/// Query string of `GET /todos`, e.g. `?completed=false&labels=1,2&sort=text&order=asc`,
+/// `?due_before=2022-11-01T00:00:00Z&overdue=false` for the todos due today
+/// or `?sort=priority` for the most urgent first. Without `sort` the todos come in list order.
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq, Default, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TodoQuery {
+    #[validate(range(min = 1, max = 100, message = "Out of range"))]
+    #[param(minimum = 1, maximum = 100)]
+    pub limit: Option<i64>,
+    /// `next_cursor` of the previous page
+    #[serde(default, deserialize_with = "deserialize_cursor")]
+    #[param(value_type = Option<String>)]
+    pub after: Option<TodoCursor>,
+    pub completed: Option<bool>,
+    /// todos carrying any of these labels
+    #[serde(default, deserialize_with = "deserialize_ids")]
+    #[param(value_type = Option<String>, example = "1,2")]
+    pub labels: Vec<i32>,
+    pub text: Option<String>,
+    /// todos due strictly before this time
+    pub due_before: Option<DateTime<Utc>>,
+    /// todos due at or after this time
+    pub due_after: Option<DateTime<Utc>>,
+    /// todos that are open and past their due date, or all others
+    pub overdue: Option<bool>,
+    #[param(inline)]
+    pub sort: Option<TodoSort>,
+    #[param(inline)]
+    pub order: Option<SortOrder>,
+}
+
+impl TodoQuery {
+    pub fn limit(&self) -> i64 {
+        self.limit.unwrap_or(DEFAULT_PAGE_LIMIT)
+    }
+
+    pub fn sort(&self) -> TodoSort {
+        self.sort.unwrap_or(TodoSort::Position)
+    }
+
+    /// Top of the list first for `position`, highest value first for the other sorts.
+    pub fn order(&self) -> SortOrder {
+        self.order.unwrap_or(match self.sort() {
+            TodoSort::Position => SortOrder::Asc,
+            _ => SortOrder::Desc,
+        })
+    }
+}
+
+/// Position after the last todo of a page, handed to clients as an opaque string.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct TodoCursor {
+    id: i32,
+    text: String,
+    completed: bool,
+    #[serde(default)]
+    priority: Priority,
+    #[serde(default)]
+    position: i64,
+}
+
+impl TodoCursor {
+    pub fn encode(&self) -> String {
+        let json = serde_json::to_vec(self).expect("cursor is always serializable");
+        base64::encode_config(json, base64::URL_SAFE_NO_PAD)
+    }
+
+    pub fn decode(cursor: &str) -> anyhow::Result<Self> {
+        let json = base64::decode_config(cursor, base64::URL_SAFE_NO_PAD)?;
+        Ok(serde_json::from_slice(&json)?)
+    }
+}
+
+impl From<&TodoEntity> for TodoCursor {
+    fn from(todo: &TodoEntity) -> Self {
+        Self {
+            id: todo.id,
+            text: todo.text.clone(),
+            completed: todo.completed,
+            priority: todo.priority,
+            position: todo.position,
+        }
+    }
+}
+
+fn deserialize_cursor<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<TodoCursor>, D::Error> {
+    let cursor = String::deserialize(deserializer)?;
+    TodoCursor::decode(&cursor)
+        .map(Some)
+        .map_err(|_| de::Error::custom("invalid cursor"))
+}
+
+fn deserialize_ids<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<i32>, D::Error> {
+    let ids = String::deserialize(deserializer)?;
+    ids.split(',')
+        .filter(|id| !id.is_empty())
+        .map(|id| id.trim().parse().map_err(de::Error::custom))
+        .collect()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, ToSchema)]
+pub struct TodoPage {
+    pub items: Vec<TodoEntity>,
+    pub next_cursor: Option<String>,
+}
+
+impl TodoPage {
+    /// Builds a page from up to `limit + 1` todos; the extra one only signals a next page.
+    fn from_overfetched(mut items: Vec<TodoEntity>, limit: i64) -> Self {
+        let next_cursor = if items.len() as i64 > limit {
+            items.truncate(limit as usize);
+            items.last().map(|todo| TodoCursor::from(todo).encode())
+        } else {
+            None
+        };
+        Self { items, next_cursor }
+    }
+}
+
+/// Query string of `GET /todos/search`, e.g. `?q=buy milk&limit=10`.
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq, Default, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchQuery {
+    #[validate(length(min = 1, message = "Can not be empty"))]
+    #[validate(length(max = 100, message = "Over text length"))]
+    #[param(min_length = 1, max_length = 100)]
+    pub q: String,
+    #[validate(range(min = 1, max = 100, message = "Out of range"))]
+    #[param(minimum = 1, maximum = 100)]
+    pub limit: Option<i64>,
+}
+
+impl SearchQuery {
+    pub fn limit(&self) -> i64 {
+        self.limit.unwrap_or(DEFAULT_PAGE_LIMIT)
+    }
+}
+
+/// Marks the matched words in `TodoSearchHit::snippet`.
+pub const HIGHLIGHT_START: &str = "<mark>";
+pub const HIGHLIGHT_STOP: &str = "</mark>";
+
+/// Placed by `ts_headline` around the matched words and taken out of the todo text first,
+/// so that only they become highlight marks once the text is escaped.
+const HEADLINE_START: char = '\u{1}';
+const HEADLINE_STOP: char = '\u{2}';
+
+/// Escapes `text` for HTML, turning the headline selectors into highlight marks.
+fn escape_snippet(text: &str) -> String {
+    let mut snippet = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            HEADLINE_START => snippet.push_str(HIGHLIGHT_START),
+            HEADLINE_STOP => snippet.push_str(HIGHLIGHT_STOP),
+            '&' => snippet.push_str("&amp;"),
+            '<' => snippet.push_str("&lt;"),
+            '>' => snippet.push_str("&gt;"),
+            '"' => snippet.push_str("&quot;"),
+            '\'' => snippet.push_str("&#39;"),
+            c => snippet.push(c),
+        }
+    }
+    snippet
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
+pub struct TodoSearchHit {
+    pub todo: TodoEntity,
+    /// relevance, only comparable between hits of the same search
+    pub rank: f32,
+    /// the todo text, escaped for HTML, with the matched words in `<mark>`
+    pub snippet: String,
 }
 
 #[derive(Debug, Clone)]
 pub struct TodoRepositoryForDb {
-    pool: PgPool,
+    ctx: DbContext,
+    /// the user changes are recorded for in the audit log
+    actor: Option<i32>,
 }
 
 impl TodoRepositoryForDb {
     pub fn new(pool: PgPool) -> Self {
-        TodoRepositoryForDb { pool }
+        TodoRepositoryForDb {
+            ctx: DbContext::Pool(pool),
+            actor: None,
+        }
+    }
+
+    pub fn with_context(ctx: DbContext) -> Self {
+        TodoRepositoryForDb { ctx, actor: None }
+    }
+
+    pub fn context(&self) -> &DbContext {
+        &self.ctx
+    }
+
+    pub fn actor(&self) -> Option<i32> {
+        self.actor
     }
 }
 
-#[async_trait]
-impl TodoRepository for TodoRepositoryForDb {
-    async fn create(&self, payload: CreateTodo) -> anyhow::Result<TodoEntity> {
-        let tx = self.pool.begin().await?;
-        let row = sqlx::query_as::<_, TodoFromRow>(
-            r#"insert into todos (text, completed) values ($1, false) returning *;"#,
+pub(super) fn todo_record(action: AuditAction, id: i32) -> AuditRecord {
+    AuditRecord::new(AuditEntity::Todo, id, action)
+}
+
+async fn find_todo(
+    conn: &mut PgConnection,
+    workspace_id: i32,
+    id: i32,
+) -> anyhow::Result<TodoEntity> {
+    let items = sqlx::query_as::<_, TodoWithLabelFromRow>(
+        r#"select todos.*, array(select children.id from todos children where children.parent_id = todos.id and children.deleted_at is null order by children.position, children.id) as child_ids, labels.id as label_id, labels.name as label_name from todos left outer join todo_labels tl on todos.id = tl.todo_id left outer join labels on labels.id = tl.label_id where todos.id=$1 and todos.workspace_id=$2 and todos.deleted_at is null;"#
+    ).bind(id)
+    .bind(workspace_id)
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::RowNotFound => RepositoryError::NotFound(id),
+        _ => RepositoryError::Unexpected(e.to_string()),
+    })?;
+
+    let todos = fold_entities(items);
+    let todo = todos.first().ok_or(RepositoryError::NotFound(id))?;
+    Ok(todo.clone())
+}
+
+/// Fails with the first of `label_ids` that is not a label of the workspace.
+async fn ensure_workspace_labels(
+    conn: &mut PgConnection,
+    workspace_id: i32,
+    label_ids: &[i32],
+) -> anyhow::Result<()> {
+    let workspace_label_ids: Vec<i32> =
+        sqlx::query_scalar(r#"select id from labels where id = any($1) and workspace_id = $2;"#)
+            .bind(label_ids)
+            .bind(workspace_id)
+            .fetch_all(&mut *conn)
+            .await?;
+    match label_ids
+        .iter()
+        .find(|id| !workspace_label_ids.contains(id))
+    {
+        Some(id) => Err(RepositoryError::NotFound(*id).into()),
+        None => Ok(()),
+    }
+}
+
+/// Fails unless `parent_id` is a todo of the workspace that todo `id` may become a subtask of,
+/// that is neither the todo itself nor one of its subtasks.
+async fn ensure_parent(
+    conn: &mut PgConnection,
+    workspace_id: i32,
+    id: Option<i32>,
+    parent_id: i32,
+) -> anyhow::Result<()> {
+    let ancestor_ids: Vec<i32> = sqlx::query_scalar(
+        r#"with recursive ancestors as (
+            select id, parent_id from todos where id = $1 and workspace_id = $2 and deleted_at is null
+            union all
+            select todos.id, todos.parent_id from todos join ancestors on todos.id = ancestors.parent_id
+        ) select id from ancestors;"#,
+    )
+    .bind(parent_id)
+    .bind(workspace_id)
+    .fetch_all(&mut *conn)
+    .await?;
+    if ancestor_ids.is_empty() {
+        return Err(RepositoryError::NotFound(parent_id).into());
+    }
+    if id.is_some_and(|id| ancestor_ids.contains(&id)) {
+        return Err(RepositoryError::Cycle(parent_id).into());
+    }
+    Ok(())
+}
+
+async fn create_todo(
+    conn: &mut PgConnection,
+    workspace_id: i32,
+    actor: Option<i32>,
+    payload: CreateTodo,
+) -> anyhow::Result<TodoEntity> {
+    ensure_workspace_labels(&mut *conn, workspace_id, &payload.labels).await?;
+    lock_positions(&mut *conn, workspace_id).await?;
+    if let Some(parent_id) = payload.parent_id {
+        ensure_parent(&mut *conn, workspace_id, None, parent_id).await?;
+    }
+    let row = sqlx::query_as::<_, TodoFromRow>(
+        r#"insert into todos (text, completed, priority, due_at, starts_at, parent_id, workspace_id, position) values ($1, false, $2, $3, $4, $5, $6, coalesce((select min(position) from todos where workspace_id = $6) - $7, 0)) returning *;"#,
+    )
+    .bind(payload.text.clone())
+    .bind(payload.priority)
+    .bind(payload.due_at)
+    .bind(payload.starts_at)
+    .bind(payload.parent_id)
+    .bind(workspace_id)
+    .bind(POSITION_GAP)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    sqlx::query(
+        r#"insert into todo_labels (todo_id, label_id) select $1, id from unnest($2) as t(id);"#,
+    )
+    .bind(row.id)
+    .bind(payload.labels)
+    .execute(&mut *conn)
+    .await?;
+    if let Some(parent_id) = payload.parent_id {
+        touch_todos(&mut *conn, &[parent_id]).await?;
+    }
+
+    let todo = find_todo(&mut *conn, workspace_id, row.id).await?;
+    let record = todo_record(AuditAction::Create, todo.id).after(&todo);
+    record_events(conn, workspace_id, actor, vec![record]).await?;
+    Ok(todo)
+}
+
+/// Locks the positions of the todos of the workspace until the end of the transaction, so that
+/// todos placed concurrently never read the same neighbours.
+async fn lock_positions(conn: &mut PgConnection, workspace_id: i32) -> anyhow::Result<()> {
+    sqlx::query(r#"select id from workspaces where id=$1 for no key update;"#)
+        .bind(workspace_id)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Locks the todo until the end of the transaction, then checks that it is at one of the
+/// `if_match` versions when they are given.
+async fn lock_todo(
+    conn: &mut PgConnection,
+    workspace_id: i32,
+    id: i32,
+    if_match: Option<Vec<i32>>,
+) -> anyhow::Result<()> {
+    let version: i32 = sqlx::query_scalar(
+        r#"select version from todos where id=$1 and workspace_id=$2 and deleted_at is null for update;"#,
+    )
+    .bind(id)
+    .bind(workspace_id)
+    .fetch_optional(conn)
+    .await?
+    .ok_or(RepositoryError::NotFound(id))?;
+    if if_match.is_some_and(|versions| !versions.contains(&version)) {
+        return Err(RepositoryError::VersionMismatch(id).into());
+    }
+    Ok(())
+}
+
+/// Bumps the version of todos whose representation changes along with other rows, like
+/// parents whose `child_ids` change with their subtasks, so that their `ETag` does too.
+async fn touch_todos(conn: &mut PgConnection, ids: &[i32]) -> anyhow::Result<()> {
+    sqlx::query(r#"update todos set version = version + 1 where id = any($1);"#)
+        .bind(ids)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+async fn update_todo(
+    conn: &mut PgConnection,
+    workspace_id: i32,
+    actor: Option<i32>,
+    id: i32,
+    payload: UpdateTodo,
+    if_match: Option<Vec<i32>>,
+) -> anyhow::Result<TodoEntity> {
+    lock_todo(&mut *conn, workspace_id, id, if_match).await?;
+    let old_todo = find_todo(&mut *conn, workspace_id, id).await?;
+    if let Some(Some(parent_id)) = payload.parent_id {
+        ensure_parent(&mut *conn, workspace_id, Some(id), parent_id).await?;
+    }
+    sqlx::query(
+        r#"update todos set text=$1, completed=$2, priority=$3, due_at=$4, starts_at=$5, parent_id=$6, version=version+1 where id = $7 returning *;"#,
+    )
+    .bind(payload.text.unwrap_or_else(|| old_todo.text.clone()))
+    .bind(payload.completed.unwrap_or(old_todo.completed))
+    .bind(payload.priority.unwrap_or(old_todo.priority))
+    .bind(payload.due_at.unwrap_or(old_todo.due_at))
+    .bind(payload.starts_at.unwrap_or(old_todo.starts_at))
+    .bind(payload.parent_id.unwrap_or(old_todo.parent_id))
+    .bind(id)
+    .fetch_one(&mut *conn)
+    .await?;
+    if let Some(labels) = payload.labels {
+        ensure_workspace_labels(&mut *conn, workspace_id, &labels).await?;
+        sqlx::query(r#"delete from todo_labels where todo_id=$1;"#)
+            .bind(id)
+            .execute(&mut *conn)
+            .await?;
+        sqlx::query(
+            r#"insert into todo_labels (todo_id, label_id) select $1, id from unnest ($2) as t(id);"#,
         )
-        .bind(payload.text.clone())
-        .fetch_one(&self.pool)
+        .bind(id)
+        .bind(labels)
+        .execute(&mut *conn)
+        .await?;
+    };
+    if let Some(parent_id) = payload.parent_id.filter(|id| *id != old_todo.parent_id) {
+        let parent_ids: Vec<i32> = [old_todo.parent_id, parent_id]
+            .into_iter()
+            .flatten()
+            .collect();
+        touch_todos(&mut *conn, &parent_ids).await?;
+    }
+
+    let todo = find_todo(&mut *conn, workspace_id, id).await?;
+    let record = todo_record(AuditAction::Update, id)
+        .before(&old_todo)
+        .after(&todo);
+    record_events(conn, workspace_id, actor, vec![record]).await?;
+    Ok(todo)
+}
+
+async fn delete_todo(
+    conn: &mut PgConnection,
+    workspace_id: i32,
+    actor: Option<i32>,
+    id: i32,
+    if_match: Option<Vec<i32>>,
+) -> anyhow::Result<()> {
+    lock_todo(&mut *conn, workspace_id, id, if_match).await?;
+    let trashed = trash_subtrees(conn, workspace_id, actor, &[id]).await?;
+    if trashed == 0 {
+        return Err(RepositoryError::NotFound(id).into());
+    }
+
+    Ok(())
+}
+
+/// Moves the todos and their subtasks to the trash, all with the same `deleted_at`.
+async fn trash_subtrees(
+    conn: &mut PgConnection,
+    workspace_id: i32,
+    actor: Option<i32>,
+    ids: &[i32],
+) -> anyhow::Result<u64> {
+    let ids: Vec<i32> = sqlx::query_scalar(
+        r#"with recursive subtree as (
+            select id from todos where id = any($1) and deleted_at is null
+            union
+            select todos.id from todos join subtree on todos.parent_id = subtree.id where todos.deleted_at is null
+        ) select id from subtree;"#,
+    )
+    .bind(ids)
+    .fetch_all(&mut *conn)
+    .await?;
+    let todos = find_todos(&mut *conn, &ids).await?;
+    sqlx::query(r#"update todos set deleted_at = $2, version = version + 1 where id = any($1);"#)
+        .bind(&ids)
+        .bind(Utc::now())
+        .execute(&mut *conn)
+        .await?;
+    let parent_ids: Vec<i32> = todos
+        .values()
+        .filter_map(|todo| todo.parent_id)
+        .filter(|parent_id| !ids.contains(parent_id))
+        .collect();
+    touch_todos(&mut *conn, &parent_ids).await?;
+
+    let records = ids
+        .iter()
+        .filter_map(|id| todos.get(id))
+        .map(|todo| todo_record(AuditAction::Delete, todo.id).before(todo))
+        .collect();
+    record_events(conn, workspace_id, actor, records).await?;
+    Ok(ids.len() as u64)
+}
+
+async fn restore_todo(
+    conn: &mut PgConnection,
+    workspace_id: i32,
+    actor: Option<i32>,
+    id: i32,
+) -> anyhow::Result<TodoEntity> {
+    let deleted_at: DateTime<Utc> = sqlx::query_scalar(
+        r#"select deleted_at from todos where id=$1 and workspace_id=$2 and deleted_at is not null;"#,
+    )
+    .bind(id)
+    .bind(workspace_id)
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or(RepositoryError::NotFound(id))?;
+    let ids: Vec<i32> = sqlx::query_scalar(
+        r#"with recursive subtree as (
+            select id from todos where id = $1
+            union
+            select todos.id from todos join subtree on todos.parent_id = subtree.id where todos.deleted_at = $2
+        ) update todos set deleted_at = null, version = version + 1 where id in (select id from subtree) returning id;"#,
+    )
+    .bind(id)
+    .bind(deleted_at)
+    .fetch_all(&mut *conn)
+    .await?;
+    // a todo whose parent is still in the trash comes back on its own
+    sqlx::query(
+        r#"update todos set parent_id = null where id = $1 and parent_id in (select id from todos where deleted_at is not null);"#,
+    )
+    .bind(id)
+    .execute(&mut *conn)
+    .await?;
+
+    let todos = find_todos(&mut *conn, &ids).await?;
+    if let Some(parent_id) = todos.get(&id).and_then(|todo| todo.parent_id) {
+        touch_todos(&mut *conn, &[parent_id]).await?;
+    }
+    let records = ids
+        .iter()
+        .filter_map(|id| todos.get(id))
+        .map(|todo| todo_record(AuditAction::Restore, todo.id).after(todo))
+        .collect();
+    record_events(&mut *conn, workspace_id, actor, records).await?;
+    find_todo(conn, workspace_id, id).await
+}
+
+async fn trashed_todos(
+    conn: &mut PgConnection,
+    workspace_id: i32,
+) -> anyhow::Result<Vec<TodoEntity>> {
+    let items = sqlx::query_as::<_, TodoWithLabelFromRow>(
+        r#"select todos.*, array(select children.id from todos children where children.parent_id = todos.id and children.deleted_at is null order by children.position, children.id) as child_ids, labels.id as label_id, labels.name as label_name from todos left outer join todo_labels tl on todos.id = tl.todo_id left outer join labels on labels.id = tl.label_id where todos.workspace_id=$1 and todos.deleted_at is not null order by todos.deleted_at desc, todos.id desc;"#
+    ).bind(workspace_id)
+    .fetch_all(conn)
+    .await?;
+
+    Ok(fold_entities(items))
+}
+
+/// Permanently deletes trashed todos, of one workspace or trashed before a time or both.
+async fn purge_todos(
+    conn: &mut PgConnection,
+    actor: Option<i32>,
+    workspace_id: Option<i32>,
+    deleted_before: Option<DateTime<Utc>>,
+) -> anyhow::Result<u64> {
+    let ids: Vec<i32> = sqlx::query_scalar(
+        r#"select id from todos where deleted_at is not null
+        and ($1::integer is null or workspace_id = $1)
+        and ($2::timestamptz is null or deleted_at < $2);"#,
+    )
+    .bind(workspace_id)
+    .bind(deleted_before)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let deleted = delete_subtrees(&mut *conn, &ids).await?;
+    for (id, workspace_id) in deleted.iter().copied() {
+        let record = todo_record(AuditAction::Purge, id);
+        record_events(&mut *conn, workspace_id, actor, vec![record]).await?;
+    }
+    Ok(deleted.len() as u64)
+}
+
+/// Deletes the todos and their subtasks, at any depth, returning the id and workspace
+/// of every deleted row.
+async fn delete_subtrees(conn: &mut PgConnection, ids: &[i32]) -> anyhow::Result<Vec<(i32, i32)>> {
+    let deleted: Vec<(i32, i32)> = sqlx::query_as(
+        r#"with recursive subtree as (
+            select id, workspace_id from todos where id = any($1)
+            union
+            select todos.id, todos.workspace_id from todos join subtree on todos.parent_id = subtree.id
+        ) select id, workspace_id from subtree;"#,
+    )
+    .bind(ids)
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|e| RepositoryError::Unexpected(e.to_string()))?;
+    let ids: Vec<i32> = deleted.iter().map(|(id, _workspace_id)| *id).collect();
+    // delete todo_label
+    sqlx::query(r#"delete from todo_labels where todo_id = any($1);"#)
+        .bind(&ids)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| RepositoryError::Unexpected(e.to_string()))?;
+    // delete todo
+    sqlx::query(r#"delete from todos where id = any($1);"#)
+        .bind(&ids)
+        .execute(&mut *conn)
         .await
-        .expect("cannot insert into todos table");
+        .map_err(|e| RepositoryError::Unexpected(e.to_string()))?;
 
-        sqlx::query(
-            r#"insert into todo_labels (todo_id, label_id) select $1, id from unnest($2) as t(id);"#,
+    Ok(deleted)
+}
+
+async fn child_todos(
+    conn: &mut PgConnection,
+    workspace_id: i32,
+    id: i32,
+) -> anyhow::Result<Vec<TodoEntity>> {
+    find_todo(&mut *conn, workspace_id, id).await?;
+    let items = sqlx::query_as::<_, TodoWithLabelFromRow>(
+        r#"select todos.*, array(select children.id from todos children where children.parent_id = todos.id and children.deleted_at is null order by children.position, children.id) as child_ids, labels.id as label_id, labels.name as label_name from todos left outer join todo_labels tl on todos.id = tl.todo_id left outer join labels on labels.id = tl.label_id where todos.parent_id=$1 and todos.deleted_at is null order by todos.position, todos.id;"#
+    ).bind(id)
+    .fetch_all(conn)
+    .await?;
+
+    Ok(fold_entities(items))
+}
+
+/// The todos with the given ids, by id.
+pub(super) async fn find_todos(
+    conn: &mut PgConnection,
+    ids: &[i32],
+) -> anyhow::Result<HashMap<i32, TodoEntity>> {
+    let items = sqlx::query_as::<_, TodoWithLabelFromRow>(
+        r#"select todos.*, array(select children.id from todos children where children.parent_id = todos.id and children.deleted_at is null order by children.position, children.id) as child_ids, labels.id as label_id, labels.name as label_name from todos left outer join todo_labels tl on todos.id = tl.todo_id left outer join labels on labels.id = tl.label_id where todos.id = any($1) and todos.deleted_at is null;"#
+    ).bind(ids)
+    .fetch_all(conn)
+    .await?;
+
+    Ok(fold_entities(items)
+        .into_iter()
+        .map(|todo| (todo.id, todo))
+        .collect())
+}
+
+async fn bulk_todos(
+    conn: &mut PgConnection,
+    workspace_id: i32,
+    actor: Option<i32>,
+    payload: BulkTodo,
+) -> anyhow::Result<Vec<BulkItemResult>> {
+    // like `lock_todo`, in id order so that overlapping bulk changes can not deadlock
+    let found: Vec<i32> = sqlx::query_scalar(
+        r#"select id from todos where id = any($1) and workspace_id = $2 and deleted_at is null order by id for update;"#,
+    )
+    .bind(&payload.ids)
+    .bind(workspace_id)
+    .fetch_all(&mut *conn)
+    .await?;
+    ensure_workspace_labels(&mut *conn, workspace_id, payload.operation.labels()).await?;
+    let before = find_todos(&mut *conn, &found).await?;
+    // completing a completed todo changes nothing, so it keeps its version and goes unrecorded
+    let changed: Vec<i32> = found
+        .iter()
+        .copied()
+        .filter(|id| {
+            payload.operation.completed().is_none_or(|completed| {
+                before
+                    .get(id)
+                    .is_some_and(|todo| todo.completed != completed)
+            })
+        })
+        .collect();
+    match &payload.operation {
+        BulkOperation::Complete | BulkOperation::Uncomplete => {
+            sqlx::query(r#"update todos set completed=$1 where id = any($2);"#)
+                .bind(payload.operation == BulkOperation::Complete)
+                .bind(&changed)
+                .execute(&mut *conn)
+                .await?;
+        }
+        BulkOperation::Delete => {
+            trash_subtrees(&mut *conn, workspace_id, actor, &changed).await?;
+        }
+        BulkOperation::RemoveLabels { labels } => {
+            sqlx::query(
+                r#"delete from todo_labels where todo_id = any($1) and label_id = any($2);"#,
+            )
+            .bind(&changed)
+            .bind(labels)
+            .execute(&mut *conn)
+            .await?;
+        }
+        BulkOperation::AddLabels { labels } | BulkOperation::SetLabels { labels } => {
+            if let BulkOperation::SetLabels { .. } = payload.operation {
+                sqlx::query(r#"delete from todo_labels where todo_id = any($1);"#)
+                    .bind(&changed)
+                    .execute(&mut *conn)
+                    .await?;
+            }
+            sqlx::query(
+                r#"insert into todo_labels (todo_id, label_id)
+                select distinct t.id, l.id from unnest($1) as t(id) cross join unnest($2) as l(id)
+                where not exists (select 1 from todo_labels where todo_id = t.id and label_id = l.id);"#,
+            )
+            .bind(&changed)
+            .bind(labels)
+            .execute(&mut *conn)
+            .await?;
+        }
+    }
+    if payload.operation != BulkOperation::Delete {
+        sqlx::query(r#"update todos set version = version + 1 where id = any($1);"#)
+            .bind(&changed)
+            .execute(&mut *conn)
+            .await?;
+    }
+    let todos = find_todos(&mut *conn, &found).await?;
+    // deletes are recorded by `trash_subtrees`, subtasks included
+    let records = changed
+        .iter()
+        .filter_map(|id| Some((before.get(id)?, todos.get(id)?)))
+        .map(|(old_todo, todo)| {
+            todo_record(AuditAction::Update, todo.id)
+                .before(old_todo)
+                .after(todo)
+        })
+        .collect();
+    record_events(conn, workspace_id, actor, records).await?;
+
+    Ok(BulkItemResult::collect(
+        &payload.ids,
+        todos,
+        &found,
+        &changed,
+    ))
+}
+
+async fn search_todos(
+    conn: &mut PgConnection,
+    workspace_id: i32,
+    query: SearchQuery,
+) -> anyhow::Result<Vec<TodoSearchHit>> {
+    let headline_options = format!(
+        "StartSel={}, StopSel={}, HighlightAll=true",
+        HEADLINE_START, HEADLINE_STOP
+    );
+    let hits = sqlx::query_as::<_, TodoSearchFromRow>(
+        r#"select todos.id, ts_rank(todos.search, query) as rank, ts_headline('simple', translate(todos.text, $5, ''), query, $4) as snippet
+        from todos, plainto_tsquery('simple', $2) query
+        where todos.workspace_id = $1 and todos.deleted_at is null and todos.search @@ query
+        order by rank desc, todos.id desc limit $3;"#,
+    )
+    .bind(workspace_id)
+    .bind(query.q.as_str())
+    .bind(query.limit())
+    .bind(headline_options)
+    .bind(format!("{}{}", HEADLINE_START, HEADLINE_STOP))
+    .fetch_all(&mut *conn)
+    .await?;
+    let ids: Vec<i32> = hits.iter().map(|hit| hit.id).collect();
+    let mut todos = find_todos(conn, &ids).await?;
+
+    Ok(hits
+        .into_iter()
+        .filter_map(|hit| {
+            todos.remove(&hit.id).map(|todo| TodoSearchHit {
+                todo,
+                rank: hit.rank,
+                snippet: escape_snippet(&hit.snippet),
+            })
+        })
+        .collect())
+}
+
+async fn position_of(conn: &mut PgConnection, id: i32) -> anyhow::Result<i64> {
+    let position = sqlx::query_scalar(r#"select position from todos where id=$1;"#)
+        .bind(id)
+        .fetch_one(conn)
+        .await?;
+    Ok(position)
+}
+
+/// Positions of the todos the moved todo `id` will sit between, ignoring its current place.
+async fn neighbour_positions(
+    conn: &mut PgConnection,
+    workspace_id: i32,
+    id: i32,
+    payload: &MoveTodo,
+) -> anyhow::Result<(Option<i64>, Option<i64>)> {
+    match (payload.after, payload.before) {
+        (Some(after), Some(before)) => {
+            let after = position_of(&mut *conn, after).await?;
+            let before = position_of(&mut *conn, before).await?;
+            Ok((Some(after.min(before)), Some(after.max(before))))
+        }
+        (Some(after), None) => {
+            let above = position_of(&mut *conn, after).await?;
+            let below = sqlx::query_scalar(
+                r#"select min(position) from todos where workspace_id=$1 and id<>$2 and position>$3 and deleted_at is null;"#,
+            )
+            .bind(workspace_id)
+            .bind(id)
+            .bind(above)
+            .fetch_one(conn)
+            .await?;
+            Ok((Some(above), below))
+        }
+        (None, Some(before)) => {
+            let below = position_of(&mut *conn, before).await?;
+            let above = sqlx::query_scalar(
+                r#"select max(position) from todos where workspace_id=$1 and id<>$2 and position<$3 and deleted_at is null;"#,
+            )
+            .bind(workspace_id)
+            .bind(id)
+            .bind(below)
+            .fetch_one(conn)
+            .await?;
+            Ok((above, Some(below)))
+        }
+        (None, None) => Ok((None, None)),
+    }
+}
+
+/// Spreads the todos of the workspace `POSITION_GAP` apart again, keeping their order. Every
+/// todo that moves gets a new version, and those outside the trash an audit record.
+async fn rebalance_positions(
+    conn: &mut PgConnection,
+    workspace_id: i32,
+    actor: Option<i32>,
+) -> anyhow::Result<()> {
+    let moved: Vec<(i32, i64)> = sqlx::query_as(
+        r#"update todos set position = ranked.rank * $2, version = version + 1 from (
+            select id, position, row_number() over (order by position, id) as rank from todos where workspace_id = $1
+        ) ranked where todos.id = ranked.id and todos.position <> ranked.rank * $2
+        returning todos.id, ranked.position;"#,
+    )
+    .bind(workspace_id)
+    .bind(POSITION_GAP)
+    .fetch_all(&mut *conn)
+    .await?;
+    let ids: Vec<i32> = moved.iter().map(|(id, _position)| *id).collect();
+    let todos = find_todos(&mut *conn, &ids).await?;
+
+    // nothing but the position and version changed
+    let records = moved
+        .iter()
+        .filter_map(|(id, position)| todos.get(id).map(|todo| (todo, *position)))
+        .map(|(todo, position)| {
+            let old_todo = TodoEntity {
+                position,
+                version: todo.version - 1,
+                ..todo.clone()
+            };
+            todo_record(AuditAction::Update, todo.id)
+                .before(&old_todo)
+                .after(todo)
+        })
+        .collect();
+    record_events(conn, workspace_id, actor, records).await?;
+    Ok(())
+}
+
+async fn move_todo(
+    conn: &mut PgConnection,
+    workspace_id: i32,
+    actor: Option<i32>,
+    id: i32,
+    payload: MoveTodo,
+) -> anyhow::Result<TodoEntity> {
+    lock_positions(&mut *conn, workspace_id).await?;
+    lock_todo(&mut *conn, workspace_id, id, None).await?;
+    let old_todo = find_todo(&mut *conn, workspace_id, id).await?;
+    for neighbour_id in payload.neighbour_ids() {
+        find_todo(&mut *conn, workspace_id, neighbour_id).await?;
+    }
+    let (above, below) = neighbour_positions(&mut *conn, workspace_id, id, &payload).await?;
+    let position = match position_between(above, below) {
+        Some(position) => position,
+        None => {
+            // the neighbours are too dense, so make room once and try again
+            rebalance_positions(&mut *conn, workspace_id, actor).await?;
+            let (above, below) =
+                neighbour_positions(&mut *conn, workspace_id, id, &payload).await?;
+            position_between(above, below)
+                .ok_or_else(|| RepositoryError::Unexpected(format!("no room to move todo {id}")))?
+        }
+    };
+    sqlx::query(r#"update todos set position=$1, version=version+1 where id=$2;"#)
+        .bind(position)
+        .bind(id)
+        .execute(&mut *conn)
+        .await?;
+    // subtasks are listed in order of position
+    if let Some(parent_id) = old_todo.parent_id {
+        touch_todos(&mut *conn, &[parent_id]).await?;
+    }
+
+    let todo = find_todo(&mut *conn, workspace_id, id).await?;
+    let record = todo_record(AuditAction::Update, id)
+        .before(&old_todo)
+        .after(&todo);
+    record_events(conn, workspace_id, actor, vec![record]).await?;
+    Ok(todo)
+}
+
+async fn all_todo(
+    conn: &mut PgConnection,
+    workspace_id: i32,
+    query: TodoQuery,
+) -> anyhow::Result<TodoPage> {
+    let (direction, op) = match query.order() {
+        SortOrder::Asc => ("asc", ">"),
+        SortOrder::Desc => ("desc", "<"),
+    };
+    let (column, after) = match query.sort() {
+        TodoSort::Position => (
+            "todos.position",
+            format!("(todos.position, todos.id) {op} ($13, $4)"),
+        ),
+        TodoSort::Id => ("todos.id", format!("todos.id {op} $4")),
+        TodoSort::Text => (
+            "todos.text",
+            format!("(todos.text, todos.id) {op} ($5, $4)"),
+        ),
+        TodoSort::Completed => (
+            "todos.completed",
+            format!("(todos.completed, todos.id) {op} ($6, $4)"),
+        ),
+        TodoSort::Priority => (
+            "todos.priority",
+            format!("(todos.priority, todos.id) {op} ($12, $4)"),
+        ),
+    };
+    let order_by = format!("{column} {direction}, todos.id {direction}");
+    let sql = format!(
+        r#"select todos.*, array(select children.id from todos children where children.parent_id = todos.id and children.deleted_at is null order by children.position, children.id) as child_ids, labels.id as label_id, labels.name as label_name from (
+            select * from todos
+            where todos.workspace_id = $8 and todos.deleted_at is null
+            and ($1::boolean is null or todos.completed = $1)
+            and ($2::text is null or todos.text ilike '%' || $2 || '%')
+            and (cardinality($3::integer[]) = 0 or exists (select 1 from todo_labels where todo_id = todos.id and label_id = any($3)))
+            and ($4::integer is null or {after})
+            and ($9::timestamptz is null or todos.due_at < $9)
+            and ($10::timestamptz is null or todos.due_at >= $10)
+            and ($11::boolean is null or (not todos.completed and coalesce(todos.due_at < now(), false)) = $11)
+            order by {order_by} limit $7
+        ) todos left outer join todo_labels tl on todos.id = tl.todo_id left outer join labels on labels.id = tl.label_id order by {order_by};"#
+    );
+    let limit = query.limit();
+    let cursor = query.after.as_ref();
+    let items = sqlx::query_as::<_, TodoWithLabelFromRow>(&sql)
+        .bind(query.completed)
+        .bind(query.text.as_deref().map(escape_like))
+        .bind(&query.labels)
+        .bind(cursor.map(|cursor| cursor.id))
+        .bind(cursor.map(|cursor| cursor.text.clone()))
+        .bind(cursor.map(|cursor| cursor.completed))
+        .bind(limit + 1)
+        .bind(workspace_id)
+        .bind(query.due_before)
+        .bind(query.due_after)
+        .bind(query.overdue)
+        .bind(cursor.map(|cursor| cursor.priority))
+        .bind(cursor.map(|cursor| cursor.position))
+        .fetch_all(conn)
+        .await?;
+
+    Ok(TodoPage::from_overfetched(fold_entities(items), limit))
+}
+
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+#[async_trait]
+impl TodoRepository for TodoRepositoryForDb {
+    fn acting_as(&self, user_id: i32) -> Self {
+        TodoRepositoryForDb {
+            ctx: self.ctx.clone(),
+            actor: Some(user_id),
+        }
+    }
+
+    #[tracing::instrument(name = "todo.create", skip(self, payload))]
+    async fn create(&self, workspace_id: i32, payload: CreateTodo) -> anyhow::Result<TodoEntity> {
+        let actor = self.actor;
+        self.ctx
+            .transaction(|conn| Box::pin(create_todo(conn, workspace_id, actor, payload)))
+            .await
+    }
+
+    #[tracing::instrument(name = "todo.find", skip(self))]
+    async fn find(&self, workspace_id: i32, id: i32) -> anyhow::Result<TodoEntity> {
+        self.ctx
+            .transaction(|conn| Box::pin(find_todo(conn, workspace_id, id)))
+            .await
+    }
+
+    #[tracing::instrument(name = "todo.all", skip(self, query))]
+    async fn all(&self, workspace_id: i32, query: TodoQuery) -> anyhow::Result<TodoPage> {
+        self.ctx
+            .transaction(|conn| Box::pin(all_todo(conn, workspace_id, query)))
+            .await
+    }
+
+    #[tracing::instrument(name = "todo.update", skip(self, payload))]
+    async fn update(
+        &self,
+        workspace_id: i32,
+        id: i32,
+        payload: UpdateTodo,
+        if_match: Option<Vec<i32>>,
+    ) -> anyhow::Result<TodoEntity> {
+        let actor = self.actor;
+        self.ctx
+            .transaction(|conn| {
+                Box::pin(update_todo(
+                    conn,
+                    workspace_id,
+                    actor,
+                    id,
+                    payload,
+                    if_match,
+                ))
+            })
+            .await
+    }
+
+    #[tracing::instrument(name = "todo.delete", skip(self))]
+    async fn delete(
+        &self,
+        workspace_id: i32,
+        id: i32,
+        if_match: Option<Vec<i32>>,
+    ) -> anyhow::Result<()> {
+        let actor = self.actor;
+        self.ctx
+            .transaction(|conn| Box::pin(delete_todo(conn, workspace_id, actor, id, if_match)))
+            .await
+    }
+
+    #[tracing::instrument(name = "todo.children", skip(self))]
+    async fn children(&self, workspace_id: i32, id: i32) -> anyhow::Result<Vec<TodoEntity>> {
+        self.ctx
+            .transaction(|conn| Box::pin(child_todos(conn, workspace_id, id)))
+            .await
+    }
+
+    #[tracing::instrument(name = "todo.reposition", skip(self, payload))]
+    async fn reposition(
+        &self,
+        workspace_id: i32,
+        id: i32,
+        payload: MoveTodo,
+    ) -> anyhow::Result<TodoEntity> {
+        let actor = self.actor;
+        self.ctx
+            .transaction(|conn| Box::pin(move_todo(conn, workspace_id, actor, id, payload)))
+            .await
+    }
+
+    #[tracing::instrument(name = "todo.search", skip(self, query))]
+    async fn search(
+        &self,
+        workspace_id: i32,
+        query: SearchQuery,
+    ) -> anyhow::Result<Vec<TodoSearchHit>> {
+        self.ctx
+            .transaction(|conn| Box::pin(search_todos(conn, workspace_id, query)))
+            .await
+    }
+
+    #[tracing::instrument(name = "todo.bulk", skip(self, payload))]
+    async fn bulk(
+        &self,
+        workspace_id: i32,
+        payload: BulkTodo,
+    ) -> anyhow::Result<Vec<BulkItemResult>> {
+        let actor = self.actor;
+        self.ctx
+            .transaction(|conn| Box::pin(bulk_todos(conn, workspace_id, actor, payload)))
+            .await
+    }
+
+    #[tracing::instrument(name = "todo.trash", skip(self))]
+    async fn trash(&self, workspace_id: i32) -> anyhow::Result<Vec<TodoEntity>> {
+        self.ctx
+            .transaction(|conn| Box::pin(trashed_todos(conn, workspace_id)))
+            .await
+    }
+
+    #[tracing::instrument(name = "todo.restore", skip(self))]
+    async fn restore(&self, workspace_id: i32, id: i32) -> anyhow::Result<TodoEntity> {
+        let actor = self.actor;
+        self.ctx
+            .transaction(|conn| Box::pin(restore_todo(conn, workspace_id, actor, id)))
+            .await
+    }
+
+    #[tracing::instrument(name = "todo.purge", skip(self))]
+    async fn purge(&self, workspace_id: i32) -> anyhow::Result<u64> {
+        let actor = self.actor;
+        self.ctx
+            .transaction(|conn| Box::pin(purge_todos(conn, actor, Some(workspace_id), None)))
+            .await
+    }
+
+    #[tracing::instrument(name = "todo.purge_expired", skip(self))]
+    async fn purge_expired(&self, deleted_before: DateTime<Utc>) -> anyhow::Result<u64> {
+        let actor = self.actor;
+        self.ctx
+            .transaction(|conn| Box::pin(purge_todos(conn, actor, None, Some(deleted_before))))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dotenv::dotenv;
+    use sqlx::PgPool;
+    use std::{env, vec};
+
+    #[test]
+    fn fold_entities_test() {
+        let label_1 = Label {
+            id: 1,
+            name: String::from("label 1"),
+        };
+        let label_2 = Label {
+            id: 2,
+            name: String::from("label 2"),
+        };
+        let rows = vec![
+            TodoWithLabelFromRow {
+                id: 1,
+                text: String::from("todo 1"),
+                completed: false,
+                priority: Priority::None,
+                due_at: None,
+                starts_at: None,
+                parent_id: None,
+                child_ids: vec![2],
+                position: 0,
+                deleted_at: None,
+                version: 1,
+                label_id: Some(label_1.id),
+                label_name: Some(label_1.name.clone()),
+            },
+            TodoWithLabelFromRow {
+                id: 1,
+                text: String::from("todo 1"),
+                completed: false,
+                priority: Priority::None,
+                due_at: None,
+                starts_at: None,
+                parent_id: None,
+                child_ids: vec![2],
+                position: 0,
+                deleted_at: None,
+                version: 1,
+                label_id: Some(label_2.id),
+                label_name: Some(label_2.name.clone()),
+            },
+            TodoWithLabelFromRow {
+                id: 2,
+                text: String::from("todo 2"),
+                completed: false,
+                priority: Priority::None,
+                due_at: None,
+                starts_at: None,
+                parent_id: Some(1),
+                child_ids: vec![],
+                position: 0,
+                deleted_at: None,
+                version: 1,
+                label_id: Some(label_1.id),
+                label_name: Some(label_1.name.clone()),
+            },
+        ];
+        let res = fold_entities(rows);
+        assert_eq!(
+            res,
+            vec![
+                TodoEntity {
+                    id: 1,
+                    text: String::from("todo 1"),
+                    completed: false,
+                    priority: Priority::None,
+                    due_at: None,
+                    starts_at: None,
+                    parent_id: None,
+                    child_ids: vec![2],
+                    position: 0,
+                    deleted_at: None,
+                    version: 1,
+                    labels: vec![label_1.clone(), label_2],
+                },
+                TodoEntity {
+                    id: 2,
+                    text: String::from("todo 2"),
+                    completed: false,
+                    priority: Priority::None,
+                    due_at: None,
+                    starts_at: None,
+                    parent_id: Some(1),
+                    child_ids: vec![],
+                    position: 0,
+                    deleted_at: None,
+                    version: 1,
+                    labels: vec![label_1],
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn position_between_test() {
+        assert_eq!(Some(0), position_between(None, None));
+        assert_eq!(Some(POSITION_GAP), position_between(Some(0), None));
+        assert_eq!(Some(-POSITION_GAP), position_between(None, Some(0)));
+        assert_eq!(Some(5), position_between(Some(0), Some(10)));
+        assert_eq!(Some(1), position_between(Some(0), Some(2)));
+        assert_eq!(None, position_between(Some(0), Some(1)));
+        assert_eq!(None, position_between(Some(3), Some(3)));
+    }
+
+    #[cfg(feature = "database-test")]
+    #[tokio::test]
+    async fn query_scenario() {
+        use crate::repositories::{
+            label::LabelRepository,
+            unit_of_work::{UnitOfWork, UnitOfWorkForDb},
+            user::UserRepositoryForDb,
+            workspace::{test_utils::prepare_workspace, WorkspaceRepositoryForDb},
+        };
+
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database_url));
+        // nothing is committed, so other tests never see these rows
+        let uow = UnitOfWorkForDb::begin(&pool)
+            .await
+            .expect("[begin] returned Err");
+        let ctx = DbContext::UnitOfWork(uow.clone());
+        let workspace = prepare_workspace(
+            &UserRepositoryForDb::with_context(ctx.clone()),
+            &WorkspaceRepositoryForDb::with_context(ctx),
+            "[query_scenario] user",
+            "[query_scenario] workspace",
+        )
+        .await;
+        let label = uow
+            .labels()
+            .create(workspace.id, "[query_scenario] label".to_string())
+            .await
+            .expect("[create label] returned Err");
+        let repository = uow.todos();
+        let mut ids = vec![];
+        for (text, labels) in [
+            ("[query_scenario] b 100%", vec![label.id]),
+            ("[query_scenario] a", vec![]),
+            ("[query_scenario] c", vec![label.id]),
+        ] {
+            let todo = repository
+                .create(workspace.id, CreateTodo::new(text.to_string(), labels))
+                .await
+                .expect("[create] returned Err");
+            ids.push(todo.id);
+        }
+        repository
+            .update(
+                workspace.id,
+                ids[2],
+                UpdateTodo::new(None, Some(true), None),
+                None,
+            )
+            .await
+            .expect("[update] returned Err");
+
+        // sort by text with cursor pagination
+        let query = TodoQuery {
+            limit: Some(2),
+            text: Some("[query_scenario]".to_string()),
+            sort: Some(TodoSort::Text),
+            order: Some(SortOrder::Asc),
+            ..TodoQuery::default()
+        };
+        let page = repository
+            .all(workspace.id, query.clone())
+            .await
+            .expect("[all] returned Err");
+        let page_ids: Vec<i32> = page.items.iter().map(|todo| todo.id).collect();
+        assert_eq!(vec![ids[1], ids[0]], page_ids);
+        assert_eq!(vec![label.clone()], page.items[1].labels);
+        let after = TodoCursor::decode(&page.next_cursor.expect("next cursor should exist"))
+            .expect("cursor should decode");
+        let page = repository
+            .all(
+                workspace.id,
+                TodoQuery {
+                    after: Some(after),
+                    ..query
+                },
+            )
+            .await
+            .expect("[all] returned Err");
+        let page_ids: Vec<i32> = page.items.iter().map(|todo| todo.id).collect();
+        assert_eq!(vec![ids[2]], page_ids);
+        assert_eq!(None, page.next_cursor);
+
+        // filters, with like wildcards matched literally
+        let page = repository
+            .all(
+                workspace.id,
+                TodoQuery {
+                    text: Some("100%".to_string()),
+                    ..TodoQuery::default()
+                },
+            )
+            .await
+            .expect("[all] returned Err");
+        assert_eq!(1, page.items.len());
+        let page = repository
+            .all(
+                workspace.id,
+                TodoQuery {
+                    completed: Some(false),
+                    labels: vec![label.id],
+                    ..TodoQuery::default()
+                },
+            )
+            .await
+            .expect("[all] returned Err");
+        let page_ids: Vec<i32> = page.items.iter().map(|todo| todo.id).collect();
+        assert_eq!(vec![ids[0]], page_ids);
+
+        // sort by priority, most urgent first
+        repository
+            .update(
+                workspace.id,
+                ids[1],
+                UpdateTodo {
+                    priority: Some(Priority::Urgent),
+                    ..UpdateTodo::default()
+                },
+                None,
+            )
+            .await
+            .expect("[update] returned Err");
+        let query = TodoQuery {
+            limit: Some(1),
+            text: Some("[query_scenario]".to_string()),
+            sort: Some(TodoSort::Priority),
+            ..TodoQuery::default()
+        };
+        let page = repository
+            .all(workspace.id, query.clone())
+            .await
+            .expect("[all] returned Err");
+        assert_eq!(ids[1], page.items[0].id);
+        assert_eq!(Priority::Urgent, page.items[0].priority);
+        let after = TodoCursor::decode(&page.next_cursor.expect("next cursor should exist"))
+            .expect("cursor should decode");
+        let page = repository
+            .all(
+                workspace.id,
+                TodoQuery {
+                    after: Some(after),
+                    ..query
+                },
+            )
+            .await
+            .expect("[all] returned Err");
+        assert_eq!(ids[2], page.items[0].id);
+    }
+
+    #[cfg(feature = "database-test")]
+    #[tokio::test]
+    async fn due_date_scenario() {
+        use crate::repositories::{
+            unit_of_work::{UnitOfWork, UnitOfWorkForDb},
+            user::UserRepositoryForDb,
+            workspace::{test_utils::prepare_workspace, WorkspaceRepositoryForDb},
+        };
+
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database_url));
+        // nothing is committed, so other tests never see these rows
+        let uow = UnitOfWorkForDb::begin(&pool)
+            .await
+            .expect("[begin] returned Err");
+        let ctx = DbContext::UnitOfWork(uow.clone());
+        let workspace = prepare_workspace(
+            &UserRepositoryForDb::with_context(ctx.clone()),
+            &WorkspaceRepositoryForDb::with_context(ctx),
+            "[due_date_scenario] user",
+            "[due_date_scenario] workspace",
+        )
+        .await;
+        let repository = uow.todos();
+        // postgres stores microseconds, so stick to whole seconds for comparisons
+        let now = DateTime::<Utc>::from_timestamp(Utc::now().timestamp(), 0).unwrap();
+        let yesterday = now - chrono::Duration::days(1);
+        let tomorrow = now + chrono::Duration::days(1);
+        let mut ids = vec![];
+        for (text, due_at) in [
+            ("[due_date_scenario] overdue", Some(yesterday)),
+            ("[due_date_scenario] due", Some(tomorrow)),
+            ("[due_date_scenario] someday", None),
+        ] {
+            let todo = repository
+                .create(
+                    workspace.id,
+                    CreateTodo {
+                        due_at,
+                        starts_at: Some(yesterday),
+                        ..CreateTodo::new(text.to_string(), vec![])
+                    },
+                )
+                .await
+                .expect("[create] returned Err");
+            assert_eq!(due_at, todo.due_at);
+            ids.push(todo.id);
+        }
+
+        let page = repository
+            .all(
+                workspace.id,
+                TodoQuery {
+                    overdue: Some(true),
+                    ..TodoQuery::default()
+                },
+            )
+            .await
+            .expect("[all] returned Err");
+        let page_ids: Vec<i32> = page.items.iter().map(|todo| todo.id).collect();
+        assert_eq!(vec![ids[0]], page_ids);
+        let page = repository
+            .all(
+                workspace.id,
+                TodoQuery {
+                    due_before: Some(tomorrow + chrono::Duration::hours(1)),
+                    overdue: Some(false),
+                    ..TodoQuery::default()
+                },
+            )
+            .await
+            .expect("[all] returned Err");
+        let page_ids: Vec<i32> = page.items.iter().map(|todo| todo.id).collect();
+        assert_eq!(vec![ids[1]], page_ids);
+        let page = repository
+            .all(
+                workspace.id,
+                TodoQuery {
+                    due_after: Some(now),
+                    ..TodoQuery::default()
+                },
+            )
+            .await
+            .expect("[all] returned Err");
+        let page_ids: Vec<i32> = page.items.iter().map(|todo| todo.id).collect();
+        assert_eq!(vec![ids[1]], page_ids);
+
+        // a missing field keeps the date, an explicit null clears it
+        let todo = repository
+            .update(
+                workspace.id,
+                ids[1],
+                UpdateTodo {
+                    due_at: Some(None),
+                    ..UpdateTodo::default()
+                },
+                None,
+            )
+            .await
+            .expect("[update] returned Err");
+        assert_eq!(None, todo.due_at);
+        assert_eq!(Some(yesterday), todo.starts_at);
+    }
+
+    #[cfg(feature = "database-test")]
+    #[tokio::test]
+    async fn hierarchy_scenario() {
+        use crate::repositories::{
+            label::{LabelRepository, UpdateLabel},
+            unit_of_work::{UnitOfWork, UnitOfWorkForDb},
+            user::UserRepositoryForDb,
+            workspace::{test_utils::prepare_workspace, WorkspaceRepositoryForDb},
+        };
+
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database_url));
+        // nothing is committed, so other tests never see these rows
+        let uow = UnitOfWorkForDb::begin(&pool)
+            .await
+            .expect("[begin] returned Err");
+        let ctx = DbContext::UnitOfWork(uow.clone());
+        let workspace = prepare_workspace(
+            &UserRepositoryForDb::with_context(ctx.clone()),
+            &WorkspaceRepositoryForDb::with_context(ctx),
+            "[hierarchy_scenario] user",
+            "[hierarchy_scenario] workspace",
+        )
+        .await;
+        let label = uow
+            .labels()
+            .create(workspace.id, "[hierarchy_scenario] label".to_string())
+            .await
+            .expect("[create label] returned Err");
+        let repository = uow.todos();
+        let root = repository
+            .create(
+                workspace.id,
+                CreateTodo::new("[hierarchy_scenario] root".to_string(), vec![]),
+            )
+            .await
+            .expect("[create] returned Err");
+        let child = repository
+            .create(
+                workspace.id,
+                CreateTodo {
+                    parent_id: Some(root.id),
+                    ..CreateTodo::new("[hierarchy_scenario] child".to_string(), vec![label.id])
+                },
+            )
+            .await
+            .expect("[create] returned Err");
+        let grandchild = repository
+            .create(
+                workspace.id,
+                CreateTodo {
+                    parent_id: Some(child.id),
+                    ..CreateTodo::new("[hierarchy_scenario] grandchild".to_string(), vec![])
+                },
+            )
+            .await
+            .expect("[create] returned Err");
+
+        let root = repository
+            .find(workspace.id, root.id)
+            .await
+            .expect("[find] returned Err");
+        assert_eq!(vec![child.id], root.child_ids);
+        // a new subtask is a new version of the parent
+        assert_eq!(2, root.version);
+        let children = repository
+            .children(workspace.id, root.id)
+            .await
+            .expect("[children] returned Err");
+        assert_eq!(1, children.len());
+        assert_eq!(Some(root.id), children[0].parent_id);
+        assert_eq!(vec![grandchild.id], children[0].child_ids);
+        assert_eq!(vec![label.clone()], children[0].labels);
+
+        // so is a renamed label
+        let label = uow
+            .labels()
+            .update(
+                workspace.id,
+                label.id,
+                UpdateLabel {
+                    name: "[hierarchy_scenario] renamed label".to_string(),
+                },
+            )
+            .await
+            .expect("[update label] returned Err");
+        let renamed = repository
+            .find(workspace.id, child.id)
+            .await
+            .expect("[find] returned Err");
+        assert_eq!(vec![label], renamed.labels);
+        assert_eq!(children[0].version + 1, renamed.version);
+
+        // a todo can not become a subtask of itself or of its subtasks
+        for parent_id in [root.id, grandchild.id] {
+            let res = repository
+                .update(
+                    workspace.id,
+                    root.id,
+                    UpdateTodo {
+                        parent_id: Some(Some(parent_id)),
+                        ..UpdateTodo::default()
+                    },
+                    None,
+                )
+                .await;
+            assert!(matches!(
+                res.unwrap_err().downcast::<RepositoryError>(),
+                Result::Ok(RepositoryError::Cycle(id)) if id == parent_id
+            ));
+        }
+
+        // deleting a todo deletes its subtasks
+        repository
+            .delete(workspace.id, child.id, None)
+            .await
+            .expect("[delete] returned Err");
+        let res = repository.find(workspace.id, grandchild.id).await;
+        assert!(res.is_err());
+        let root = repository
+            .find(workspace.id, root.id)
+            .await
+            .expect("[find] returned Err");
+        assert!(root.child_ids.is_empty());
+        assert_eq!(3, root.version);
+    }
+
+    #[cfg(feature = "database-test")]
+    #[tokio::test]
+    async fn move_scenario() {
+        use crate::repositories::{
+            audit::{AuditRepository, AuditRepositoryForDb},
+            unit_of_work::{UnitOfWork, UnitOfWorkForDb},
+            user::UserRepositoryForDb,
+            workspace::{test_utils::prepare_workspace, WorkspaceRepositoryForDb},
+        };
+
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database_url));
+        // nothing is committed, so other tests never see these rows
+        let uow = UnitOfWorkForDb::begin(&pool)
+            .await
+            .expect("[begin] returned Err");
+        let ctx = DbContext::UnitOfWork(uow.clone());
+        let workspace = prepare_workspace(
+            &UserRepositoryForDb::with_context(ctx.clone()),
+            &WorkspaceRepositoryForDb::with_context(ctx),
+            "[move_scenario] user",
+            "[move_scenario] workspace",
+        )
+        .await;
+        let audit = AuditRepositoryForDb::with_context(DbContext::UnitOfWork(uow.clone()));
+        let repository = uow.todos();
+        let mut ids = vec![];
+        for text in ["a", "b", "c"] {
+            let todo = repository
+                .create(
+                    workspace.id,
+                    CreateTodo::new(format!("[move_scenario] {}", text), vec![]),
+                )
+                .await
+                .expect("[create] returned Err");
+            ids.push(todo.id);
+        }
+        let (a, b, c) = (ids[0], ids[1], ids[2]);
+        let list_order = || async {
+            let page = repository
+                .all(workspace.id, TodoQuery::default())
+                .await
+                .expect("[all] returned Err");
+            page.items.iter().map(|todo| todo.id).collect::<Vec<i32>>()
+        };
+        // new todos go on top
+        assert_eq!(vec![c, b, a], list_order().await);
+
+        repository
+            .reposition(
+                workspace.id,
+                a,
+                MoveTodo {
+                    before: Some(c),
+                    after: None,
+                },
+            )
+            .await
+            .expect("[reposition] returned Err");
+        assert_eq!(vec![a, c, b], list_order().await);
+        repository
+            .reposition(
+                workspace.id,
+                c,
+                MoveTodo {
+                    before: None,
+                    after: Some(b),
+                },
+            )
+            .await
+            .expect("[reposition] returned Err");
+        assert_eq!(vec![a, b, c], list_order().await);
+
+        // squeezing todos into the same gap over and over rebalances the positions
+        for _ in 0..12 {
+            for (moved, below) in [(c, b), (b, c)] {
+                repository
+                    .reposition(
+                        workspace.id,
+                        moved,
+                        MoveTodo {
+                            before: Some(below),
+                            after: Some(a),
+                        },
+                    )
+                    .await
+                    .expect("[reposition] returned Err");
+            }
+        }
+        assert_eq!(vec![a, b, c], list_order().await);
+        // rebalancing is a change like any other
+        let todo = repository
+            .find(workspace.id, a)
+            .await
+            .expect("[find] returned Err");
+        assert_eq!(POSITION_GAP, todo.position);
+        let history = audit
+            .history(workspace.id, AuditEntity::Todo, a)
+            .await
+            .expect("[history] returned Err");
+        let last = history.last().unwrap();
+        assert_eq!(AuditAction::Update, last.action);
+        assert_eq!(Some(serde_json::to_value(&todo).unwrap()), last.after);
+
+        // neighbours that rebalancing can not pull apart are an error, not a panic
+        let res = repository
+            .reposition(
+                workspace.id,
+                a,
+                MoveTodo {
+                    before: Some(b),
+                    after: Some(b),
+                },
+            )
+            .await;
+        assert!(res.is_err());
+
+        let res = repository
+            .reposition(
+                workspace.id,
+                a,
+                MoveTodo {
+                    before: Some(i32::MAX),
+                    after: None,
+                },
+            )
+            .await;
+        assert!(res.is_err());
+    }
+
+    #[cfg(feature = "database-test")]
+    #[tokio::test]
+    async fn search_scenario() {
+        use crate::repositories::{
+            unit_of_work::{UnitOfWork, UnitOfWorkForDb},
+            user::UserRepositoryForDb,
+            workspace::{test_utils::prepare_workspace, WorkspaceRepositoryForDb},
+        };
+
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database_url));
+        // nothing is committed, so other tests never see these rows
+        let uow = UnitOfWorkForDb::begin(&pool)
+            .await
+            .expect("[begin] returned Err");
+        let ctx = DbContext::UnitOfWork(uow.clone());
+        let workspace = prepare_workspace(
+            &UserRepositoryForDb::with_context(ctx.clone()),
+            &WorkspaceRepositoryForDb::with_context(ctx),
+            "[search_scenario] user",
+            "[search_scenario] workspace",
+        )
+        .await;
+        let repository = uow.todos();
+        let mut ids = vec![];
+        for text in [
+            "Buy milk and bread",
+            "Milk the cows, then milk the goats",
+            "Call the bank",
+        ] {
+            let todo = repository
+                .create(workspace.id, CreateTodo::new(text.to_string(), vec![]))
+                .await
+                .expect("[create] returned Err");
+            ids.push(todo.id);
+        }
+
+        let hits = repository
+            .search(
+                workspace.id,
+                SearchQuery {
+                    q: "MILK".to_string(),
+                    ..SearchQuery::default()
+                },
+            )
+            .await
+            .expect("[search] returned Err");
+        let hit_ids: Vec<i32> = hits.iter().map(|hit| hit.todo.id).collect();
+        assert_eq!(vec![ids[1], ids[0]], hit_ids);
+        assert!(hits[0].rank > hits[1].rank);
+        assert_eq!("Buy <mark>milk</mark> and bread", hits[1].snippet);
+
+        // every word has to match
+        let hits = repository
+            .search(
+                workspace.id,
+                SearchQuery {
+                    q: "milk bank".to_string(),
+                    ..SearchQuery::default()
+                },
+            )
+            .await
+            .expect("[search] returned Err");
+        assert!(hits.is_empty());
+
+        // the snippet is safe to render as HTML
+        repository
+            .create(
+                workspace.id,
+                CreateTodo::new(
+                    "<script>alert(1)</script> & \u{1}cheese".to_string(),
+                    vec![],
+                ),
+            )
+            .await
+            .expect("[create] returned Err");
+        let hits = repository
+            .search(
+                workspace.id,
+                SearchQuery {
+                    q: "cheese".to_string(),
+                    ..SearchQuery::default()
+                },
+            )
+            .await
+            .expect("[search] returned Err");
+        assert_eq!(
+            "&lt;script&gt;alert(1)&lt;/script&gt; &amp; <mark>cheese</mark>",
+            hits[0].snippet
+        );
+    }
+
+    #[cfg(feature = "database-test")]
+    #[tokio::test]
+    async fn bulk_scenario() {
+        use crate::repositories::{
+            label::LabelRepository,
+            unit_of_work::{UnitOfWork, UnitOfWorkForDb},
+            user::UserRepositoryForDb,
+            workspace::{test_utils::prepare_workspace, WorkspaceRepositoryForDb},
+        };
+
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database_url));
+        // nothing is committed, so other tests never see these rows
+        let uow = UnitOfWorkForDb::begin(&pool)
+            .await
+            .expect("[begin] returned Err");
+        let ctx = DbContext::UnitOfWork(uow.clone());
+        let workspace = prepare_workspace(
+            &UserRepositoryForDb::with_context(ctx.clone()),
+            &WorkspaceRepositoryForDb::with_context(ctx),
+            "[bulk_scenario] user",
+            "[bulk_scenario] workspace",
+        )
+        .await;
+        let mut labels = vec![];
+        for name in ["[bulk_scenario] label 1", "[bulk_scenario] label 2"] {
+            let label = uow
+                .labels()
+                .create(workspace.id, name.to_string())
+                .await
+                .expect("[create label] returned Err");
+            labels.push(label);
+        }
+        let repository = uow.todos();
+        let mut ids = vec![];
+        for (text, parent_id) in [
+            ("[bulk_scenario] a", None),
+            ("[bulk_scenario] b", None),
+            ("[bulk_scenario] subtask of b", Some(1)),
+        ] {
+            let todo = repository
+                .create(
+                    workspace.id,
+                    CreateTodo {
+                        parent_id: parent_id.map(|index| ids[index]),
+                        ..CreateTodo::new(text.to_string(), vec![labels[0].id])
+                    },
+                )
+                .await
+                .expect("[create] returned Err");
+            ids.push(todo.id);
+        }
+        let bulk = |ids: Vec<i32>, operation: BulkOperation| {
+            repository.bulk(workspace.id, BulkTodo { ids, operation })
+        };
+
+        // unknown todos are reported, the others changed
+        let results = bulk(vec![ids[0], i32::MAX, ids[1]], BulkOperation::Complete)
+            .await
+            .expect("[bulk] returned Err");
+        let statuses: Vec<(i32, BulkStatus)> = results
+            .iter()
+            .map(|result| (result.id, result.status))
+            .collect();
+        assert_eq!(
+            vec![
+                (ids[0], BulkStatus::Ok),
+                (i32::MAX, BulkStatus::NotFound),
+                (ids[1], BulkStatus::Ok)
+            ],
+            statuses
+        );
+        assert!(results[0].todo.as_ref().unwrap().completed);
+
+        // todos already completed are left as they are
+        let completed = results[0].todo.clone();
+        let results = bulk(vec![ids[0], ids[2]], BulkOperation::Complete)
+            .await
+            .expect("[bulk] returned Err");
+        let statuses: Vec<BulkStatus> = results.iter().map(|result| result.status).collect();
+        assert_eq!(vec![BulkStatus::Unchanged, BulkStatus::Ok], statuses);
+        assert_eq!(completed, results[0].todo);
+
+        let label_ids_of = |result: &BulkItemResult| -> Vec<i32> {
+            let mut label_ids: Vec<i32> = result
+                .todo
+                .as_ref()
+                .unwrap()
+                .labels
+                .iter()
+                .map(|label| label.id)
+                .collect();
+            label_ids.sort_unstable();
+            label_ids
+        };
+        let labels_op = BulkOperation::AddLabels {
+            labels: vec![labels[0].id, labels[1].id],
+        };
+        let results = bulk(vec![ids[0]], labels_op)
+            .await
+            .expect("[bulk] returned Err");
+        assert_eq!(vec![labels[0].id, labels[1].id], label_ids_of(&results[0]));
+        let labels_op = BulkOperation::RemoveLabels {
+            labels: vec![labels[0].id],
+        };
+        let results = bulk(vec![ids[0]], labels_op)
+            .await
+            .expect("[bulk] returned Err");
+        assert_eq!(vec![labels[1].id], label_ids_of(&results[0]));
+        let labels_op = BulkOperation::SetLabels {
+            labels: vec![labels[0].id],
+        };
+        let results = bulk(vec![ids[0], ids[1]], labels_op)
+            .await
+            .expect("[bulk] returned Err");
+        assert_eq!(vec![labels[0].id], label_ids_of(&results[0]));
+        assert_eq!(vec![labels[0].id], label_ids_of(&results[1]));
+
+        // a missing label changes nothing
+        let labels_op = BulkOperation::SetLabels {
+            labels: vec![i32::MAX],
+        };
+        let res = bulk(vec![ids[0]], labels_op).await;
+        assert!(res.is_err());
+
+        // deleting takes the subtasks along
+        let results = bulk(vec![ids[1], ids[2]], BulkOperation::Delete)
+            .await
+            .expect("[bulk] returned Err");
+        assert!(results
+            .iter()
+            .all(|result| result.status == BulkStatus::Ok && result.todo.is_none()));
+        let page = repository
+            .all(
+                workspace.id,
+                TodoQuery {
+                    text: Some("[bulk_scenario]".to_string()),
+                    ..TodoQuery::default()
+                },
+            )
+            .await
+            .expect("[all] returned Err");
+        let page_ids: Vec<i32> = page.items.iter().map(|todo| todo.id).collect();
+        assert_eq!(vec![ids[0]], page_ids);
+        assert_eq!(vec![labels[0].clone()], page.items[0].labels);
+    }
+
+    #[cfg(feature = "database-test")]
+    #[tokio::test]
+    async fn bulk_lock_scenario() {
+        use crate::repositories::{
+            audit::{AuditRepository, AuditRepositoryForDb},
+            unit_of_work::{UnitOfWork, UnitOfWorkForDb},
+            user::UserRepositoryForDb,
+            workspace::{test_utils::prepare_workspace, WorkspaceRepositoryForDb},
+        };
+        use std::time::Duration;
+
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database_url));
+        let workspace = prepare_workspace(
+            &UserRepositoryForDb::new(pool.clone()),
+            &WorkspaceRepositoryForDb::new(pool.clone()),
+            "[bulk_lock_scenario] user",
+            "[bulk_lock_scenario] workspace",
+        )
+        .await;
+        let repository = TodoRepositoryForDb::new(pool.clone());
+        let todo = repository
+            .create(
+                workspace.id,
+                CreateTodo::new("[bulk_lock_scenario] todo".to_string(), vec![]),
+            )
+            .await
+            .expect("[create] returned Err");
+        let bulk = |operation| BulkTodo {
+            ids: vec![todo.id],
+            operation,
+        };
+
+        // a second bulk change waits for the first to commit before taking its snapshot
+        let uow = UnitOfWorkForDb::begin(&pool)
+            .await
+            .expect("[begin] returned Err");
+        uow.todos()
+            .bulk(workspace.id, bulk(BulkOperation::Complete))
+            .await
+            .expect("[bulk] returned Err");
+        let second = tokio::spawn({
+            let repository = repository.clone();
+            let payload = bulk(BulkOperation::Uncomplete);
+            async move { repository.bulk(workspace.id, payload).await }
+        });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!second.is_finished());
+        uow.commit().await.expect("[commit] returned Err");
+        second.await.unwrap().expect("[bulk] returned Err");
+
+        let history = AuditRepositoryForDb::new(pool)
+            .history(workspace.id, AuditEntity::Todo, todo.id)
+            .await
+            .expect("[history] returned Err");
+        let snapshots: Vec<(bool, bool)> = history[1..]
+            .iter()
+            .map(|event| {
+                let completed = |snapshot: &Option<serde_json::Value>| {
+                    snapshot.as_ref().unwrap()["completed"].as_bool().unwrap()
+                };
+                (completed(&event.before), completed(&event.after))
+            })
+            .collect();
+        assert_eq!(vec![(false, true), (true, false)], snapshots);
+    }
+
+    #[cfg(feature = "database-test")]
+    #[tokio::test]
+    async fn position_lock_scenario() {
+        use crate::repositories::{
+            unit_of_work::{UnitOfWork, UnitOfWorkForDb},
+            user::UserRepositoryForDb,
+            workspace::{test_utils::prepare_workspace, WorkspaceRepositoryForDb},
+        };
+        use std::{collections::HashSet, time::Duration};
+
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database_url));
+        let workspace = prepare_workspace(
+            &UserRepositoryForDb::new(pool.clone()),
+            &WorkspaceRepositoryForDb::new(pool.clone()),
+            "[position_lock_scenario] user",
+            "[position_lock_scenario] workspace",
         )
-        .bind(row.id)
-        .bind(payload.labels)
-        .execute(&self.pool)
-        .await
-        .expect("cannot insert into todo_labels table");
-
-        tx.commit().await.expect("cannot commit transaction");
-
-        let todo = self.find(row.id).await.expect("cannot find committed todo");
-        Ok(todo)
-    }
-
-    async fn find(&self, id: i32) -> anyhow::Result<TodoEntity> {
-        let items = sqlx::query_as::<_, TodoWithLabelFromRow>(
-            r#"select todos.*, labels.id as label_id, labels.name as label_name from todos left outer join todo_labels tl on todos.id = tl.todo_id left outer join labels on labels.id = tl.label_id where todos.id=$1;"#
-        ).bind(id)
-        .fetch_all(&self.pool)
-        .await
-        .map_err(|e| match e {
-            sqlx::Error::RowNotFound => RepositoryError::NotFound(id),
-            _ => RepositoryError::Unexpected(e.to_string()),
-        }).expect("cannot find items");
+        .await;
+        let repository = TodoRepositoryForDb::new(pool.clone());
+        let create =
+            |text: &str| CreateTodo::new(format!("[position_lock_scenario] {}", text), vec![]);
+        let first = repository
+            .create(workspace.id, create("first"))
+            .await
+            .expect("[create] returned Err");
 
-        let todos = fold_entities(items);
-        let todo = todos.first().ok_or(RepositoryError::NotFound(id))?;
-        Ok(todo.clone())
-    }
+        // a todo created while another one is being placed waits for it, then goes above it
+        let uow = UnitOfWorkForDb::begin(&pool)
+            .await
+            .expect("[begin] returned Err");
+        let second = uow
+            .todos()
+            .create(workspace.id, create("second"))
+            .await
+            .expect("[create] returned Err");
+        let third = tokio::spawn({
+            let repository = repository.clone();
+            let payload = create("third");
+            async move { repository.create(workspace.id, payload).await }
+        });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!third.is_finished());
+        uow.commit().await.expect("[commit] returned Err");
+        let third = third.await.unwrap().expect("[create] returned Err");
+        assert!(third.position < second.position);
 
-    async fn all(&self) -> anyhow::Result<Vec<TodoEntity>> {
-        let items = sqlx::query_as::<_, TodoWithLabelFromRow>(
-            r#"select todos.*, labels.id as label_id, labels.name as label_name from todos left outer join todo_labels tl on todos.id = tl.todo_id left outer join labels on labels.id = tl.label_id order by todos.id desc;"#,
-        ).fetch_all(&self.pool).await?;
-        Ok(fold_entities(items))
+        // a move waits for the moves before it, so both never land in the same gap
+        let fourth = repository
+            .create(workspace.id, create("fourth"))
+            .await
+            .expect("[create] returned Err");
+        let after_third = || MoveTodo {
+            before: None,
+            after: Some(third.id),
+        };
+        let uow = UnitOfWorkForDb::begin(&pool)
+            .await
+            .expect("[begin] returned Err");
+        uow.todos()
+            .reposition(workspace.id, first.id, after_third())
+            .await
+            .expect("[reposition] returned Err");
+        let other = tokio::spawn({
+            let repository = repository.clone();
+            let payload = after_third();
+            async move {
+                repository
+                    .reposition(workspace.id, fourth.id, payload)
+                    .await
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!other.is_finished());
+        uow.commit().await.expect("[commit] returned Err");
+        other.await.unwrap().expect("[reposition] returned Err");
+        let page = repository
+            .all(workspace.id, TodoQuery::default())
+            .await
+            .expect("[all] returned Err");
+        // earlier runs leave their todos behind in the same workspace
+        let ids = [first.id, second.id, third.id, fourth.id];
+        let todos: Vec<&TodoEntity> = page
+            .items
+            .iter()
+            .filter(|todo| ids.contains(&todo.id))
+            .collect();
+        let order: Vec<i32> = todos.iter().map(|todo| todo.id).collect();
+        assert_eq!(vec![third.id, fourth.id, first.id, second.id], order);
+        let positions: HashSet<i64> = todos.iter().map(|todo| todo.position).collect();
+        assert_eq!(4, positions.len());
     }
 
-    async fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<TodoEntity> {
-        let tx = self.pool.begin().await?;
-        let old_todo = self.find(id).await?;
-        sqlx::query(r#"update todos set text=$1, completed=$2 where id = $3 returning *;"#)
-            .bind(payload.text.unwrap_or(old_todo.text))
-            .bind(payload.completed.unwrap_or(old_todo.completed))
-            .bind(id)
-            .fetch_one(&self.pool)
-            .await?;
-        if let Some(labels) = payload.labels {
-            sqlx::query(r#"delete from todo_labels where todo_id=$1;"#)
-                .bind(id)
-                .execute(&self.pool)
-                .await?;
-            sqlx::query(
-                r#"insert into todo_labels (todo_id, label_id) select $1, id from unnest ($2) as t(id);"#,
-            ).bind(id).bind(labels).execute(&self.pool).await?;
+    #[cfg(feature = "database-test")]
+    #[tokio::test]
+    async fn trash_scenario() {
+        use crate::repositories::{
+            unit_of_work::{UnitOfWork, UnitOfWorkForDb},
+            user::UserRepositoryForDb,
+            workspace::{test_utils::prepare_workspace, WorkspaceRepositoryForDb},
         };
 
-        tx.commit().await?;
-        let todo = self.find(id).await?;
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database_url));
+        // nothing is committed, so other tests never see these rows
+        let uow = UnitOfWorkForDb::begin(&pool)
+            .await
+            .expect("[begin] returned Err");
+        let ctx = DbContext::UnitOfWork(uow.clone());
+        let workspace = prepare_workspace(
+            &UserRepositoryForDb::with_context(ctx.clone()),
+            &WorkspaceRepositoryForDb::with_context(ctx),
+            "[trash_scenario] user",
+            "[trash_scenario] workspace",
+        )
+        .await;
+        let repository = uow.todos();
+        let parent = repository
+            .create(
+                workspace.id,
+                CreateTodo::new("[trash_scenario] parent".to_string(), vec![]),
+            )
+            .await
+            .expect("[create] returned Err");
+        let subtask = repository
+            .create(
+                workspace.id,
+                CreateTodo {
+                    parent_id: Some(parent.id),
+                    ..CreateTodo::new("[trash_scenario] subtask".to_string(), vec![])
+                },
+            )
+            .await
+            .expect("[create] returned Err");
+        let trashed_ids = || async {
+            let trash = repository
+                .trash(workspace.id)
+                .await
+                .expect("[trash] returned Err");
+            trash.iter().map(|todo| todo.id).collect::<Vec<i32>>()
+        };
 
-        Ok(todo)
-    }
+        // the subtask goes first, then its parent; the latest delete is listed first
+        repository
+            .delete(workspace.id, subtask.id, None)
+            .await
+            .expect("[delete] returned Err");
+        repository
+            .delete(workspace.id, parent.id, None)
+            .await
+            .expect("[delete] returned Err");
+        assert_eq!(vec![parent.id, subtask.id], trashed_ids().await);
+        assert!(repository.find(workspace.id, parent.id).await.is_err());
+        assert!(repository
+            .update(workspace.id, parent.id, UpdateTodo::default(), None)
+            .await
+            .is_err());
 
-    async fn delete(&self, id: i32) -> anyhow::Result<()> {
-        let tx = self.pool.begin().await?;
-        // delete todo_label
-        sqlx::query(r#"delete from todo_labels where todo_id=$1;"#)
-            .bind(id)
-            .execute(&self.pool)
-            .await
-            .map_err(|e| match e {
-                sqlx::Error::RowNotFound => RepositoryError::NotFound(id),
-                _ => RepositoryError::Unexpected(e.to_string()),
-            })?;
-        // delete todo
-        sqlx::query(r#"delete from todos where id=$1;"#)
-            .bind(id)
-            .execute(&self.pool)
+        // restoring the parent leaves the separately deleted subtask in the trash
+        let restored = repository
+            .restore(workspace.id, parent.id)
             .await
-            .map_err(|e| match e {
-                sqlx::Error::RowNotFound => RepositoryError::NotFound(id),
-                _ => RepositoryError::Unexpected(e.to_string()),
-            })?;
+            .expect("[restore] returned Err");
+        assert_eq!(None, restored.deleted_at);
+        assert!(restored.child_ids.is_empty());
+        assert_eq!(vec![subtask.id], trashed_ids().await);
+        assert!(repository.restore(workspace.id, parent.id).await.is_err());
 
-        tx.commit().await?;
+        let restored = repository
+            .restore(workspace.id, subtask.id)
+            .await
+            .expect("[restore] returned Err");
+        assert_eq!(Some(parent.id), restored.parent_id);
 
-        Ok(())
-    }
-}
+        // deleted together, restored together
+        repository
+            .delete(workspace.id, parent.id, None)
+            .await
+            .expect("[delete] returned Err");
+        let restored = repository
+            .restore(workspace.id, parent.id)
+            .await
+            .expect("[restore] returned Err");
+        assert_eq!(vec![subtask.id], restored.child_ids);
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use dotenv::dotenv;
-    use sqlx::PgPool;
-    use std::{env, vec};
+        // a subtask restored without its parent loses it
+        repository
+            .delete(workspace.id, parent.id, None)
+            .await
+            .expect("[delete] returned Err");
+        let restored = repository
+            .restore(workspace.id, subtask.id)
+            .await
+            .expect("[restore] returned Err");
+        assert_eq!(None, restored.parent_id);
 
-    #[test]
-    fn fold_entities_test() {
-        let label_1 = Label {
-            id: 1,
-            name: String::from("label 1"),
-        };
-        let label_2 = Label {
-            id: 2,
-            name: String::from("label 2"),
-        };
-        let rows = vec![
-            TodoWithLabelFromRow {
-                id: 1,
-                text: String::from("todo 1"),
-                completed: false,
-                label_id: Some(label_1.id),
-                label_name: Some(label_1.name.clone()),
-            },
-            TodoWithLabelFromRow {
-                id: 1,
-                text: String::from("todo 1"),
-                completed: false,
-                label_id: Some(label_2.id),
-                label_name: Some(label_2.name.clone()),
-            },
-            TodoWithLabelFromRow {
-                id: 2,
-                text: String::from("todo 2"),
-                completed: false,
-                label_id: Some(label_1.id),
-                label_name: Some(label_1.name.clone()),
-            },
-        ];
-        let res = fold_entities(rows);
-        assert_eq!(
-            res,
-            vec![
-                TodoEntity {
-                    id: 1,
-                    text: String::from("todo 1"),
-                    completed: false,
-                    labels: vec![label_1.clone(), label_2],
-                },
-                TodoEntity {
-                    id: 2,
-                    text: String::from("todo 2"),
-                    completed: false,
-                    labels: vec![label_1],
-                }
-            ]
-        );
+        let purged = repository
+            .purge(workspace.id)
+            .await
+            .expect("[purge] returned Err");
+        assert_eq!(1, purged);
+        assert!(trashed_ids().await.is_empty());
+        assert!(repository.restore(workspace.id, parent.id).await.is_err());
+        repository
+            .find(workspace.id, subtask.id)
+            .await
+            .expect("[find] returned Err");
     }
 
     #[cfg(feature = "database-test")]
     #[tokio::test]
     async fn crud_scenario() {
+        use crate::repositories::{
+            user::UserRepositoryForDb,
+            workspace::{test_utils::prepare_workspace, WorkspaceRepositoryForDb},
+        };
+
         dotenv().ok();
         let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
         let pool = PgPool::connect(database_url)
             .await
             .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database_url));
 
+        // prepare workspace data
+        let workspace = prepare_workspace(
+            &UserRepositoryForDb::new(pool.clone()),
+            &WorkspaceRepositoryForDb::new(pool.clone()),
+            "[crud_scenario] todo user",
+            "[crud_scenario] todo workspace",
+        )
+        .await;
+
         // prepare label data
         let label_name = String::from("[crud_scenario] test label");
-        let optional_label = sqlx::query_as::<_, Label>(r#"select * from labels where name = $1;"#)
-            .bind(label_name.clone())
-            .fetch_optional(&pool)
-            .await
-            .expect("Failed to prepare label data.");
+        let optional_label = sqlx::query_as::<_, Label>(
+            r#"select * from labels where name = $1 and workspace_id = $2;"#,
+        )
+        .bind(label_name.clone())
+        .bind(workspace.id)
+        .fetch_optional(&pool)
+        .await
+        .expect("Failed to prepare label data.");
         let label_1 = if let Some(label) = optional_label {
             label
         } else {
-            sqlx::query_as::<_, Label>(r#"insert into labels ( name ) values ( $1 ) returning *;"#)
-                .bind(label_name)
-                .fetch_one(&pool)
-                .await
-                .expect("Failed to insert label data.")
+            sqlx::query_as::<_, Label>(
+                r#"insert into labels ( name, workspace_id ) values ( $1, $2 ) returning *;"#,
+            )
+            .bind(label_name)
+            .bind(workspace.id)
+            .fetch_one(&pool)
+            .await
+            .expect("Failed to insert label data.")
         };
 
         // prepare todo data
@@ -296,7 +2711,10 @@ mod tests {
 
         // create
         let created = repository
-            .create(CreateTodo::new(todo_text.to_string(), vec![label_1.id]))
+            .create(
+                workspace.id,
+                CreateTodo::new(todo_text.to_string(), vec![label_1.id]),
+            )
             .await
             .expect("[create] returned Err");
         assert_eq!(created.text, todo_text);
@@ -305,41 +2723,117 @@ mod tests {
 
         // find
         let todo = repository
-            .find(created.id)
+            .find(workspace.id, created.id)
             .await
             .expect("[find] returned Err");
         assert_eq!(created, todo);
 
         // all
-        let todos = repository.all().await.expect("[all] returned Err");
-        let todo = todos.first().unwrap();
+        let todos = repository
+            .all(workspace.id, TodoQuery::default())
+            .await
+            .expect("[all] returned Err");
+        let todo = todos.items.first().unwrap();
         assert_eq!(created, *todo);
 
         // update
         let updated_text = "[crud_scenario] updated text";
         let todo = repository
             .update(
+                workspace.id,
                 todo.id,
                 UpdateTodo {
                     text: Some(updated_text.to_string()),
                     completed: Some(true),
                     labels: Some(vec![]),
+                    ..UpdateTodo::default()
                 },
+                None,
             )
             .await
             .expect("[update] returned Err");
         assert_eq!(created.id, todo.id);
         assert_eq!(todo.text, updated_text);
         assert!(todo.labels.is_empty());
+        assert_eq!(created.version + 1, todo.version);
+
+        // a stale version is refused
+        let res = repository
+            .update(
+                workspace.id,
+                todo.id,
+                UpdateTodo::new(None, Some(false), None),
+                Some(vec![created.version]),
+            )
+            .await;
+        assert!(matches!(
+            res.unwrap_err().downcast::<RepositoryError>(),
+            Result::Ok(RepositoryError::VersionMismatch(id)) if id == todo.id
+        ));
+
+        // update is atomic: a failing label insert rolls back the text change
+        let res = repository
+            .update(
+                workspace.id,
+                todo.id,
+                UpdateTodo {
+                    text: Some("[crud_scenario] rolled back text".to_string()),
+                    completed: None,
+                    labels: Some(vec![i32::MAX]),
+                    ..UpdateTodo::default()
+                },
+                None,
+            )
+            .await;
+        assert!(res.is_err());
+        let not_updated = repository
+            .find(workspace.id, todo.id)
+            .await
+            .expect("[find] returned Err");
+        assert_eq!(todo, not_updated);
+
+        // create is atomic: no todo is left behind when its labels fail
+        let rolled_back_text = "[crud_scenario] rolled back create";
+        let res = repository
+            .create(
+                workspace.id,
+                CreateTodo::new(rolled_back_text.to_string(), vec![i32::MAX]),
+            )
+            .await;
+        assert!(res.is_err());
+        let rows = sqlx::query(r#"select * from todos where text=$1"#)
+            .bind(rolled_back_text)
+            .fetch_all(&pool)
+            .await
+            .expect("[create] todos fetch error");
+        assert!(rows.is_empty());
 
-        // delete
+        // delete only moves the todo to the trash
         repository
-            .delete(todo.id)
+            .delete(
+                workspace.id,
+                todo.id,
+                Some(vec![created.version, todo.version]),
+            )
             .await
             .expect("[delete] returned Err");
-        let res = repository.find(created.id).await;
+        let res = repository.find(workspace.id, created.id).await;
 
         assert!(res.is_err());
+        let deleted_at: Option<DateTime<Utc>> =
+            sqlx::query_scalar(r#"select deleted_at from todos where id=$1"#)
+                .bind(todo.id)
+                .fetch_one(&pool)
+                .await
+                .expect("[delete] todos fetch error");
+        assert!(deleted_at.is_some());
+
+        // purge removes it for good
+        let purged = repository
+            .purge(workspace.id)
+            .await
+            .expect("[purge] returned Err");
+        assert_eq!(1, purged);
 
         // todo
         let todo_rows = sqlx::query(r#"select * from todos where id=$1"#)
@@ -375,9 +2869,14 @@ mod tests {
 #[cfg(test)]
 pub mod test_utils {
     use super::*;
+    use crate::repositories::{
+        audit::test_utils::AuditRepositoryForMemory,
+        label::{test_utils::LabelRepositoryForMemory, LabelRepository},
+    };
     use anyhow::Context;
     use axum::async_trait;
     use std::{
+        cmp::Ordering,
         collections::HashMap,
         sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
     };
@@ -388,90 +2887,788 @@ pub mod test_utils {
                 id,
                 text,
                 completed: false,
+                priority: Priority::None,
+                due_at: None,
+                starts_at: None,
+                parent_id: None,
+                child_ids: vec![],
+                position: 0,
+                deleted_at: None,
+                version: 1,
                 labels: vec![],
             }
         }
-    }
 
-    impl CreateTodo {
-        pub fn new(text: String, labels: Vec<i32>) -> Self {
-            Self { text, labels }
+        /// Open and past its due date at `now`, like the `overdue` filter of `all_todo`.
+        fn is_overdue(&self, now: DateTime<Utc>) -> bool {
+            !self.completed && self.due_at.is_some_and(|due_at| due_at < now)
+        }
+    }
+
+    impl CreateTodo {
+        pub fn new(text: String, labels: Vec<i32>) -> Self {
+            Self {
+                text,
+                labels,
+                priority: Priority::None,
+                due_at: None,
+                starts_at: None,
+                parent_id: None,
+            }
+        }
+    }
+
+    impl UpdateTodo {
+        pub fn new(
+            text: Option<String>,
+            completed: Option<bool>,
+            labels: Option<Vec<i32>>,
+        ) -> Self {
+            Self {
+                text,
+                completed,
+                labels,
+                ..Self::default()
+            }
+        }
+    }
+
+    /// Lowercased words of `text`, split like the 'simple' text search configuration does.
+    fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+        text.split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .map(str::to_lowercase)
+    }
+
+    /// `text` with every word in `words` between headline selectors, like `ts_headline`,
+    /// escaped like `search_todos` does.
+    fn highlight(text: &str, words: &[String]) -> String {
+        let mut headline = String::new();
+        let mut word = String::new();
+        let flush = |word: &mut String, headline: &mut String| {
+            if words.contains(&word.to_lowercase()) {
+                headline.push(HEADLINE_START);
+                headline.push_str(word);
+                headline.push(HEADLINE_STOP);
+            } else {
+                headline.push_str(word);
+            }
+            word.clear();
+        };
+        for c in text
+            .chars()
+            .filter(|&c| c != HEADLINE_START && c != HEADLINE_STOP)
+        {
+            if c.is_alphanumeric() {
+                word.push(c);
+            } else {
+                flush(&mut word, &mut headline);
+                headline.push(c);
+            }
+        }
+        flush(&mut word, &mut headline);
+        escape_snippet(&headline)
+    }
+
+    /// todos per id, together with the id of the workspace holding them
+    pub type TodoData = HashMap<i32, (i32, TodoEntity)>;
+
+    /// The todo, unless it belongs to another workspace or is in the trash.
+    fn live_todo(store: &TodoData, workspace_id: i32, id: i32) -> Option<&TodoEntity> {
+        store
+            .get(&id)
+            .filter(|(scope, todo)| *scope == workspace_id && todo.deleted_at.is_none())
+            .map(|(_scope, todo)| todo)
+    }
+
+    /// Fills in the labels and subtasks, which are not kept on the stored todo.
+    pub fn with_relations(
+        store: &TodoData,
+        labels: &LabelRepositoryForMemory,
+        todo: &TodoEntity,
+    ) -> TodoEntity {
+        let mut children: Vec<&TodoEntity> = store
+            .values()
+            .filter(|(_scope, child)| {
+                child.parent_id == Some(todo.id) && child.deleted_at.is_none()
+            })
+            .map(|(_scope, child)| child)
+            .collect();
+        children.sort_by_key(|child| (child.position, child.id));
+        let child_ids = children.iter().map(|child| child.id).collect();
+        TodoEntity {
+            child_ids,
+            labels: labels.labels_of(todo.id),
+            ..todo.clone()
+        }
+    }
+
+    /// Same as `touch_todos` of the database repository.
+    pub fn touch_todos(store: &mut TodoData, ids: impl IntoIterator<Item = i32>) {
+        for id in ids {
+            if let Some((_scope, todo)) = store.get_mut(&id) {
+                todo.version += 1;
+            }
+        }
+    }
+
+    /// Same check as `lock_todo` of the database repository.
+    fn ensure_version(todo: &TodoEntity, if_match: Option<Vec<i32>>) -> anyhow::Result<()> {
+        if if_match.is_some_and(|versions| !versions.contains(&todo.version)) {
+            return Err(RepositoryError::VersionMismatch(todo.id).into());
+        }
+        Ok(())
+    }
+
+    /// Todos of the workspace that are not in the trash.
+    fn live_todos(store: &TodoData, workspace_id: i32) -> impl Iterator<Item = &TodoEntity> {
+        store
+            .values()
+            .filter(move |(scope, todo)| *scope == workspace_id && todo.deleted_at.is_none())
+            .map(|(_scope, todo)| todo)
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct TodoRepositoryForMemory {
+        store: Arc<RwLock<TodoData>>,
+        labels: LabelRepositoryForMemory,
+        audit: AuditRepositoryForMemory,
+        actor: Option<i32>,
+    }
+
+    impl TodoRepositoryForMemory {
+        pub fn new() -> Self {
+            let store: Arc<RwLock<TodoData>> = Arc::default();
+            TodoRepositoryForMemory {
+                labels: LabelRepositoryForMemory::new().with_todo_store(store.clone()),
+                store,
+                audit: AuditRepositoryForMemory::new(),
+                actor: None,
+            }
+        }
+
+        /// Shares the todo store but keeps todo labels in `labels`, which in turn sees the
+        /// todos of this store.
+        pub fn with_label_repository(&self, labels: LabelRepositoryForMemory) -> Self {
+            TodoRepositoryForMemory {
+                labels: labels.with_todo_store(self.store.clone()),
+                ..self.clone()
+            }
+        }
+
+        /// The repository keeping the todo labels, sharing the todo store.
+        pub fn label_repository(&self) -> LabelRepositoryForMemory {
+            self.labels.clone()
+        }
+
+        /// Shares the todo store but records changes in `audit`.
+        pub fn with_audit_repository(&self, audit: AuditRepositoryForMemory) -> Self {
+            TodoRepositoryForMemory {
+                audit,
+                ..self.clone()
+            }
+        }
+
+        fn record(&self, workspace_id: i32, records: Vec<AuditRecord>) {
+            self.audit.record(workspace_id, self.actor, records);
+        }
+
+        fn with_relations(&self, store: &TodoData, todo: &TodoEntity) -> TodoEntity {
+            with_relations(store, &self.labels, todo)
+        }
+
+        /// Same checks as `ensure_parent` of the database repository.
+        fn ensure_parent(
+            store: &TodoData,
+            workspace_id: i32,
+            id: Option<i32>,
+            parent_id: i32,
+        ) -> anyhow::Result<()> {
+            if live_todo(store, workspace_id, parent_id).is_none() {
+                return Err(RepositoryError::NotFound(parent_id).into());
+            }
+            let mut ancestor_id = Some(parent_id);
+            while let Some(current) = ancestor_id {
+                if Some(current) == id {
+                    return Err(RepositoryError::Cycle(parent_id).into());
+                }
+                ancestor_id = store
+                    .get(&current)
+                    .and_then(|(_scope, todo)| todo.parent_id);
+            }
+            Ok(())
+        }
+
+        /// Same as `neighbour_positions` of the database repository.
+        fn neighbour_positions(
+            store: &TodoData,
+            workspace_id: i32,
+            id: i32,
+            payload: &MoveTodo,
+        ) -> (Option<i64>, Option<i64>) {
+            let position_of = |id: i32| store[&id].1.position;
+            let others = || {
+                live_todos(store, workspace_id)
+                    .filter(move |todo| todo.id != id)
+                    .map(|todo| todo.position)
+            };
+            match (payload.after, payload.before) {
+                (Some(after), Some(before)) => {
+                    let (after, before) = (position_of(after), position_of(before));
+                    (Some(after.min(before)), Some(after.max(before)))
+                }
+                (Some(after), None) => {
+                    let above = position_of(after);
+                    (Some(above), others().filter(|p| *p > above).min())
+                }
+                (None, Some(before)) => {
+                    let below = position_of(before);
+                    (others().filter(|p| *p < below).max(), Some(below))
+                }
+                (None, None) => (None, None),
+            }
+        }
+
+        /// Moves the todo and its subtasks to the trash, all with the same `deleted_at`.
+        fn trash_subtree(
+            &self,
+            store: &mut TodoData,
+            workspace_id: i32,
+            id: i32,
+            deleted_at: DateTime<Utc>,
+        ) {
+            let parent_id = store[&id].1.parent_id;
+            touch_todos(store, parent_id);
+            let mut subtree = vec![id];
+            let mut records = vec![];
+            while let Some(id) = subtree.pop() {
+                subtree.extend(
+                    store
+                        .values()
+                        .filter(|(_scope, child)| {
+                            child.parent_id == Some(id) && child.deleted_at.is_none()
+                        })
+                        .map(|(_scope, child)| child.id),
+                );
+                let todo = self.with_relations(store, &store[&id].1);
+                records.push(todo_record(AuditAction::Delete, id).before(&todo));
+                if let Some((_scope, todo)) = store.get_mut(&id) {
+                    todo.deleted_at = Some(deleted_at);
+                    todo.version += 1;
+                }
+            }
+            self.record(workspace_id, records);
+        }
+
+        /// Permanently removes the trashed todos `purged` picks, with their subtasks.
+        fn purge_trashed(&self, purged: impl Fn(i32, &TodoEntity) -> bool) -> u64 {
+            let mut store = self.write_store_ref();
+            let ids: Vec<i32> = store
+                .values()
+                .filter(|(scope, todo)| todo.deleted_at.is_some() && purged(*scope, todo))
+                .map(|(_scope, todo)| todo.id)
+                .collect();
+            let mut purged = 0;
+            for id in ids {
+                for (id, workspace_id) in self.remove_subtree(&mut store, id) {
+                    self.record(workspace_id, vec![todo_record(AuditAction::Purge, id)]);
+                    purged += 1;
+                }
+            }
+            purged
+        }
+
+        /// Removes the todo and its subtasks, at any depth, returning the id and
+        /// workspace of every removed todo.
+        fn remove_subtree(&self, store: &mut TodoData, id: i32) -> Vec<(i32, i32)> {
+            let mut subtree = vec![id];
+            let mut removed = vec![];
+            while let Some(id) = subtree.pop() {
+                subtree.extend(
+                    store
+                        .values()
+                        .filter(|(_scope, child)| child.parent_id == Some(id))
+                        .map(|(_scope, child)| child.id),
+                );
+                if let Some((workspace_id, _todo)) = store.remove(&id) {
+                    removed.push((id, workspace_id));
+                }
+                self.labels.remove_labels_of(id);
+            }
+            removed
         }
-    }
-
-    type TodoData = HashMap<i32, TodoEntity>;
-
-    #[derive(Debug, Clone)]
-    pub struct TodoRepositoryForMemory {
-        store: Arc<RwLock<TodoData>>,
-    }
 
-    impl TodoRepositoryForMemory {
-        pub fn new() -> Self {
-            TodoRepositoryForMemory {
-                store: Arc::default(),
+        /// Same as `rebalance_positions` of the database repository.
+        fn rebalance_positions(&self, store: &mut TodoData, workspace_id: i32) {
+            let mut todos: Vec<(i64, i32)> = store
+                .values()
+                .filter(|(scope, _todo)| *scope == workspace_id)
+                .map(|(_scope, todo)| (todo.position, todo.id))
+                .collect();
+            todos.sort_unstable();
+            let mut records = vec![];
+            for (rank, (position, id)) in todos.into_iter().enumerate() {
+                let rebalanced = (rank as i64 + 1) * POSITION_GAP;
+                if position == rebalanced {
+                    continue;
+                }
+                let (_scope, todo) = store.get_mut(&id).unwrap();
+                let old_todo = todo.clone();
+                todo.position = rebalanced;
+                todo.version += 1;
+                if todo.deleted_at.is_none() {
+                    records.push((old_todo, todo.clone()));
+                }
             }
+            let records = records
+                .iter()
+                .map(|(old_todo, todo)| {
+                    todo_record(AuditAction::Update, todo.id)
+                        .before(&self.with_relations(store, old_todo))
+                        .after(&self.with_relations(store, todo))
+                })
+                .collect();
+            self.record(workspace_id, records);
         }
 
-        fn write_store_ref(&self) -> RwLockWriteGuard<TodoData> {
+        fn write_store_ref(&self) -> RwLockWriteGuard<'_, TodoData> {
             self.store.write().unwrap()
         }
 
-        fn read_store_ref(&self) -> RwLockReadGuard<TodoData> {
+        fn read_store_ref(&self) -> RwLockReadGuard<'_, TodoData> {
             self.store.read().unwrap()
         }
     }
 
     #[async_trait]
     impl TodoRepository for TodoRepositoryForMemory {
-        async fn create(&self, payload: CreateTodo) -> anyhow::Result<TodoEntity> {
+        fn acting_as(&self, user_id: i32) -> Self {
+            TodoRepositoryForMemory {
+                actor: Some(user_id),
+                ..self.clone()
+            }
+        }
+
+        async fn create(
+            &self,
+            workspace_id: i32,
+            payload: CreateTodo,
+        ) -> anyhow::Result<TodoEntity> {
             let mut store = self.write_store_ref();
-            let id = (store.len() + 1) as i32;
-            let todo = TodoEntity::new(id, payload.text);
-            store.insert(id, todo.clone());
+            let id = store.keys().max().unwrap_or(&0) + 1;
+            if let Some(parent_id) = payload.parent_id {
+                Self::ensure_parent(&store, workspace_id, None, parent_id)?;
+            }
+            self.labels
+                .set_labels_of(workspace_id, id, &payload.labels)?;
+            let position = store
+                .values()
+                .filter(|(scope, _todo)| *scope == workspace_id)
+                .map(|(_scope, todo)| todo.position - POSITION_GAP)
+                .min()
+                .unwrap_or(0);
+            let todo = TodoEntity {
+                position,
+                priority: payload.priority,
+                due_at: payload.due_at,
+                starts_at: payload.starts_at,
+                parent_id: payload.parent_id,
+                ..TodoEntity::new(id, payload.text)
+            };
+            store.insert(id, (workspace_id, todo.clone()));
+            touch_todos(&mut store, payload.parent_id);
+            let todo = self.with_relations(&store, &todo);
+            self.record(
+                workspace_id,
+                vec![todo_record(AuditAction::Create, id).after(&todo)],
+            );
             Ok(todo)
         }
 
-        async fn find(&self, id: i32) -> anyhow::Result<TodoEntity> {
+        async fn find(&self, workspace_id: i32, id: i32) -> anyhow::Result<TodoEntity> {
             let store = self.read_store_ref();
-            let todo = store
-                .get(&id)
-                .cloned()
-                .ok_or(RepositoryError::NotFound(id))?;
-            Ok(todo)
+            let todo = live_todo(&store, workspace_id, id).ok_or(RepositoryError::NotFound(id))?;
+            Ok(self.with_relations(&store, todo))
         }
 
-        async fn all(&self) -> anyhow::Result<Vec<TodoEntity>> {
+        async fn all(&self, workspace_id: i32, query: TodoQuery) -> anyhow::Result<TodoPage> {
             let store = self.read_store_ref();
-            let todos = Vec::from_iter(store.values().cloned());
-            Ok(todos)
+            let now = Utc::now();
+            let text = query.text.as_deref().map(str::to_lowercase);
+            let compare = |a: &TodoCursor, b: &TodoCursor| {
+                let ordering = match query.sort() {
+                    TodoSort::Position => a.position.cmp(&b.position),
+                    TodoSort::Id => Ordering::Equal,
+                    TodoSort::Text => a.text.cmp(&b.text),
+                    TodoSort::Completed => a.completed.cmp(&b.completed),
+                    TodoSort::Priority => a.priority.cmp(&b.priority),
+                }
+                .then(a.id.cmp(&b.id));
+                match query.order() {
+                    SortOrder::Asc => ordering,
+                    SortOrder::Desc => ordering.reverse(),
+                }
+            };
+            let mut todos: Vec<TodoEntity> = live_todos(&store, workspace_id)
+                .map(|todo| self.with_relations(&store, todo))
+                .filter(|todo| query.completed.is_none_or(|c| todo.completed == c))
+                .filter(|todo| {
+                    text.as_ref()
+                        .is_none_or(|text| todo.text.to_lowercase().contains(text))
+                })
+                .filter(|todo| {
+                    query.labels.is_empty()
+                        || todo
+                            .labels
+                            .iter()
+                            .any(|label| query.labels.contains(&label.id))
+                })
+                .filter(|todo| {
+                    query
+                        .due_before
+                        .is_none_or(|before| todo.due_at.is_some_and(|due_at| due_at < before))
+                })
+                .filter(|todo| {
+                    query
+                        .due_after
+                        .is_none_or(|after| todo.due_at.is_some_and(|due_at| due_at >= after))
+                })
+                .filter(|todo| {
+                    query
+                        .overdue
+                        .is_none_or(|overdue| todo.is_overdue(now) == overdue)
+                })
+                .filter(|todo| {
+                    query.after.as_ref().is_none_or(|after| {
+                        compare(&TodoCursor::from(todo), after) == Ordering::Greater
+                    })
+                })
+                .collect();
+            todos.sort_by(|a, b| compare(&a.into(), &b.into()));
+            todos.truncate(query.limit() as usize + 1);
+            Ok(TodoPage::from_overfetched(todos, query.limit()))
         }
 
-        async fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<TodoEntity> {
+        async fn update(
+            &self,
+            workspace_id: i32,
+            id: i32,
+            payload: UpdateTodo,
+            if_match: Option<Vec<i32>>,
+        ) -> anyhow::Result<TodoEntity> {
             let mut store = self.write_store_ref();
-            let todo = store.get(&id).context(RepositoryError::NotFound(id))?;
+            let todo =
+                live_todo(&store, workspace_id, id).context(RepositoryError::NotFound(id))?;
+            ensure_version(todo, if_match)?;
+            let old_todo = self.with_relations(&store, todo);
             let text = payload.text.unwrap_or(todo.text.clone());
             let completed = payload.completed.unwrap_or(todo.completed);
+            let priority = payload.priority.unwrap_or(todo.priority);
+            let due_at = payload.due_at.unwrap_or(todo.due_at);
+            let starts_at = payload.starts_at.unwrap_or(todo.starts_at);
+            let parent_id = payload.parent_id.unwrap_or(todo.parent_id);
+            if let Some(Some(parent_id)) = payload.parent_id {
+                Self::ensure_parent(&store, workspace_id, Some(id), parent_id)?;
+            }
+            if let Some(labels) = payload.labels {
+                self.labels.set_labels_of(workspace_id, id, &labels)?;
+            }
             let todo = TodoEntity {
                 id,
                 text,
                 completed,
+                priority,
+                due_at,
+                starts_at,
+                parent_id,
+                child_ids: vec![],
+                position: todo.position,
+                deleted_at: None,
+                version: todo.version + 1,
                 labels: vec![],
             };
-            store.insert(id, todo.clone());
+            if parent_id != old_todo.parent_id {
+                touch_todos(
+                    &mut store,
+                    [old_todo.parent_id, parent_id].into_iter().flatten(),
+                );
+            }
+            store.insert(id, (workspace_id, todo.clone()));
+            let todo = self.with_relations(&store, &todo);
+            self.record(
+                workspace_id,
+                vec![todo_record(AuditAction::Update, id)
+                    .before(&old_todo)
+                    .after(&todo)],
+            );
             Ok(todo)
         }
 
-        async fn delete(&self, id: i32) -> anyhow::Result<()> {
+        async fn delete(
+            &self,
+            workspace_id: i32,
+            id: i32,
+            if_match: Option<Vec<i32>>,
+        ) -> anyhow::Result<()> {
             let mut store = self.write_store_ref();
-            store.remove(&id).ok_or(RepositoryError::NotFound(id))?;
+            let todo = live_todo(&store, workspace_id, id).ok_or(RepositoryError::NotFound(id))?;
+            ensure_version(todo, if_match)?;
+            self.trash_subtree(&mut store, workspace_id, id, Utc::now());
             Ok(())
         }
+
+        async fn children(&self, workspace_id: i32, id: i32) -> anyhow::Result<Vec<TodoEntity>> {
+            let store = self.read_store_ref();
+            let todo = live_todo(&store, workspace_id, id).ok_or(RepositoryError::NotFound(id))?;
+            let children = self
+                .with_relations(&store, todo)
+                .child_ids
+                .iter()
+                .map(|child_id| self.with_relations(&store, &store[child_id].1))
+                .collect();
+            Ok(children)
+        }
+
+        async fn reposition(
+            &self,
+            workspace_id: i32,
+            id: i32,
+            payload: MoveTodo,
+        ) -> anyhow::Result<TodoEntity> {
+            let mut store = self.write_store_ref();
+            for id in [id].into_iter().chain(payload.neighbour_ids()) {
+                if live_todo(&store, workspace_id, id).is_none() {
+                    return Err(RepositoryError::NotFound(id).into());
+                }
+            }
+            let old_todo = self.with_relations(&store, &store[&id].1);
+            let (above, below) = Self::neighbour_positions(&store, workspace_id, id, &payload);
+            let position = match position_between(above, below) {
+                Some(position) => position,
+                None => {
+                    self.rebalance_positions(&mut store, workspace_id);
+                    let (above, below) =
+                        Self::neighbour_positions(&store, workspace_id, id, &payload);
+                    position_between(above, below).ok_or_else(|| {
+                        RepositoryError::Unexpected(format!("no room to move todo {id}"))
+                    })?
+                }
+            };
+            let (_scope, todo) = store.get_mut(&id).unwrap();
+            todo.position = position;
+            todo.version += 1;
+            let todo = todo.clone();
+            // subtasks are listed in order of position
+            touch_todos(&mut store, todo.parent_id);
+            let todo = self.with_relations(&store, &todo);
+            self.record(
+                workspace_id,
+                vec![todo_record(AuditAction::Update, id)
+                    .before(&old_todo)
+                    .after(&todo)],
+            );
+            Ok(todo)
+        }
+
+        async fn search(
+            &self,
+            workspace_id: i32,
+            query: SearchQuery,
+        ) -> anyhow::Result<Vec<TodoSearchHit>> {
+            let store = self.read_store_ref();
+            let terms: Vec<String> = words(&query.q).collect();
+            let mut hits: Vec<TodoSearchHit> = live_todos(&store, workspace_id)
+                .filter_map(|todo| {
+                    let todo_words: Vec<String> = words(&todo.text).collect();
+                    if terms.is_empty() || !terms.iter().all(|term| todo_words.contains(term)) {
+                        return None;
+                    }
+                    // share of the words that matched, in place of `ts_rank`
+                    let matched = todo_words
+                        .iter()
+                        .filter(|word| terms.contains(word))
+                        .count();
+                    Some(TodoSearchHit {
+                        todo: self.with_relations(&store, todo),
+                        rank: matched as f32 / todo_words.len() as f32,
+                        snippet: highlight(&todo.text, &terms),
+                    })
+                })
+                .collect();
+            hits.sort_by(|a, b| b.rank.total_cmp(&a.rank).then(b.todo.id.cmp(&a.todo.id)));
+            hits.truncate(query.limit() as usize);
+            Ok(hits)
+        }
+
+        async fn bulk(
+            &self,
+            workspace_id: i32,
+            payload: BulkTodo,
+        ) -> anyhow::Result<Vec<BulkItemResult>> {
+            // check the labels up front, so that nothing changes when one is missing
+            let workspace_labels = self.labels.all(workspace_id).await?;
+            if let Some(id) = payload
+                .operation
+                .labels()
+                .iter()
+                .find(|id| !workspace_labels.iter().any(|label| label.id == **id))
+            {
+                return Err(RepositoryError::NotFound(*id).into());
+            }
+            let mut store = self.write_store_ref();
+            let found: Vec<i32> = payload
+                .ids
+                .iter()
+                .copied()
+                .filter(|id| live_todo(&store, workspace_id, *id).is_some())
+                .collect();
+            let before: HashMap<i32, TodoEntity> = found
+                .iter()
+                .map(|id| (*id, self.with_relations(&store, &store[id].1)))
+                .collect();
+            let changed: Vec<i32> = found
+                .iter()
+                .copied()
+                .filter(|id| {
+                    payload
+                        .operation
+                        .completed()
+                        .is_none_or(|completed| before[id].completed != completed)
+                })
+                .collect();
+            let now = Utc::now();
+            for id in changed.iter().copied() {
+                let label_ids = || self.labels.labels_of(id).into_iter().map(|label| label.id);
+                match &payload.operation {
+                    BulkOperation::Complete | BulkOperation::Uncomplete => {
+                        if let Some((_scope, todo)) = store.get_mut(&id) {
+                            todo.completed = payload.operation == BulkOperation::Complete;
+                        }
+                    }
+                    BulkOperation::Delete => self.trash_subtree(&mut store, workspace_id, id, now),
+                    BulkOperation::AddLabels { labels } => {
+                        let mut label_ids: Vec<i32> = label_ids().collect();
+                        for label_id in labels {
+                            if !label_ids.contains(label_id) {
+                                label_ids.push(*label_id);
+                            }
+                        }
+                        self.labels.set_labels_of(workspace_id, id, &label_ids)?;
+                    }
+                    BulkOperation::RemoveLabels { labels } => {
+                        let label_ids: Vec<i32> =
+                            label_ids().filter(|id| !labels.contains(id)).collect();
+                        self.labels.set_labels_of(workspace_id, id, &label_ids)?;
+                    }
+                    BulkOperation::SetLabels { labels } => {
+                        self.labels.set_labels_of(workspace_id, id, labels)?;
+                    }
+                }
+                if payload.operation != BulkOperation::Delete {
+                    if let Some((_scope, todo)) = store.get_mut(&id) {
+                        todo.version += 1;
+                    }
+                }
+            }
+            let todos: HashMap<i32, TodoEntity> = found
+                .iter()
+                .filter_map(|id| live_todo(&store, workspace_id, *id))
+                .map(|todo| (todo.id, self.with_relations(&store, todo)))
+                .collect();
+            let records = changed
+                .iter()
+                .filter_map(|id| Some((before.get(id)?, todos.get(id)?)))
+                .map(|(old_todo, todo)| {
+                    todo_record(AuditAction::Update, todo.id)
+                        .before(old_todo)
+                        .after(todo)
+                })
+                .collect();
+            self.record(workspace_id, records);
+            Ok(BulkItemResult::collect(
+                &payload.ids,
+                todos,
+                &found,
+                &changed,
+            ))
+        }
+
+        async fn trash(&self, workspace_id: i32) -> anyhow::Result<Vec<TodoEntity>> {
+            let store = self.read_store_ref();
+            let mut todos: Vec<TodoEntity> = store
+                .values()
+                .filter(|(scope, todo)| *scope == workspace_id && todo.deleted_at.is_some())
+                .map(|(_scope, todo)| self.with_relations(&store, todo))
+                .collect();
+            todos.sort_by(|a, b| b.deleted_at.cmp(&a.deleted_at).then(b.id.cmp(&a.id)));
+            Ok(todos)
+        }
+
+        async fn restore(&self, workspace_id: i32, id: i32) -> anyhow::Result<TodoEntity> {
+            let mut store = self.write_store_ref();
+            let deleted_at = store
+                .get(&id)
+                .filter(|(scope, _todo)| *scope == workspace_id)
+                .and_then(|(_scope, todo)| todo.deleted_at)
+                .ok_or(RepositoryError::NotFound(id))?;
+            let mut subtree = vec![id];
+            let mut restored = vec![];
+            while let Some(id) = subtree.pop() {
+                subtree.extend(
+                    store
+                        .values()
+                        .filter(|(_scope, child)| {
+                            child.parent_id == Some(id) && child.deleted_at == Some(deleted_at)
+                        })
+                        .map(|(_scope, child)| child.id),
+                );
+                if let Some((_scope, todo)) = store.get_mut(&id) {
+                    todo.deleted_at = None;
+                    todo.version += 1;
+                }
+                restored.push(id);
+            }
+            // a todo whose parent is still in the trash comes back on its own
+            let parent_trashed = store[&id]
+                .1
+                .parent_id
+                .and_then(|parent_id| store.get(&parent_id))
+                .is_some_and(|(_scope, parent)| parent.deleted_at.is_some());
+            let (_scope, todo) = store.get_mut(&id).unwrap();
+            if parent_trashed {
+                todo.parent_id = None;
+            }
+            let parent_id = todo.parent_id;
+            touch_todos(&mut store, parent_id);
+            let records = restored
+                .into_iter()
+                .map(|id| {
+                    let todo = self.with_relations(&store, &store[&id].1);
+                    todo_record(AuditAction::Restore, id).after(&todo)
+                })
+                .collect();
+            self.record(workspace_id, records);
+            Ok(self.with_relations(&store, &store[&id].1))
+        }
+
+        async fn purge(&self, workspace_id: i32) -> anyhow::Result<u64> {
+            Ok(self.purge_trashed(|scope, _todo| scope == workspace_id))
+        }
+
+        async fn purge_expired(&self, deleted_before: DateTime<Utc>) -> anyhow::Result<u64> {
+            Ok(self.purge_trashed(|_scope, todo| {
+                todo.deleted_at
+                    .is_some_and(|deleted_at| deleted_at < deleted_before)
+            }))
+        }
     }
 
     mod test {
         use super::*;
 
+        const WORKSPACE_ID: i32 = 1;
+        const OTHER_WORKSPACE_ID: i32 = 2;
+
         #[tokio::test]
         async fn todo_crud_scenario() {
             let text = "todo text".to_string();
@@ -482,29 +3679,46 @@ pub mod test_utils {
             // create
             let repository = TodoRepositoryForMemory::new();
             let todo = repository
-                .create(CreateTodo { text, labels })
+                .create(WORKSPACE_ID, CreateTodo::new(text, labels))
                 .await
                 .expect("failed create todo");
             assert_eq!(expected, todo);
 
             // find
-            let todo = repository.find(todo.id).await.unwrap();
+            let todo = repository.find(WORKSPACE_ID, todo.id).await.unwrap();
             assert_eq!(expected, todo);
 
+            // other workspaces neither see nor touch it
+            let res = repository.find(OTHER_WORKSPACE_ID, id).await;
+            assert!(res.is_err());
+            let todos = repository
+                .all(OTHER_WORKSPACE_ID, TodoQuery::default())
+                .await
+                .expect("failed get all todo");
+            assert!(todos.items.is_empty());
+            let res = repository.delete(OTHER_WORKSPACE_ID, id, None).await;
+            assert!(res.is_err());
+
             // all
-            let todos = repository.all().await.expect("failed get all todo");
-            assert_eq!(vec![expected], todos);
+            let todos = repository
+                .all(WORKSPACE_ID, TodoQuery::default())
+                .await
+                .expect("failed get all todo");
+            assert_eq!(vec![expected], todos.items);
 
             // update
             let text = "update todo text".to_string();
             let todo = repository
                 .update(
+                    WORKSPACE_ID,
                     1,
                     UpdateTodo {
                         text: Some(text.clone()),
                         completed: Some(true),
                         labels: Some(vec![]),
+                        ..UpdateTodo::default()
                     },
+                    None,
                 )
                 .await
                 .expect("failed update todo.");
@@ -513,14 +3727,660 @@ pub mod test_utils {
                     id,
                     text,
                     completed: true,
+                    priority: Priority::None,
+                    due_at: None,
+                    starts_at: None,
+                    parent_id: None,
+                    child_ids: vec![],
+                    position: 0,
+                    deleted_at: None,
+                    version: 2,
                     labels: vec![],
                 },
                 todo
             );
 
-            // delete
-            let res = repository.delete(id).await;
+            // delete, at the current version only
+            let res = repository.delete(WORKSPACE_ID, id, Some(vec![1])).await;
+            assert!(matches!(
+                res.unwrap_err().downcast::<RepositoryError>(),
+                Result::Ok(RepositoryError::VersionMismatch(1))
+            ));
+            let res = repository.delete(WORKSPACE_ID, id, Some(vec![2])).await;
             assert!(res.is_ok());
         }
+
+        #[tokio::test]
+        async fn todo_query_scenario() {
+            let repository = TodoRepositoryForMemory::new();
+            for text in ["b", "a", "c"] {
+                repository
+                    .create(WORKSPACE_ID, CreateTodo::new(text.to_string(), vec![]))
+                    .await
+                    .expect("failed create todo");
+            }
+            repository
+                .update(
+                    WORKSPACE_ID,
+                    2,
+                    UpdateTodo::new(None, Some(true), None),
+                    None,
+                )
+                .await
+                .expect("failed update todo");
+
+            let query = TodoQuery {
+                limit: Some(2),
+                sort: Some(TodoSort::Completed),
+                order: Some(SortOrder::Asc),
+                ..TodoQuery::default()
+            };
+            let page = repository
+                .all(WORKSPACE_ID, query.clone())
+                .await
+                .expect("failed get all todo");
+            let ids: Vec<i32> = page.items.iter().map(|todo| todo.id).collect();
+            assert_eq!(vec![1, 3], ids);
+
+            let after = TodoCursor::decode(&page.next_cursor.unwrap()).unwrap();
+            let page = repository
+                .all(
+                    WORKSPACE_ID,
+                    TodoQuery {
+                        after: Some(after),
+                        ..query
+                    },
+                )
+                .await
+                .expect("failed get all todo");
+            let ids: Vec<i32> = page.items.iter().map(|todo| todo.id).collect();
+            assert_eq!(vec![2], ids);
+            assert_eq!(None, page.next_cursor);
+        }
+
+        #[tokio::test]
+        async fn todo_due_date_scenario() {
+            let now = Utc::now();
+            let yesterday = now - chrono::Duration::days(1);
+            let tomorrow = now + chrono::Duration::days(1);
+            let repository = TodoRepositoryForMemory::new();
+            for (text, due_at) in [
+                ("overdue", Some(yesterday)),
+                ("due", Some(tomorrow)),
+                ("someday", None),
+            ] {
+                repository
+                    .create(
+                        WORKSPACE_ID,
+                        CreateTodo {
+                            due_at,
+                            starts_at: Some(now - chrono::Duration::days(2)),
+                            ..CreateTodo::new(text.to_string(), vec![])
+                        },
+                    )
+                    .await
+                    .expect("failed create todo");
+            }
+            let ids_of =
+                |page: TodoPage| -> Vec<i32> { page.items.iter().map(|todo| todo.id).collect() };
+
+            let page = repository
+                .all(
+                    WORKSPACE_ID,
+                    TodoQuery {
+                        overdue: Some(true),
+                        ..TodoQuery::default()
+                    },
+                )
+                .await
+                .unwrap();
+            assert_eq!(vec![1], ids_of(page));
+            let page = repository
+                .all(
+                    WORKSPACE_ID,
+                    TodoQuery {
+                        due_before: Some(tomorrow + chrono::Duration::hours(1)),
+                        overdue: Some(false),
+                        ..TodoQuery::default()
+                    },
+                )
+                .await
+                .unwrap();
+            assert_eq!(vec![2], ids_of(page));
+            let page = repository
+                .all(
+                    WORKSPACE_ID,
+                    TodoQuery {
+                        due_after: Some(now),
+                        ..TodoQuery::default()
+                    },
+                )
+                .await
+                .unwrap();
+            assert_eq!(vec![2], ids_of(page));
+
+            // completed todos are never overdue
+            repository
+                .update(
+                    WORKSPACE_ID,
+                    1,
+                    UpdateTodo::new(None, Some(true), None),
+                    None,
+                )
+                .await
+                .unwrap();
+            let page = repository
+                .all(
+                    WORKSPACE_ID,
+                    TodoQuery {
+                        overdue: Some(true),
+                        ..TodoQuery::default()
+                    },
+                )
+                .await
+                .unwrap();
+            assert!(page.items.is_empty());
+
+            // a missing field keeps the date, an explicit null clears it
+            let todo = repository
+                .update(
+                    WORKSPACE_ID,
+                    2,
+                    UpdateTodo {
+                        due_at: Some(None),
+                        ..UpdateTodo::default()
+                    },
+                    None,
+                )
+                .await
+                .unwrap();
+            assert_eq!(None, todo.due_at);
+            assert!(todo.starts_at.is_some());
+        }
+
+        #[tokio::test]
+        async fn todo_priority_scenario() {
+            let repository = TodoRepositoryForMemory::new();
+            for (text, priority) in [
+                ("low", Priority::Low),
+                ("urgent", Priority::Urgent),
+                ("none", Priority::None),
+                ("also low", Priority::Low),
+            ] {
+                repository
+                    .create(
+                        WORKSPACE_ID,
+                        CreateTodo {
+                            priority,
+                            ..CreateTodo::new(text.to_string(), vec![])
+                        },
+                    )
+                    .await
+                    .expect("failed create todo");
+            }
+
+            // ties are broken by id, in the same direction
+            let query = TodoQuery {
+                limit: Some(2),
+                sort: Some(TodoSort::Priority),
+                ..TodoQuery::default()
+            };
+            let page = repository
+                .all(WORKSPACE_ID, query.clone())
+                .await
+                .expect("failed get all todo");
+            let ids: Vec<i32> = page.items.iter().map(|todo| todo.id).collect();
+            assert_eq!(vec![2, 4], ids);
+            let after = TodoCursor::decode(&page.next_cursor.unwrap()).unwrap();
+            let page = repository
+                .all(
+                    WORKSPACE_ID,
+                    TodoQuery {
+                        after: Some(after),
+                        ..query
+                    },
+                )
+                .await
+                .expect("failed get all todo");
+            let ids: Vec<i32> = page.items.iter().map(|todo| todo.id).collect();
+            assert_eq!(vec![1, 3], ids);
+
+            // a missing field keeps the priority
+            let todo = repository
+                .update(
+                    WORKSPACE_ID,
+                    2,
+                    UpdateTodo::new(None, Some(true), None),
+                    None,
+                )
+                .await
+                .expect("failed update todo");
+            assert_eq!(Priority::Urgent, todo.priority);
+            let todo = repository
+                .update(
+                    WORKSPACE_ID,
+                    2,
+                    UpdateTodo {
+                        priority: Some(Priority::Medium),
+                        ..UpdateTodo::default()
+                    },
+                    None,
+                )
+                .await
+                .expect("failed update todo");
+            assert_eq!(Priority::Medium, todo.priority);
+        }
+
+        #[tokio::test]
+        async fn todo_hierarchy_scenario() {
+            let repository = TodoRepositoryForMemory::new();
+            for (text, parent_id) in [("root", None), ("child", Some(1)), ("grandchild", Some(2))] {
+                repository
+                    .create(
+                        WORKSPACE_ID,
+                        CreateTodo {
+                            parent_id,
+                            ..CreateTodo::new(text.to_string(), vec![])
+                        },
+                    )
+                    .await
+                    .expect("failed create todo");
+            }
+            let todo = repository.find(WORKSPACE_ID, 1).await.unwrap();
+            assert_eq!(vec![2], todo.child_ids);
+            // a new subtask is a new version of the parent
+            assert_eq!(2, todo.version);
+            let children = repository.children(WORKSPACE_ID, 2).await.unwrap();
+            assert_eq!(
+                vec![3],
+                children.iter().map(|todo| todo.id).collect::<Vec<_>>()
+            );
+
+            // parents must exist in the same workspace
+            let res = repository
+                .create(
+                    OTHER_WORKSPACE_ID,
+                    CreateTodo {
+                        parent_id: Some(1),
+                        ..CreateTodo::new("stranger".to_string(), vec![])
+                    },
+                )
+                .await;
+            assert!(res.is_err());
+
+            // a todo can not become a subtask of its own subtask
+            let res = repository
+                .update(
+                    WORKSPACE_ID,
+                    1,
+                    UpdateTodo {
+                        parent_id: Some(Some(3)),
+                        ..UpdateTodo::default()
+                    },
+                    None,
+                )
+                .await;
+            assert!(res.is_err());
+
+            // an explicit null detaches it
+            let todo = repository
+                .update(
+                    WORKSPACE_ID,
+                    3,
+                    UpdateTodo {
+                        parent_id: Some(None),
+                        ..UpdateTodo::default()
+                    },
+                    None,
+                )
+                .await
+                .unwrap();
+            assert_eq!(None, todo.parent_id);
+            let todo = repository.find(WORKSPACE_ID, 2).await.unwrap();
+            assert!(todo.child_ids.is_empty());
+            assert_eq!(3, todo.version);
+
+            // deleting a todo deletes its subtasks
+            repository
+                .update(
+                    WORKSPACE_ID,
+                    3,
+                    UpdateTodo {
+                        parent_id: Some(Some(2)),
+                        ..UpdateTodo::default()
+                    },
+                    None,
+                )
+                .await
+                .unwrap();
+            repository.delete(WORKSPACE_ID, 1, None).await.unwrap();
+            let todos = repository
+                .all(WORKSPACE_ID, TodoQuery::default())
+                .await
+                .unwrap();
+            assert!(todos.items.is_empty());
+        }
+
+        #[tokio::test]
+        async fn todo_move_scenario() {
+            use crate::repositories::audit::AuditRepository;
+
+            let repository = TodoRepositoryForMemory::new();
+            for text in ["a", "b", "c"] {
+                repository
+                    .create(WORKSPACE_ID, CreateTodo::new(text.to_string(), vec![]))
+                    .await
+                    .expect("failed create todo");
+            }
+            let list_order = || async {
+                let page = repository
+                    .all(WORKSPACE_ID, TodoQuery::default())
+                    .await
+                    .expect("failed get all todo");
+                page.items.iter().map(|todo| todo.id).collect::<Vec<i32>>()
+            };
+            assert_eq!(vec![3, 2, 1], list_order().await);
+
+            repository
+                .reposition(
+                    WORKSPACE_ID,
+                    1,
+                    MoveTodo {
+                        before: Some(3),
+                        after: None,
+                    },
+                )
+                .await
+                .expect("failed move todo");
+            assert_eq!(vec![1, 3, 2], list_order().await);
+            repository
+                .reposition(
+                    WORKSPACE_ID,
+                    3,
+                    MoveTodo {
+                        before: None,
+                        after: Some(2),
+                    },
+                )
+                .await
+                .expect("failed move todo");
+            assert_eq!(vec![1, 2, 3], list_order().await);
+
+            // squeezing todos into the same gap over and over rebalances the positions
+            for _ in 0..12 {
+                for (moved, below) in [(3, 2), (2, 3)] {
+                    repository
+                        .reposition(
+                            WORKSPACE_ID,
+                            moved,
+                            MoveTodo {
+                                before: Some(below),
+                                after: Some(1),
+                            },
+                        )
+                        .await
+                        .expect("failed move todo");
+                }
+            }
+            assert_eq!(vec![1, 2, 3], list_order().await);
+            let todo = repository.find(WORKSPACE_ID, 1).await.unwrap();
+            assert_eq!(POSITION_GAP, todo.position);
+            // rebalancing is a change like any other
+            let history = repository
+                .audit
+                .history(WORKSPACE_ID, AuditEntity::Todo, 1)
+                .await
+                .unwrap();
+            let last = history.last().unwrap();
+            assert_eq!(AuditAction::Update, last.action);
+            assert_eq!(Some(serde_json::to_value(&todo).unwrap()), last.after);
+
+            // neighbours that rebalancing can not pull apart are an error, not a panic
+            let res = repository
+                .reposition(
+                    WORKSPACE_ID,
+                    1,
+                    MoveTodo {
+                        before: Some(2),
+                        after: Some(2),
+                    },
+                )
+                .await;
+            assert!(res.is_err());
+
+            let res = repository
+                .reposition(
+                    OTHER_WORKSPACE_ID,
+                    1,
+                    MoveTodo {
+                        before: Some(2),
+                        after: None,
+                    },
+                )
+                .await;
+            assert!(res.is_err());
+        }
+
+        #[tokio::test]
+        async fn todo_search_scenario() {
+            let repository = TodoRepositoryForMemory::new();
+            for text in [
+                "Buy milk and bread",
+                "Milk the cows, then milk the goats",
+                "Call the bank",
+            ] {
+                repository
+                    .create(WORKSPACE_ID, CreateTodo::new(text.to_string(), vec![]))
+                    .await
+                    .expect("failed create todo");
+            }
+            let search = |q: &str| {
+                repository.search(
+                    WORKSPACE_ID,
+                    SearchQuery {
+                        q: q.to_string(),
+                        ..SearchQuery::default()
+                    },
+                )
+            };
+
+            let hits = search("MILK").await.unwrap();
+            let ids: Vec<i32> = hits.iter().map(|hit| hit.todo.id).collect();
+            assert_eq!(vec![2, 1], ids);
+            assert_eq!(
+                "<mark>Milk</mark> the cows, then <mark>milk</mark> the goats",
+                hits[0].snippet
+            );
+            assert!(search("milk bank").await.unwrap().is_empty());
+            assert!(search("!!").await.unwrap().is_empty());
+            let hits = repository
+                .search(
+                    OTHER_WORKSPACE_ID,
+                    SearchQuery {
+                        q: "milk".to_string(),
+                        ..SearchQuery::default()
+                    },
+                )
+                .await
+                .unwrap();
+            assert!(hits.is_empty());
+
+            repository
+                .create(
+                    WORKSPACE_ID,
+                    CreateTodo::new(
+                        "<script>alert(1)</script> & \u{1}cheese".to_string(),
+                        vec![],
+                    ),
+                )
+                .await
+                .unwrap();
+            let hits = search("cheese").await.unwrap();
+            assert_eq!(
+                "&lt;script&gt;alert(1)&lt;/script&gt; &amp; <mark>cheese</mark>",
+                hits[0].snippet
+            );
+        }
+
+        #[tokio::test]
+        async fn todo_bulk_scenario() {
+            use crate::repositories::audit::AuditRepository;
+
+            let labels = LabelRepositoryForMemory::new();
+            let repository = TodoRepositoryForMemory::new().with_label_repository(labels.clone());
+            let label_1 = labels
+                .create(WORKSPACE_ID, "label 1".to_string())
+                .await
+                .unwrap();
+            let label_2 = labels
+                .create(WORKSPACE_ID, "label 2".to_string())
+                .await
+                .unwrap();
+            for (text, parent_id) in [("a", None), ("b", None), ("subtask of b", Some(2))] {
+                repository
+                    .create(
+                        WORKSPACE_ID,
+                        CreateTodo {
+                            parent_id,
+                            ..CreateTodo::new(text.to_string(), vec![label_1.id])
+                        },
+                    )
+                    .await
+                    .expect("failed create todo");
+            }
+            let bulk = |ids: Vec<i32>, operation: BulkOperation| {
+                repository.bulk(WORKSPACE_ID, BulkTodo { ids, operation })
+            };
+
+            // duplicates are reported once, unknown todos as not found
+            let results = bulk(vec![1, 1, 99], BulkOperation::Complete).await.unwrap();
+            assert_eq!(2, results.len());
+            assert_eq!(BulkStatus::Ok, results[0].status);
+            assert!(results[0].todo.as_ref().unwrap().completed);
+            assert_eq!(BulkStatus::NotFound, results[1].status);
+            assert!(results[1].todo.is_none());
+            let results = bulk(vec![1], BulkOperation::Uncomplete).await.unwrap();
+            assert!(!results[0].todo.as_ref().unwrap().completed);
+            // uncompleting it again is neither a new version nor recorded
+            let history = || async {
+                repository
+                    .audit
+                    .history(WORKSPACE_ID, AuditEntity::Todo, 1)
+                    .await
+                    .unwrap()
+                    .len()
+            };
+            let (version, events) = (results[0].todo.as_ref().unwrap().version, history().await);
+            let results = bulk(vec![1], BulkOperation::Uncomplete).await.unwrap();
+            assert_eq!(BulkStatus::Unchanged, results[0].status);
+            assert_eq!(version, results[0].todo.as_ref().unwrap().version);
+            assert_eq!(events, history().await);
+
+            let labels_op = BulkOperation::AddLabels {
+                labels: vec![label_1.id, label_2.id],
+            };
+            let results = bulk(vec![1], labels_op).await.unwrap();
+            assert_eq!(
+                vec![label_1.clone(), label_2.clone()],
+                results[0].todo.as_ref().unwrap().labels
+            );
+            let labels_op = BulkOperation::RemoveLabels {
+                labels: vec![label_1.id],
+            };
+            let results = bulk(vec![1], labels_op).await.unwrap();
+            assert_eq!(
+                vec![label_2.clone()],
+                results[0].todo.as_ref().unwrap().labels
+            );
+            let labels_op = BulkOperation::SetLabels {
+                labels: vec![label_1.id],
+            };
+            let results = bulk(vec![1, 2], labels_op).await.unwrap();
+            assert!(results
+                .iter()
+                .all(|result| result.todo.as_ref().unwrap().labels == vec![label_1.clone()]));
+
+            // a missing label changes nothing
+            let labels_op = BulkOperation::SetLabels { labels: vec![99] };
+            assert!(bulk(vec![1], labels_op).await.is_err());
+            let todo = repository.find(WORKSPACE_ID, 1).await.unwrap();
+            assert_eq!(vec![label_1], todo.labels);
+
+            // deleting takes the subtasks along
+            let results = bulk(vec![2], BulkOperation::Delete).await.unwrap();
+            assert_eq!(BulkStatus::Ok, results[0].status);
+            let todos = repository
+                .all(WORKSPACE_ID, TodoQuery::default())
+                .await
+                .unwrap();
+            let ids: Vec<i32> = todos.items.iter().map(|todo| todo.id).collect();
+            assert_eq!(vec![1], ids);
+        }
+
+        #[tokio::test]
+        async fn todo_trash_scenario() {
+            let repository = TodoRepositoryForMemory::new();
+            for (text, parent_id) in [("parent", None), ("subtask", Some(1)), ("other", None)] {
+                repository
+                    .create(
+                        WORKSPACE_ID,
+                        CreateTodo {
+                            parent_id,
+                            ..CreateTodo::new(text.to_string(), vec![])
+                        },
+                    )
+                    .await
+                    .expect("failed create todo");
+            }
+            let trashed_ids = || async {
+                let trash = repository.trash(WORKSPACE_ID).await.unwrap();
+                trash.iter().map(|todo| todo.id).collect::<Vec<i32>>()
+            };
+
+            repository.delete(WORKSPACE_ID, 1, None).await.unwrap();
+            assert!(repository.find(WORKSPACE_ID, 2).await.is_err());
+            assert!(repository.children(WORKSPACE_ID, 1).await.is_err());
+            let todos = repository
+                .all(WORKSPACE_ID, TodoQuery::default())
+                .await
+                .unwrap();
+            let ids: Vec<i32> = todos.items.iter().map(|todo| todo.id).collect();
+            assert_eq!(vec![3], ids);
+            assert_eq!(vec![2, 1], trashed_ids().await);
+            assert!(repository
+                .trash(OTHER_WORKSPACE_ID)
+                .await
+                .unwrap()
+                .is_empty());
+            assert!(repository.restore(OTHER_WORKSPACE_ID, 1).await.is_err());
+
+            // the subtask comes back with its parent
+            let restored = repository.restore(WORKSPACE_ID, 1).await.unwrap();
+            assert_eq!(None, restored.deleted_at);
+            assert_eq!(vec![2], restored.child_ids);
+            assert!(trashed_ids().await.is_empty());
+            assert!(repository.restore(WORKSPACE_ID, 1).await.is_err());
+
+            // but a subtask restored on its own leaves the trashed parent behind
+            repository.delete(WORKSPACE_ID, 1, None).await.unwrap();
+            let restored = repository.restore(WORKSPACE_ID, 2).await.unwrap();
+            assert_eq!(None, restored.parent_id);
+
+            // only todos trashed long enough expire
+            repository.delete(WORKSPACE_ID, 3, None).await.unwrap();
+            let purged = repository
+                .purge_expired(Utc::now() - chrono::Duration::days(1))
+                .await
+                .unwrap();
+            assert_eq!(0, purged);
+            let purged = repository.purge_expired(Utc::now()).await.unwrap();
+            assert_eq!(2, purged);
+            assert!(trashed_ids().await.is_empty());
+
+            repository.delete(WORKSPACE_ID, 2, None).await.unwrap();
+            assert_eq!(0, repository.purge(OTHER_WORKSPACE_ID).await.unwrap());
+            assert_eq!(1, repository.purge(WORKSPACE_ID).await.unwrap());
+            assert!(repository.find(WORKSPACE_ID, 2).await.is_err());
+        }
     }
 }
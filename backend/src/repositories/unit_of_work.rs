@@ -0,0 +1,452 @@
+#[cfg(test)]
+use super::label::{LabelRepository, LabelRepositoryForDb};
+use super::{
+    todo::{TodoRepository, TodoRepositoryForDb},
+    user::{UserRepository, UserRepositoryForDb},
+    workspace::{WorkspaceRepository, WorkspaceRepositoryForDb},
+};
+use anyhow::anyhow;
+use axum::async_trait;
+use futures::future::BoxFuture;
+use sqlx::{Connection, PgConnection, PgPool, Postgres, Transaction};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// Todo repository operations that commit or roll back together.
+#[async_trait]
+pub trait UnitOfWork: Send + Sync + Sized + 'static {
+    type Todo: TodoRepository;
+    fn todos(&self) -> Self::Todo;
+    /// Dropping a unit of work without committing discards its writes.
+    async fn commit(self) -> anyhow::Result<()>;
+}
+
+/// A todo repository able to open a unit of work.
+#[async_trait]
+pub trait Transactional: TodoRepository {
+    type UnitOfWork: UnitOfWork;
+    async fn begin(&self) -> anyhow::Result<Self::UnitOfWork>;
+}
+
+/// User and workspace repository operations that commit or roll back together, so that
+/// no user is left without the personal workspace created on signup.
+#[async_trait]
+pub trait AccountUnitOfWork: Send + Sync + Sized + 'static {
+    type User: UserRepository;
+    type Workspace: WorkspaceRepository;
+    fn users(&self) -> Self::User;
+    fn workspaces(&self) -> Self::Workspace;
+    /// Dropping a unit of work without committing discards its writes.
+    async fn commit(self) -> anyhow::Result<()>;
+}
+
+/// A user repository able to open a unit of work together with the workspaces.
+#[async_trait]
+pub trait TransactionalUser: UserRepository {
+    type UnitOfWork: AccountUnitOfWork;
+    async fn begin(&self) -> anyhow::Result<Self::UnitOfWork>;
+}
+
+/// A database transaction shared by the repositories created from it.
+///
+/// Every repository operation runs inside a savepoint of the shared transaction,
+/// so nothing is visible to other connections until `commit` is called.
+/// Dropping the unit of work without committing rolls everything back.
+#[derive(Debug, Clone)]
+pub struct UnitOfWorkForDb {
+    tx: Arc<Mutex<Option<Transaction<'static, Postgres>>>>,
+    /// the user the repositories record changes for
+    actor: Option<i32>,
+}
+
+impl UnitOfWorkForDb {
+    pub async fn begin(pool: &PgPool) -> anyhow::Result<Self> {
+        let tx = pool.begin().await?;
+        Ok(Self {
+            tx: Arc::new(Mutex::new(Some(tx))),
+            actor: None,
+        })
+    }
+
+    /// A label repository on the same transaction, for tests changing labels and todos
+    /// together.
+    #[cfg(test)]
+    pub fn labels(&self) -> LabelRepositoryForDb {
+        let labels = LabelRepositoryForDb::with_context(DbContext::UnitOfWork(self.clone()));
+        match self.actor {
+            Some(user_id) => labels.acting_as(user_id),
+            None => labels,
+        }
+    }
+
+    #[tracing::instrument(name = "unit_of_work.commit", skip(self))]
+    pub async fn commit(self) -> anyhow::Result<()> {
+        let tx = self.take().await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn take(&self) -> anyhow::Result<Transaction<'static, Postgres>> {
+        self.tx
+            .lock()
+            .await
+            .take()
+            .ok_or_else(|| anyhow!("unit of work is already finished"))
+    }
+}
+
+#[async_trait]
+impl UnitOfWork for UnitOfWorkForDb {
+    type Todo = TodoRepositoryForDb;
+
+    fn todos(&self) -> TodoRepositoryForDb {
+        let todos = TodoRepositoryForDb::with_context(DbContext::UnitOfWork(self.clone()));
+        match self.actor {
+            Some(user_id) => todos.acting_as(user_id),
+            None => todos,
+        }
+    }
+
+    async fn commit(self) -> anyhow::Result<()> {
+        UnitOfWorkForDb::commit(self).await
+    }
+}
+
+#[async_trait]
+impl AccountUnitOfWork for UnitOfWorkForDb {
+    type User = UserRepositoryForDb;
+    type Workspace = WorkspaceRepositoryForDb;
+
+    fn users(&self) -> UserRepositoryForDb {
+        UserRepositoryForDb::with_context(DbContext::UnitOfWork(self.clone()))
+    }
+
+    fn workspaces(&self) -> WorkspaceRepositoryForDb {
+        WorkspaceRepositoryForDb::with_context(DbContext::UnitOfWork(self.clone()))
+    }
+
+    async fn commit(self) -> anyhow::Result<()> {
+        UnitOfWorkForDb::commit(self).await
+    }
+}
+
+#[async_trait]
+impl Transactional for TodoRepositoryForDb {
+    type UnitOfWork = UnitOfWorkForDb;
+
+    #[tracing::instrument(name = "unit_of_work.begin", skip(self))]
+    async fn begin(&self) -> anyhow::Result<UnitOfWorkForDb> {
+        match self.context() {
+            DbContext::Pool(pool) => Ok(UnitOfWorkForDb {
+                actor: self.actor(),
+                ..UnitOfWorkForDb::begin(pool).await?
+            }),
+            DbContext::UnitOfWork(_) => Err(anyhow!("nested unit of work is not supported")),
+        }
+    }
+}
+
+#[async_trait]
+impl TransactionalUser for UserRepositoryForDb {
+    type UnitOfWork = UnitOfWorkForDb;
+
+    #[tracing::instrument(name = "unit_of_work.begin", skip(self))]
+    async fn begin(&self) -> anyhow::Result<UnitOfWorkForDb> {
+        match self.context() {
+            DbContext::Pool(pool) => UnitOfWorkForDb::begin(pool).await,
+            DbContext::UnitOfWork(_) => Err(anyhow!("nested unit of work is not supported")),
+        }
+    }
+}
+
+tokio::task_local! {
+    /// The units of work whose transaction the current task holds, by address.
+    static HELD: Vec<usize>;
+}
+
+/// Where a database repository sends its statements.
+#[derive(Debug, Clone)]
+pub enum DbContext {
+    Pool(PgPool),
+    UnitOfWork(UnitOfWorkForDb),
+}
+
+impl DbContext {
+    /// Runs `f` on a single transaction and commits it when `f` succeeds.
+    ///
+    /// With a pool this is a fresh transaction; inside a unit of work it is a
+    /// savepoint, so a failed operation leaves the outer transaction usable.
+    ///
+    /// The unit of work stays locked while `f` runs, so `f` has to do its work on the
+    /// connection it is handed. Calling back into a repository of the same unit of work
+    /// would wait for itself forever, so it fails right away instead.
+    pub async fn transaction<T, F>(&self, f: F) -> anyhow::Result<T>
+    where
+        T: Send,
+        F: for<'c> FnOnce(&'c mut PgConnection) -> BoxFuture<'c, anyhow::Result<T>> + Send,
+    {
+        match self {
+            DbContext::Pool(pool) => {
+                let mut tx = pool.begin().await?;
+                let value = f(&mut tx).await?;
+                tx.commit().await?;
+                Ok(value)
+            }
+            DbContext::UnitOfWork(uow) => {
+                let key = Arc::as_ptr(&uow.tx) as usize;
+                let mut held = HELD.try_with(Vec::clone).unwrap_or_default();
+                if held.contains(&key) {
+                    return Err(anyhow!(
+                        "unit of work is used again while it runs an operation"
+                    ));
+                }
+                held.push(key);
+                let mut guard = uow.tx.lock().await;
+                let tx = guard
+                    .as_mut()
+                    .ok_or_else(|| anyhow!("unit of work is already finished"))?;
+                let mut savepoint = tx.begin().await?;
+                let value = HELD.scope(held, f(&mut savepoint)).await?;
+                savepoint.commit().await?;
+                Ok(value)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod test {
+    use super::*;
+    use crate::repositories::{
+        todo::CreateTodo,
+        user::UserRepositoryForDb,
+        workspace::{test_utils::prepare_workspace, WorkspaceRepositoryForDb},
+    };
+    use dotenv::dotenv;
+    use std::env;
+
+    #[tokio::test]
+    async fn rollback_discards_writes_across_repositories() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database_url));
+        let label_name = "[unit_of_work] rollback label";
+        let todo_text = "[unit_of_work] rollback todo";
+
+        let uow = UnitOfWorkForDb::begin(&pool)
+            .await
+            .expect("[begin] returned Err");
+        let ctx = DbContext::UnitOfWork(uow.clone());
+        let workspace = prepare_workspace(
+            &UserRepositoryForDb::with_context(ctx.clone()),
+            &WorkspaceRepositoryForDb::with_context(ctx),
+            "[unit_of_work] user",
+            "[unit_of_work] workspace",
+        )
+        .await;
+        let label = uow
+            .labels()
+            .create(workspace.id, label_name.to_string())
+            .await
+            .expect("[create label] returned Err");
+        let todo = uow
+            .todos()
+            .create(
+                workspace.id,
+                CreateTodo::new(todo_text.to_string(), vec![label.id]),
+            )
+            .await
+            .expect("[create todo] returned Err");
+        assert_eq!(vec![label.clone()], todo.labels);
+
+        // statements in the unit of work are invisible outside of it
+        let res = TodoRepositoryForDb::new(pool.clone())
+            .find(workspace.id, todo.id)
+            .await;
+        assert!(res.is_err());
+
+        drop(uow);
+
+        let labels = sqlx::query(r#"select * from labels where id=$1"#)
+            .bind(label.id)
+            .fetch_all(&pool)
+            .await
+            .expect("labels fetch error");
+        assert!(labels.is_empty());
+        let todos = sqlx::query(r#"select * from todos where id=$1"#)
+            .bind(todo.id)
+            .fetch_all(&pool)
+            .await
+            .expect("todos fetch error");
+        assert!(todos.is_empty());
+    }
+
+    #[tokio::test]
+    async fn reentering_fails_instead_of_waiting_for_itself() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database_url));
+
+        let uow = UnitOfWorkForDb::begin(&pool)
+            .await
+            .expect("[begin] returned Err");
+        let ctx = DbContext::UnitOfWork(uow.clone());
+        let labels = uow.labels();
+        let res = ctx.transaction(|_conn| Box::pin(async move { labels.all(1).await }));
+        let res = tokio::time::timeout(std::time::Duration::from_secs(5), res)
+            .await
+            .expect("re-entered unit of work waited for itself");
+        assert!(res.is_err());
+
+        // other operations are not held up
+        uow.labels().all(1).await.expect("[all] returned Err");
+    }
+
+    #[tokio::test]
+    async fn failed_signup_leaves_no_user_behind() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database_url));
+        let users = UserRepositoryForDb::new(pool.clone());
+        let username = "[failed_signup_leaves_no_user_behind] user";
+
+        let uow = users.begin().await.expect("[begin] returned Err");
+        let user = uow
+            .users()
+            .create(username.to_string(), "hash".to_string())
+            .await
+            .expect("[create user] returned Err");
+        // postgres rejects NUL bytes in text, so the workspace can not be created
+        let res = uow.workspaces().create(user.id, "\0".to_string()).await;
+        assert!(res.is_err());
+        drop(uow);
+
+        let found = users
+            .find_by_username(username)
+            .await
+            .expect("[find_by_username] returned Err");
+        assert_eq!(None, found);
+    }
+
+    #[tokio::test]
+    async fn failed_operation_keeps_unit_of_work_usable() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database_url));
+
+        let uow = UnitOfWorkForDb::begin(&pool)
+            .await
+            .expect("[begin] returned Err");
+        let ctx = DbContext::UnitOfWork(uow.clone());
+        let workspace = prepare_workspace(
+            &UserRepositoryForDb::with_context(ctx.clone()),
+            &WorkspaceRepositoryForDb::with_context(ctx),
+            "[unit_of_work] user",
+            "[unit_of_work] workspace",
+        )
+        .await;
+        // postgres rejects NUL bytes in text, which aborts the failing statement
+        let res = uow
+            .labels()
+            .create(workspace.id, "[unit_of_work] \0".to_string())
+            .await;
+        assert!(res.is_err());
+
+        let label = uow
+            .labels()
+            .create(workspace.id, "[unit_of_work] usable label".to_string())
+            .await
+            .expect("[create label] returned Err");
+        let labels = uow
+            .labels()
+            .all(workspace.id)
+            .await
+            .expect("[all] returned Err");
+        assert!(labels.contains(&label));
+    }
+}
+
+#[cfg(test)]
+pub mod test_utils {
+    use super::*;
+    use crate::repositories::{
+        todo::test_utils::TodoRepositoryForMemory, user::test_utils::UserRepositoryForMemory,
+        workspace::test_utils::WorkspaceRepositoryForMemory,
+    };
+
+    /// Shares the in-memory stores; there is nothing to roll back.
+    #[derive(Debug, Clone)]
+    pub struct UnitOfWorkForMemory {
+        todos: TodoRepositoryForMemory,
+    }
+
+    #[async_trait]
+    impl UnitOfWork for UnitOfWorkForMemory {
+        type Todo = TodoRepositoryForMemory;
+
+        fn todos(&self) -> TodoRepositoryForMemory {
+            self.todos.clone()
+        }
+
+        async fn commit(self) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl Transactional for TodoRepositoryForMemory {
+        type UnitOfWork = UnitOfWorkForMemory;
+
+        async fn begin(&self) -> anyhow::Result<UnitOfWorkForMemory> {
+            Ok(UnitOfWorkForMemory {
+                todos: self.clone(),
+            })
+        }
+    }
+
+    /// Shares the in-memory stores like `UnitOfWorkForMemory`.
+    #[derive(Debug, Clone)]
+    pub struct AccountUnitOfWorkForMemory {
+        users: UserRepositoryForMemory,
+        workspaces: WorkspaceRepositoryForMemory,
+    }
+
+    #[async_trait]
+    impl AccountUnitOfWork for AccountUnitOfWorkForMemory {
+        type User = UserRepositoryForMemory;
+        type Workspace = WorkspaceRepositoryForMemory;
+
+        fn users(&self) -> UserRepositoryForMemory {
+            self.users.clone()
+        }
+
+        fn workspaces(&self) -> WorkspaceRepositoryForMemory {
+            self.workspaces.clone()
+        }
+
+        async fn commit(self) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl TransactionalUser for UserRepositoryForMemory {
+        type UnitOfWork = AccountUnitOfWorkForMemory;
+
+        async fn begin(&self) -> anyhow::Result<AccountUnitOfWorkForMemory> {
+            Ok(AccountUnitOfWorkForMemory {
+                users: self.clone(),
+                workspaces: self.workspace_repository(),
+            })
+        }
+    }
+}
@@ -0,0 +1,321 @@
+use super::unit_of_work::DbContext;
+use super::RepositoryError;
+use anyhow::anyhow;
+use axum::async_trait;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection, PgPool};
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, PartialEq, Eq, FromRow)]
+pub struct UserEntity {
+    pub id: i32,
+    pub username: String,
+    pub password_hash: String,
+}
+
+/// The public view of a user, without credentials.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, ToSchema)]
+pub struct User {
+    pub id: i32,
+    pub username: String,
+}
+
+impl From<UserEntity> for User {
+    fn from(user: UserEntity) -> Self {
+        Self {
+            id: user.id,
+            username: user.username,
+        }
+    }
+}
+
+#[async_trait]
+pub trait UserRepository: Clone + std::marker::Send + std::marker::Sync + 'static {
+    async fn create(&self, username: String, password_hash: String) -> anyhow::Result<UserEntity>;
+    async fn find(&self, id: i32) -> anyhow::Result<UserEntity>;
+    async fn find_by_username(&self, username: &str) -> anyhow::Result<Option<UserEntity>>;
+}
+
+#[derive(Debug, Clone)]
+pub struct UserRepositoryForDb {
+    ctx: DbContext,
+}
+
+impl UserRepositoryForDb {
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            ctx: DbContext::Pool(pool),
+        }
+    }
+
+    pub fn with_context(ctx: DbContext) -> Self {
+        Self { ctx }
+    }
+
+    pub fn context(&self) -> &DbContext {
+        &self.ctx
+    }
+}
+
+async fn find_user_by_username(
+    conn: &mut PgConnection,
+    username: String,
+) -> anyhow::Result<Option<UserEntity>> {
+    let user = sqlx::query_as::<_, UserEntity>(r#"select * from users where username = $1;"#)
+        .bind(username)
+        .fetch_optional(&mut *conn)
+        .await?;
+
+    Ok(user)
+}
+
+async fn create_user(
+    conn: &mut PgConnection,
+    username: String,
+    password_hash: String,
+) -> anyhow::Result<UserEntity> {
+    // checking first would race with a concurrent signup for the same name, so the insert
+    // skips taken names itself and waits for such a signup to finish
+    let user = sqlx::query_as::<_, UserEntity>(
+        r#"insert into users ( username, password_hash ) values ( $1, $2 ) on conflict ( username ) do nothing returning *;"#,
+    )
+    .bind(username.clone())
+    .bind(password_hash)
+    .fetch_optional(&mut *conn)
+    .await?;
+    match user {
+        Some(user) => Ok(user),
+        None => {
+            let user = find_user_by_username(&mut *conn, username.clone())
+                .await?
+                .ok_or_else(|| anyhow!("user {} is taken and gone", username))?;
+            Err(RepositoryError::Duplicate(user.id).into())
+        }
+    }
+}
+
+#[async_trait]
+impl UserRepository for UserRepositoryForDb {
+    #[tracing::instrument(name = "user.create", skip(self, username, password_hash))]
+    async fn create(&self, username: String, password_hash: String) -> anyhow::Result<UserEntity> {
+        self.ctx
+            .transaction(|conn| Box::pin(create_user(conn, username, password_hash)))
+            .await
+    }
+
+    #[tracing::instrument(name = "user.find", skip(self))]
+    async fn find(&self, id: i32) -> anyhow::Result<UserEntity> {
+        self.ctx
+            .transaction(|conn| {
+                Box::pin(async move {
+                    let user =
+                        sqlx::query_as::<_, UserEntity>(r#"select * from users where id = $1;"#)
+                            .bind(id)
+                            .fetch_optional(conn)
+                            .await?
+                            .ok_or(RepositoryError::NotFound(id))?;
+                    Ok(user)
+                })
+            })
+            .await
+    }
+
+    #[tracing::instrument(name = "user.find_by_username", skip(self, username))]
+    async fn find_by_username(&self, username: &str) -> anyhow::Result<Option<UserEntity>> {
+        let username = username.to_string();
+        self.ctx
+            .transaction(|conn| Box::pin(find_user_by_username(conn, username)))
+            .await
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod test {
+    use super::*;
+    use crate::repositories::unit_of_work::UnitOfWorkForDb;
+    use dotenv::dotenv;
+    use std::env;
+
+    #[tokio::test]
+    async fn crud_scenario() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database_url));
+        // nothing is committed, so other tests never see these rows
+        let uow = UnitOfWorkForDb::begin(&pool)
+            .await
+            .expect("[begin] returned Err");
+        let repository = UserRepositoryForDb::with_context(DbContext::UnitOfWork(uow));
+        let username = "[crud_scenario] user";
+
+        // create
+        let user = repository
+            .create(username.to_string(), "hash".to_string())
+            .await
+            .expect("[create] returned Err");
+        assert_eq!(user.username, username);
+
+        // create with a name already in use
+        let res = repository
+            .create(username.to_string(), "other hash".to_string())
+            .await;
+        assert!(matches!(
+            res.unwrap_err().downcast::<RepositoryError>(),
+            Ok(RepositoryError::Duplicate(id)) if id == user.id
+        ));
+
+        // find
+        let found = repository
+            .find_by_username(username)
+            .await
+            .expect("[find_by_username] returned Err");
+        assert_eq!(Some(user.clone()), found);
+        let found = repository.find(user.id).await.expect("[find] returned Err");
+        assert_eq!(user, found);
+        let found = repository
+            .find_by_username("[crud_scenario] nobody")
+            .await
+            .expect("[find_by_username] returned Err");
+        assert_eq!(None, found);
+    }
+
+    #[tokio::test]
+    async fn concurrent_signup_scenario() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database_url));
+        // the first signup commits, so every run needs a name of its own
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let username = format!("[concurrent_signup_scenario] user {}", nanos);
+
+        let first = UnitOfWorkForDb::begin(&pool)
+            .await
+            .expect("[begin] returned Err");
+        let user = UserRepositoryForDb::with_context(DbContext::UnitOfWork(first.clone()))
+            .create(username.clone(), "hash".to_string())
+            .await
+            .expect("[create] returned Err");
+        // the second signup checks for the name before the first one is committed
+        let second = tokio::spawn({
+            let repository = UserRepositoryForDb::new(pool.clone());
+            let username = username.clone();
+            async move { repository.create(username, "other hash".to_string()).await }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        first.commit().await.expect("[commit] returned Err");
+
+        let res = second.await.unwrap();
+        assert!(matches!(
+            res.unwrap_err().downcast::<RepositoryError>(),
+            Ok(RepositoryError::Duplicate(id)) if id == user.id
+        ));
+    }
+}
+
+#[cfg(test)]
+pub mod test_utils {
+    use super::*;
+    use crate::repositories::workspace::test_utils::WorkspaceRepositoryForMemory;
+    use std::{
+        collections::HashMap,
+        sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    };
+
+    /// Finds `username` or creates it, for tests owning rows that are committed.
+    pub async fn prepare_user<U: UserRepository>(repository: &U, username: &str) -> UserEntity {
+        let user = repository
+            .find_by_username(username)
+            .await
+            .expect("Failed to find user data.");
+        match user {
+            Some(user) => user,
+            None => repository
+                .create(username.to_string(), "hash".to_string())
+                .await
+                .expect("Failed to insert user data."),
+        }
+    }
+
+    type UserData = HashMap<i32, UserEntity>;
+
+    #[derive(Debug, Clone)]
+    pub struct UserRepositoryForMemory {
+        store: Arc<RwLock<UserData>>,
+        /// where signing up creates the personal workspace
+        workspaces: WorkspaceRepositoryForMemory,
+    }
+
+    impl UserRepositoryForMemory {
+        pub fn new() -> Self {
+            UserRepositoryForMemory {
+                store: Arc::default(),
+                workspaces: WorkspaceRepositoryForMemory::new(),
+            }
+        }
+
+        /// Signs up into the workspaces of `workspaces` rather than into its own.
+        pub fn with_workspace_repository(&self, workspaces: WorkspaceRepositoryForMemory) -> Self {
+            UserRepositoryForMemory {
+                workspaces,
+                ..self.clone()
+            }
+        }
+
+        pub fn workspace_repository(&self) -> WorkspaceRepositoryForMemory {
+            self.workspaces.clone()
+        }
+
+        fn write_store_ref(&self) -> RwLockWriteGuard<'_, UserData> {
+            self.store.write().unwrap()
+        }
+
+        fn read_store_ref(&self) -> RwLockReadGuard<'_, UserData> {
+            self.store.read().unwrap()
+        }
+    }
+
+    #[async_trait]
+    impl UserRepository for UserRepositoryForMemory {
+        async fn create(
+            &self,
+            username: String,
+            password_hash: String,
+        ) -> anyhow::Result<UserEntity> {
+            let mut store = self.write_store_ref();
+            if let Some(user) = store.values().find(|user| user.username == username) {
+                return Err(RepositoryError::Duplicate(user.id).into());
+            }
+            let id = store.keys().max().unwrap_or(&0) + 1;
+            let user = UserEntity {
+                id,
+                username,
+                password_hash,
+            };
+            store.insert(id, user.clone());
+            Ok(user)
+        }
+
+        async fn find(&self, id: i32) -> anyhow::Result<UserEntity> {
+            let store = self.read_store_ref();
+            let user = store
+                .get(&id)
+                .cloned()
+                .ok_or(RepositoryError::NotFound(id))?;
+            Ok(user)
+        }
+
+        async fn find_by_username(&self, username: &str) -> anyhow::Result<Option<UserEntity>> {
+            let store = self.read_store_ref();
+            let user = store.values().find(|user| user.username == username);
+            Ok(user.cloned())
+        }
+    }
+}
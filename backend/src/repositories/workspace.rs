@@ -0,0 +1,501 @@
+use super::unit_of_work::DbContext;
+use super::RepositoryError;
+use axum::async_trait;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection, PgPool};
+use utoipa::ToSchema;
+
+/// What a member may do in a workspace; each role includes the ones before it.
+#[derive(
+    Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, sqlx::Type, ToSchema,
+)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "workspace_role", rename_all = "lowercase")]
+pub enum Role {
+    /// read todos and labels
+    Viewer,
+    /// also create, update and delete them
+    Editor,
+    /// also manage the members
+    Owner,
+}
+
+/// A workspace as seen by one of its members.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, FromRow, ToSchema)]
+pub struct Workspace {
+    pub id: i32,
+    pub name: String,
+    pub role: Role,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, FromRow, ToSchema)]
+pub struct Member {
+    pub user_id: i32,
+    pub role: Role,
+}
+
+#[async_trait]
+pub trait WorkspaceRepository: Clone + std::marker::Send + std::marker::Sync + 'static {
+    /// Creates a workspace owned by `user_id`.
+    async fn create(&self, user_id: i32, name: String) -> anyhow::Result<Workspace>;
+    async fn all(&self, user_id: i32) -> anyhow::Result<Vec<Workspace>>;
+    async fn role_of(&self, workspace_id: i32, user_id: i32) -> anyhow::Result<Option<Role>>;
+    async fn members(&self, workspace_id: i32) -> anyhow::Result<Vec<Member>>;
+    /// Adds `user_id` to the workspace, or changes the role of an existing member.
+    async fn set_member(
+        &self,
+        workspace_id: i32,
+        user_id: i32,
+        role: Role,
+    ) -> anyhow::Result<Member>;
+    async fn remove_member(&self, workspace_id: i32, user_id: i32) -> anyhow::Result<()>;
+}
+
+#[derive(Debug, Clone)]
+pub struct WorkspaceRepositoryForDb {
+    ctx: DbContext,
+}
+
+impl WorkspaceRepositoryForDb {
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            ctx: DbContext::Pool(pool),
+        }
+    }
+
+    pub fn with_context(ctx: DbContext) -> Self {
+        Self { ctx }
+    }
+}
+
+async fn create_workspace(
+    conn: &mut PgConnection,
+    user_id: i32,
+    name: String,
+) -> anyhow::Result<Workspace> {
+    let id: i32 =
+        sqlx::query_scalar(r#"insert into workspaces ( name ) values ( $1 ) returning id;"#)
+            .bind(name.clone())
+            .fetch_one(&mut *conn)
+            .await?;
+    set_member(&mut *conn, id, user_id, Role::Owner).await?;
+
+    Ok(Workspace {
+        id,
+        name,
+        role: Role::Owner,
+    })
+}
+
+async fn set_member(
+    conn: &mut PgConnection,
+    workspace_id: i32,
+    user_id: i32,
+    role: Role,
+) -> anyhow::Result<Member> {
+    let member = sqlx::query_as::<_, Member>(
+        r#"insert into workspace_members ( workspace_id, user_id, role ) values ( $1, $2, $3 )
+        on conflict ( workspace_id, user_id ) do update set role = excluded.role
+        returning user_id, role;"#,
+    )
+    .bind(workspace_id)
+    .bind(user_id)
+    .bind(role)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok(member)
+}
+
+#[async_trait]
+impl WorkspaceRepository for WorkspaceRepositoryForDb {
+    #[tracing::instrument(name = "workspace.create", skip(self, name))]
+    async fn create(&self, user_id: i32, name: String) -> anyhow::Result<Workspace> {
+        self.ctx
+            .transaction(|conn| Box::pin(create_workspace(conn, user_id, name)))
+            .await
+    }
+
+    #[tracing::instrument(name = "workspace.all", skip(self))]
+    async fn all(&self, user_id: i32) -> anyhow::Result<Vec<Workspace>> {
+        self.ctx
+            .transaction(|conn| {
+                Box::pin(async move {
+                    let workspaces = sqlx::query_as::<_, Workspace>(
+                        r#"select workspaces.id, workspaces.name, wm.role from workspaces
+                        inner join workspace_members wm on workspaces.id = wm.workspace_id
+                        where wm.user_id = $1 order by workspaces.id asc;"#,
+                    )
+                    .bind(user_id)
+                    .fetch_all(conn)
+                    .await?;
+                    Ok(workspaces)
+                })
+            })
+            .await
+    }
+
+    #[tracing::instrument(name = "workspace.role_of", skip(self))]
+    async fn role_of(&self, workspace_id: i32, user_id: i32) -> anyhow::Result<Option<Role>> {
+        self.ctx
+            .transaction(|conn| {
+                Box::pin(async move {
+                    let role = sqlx::query_scalar(
+                        r#"select role from workspace_members where workspace_id = $1 and user_id = $2;"#,
+                    )
+                    .bind(workspace_id)
+                    .bind(user_id)
+                    .fetch_optional(conn)
+                    .await?;
+                    Ok(role)
+                })
+            })
+            .await
+    }
+
+    #[tracing::instrument(name = "workspace.members", skip(self))]
+    async fn members(&self, workspace_id: i32) -> anyhow::Result<Vec<Member>> {
+        self.ctx
+            .transaction(|conn| {
+                Box::pin(async move {
+                    let members = sqlx::query_as::<_, Member>(
+                        r#"select user_id, role from workspace_members where workspace_id = $1 order by user_id asc;"#,
+                    )
+                    .bind(workspace_id)
+                    .fetch_all(conn)
+                    .await?;
+                    Ok(members)
+                })
+            })
+            .await
+    }
+
+    #[tracing::instrument(name = "workspace.set_member", skip(self))]
+    async fn set_member(
+        &self,
+        workspace_id: i32,
+        user_id: i32,
+        role: Role,
+    ) -> anyhow::Result<Member> {
+        self.ctx
+            .transaction(|conn| Box::pin(set_member(conn, workspace_id, user_id, role)))
+            .await
+    }
+
+    #[tracing::instrument(name = "workspace.remove_member", skip(self))]
+    async fn remove_member(&self, workspace_id: i32, user_id: i32) -> anyhow::Result<()> {
+        self.ctx
+            .transaction(|conn| {
+                Box::pin(async move {
+                    let result = sqlx::query(
+                        r#"delete from workspace_members where workspace_id = $1 and user_id = $2;"#,
+                    )
+                    .bind(workspace_id)
+                    .bind(user_id)
+                    .execute(conn)
+                    .await?;
+                    if result.rows_affected() == 0 {
+                        return Err(RepositoryError::NotFound(user_id).into());
+                    }
+                    Ok(())
+                })
+            })
+            .await
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod test {
+    use super::*;
+    use crate::repositories::{
+        unit_of_work::UnitOfWorkForDb,
+        user::{test_utils::prepare_user, UserRepositoryForDb},
+    };
+    use dotenv::dotenv;
+    use std::env;
+
+    #[tokio::test]
+    async fn membership_scenario() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database_url));
+        // nothing is committed, so other tests never see these rows
+        let uow = UnitOfWorkForDb::begin(&pool)
+            .await
+            .expect("[begin] returned Err");
+        let users = UserRepositoryForDb::with_context(DbContext::UnitOfWork(uow.clone()));
+        let owner = prepare_user(&users, "[membership_scenario] owner").await;
+        let viewer = prepare_user(&users, "[membership_scenario] viewer").await;
+        let repository = WorkspaceRepositoryForDb::with_context(DbContext::UnitOfWork(uow));
+
+        // create
+        let workspace = repository
+            .create(owner.id, "[membership_scenario] workspace".to_string())
+            .await
+            .expect("[create] returned Err");
+        assert_eq!(Role::Owner, workspace.role);
+
+        // add a member
+        let member = repository
+            .set_member(workspace.id, viewer.id, Role::Viewer)
+            .await
+            .expect("[set_member] returned Err");
+        assert_eq!(Role::Viewer, member.role);
+        let role = repository
+            .role_of(workspace.id, viewer.id)
+            .await
+            .expect("[role_of] returned Err");
+        assert_eq!(Some(Role::Viewer), role);
+        let workspaces = repository.all(viewer.id).await.expect("[all] returned Err");
+        assert_eq!(
+            vec![Workspace {
+                role: Role::Viewer,
+                ..workspace.clone()
+            }],
+            workspaces
+        );
+
+        // change a role
+        repository
+            .set_member(workspace.id, viewer.id, Role::Editor)
+            .await
+            .expect("[set_member] returned Err");
+        let members = repository
+            .members(workspace.id)
+            .await
+            .expect("[members] returned Err");
+        assert_eq!(
+            vec![
+                Member {
+                    user_id: owner.id,
+                    role: Role::Owner
+                },
+                Member {
+                    user_id: viewer.id,
+                    role: Role::Editor
+                },
+            ],
+            members
+        );
+
+        // remove a member
+        repository
+            .remove_member(workspace.id, viewer.id)
+            .await
+            .expect("[remove_member] returned Err");
+        let role = repository
+            .role_of(workspace.id, viewer.id)
+            .await
+            .expect("[role_of] returned Err");
+        assert_eq!(None, role);
+        let res = repository.remove_member(workspace.id, viewer.id).await;
+        assert!(res.is_err());
+    }
+}
+
+#[cfg(test)]
+pub mod test_utils {
+    use super::*;
+    use crate::repositories::user::{test_utils::prepare_user, UserRepository};
+    use std::{
+        collections::HashMap,
+        sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    };
+
+    /// Finds the workspace `name` of `username` or creates both, for tests owning
+    /// rows that are committed.
+    pub async fn prepare_workspace<U: UserRepository, W: WorkspaceRepository>(
+        users: &U,
+        workspaces: &W,
+        username: &str,
+        name: &str,
+    ) -> Workspace {
+        let user = prepare_user(users, username).await;
+        let workspace = workspaces
+            .all(user.id)
+            .await
+            .expect("Failed to find workspace data.")
+            .into_iter()
+            .find(|workspace| workspace.name == name);
+        match workspace {
+            Some(workspace) => workspace,
+            None => workspaces
+                .create(user.id, name.to_string())
+                .await
+                .expect("Failed to insert workspace data."),
+        }
+    }
+
+    /// workspace names per id
+    type WorkspaceData = HashMap<i32, String>;
+    /// roles per workspace id and user id
+    type MemberData = HashMap<(i32, i32), Role>;
+
+    #[derive(Debug, Clone)]
+    pub struct WorkspaceRepositoryForMemory {
+        store: Arc<RwLock<WorkspaceData>>,
+        members: Arc<RwLock<MemberData>>,
+    }
+
+    impl WorkspaceRepositoryForMemory {
+        pub fn new() -> Self {
+            WorkspaceRepositoryForMemory {
+                store: Arc::default(),
+                members: Arc::default(),
+            }
+        }
+
+        /// A repository holding a single workspace with id 1, owned by `user_id`.
+        pub fn with_owner(user_id: i32) -> Self {
+            let repository = Self::new();
+            repository
+                .write_store_ref()
+                .insert(1, "workspace".to_string());
+            repository
+                .write_members_ref()
+                .insert((1, user_id), Role::Owner);
+            repository
+        }
+
+        fn write_store_ref(&self) -> RwLockWriteGuard<'_, WorkspaceData> {
+            self.store.write().unwrap()
+        }
+
+        fn read_store_ref(&self) -> RwLockReadGuard<'_, WorkspaceData> {
+            self.store.read().unwrap()
+        }
+
+        fn write_members_ref(&self) -> RwLockWriteGuard<'_, MemberData> {
+            self.members.write().unwrap()
+        }
+
+        fn read_members_ref(&self) -> RwLockReadGuard<'_, MemberData> {
+            self.members.read().unwrap()
+        }
+    }
+
+    #[async_trait]
+    impl WorkspaceRepository for WorkspaceRepositoryForMemory {
+        async fn create(&self, user_id: i32, name: String) -> anyhow::Result<Workspace> {
+            let mut store = self.write_store_ref();
+            let id = store.keys().max().unwrap_or(&0) + 1;
+            store.insert(id, name.clone());
+            self.write_members_ref().insert((id, user_id), Role::Owner);
+            Ok(Workspace {
+                id,
+                name,
+                role: Role::Owner,
+            })
+        }
+
+        async fn all(&self, user_id: i32) -> anyhow::Result<Vec<Workspace>> {
+            let store = self.read_store_ref();
+            let members = self.read_members_ref();
+            let mut workspaces: Vec<Workspace> = members
+                .iter()
+                .filter(|((_workspace_id, member_id), _role)| *member_id == user_id)
+                .map(|((workspace_id, _member_id), role)| Workspace {
+                    id: *workspace_id,
+                    name: store[workspace_id].clone(),
+                    role: *role,
+                })
+                .collect();
+            workspaces.sort_by_key(|workspace| workspace.id);
+            Ok(workspaces)
+        }
+
+        async fn role_of(&self, workspace_id: i32, user_id: i32) -> anyhow::Result<Option<Role>> {
+            let members = self.read_members_ref();
+            Ok(members.get(&(workspace_id, user_id)).copied())
+        }
+
+        async fn members(&self, workspace_id: i32) -> anyhow::Result<Vec<Member>> {
+            let members = self.read_members_ref();
+            let mut members: Vec<Member> = members
+                .iter()
+                .filter(|((id, _user_id), _role)| *id == workspace_id)
+                .map(|((_id, user_id), role)| Member {
+                    user_id: *user_id,
+                    role: *role,
+                })
+                .collect();
+            members.sort_by_key(|member| member.user_id);
+            Ok(members)
+        }
+
+        async fn set_member(
+            &self,
+            workspace_id: i32,
+            user_id: i32,
+            role: Role,
+        ) -> anyhow::Result<Member> {
+            if !self.read_store_ref().contains_key(&workspace_id) {
+                return Err(RepositoryError::NotFound(workspace_id).into());
+            }
+            self.write_members_ref()
+                .insert((workspace_id, user_id), role);
+            Ok(Member { user_id, role })
+        }
+
+        async fn remove_member(&self, workspace_id: i32, user_id: i32) -> anyhow::Result<()> {
+            self.write_members_ref()
+                .remove(&(workspace_id, user_id))
+                .ok_or(RepositoryError::NotFound(user_id))?;
+            Ok(())
+        }
+    }
+
+    mod test {
+        use super::*;
+
+        #[tokio::test]
+        async fn workspace_membership_scenario() {
+            let (owner_id, viewer_id) = (1, 2);
+            let repository = WorkspaceRepositoryForMemory::new();
+
+            // create
+            let workspace = repository
+                .create(owner_id, "workspace".to_string())
+                .await
+                .expect("failed create workspace");
+            assert_eq!(
+                Workspace {
+                    id: 1,
+                    name: "workspace".to_string(),
+                    role: Role::Owner,
+                },
+                workspace
+            );
+
+            // add a member
+            repository
+                .set_member(workspace.id, viewer_id, Role::Viewer)
+                .await
+                .expect("failed set member");
+            let role = repository.role_of(workspace.id, viewer_id).await.unwrap();
+            assert_eq!(Some(Role::Viewer), role);
+            let workspaces = repository.all(viewer_id).await.unwrap();
+            assert_eq!(
+                vec![Workspace {
+                    role: Role::Viewer,
+                    ..workspace.clone()
+                }],
+                workspaces
+            );
+            let members = repository.members(workspace.id).await.unwrap();
+            assert_eq!(2, members.len());
+
+            // remove a member
+            repository
+                .remove_member(workspace.id, viewer_id)
+                .await
+                .expect("failed remove member");
+            let role = repository.role_of(workspace.id, viewer_id).await.unwrap();
+            assert_eq!(None, role);
+            assert!(repository.all(viewer_id).await.unwrap().is_empty());
+        }
+    }
+}
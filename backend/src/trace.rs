@@ -0,0 +1,124 @@
+use axum::{
+    body::Body,
+    http::{header::HeaderName, HeaderValue, Request},
+    response::Response,
+};
+use futures::future::BoxFuture;
+use std::{
+    fmt,
+    task::{Context, Poll},
+    time::Instant,
+};
+use tower::{Layer, Service};
+use tracing::{field, Instrument};
+use uuid::Uuid;
+
+pub static X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest id taken over from a client; longer ones are replaced.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Identifies a request in the logs, kept in the request extensions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(HeaderValue);
+
+impl RequestId {
+    /// Takes over the `X-Request-Id` of the client if it is printable, or makes a new one.
+    fn of(req: &Request<Body>) -> Self {
+        req.headers()
+            .get(&X_REQUEST_ID)
+            .filter(|value| {
+                let bytes = value.as_bytes();
+                !bytes.is_empty()
+                    && bytes.len() <= MAX_REQUEST_ID_LEN
+                    && bytes.iter().all(u8::is_ascii_graphic)
+            })
+            .cloned()
+            .map(Self)
+            .unwrap_or_else(|| {
+                let id = Uuid::new_v4().to_string();
+                Self(HeaderValue::from_str(&id).expect("uuid is a header value"))
+            })
+    }
+
+    pub fn as_str(&self) -> &str {
+        // only visible ASCII gets in
+        self.0.to_str().unwrap_or_default()
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Gives each request an `X-Request-Id`, echoed in the response, and handles it in a span
+/// carrying the id, method, path, status and latency, so that every log line of the request
+/// can be told apart. Added last, so that timeouts and CORS answers are logged as well.
+#[derive(Debug, Clone, Default)]
+pub struct TraceLayer;
+
+impl TraceLayer {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl<S> Layer<S> for TraceLayer {
+    type Service = TraceService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        TraceService { inner }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TraceService<S> {
+    inner: S,
+}
+
+impl<S> Service<Request<Body>> for TraceService<S>
+where
+    S: Service<Request<Body>, Response = Response>,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<Body>) -> Self::Future {
+        let request_id = RequestId::of(&req);
+        let span = tracing::info_span!(
+            "request",
+            request_id = %request_id,
+            method = %req.method(),
+            path = %req.uri().path(),
+            status = field::Empty,
+            latency_ms = field::Empty,
+        );
+        req.headers_mut()
+            .insert(X_REQUEST_ID.clone(), request_id.0.clone());
+        req.extensions_mut().insert(request_id.clone());
+        let start = Instant::now();
+        let response = span.in_scope(|| self.inner.call(req));
+        Box::pin(
+            async move {
+                let mut response = response.await?;
+                let span = tracing::Span::current();
+                span.record("status", response.status().as_u16());
+                span.record("latency_ms", start.elapsed().as_millis() as u64);
+                tracing::info!("finished request");
+                response
+                    .headers_mut()
+                    .insert(X_REQUEST_ID.clone(), request_id.0);
+                Ok(response)
+            }
+            .instrument(span),
+        )
+    }
+}
@@ -1,7 +1,14 @@
 import { useEffect, useState, FC } from "react";
 import "modern-css-reset";
 import { ThemeProvider, createTheme } from "@mui/material/styles";
-import { Box, Stack, Typography } from "@mui/material";
+import {
+    Box,
+    Button,
+    MenuItem,
+    Select,
+    Stack,
+    Typography,
+} from "@mui/material";
 import {
     Label,
     NewTodoPayload,
@@ -9,9 +16,11 @@ import {
     NewLabelPayload,
     UpdateTodoPayload,
 } from "./types/todo";
+import { Credentials, Workspace } from "./types/auth";
 import TodoList from "./components/TodoList";
 import TodoForm from "./components/TodoForm";
 import SideNav from "./components/SideNav";
+import LoginForm from "./components/LoginForm";
 import {
     addTodoItem,
     deleteTodoItem,
@@ -19,30 +28,44 @@ import {
     updateTodoItem,
 } from "./lib/api/todo";
 import { addLabelItem, deleteLabelItem, getLabelItems } from "./lib/api/label";
+import { getWorkspaces, login, logout, signup } from "./lib/api/auth";
+import { getToken } from "./lib/api/helper";
+
+type TodoAppProps = {
+    workspaceId: number;
+    workspaces: Workspace[];
+    onSelectWorkspace: (id: number) => void;
+    onLogout: () => void;
+};
 
-const TodoApp: FC = () => {
+const TodoApp: FC<TodoAppProps> = ({
+    workspaceId,
+    workspaces,
+    onSelectWorkspace,
+    onLogout,
+}) => {
     const [todos, setTodos] = useState<Todo[]>([]);
     const [labels, setLabels] = useState<Label[]>([]);
     const [filterLabelId, setFilterLabelId] = useState<number | null>(null);
 
     const onSubmit = async (payload: NewTodoPayload) => {
-        await addTodoItem(payload);
+        await addTodoItem(workspaceId, payload);
         // APIより再度Todo配列を取得
-        const todos = await getTodoItems();
+        const todos = await getTodoItems(workspaceId);
         setTodos(todos);
     };
 
     const onUpdate = async (updateTodo: UpdateTodoPayload) => {
-        await updateTodoItem(updateTodo);
+        await updateTodoItem(workspaceId, updateTodo);
         // APIより再度Todo配列を取得
-        const todos = await getTodoItems();
+        const todos = await getTodoItems(workspaceId);
         setTodos(todos);
     };
 
     const onDelete = async (id: number) => {
-        await deleteTodoItem(id);
+        await deleteTodoItem(workspaceId, id);
         // APIより再度Todo配列を取得
-        const todos = await getTodoItems();
+        const todos = await getTodoItems(workspaceId);
         setTodos(todos);
     };
 
@@ -52,14 +75,20 @@ const TodoApp: FC = () => {
 
     const onSubmitNewLabel = async (newLabel: NewLabelPayload) => {
         if (!labels.some((label) => label.name === newLabel.name)) {
-            const res = await addLabelItem(newLabel);
+            const res = await addLabelItem(workspaceId, newLabel);
             setLabels([...labels, res]);
         }
     };
 
     const onDeleteLabel = async (id: number) => {
-        await deleteLabelItem(id);
+        await deleteLabelItem(workspaceId, id);
         setLabels((prev) => prev.filter((label) => label.id !== id));
+        setTodos((prev) =>
+            prev.map((todo) => ({
+                ...todo,
+                labels: todo.labels.filter((label) => label.id !== id),
+            }))
+        );
     };
 
     const dispTodo = filterLabelId
@@ -70,12 +99,12 @@ const TodoApp: FC = () => {
 
     useEffect(() => {
         (async () => {
-            const todos = await getTodoItems();
+            const todos = await getTodoItems(workspaceId);
             setTodos(todos);
-            const labelResponse = await getLabelItems();
+            const labelResponse = await getLabelItems(workspaceId);
             setLabels(labelResponse);
         })();
-    }, []);
+    }, [workspaceId]);
 
     return (
         <>
@@ -85,6 +114,7 @@ const TodoApp: FC = () => {
                     borderBottom: "1px solid gray",
                     display: "flex",
                     alignItems: "center",
+                    justifyContent: "space-between",
                     position: "fixed",
                     top: 0,
                     p: 2,
@@ -93,6 +123,21 @@ const TodoApp: FC = () => {
                     zIndex: 3,
                 }}>
                 <Typography variant="h1">Todo App</Typography>
+                <Stack direction="row" spacing={2} alignItems="center">
+                    <Select
+                        size="small"
+                        value={workspaceId}
+                        onChange={(e) =>
+                            onSelectWorkspace(Number(e.target.value))
+                        }>
+                        {workspaces.map((workspace) => (
+                            <MenuItem key={workspace.id} value={workspace.id}>
+                                {workspace.name}
+                            </MenuItem>
+                        ))}
+                    </Select>
+                    <Button onClick={onLogout}>log out</Button>
+                </Stack>
             </Box>
             <Box
                 sx={{
@@ -135,6 +180,41 @@ const TodoApp: FC = () => {
     );
 };
 
+type SessionProps = {
+    onLogout: () => void;
+};
+
+const Session: FC<SessionProps> = ({ onLogout }) => {
+    const [workspaces, setWorkspaces] = useState<Workspace[]>([]);
+    const [workspaceId, setWorkspaceId] = useState<number | null>(null);
+
+    useEffect(() => {
+        (async () => {
+            try {
+                const workspaces = await getWorkspaces();
+                setWorkspaces(workspaces);
+                setWorkspaceId(workspaces[0]?.id ?? null);
+            } catch {
+                // the token expired, so log in again
+                onLogout();
+            }
+        })();
+    }, []);
+
+    if (workspaceId === null) {
+        return null;
+    }
+    return (
+        <TodoApp
+            key={workspaceId}
+            workspaceId={workspaceId}
+            workspaces={workspaces}
+            onSelectWorkspace={setWorkspaceId}
+            onLogout={onLogout}
+        />
+    );
+};
+
 const theme = createTheme({
     typography: {
         h1: {
@@ -147,9 +227,30 @@ const theme = createTheme({
 });
 
 const App: FC = () => {
+    const [loggedIn, setLoggedIn] = useState(getToken() !== null);
+
+    const onLogin = async (credentials: Credentials) => {
+        await login(credentials);
+        setLoggedIn(true);
+    };
+
+    const onSignup = async (credentials: Credentials) => {
+        await signup(credentials);
+        setLoggedIn(true);
+    };
+
+    const onLogout = () => {
+        logout();
+        setLoggedIn(false);
+    };
+
     return (
         <ThemeProvider theme={theme}>
-            <TodoApp />
+            {loggedIn ? (
+                <Session onLogout={onLogout} />
+            ) : (
+                <LoginForm onLogin={onLogin} onSignup={onSignup} />
+            )}
         </ThemeProvider>
     );
 };
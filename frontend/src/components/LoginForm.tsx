@@ -0,0 +1,64 @@
+import { FC, useState } from "react";
+import { Credentials } from "../types/auth";
+import { Box, Button, Paper, Stack, TextField, Typography } from "@mui/material";
+
+type Props = {
+    onLogin: (credentials: Credentials) => Promise<void>;
+    onSignup: (credentials: Credentials) => Promise<void>;
+};
+
+const LoginForm: FC<Props> = ({ onLogin, onSignup }) => {
+    const [username, setUsername] = useState("");
+    const [password, setPassword] = useState("");
+    const [error, setError] = useState<string | null>(null);
+
+    const submitHandler =
+        (submit: (credentials: Credentials) => Promise<void>) => async () => {
+            if (!username || !password) return;
+
+            try {
+                await submit({ username, password });
+            } catch (e) {
+                setError(e instanceof Error ? e.message : String(e));
+            }
+        };
+
+    return (
+        <Box sx={{ display: "flex", justifyContent: "center", p: 5 }}>
+            <Paper elevation={2} sx={{ maxWidth: 400, width: "100%" }}>
+                <Stack spacing={2} sx={{ p: 2 }}>
+                    <Typography variant="h2">Log in</Typography>
+                    <TextField
+                        label="username"
+                        variant="filled"
+                        value={username}
+                        onChange={(e) => setUsername(e.target.value)}
+                        fullWidth
+                    />
+                    <TextField
+                        label="password"
+                        type="password"
+                        variant="filled"
+                        value={password}
+                        onChange={(e) => setPassword(e.target.value)}
+                        fullWidth
+                    />
+                    {error && <Typography color="error">{error}</Typography>}
+                    <Stack direction="row" spacing={2}>
+                        <Button onClick={submitHandler(onLogin)} fullWidth>
+                            log in
+                        </Button>
+                        <Button
+                            onClick={submitHandler(onSignup)}
+                            fullWidth
+                            color="secondary">
+                            sign up
+                        </Button>
+                    </Stack>
+                </Stack>
+            </Paper>
+        </Box>
+    );
+};
+
+export default LoginForm;
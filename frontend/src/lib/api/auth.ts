@@ -0,0 +1,34 @@
+import type { AuthResponse, Credentials, Workspace } from "../../types/auth";
+import { API_HEADER, API_URL, authHeader, setToken } from "./helper";
+
+const authenticate = async (path: string, payload: Credentials) => {
+    const res = await fetch(`${API_URL}/auth/${path}`, {
+        method: "POST",
+        headers: API_HEADER,
+        body: JSON.stringify(payload),
+    });
+    if (!res.ok) {
+        throw new Error(`${path} request failed`);
+    }
+    const json: AuthResponse = await res.json();
+    setToken(json.token);
+    return json;
+};
+
+export const login = (payload: Credentials) => authenticate("login", payload);
+
+export const signup = (payload: Credentials) =>
+    authenticate("signup", payload);
+
+export const logout = () => setToken(null);
+
+export const getWorkspaces = async () => {
+    const res = await fetch(`${API_URL}/workspaces`, {
+        headers: authHeader(),
+    });
+    if (!res.ok) {
+        throw new Error("get workspace request failed");
+    }
+    const json: Workspace[] = await res.json();
+    return json;
+};
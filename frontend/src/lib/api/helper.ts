@@ -1,2 +1,19 @@
 export const API_URL = "http://localhost:3000";
 export const API_HEADER = { "Content-Type": "application/json" };
+
+const TOKEN_KEY = "token";
+
+export const getToken = () => localStorage.getItem(TOKEN_KEY);
+
+export const setToken = (token: string | null) => {
+    if (token) {
+        localStorage.setItem(TOKEN_KEY, token);
+    } else {
+        localStorage.removeItem(TOKEN_KEY);
+    }
+};
+
+export const authHeader = () => ({ Authorization: `Bearer ${getToken()}` });
+
+export const workspaceUrl = (workspaceId: number, path: string) =>
+    `${API_URL}/workspaces/${workspaceId}${path}`;
@@ -1,8 +1,10 @@
 import type { Label, NewLabelPayload } from "../../types/todo";
-import { API_HEADER, API_URL } from "./helper";
+import { API_HEADER, authHeader, workspaceUrl } from "./helper";
 
-export const getLabelItems = async () => {
-    const res = await fetch(`${API_URL}/labels`);
+export const getLabelItems = async (workspaceId: number) => {
+    const res = await fetch(workspaceUrl(workspaceId, "/labels"), {
+        headers: authHeader(),
+    });
     if (!res.ok) {
         throw new Error("get label request failed");
     }
@@ -10,10 +12,13 @@ export const getLabelItems = async () => {
     return json;
 };
 
-export const addLabelItem = async (payload: NewLabelPayload) => {
-    const res = await fetch(`${API_URL}/labels`, {
+export const addLabelItem = async (
+    workspaceId: number,
+    payload: NewLabelPayload
+) => {
+    const res = await fetch(workspaceUrl(workspaceId, "/labels"), {
         method: "POST",
-        headers: API_HEADER,
+        headers: { ...API_HEADER, ...authHeader() },
         body: JSON.stringify(payload),
     });
     if (!res.ok) {
@@ -23,10 +28,14 @@ export const addLabelItem = async (payload: NewLabelPayload) => {
     return json;
 };
 
-export const deleteLabelItem = async (id: number) => {
-    const res = await fetch(`${API_URL}/labels/${id}`, {
-        method: "DELETE",
-    });
+export const deleteLabelItem = async (workspaceId: number, id: number) => {
+    const res = await fetch(
+        workspaceUrl(workspaceId, `/labels/${id}?strategy=detach`),
+        {
+            method: "DELETE",
+            headers: authHeader(),
+        }
+    );
     if (!res.ok) {
         throw new Error("delete label request failed");
     }
@@ -1,10 +1,13 @@
-import type { NewTodoPayload, Todo, UpdateTodoPayload } from "../../types/todo";
-import { API_HEADER, API_URL } from "./helper";
+import type { NewTodoPayload, Todo, TodoPage, UpdateTodoPayload } from "../../types/todo";
+import { API_HEADER, authHeader, workspaceUrl } from "./helper";
 
-export const addTodoItem = async (payload: NewTodoPayload) => {
-    const res = await fetch(`${API_URL}/todos`, {
+export const addTodoItem = async (
+    workspaceId: number,
+    payload: NewTodoPayload
+) => {
+    const res = await fetch(workspaceUrl(workspaceId, "/todos"), {
         method: "POST",
-        headers: API_HEADER,
+        headers: { ...API_HEADER, ...authHeader() },
         body: JSON.stringify(payload),
     });
     if (!res.ok) {
@@ -14,20 +17,32 @@ export const addTodoItem = async (payload: NewTodoPayload) => {
     return json;
 };
 
-export const getTodoItems = async () => {
-    const res = await fetch(`${API_URL}/todos`);
-    if (!res.ok) {
-        throw new Error("get todo request failed");
-    }
-    const json: Todo[] = await res.json();
-    return json;
+export const getTodoItems = async (workspaceId: number) => {
+    const todos: Todo[] = [];
+    let cursor: string | null = null;
+    do {
+        const query: string = cursor ? `?after=${cursor}` : "";
+        const res = await fetch(workspaceUrl(workspaceId, `/todos${query}`), {
+            headers: authHeader(),
+        });
+        if (!res.ok) {
+            throw new Error("get todo request failed");
+        }
+        const json: TodoPage = await res.json();
+        todos.push(...json.items);
+        cursor = json.next_cursor;
+    } while (cursor);
+    return todos;
 };
 
-export const updateTodoItem = async (todo: UpdateTodoPayload) => {
+export const updateTodoItem = async (
+    workspaceId: number,
+    todo: UpdateTodoPayload
+) => {
     const { id, ...updateTodo } = todo;
-    const res = await fetch(`${API_URL}/todos/${id}`, {
+    const res = await fetch(workspaceUrl(workspaceId, `/todos/${id}`), {
         method: "PATCH",
-        headers: API_HEADER,
+        headers: { ...API_HEADER, ...authHeader() },
         body: JSON.stringify(updateTodo),
     });
     if (!res.ok) {
@@ -37,9 +52,10 @@ export const updateTodoItem = async (todo: UpdateTodoPayload) => {
     return json;
 };
 
-export const deleteTodoItem = async (id: number) => {
-    const res = await fetch(`${API_URL}/todos/${id}`, {
+export const deleteTodoItem = async (workspaceId: number, id: number) => {
+    const res = await fetch(workspaceUrl(workspaceId, `/todos/${id}`), {
         method: "DELETE",
+        headers: authHeader(),
     });
     if (!res.ok) {
         throw new Error("delete todo request failed");
@@ -0,0 +1,22 @@
+export type Credentials = {
+    username: string;
+    password: string;
+};
+
+export type User = {
+    id: number;
+    username: string;
+};
+
+export type AuthResponse = {
+    token: string;
+    user: User;
+};
+
+export type Role = "viewer" | "editor" | "owner";
+
+export type Workspace = {
+    id: number;
+    name: string;
+    role: Role;
+};
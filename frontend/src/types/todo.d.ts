@@ -5,6 +5,11 @@ export type Todo = {
     labels: Label[];
 };
 
+export type TodoPage = {
+    items: Todo[];
+    next_cursor: string | null;
+};
+
 export type NewTodoPayload = {
     text: string;
     labels: number[];